@@ -0,0 +1,167 @@
+// This file implements the conversion of the barometer pressure to an altitude.
+// The pressure is around 100 000 Pa, far outside the range of I16F16, so it is handled as I48F16.
+// The standard atmosphere gives the altitude of a pressure as 44330.8 * (1 - (p / 101325)^0.190263)
+// metres. A power with a fractional exponent is too slow without an FPU, so the formula is
+// tabulated every 500 Pa and interpolated linearly in between, which is accurate to 2 cm near sea
+// level and to 20 cm at the top of the table. The altitude relative to the pressure measured during
+// calibration is the difference of the two standard altitudes, it fits I16F16 easily.
+
+use fixed::types::{I16F16, I48F16};
+
+pub type Pressure = I48F16;
+// Metres, positive is up.
+pub type Altitude = I16F16;
+
+pub const SEA_LEVEL_PRESSURE: i32 = 101_325;
+
+// The pressure range of the table in Pa, readings outside of it are clamped.
+pub const MIN_PRESSURE: i32 = 30_000;
+pub const MAX_PRESSURE: i32 = 110_000;
+const STEP: i32 = 500;
+
+// The standard altitude in mm from MIN_PRESSURE to MAX_PRESSURE, every STEP Pa.
+const ALTITUDE_MM: [i32; ((MAX_PRESSURE - MIN_PRESSURE) / STEP + 1) as usize] = [
+    9163953, 9053182, 8943872, 8835981, 8729467, 8624293, 8520420, 8417815, 8316442, 8216269,
+    8117264, 8019399, 7922643, 7826970, 7732352, 7638763, 7546180, 7454578, 7363934, 7274226,
+    7185433, 7097535, 7010511, 6924341, 6839009, 6754495, 6670782, 6587854, 6505695, 6424288,
+    6343618, 6263670, 6184431, 6105887, 6028023, 5950827, 5874286, 5798389, 5723122, 5648474,
+    5574435, 5500993, 5428137, 5355857, 5284143, 5212986, 5142375, 5072302, 5002756, 4933731,
+    4865216, 4797204, 4729686, 4662654, 4596101, 4530019, 4464401, 4399239, 4334527, 4270257,
+    4206423, 4143019, 4080037, 4017472, 3955317, 3893567, 3832216, 3771258, 3710687, 3650498,
+    3590686, 3531245, 3472170, 3413457, 3355100, 3297095, 3239437, 3182120, 3125142, 3068497,
+    3012181, 2956190, 2900519, 2845165, 2790124, 2735391, 2680963, 2626836, 2573006, 2519470,
+    2466225, 2413266, 2360590, 2308194, 2256074, 2204228, 2152652, 2101343, 2050298, 1999514,
+    1948988, 1898717, 1848698, 1798929, 1749406, 1700127, 1651090, 1602291, 1553728, 1505398,
+    1457300, 1409430, 1361786, 1314366, 1267168, 1220188, 1173426, 1126878, 1080542, 1034417,
+    988500, 942789, 897283, 851978, 806873, 761966, 717256, 672739, 628416, 584282, 540337, 496579,
+    453006, 409617, 366409, 323381, 280532, 237859, 195361, 153037, 110884, 68902, 27089, -14557,
+    -56038, -97353, -138507, -179498, -220330, -261003, -301519, -341878, -382084, -422135,
+    -462035, -501785, -541385, -580836, -620141, -659300, -698314,
+];
+
+// The standard altitude of a pressure in Pa.
+pub fn standard_altitude(pressure: Pressure) -> Altitude {
+    let min = (MIN_PRESSURE as i64) << 16;
+    let max = (MAX_PRESSURE as i64) << 16;
+    let step = (STEP as i64) << 16;
+    let offset = pressure.to_bits().clamp(min, max) - min;
+    let index = (offset / step) as usize;
+    let low = ALTITUDE_MM[index] as i64;
+    // mm with 16 fractional bits
+    let altitude = match ALTITUDE_MM.get(index + 1) {
+        Some(&high) => (low << 16) + (high as i64 - low) * (offset % step) / STEP as i64,
+        None => low << 16,
+    };
+    Altitude::from_bits((altitude / 1000) as i32)
+}
+
+// The altitude relative to a reference pressure, the one measured on the ground during calibration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PressureAltitude {
+    reference: Altitude,
+}
+
+impl PressureAltitude {
+    pub fn new(reference: Pressure) -> Self {
+        PressureAltitude {
+            reference: standard_altitude(reference),
+        }
+    }
+
+    pub fn altitude(&self, pressure: Pressure) -> Altitude {
+        standard_altitude(pressure) - self.reference
+    }
+}
+
+impl Default for PressureAltitude {
+    // Altitude above the standard sea level, until there is a calibration.
+    fn default() -> Self {
+        PressureAltitude::new(Pressure::from_num(SEA_LEVEL_PRESSURE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn barometric(pressure: f64) -> f64 {
+        44330.8 * (1.0 - (pressure / SEA_LEVEL_PRESSURE as f64).powf(0.190263))
+    }
+
+    #[test]
+    fn follows_the_barometric_formula_over_the_whole_range() {
+        // every 37 Pa, so the samples fall between the table entries
+        for pressure in (MIN_PRESSURE..=MAX_PRESSURE).step_by(37) {
+            let altitude = standard_altitude(Pressure::from_num(pressure)).to_num::<f64>();
+            let expected = barometric(pressure as f64);
+            assert!(
+                (altitude - expected).abs() < 0.25,
+                "{} Pa: {} m instead of {} m",
+                pressure,
+                altitude,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn is_accurate_near_sea_level() {
+        for pressure in (95_000..=105_000).step_by(13) {
+            let altitude = standard_altitude(Pressure::from_num(pressure)).to_num::<f64>();
+            assert!((altitude - barometric(pressure as f64)).abs() < 0.03);
+        }
+    }
+
+    #[test]
+    fn uses_the_fraction_of_the_pressure() {
+        let low = standard_altitude(Pressure::from_num(99_400));
+        let middle = standard_altitude(Pressure::from_num(99_400.5));
+        let high = standard_altitude(Pressure::from_num(99_401));
+        assert!(low > middle && middle > high);
+    }
+
+    #[test]
+    fn clamps_outside_the_table() {
+        assert_eq!(
+            standard_altitude(Pressure::from_num(0)),
+            standard_altitude(Pressure::from_num(MIN_PRESSURE))
+        );
+        assert_eq!(
+            standard_altitude(Pressure::from_num(200_000)),
+            standard_altitude(Pressure::from_num(MAX_PRESSURE))
+        );
+        assert_eq!(
+            standard_altitude(Pressure::from_num(-1)),
+            standard_altitude(Pressure::from_num(MIN_PRESSURE))
+        );
+    }
+
+    #[test]
+    fn is_zero_at_the_reference() {
+        for reference in [MIN_PRESSURE, 99_400, SEA_LEVEL_PRESSURE, MAX_PRESSURE] {
+            let altitude = PressureAltitude::new(Pressure::from_num(reference));
+            assert_eq!(altitude.altitude(Pressure::from_num(reference)), 0);
+        }
+    }
+
+    #[test]
+    fn relative_altitude_rises_as_the_pressure_drops() {
+        let altitude = PressureAltitude::new(Pressure::from_num(99_400));
+        // about 12 Pa per metre near the ground
+        let one_metre = altitude
+            .altitude(Pressure::from_num(99_388))
+            .to_num::<f64>();
+        assert!((one_metre - 1.0).abs() < 0.05, "{}", one_metre);
+        let below = altitude
+            .altitude(Pressure::from_num(99_600))
+            .to_num::<f64>();
+        assert!(below < -16.0 && below > -17.5, "{}", below);
+        // the extremes still fit the altitude type
+        let top = PressureAltitude::new(Pressure::from_num(MAX_PRESSURE))
+            .altitude(Pressure::from_num(MIN_PRESSURE))
+            .to_num::<f64>();
+        assert!(
+            (top - (barometric(MIN_PRESSURE as f64) - barometric(MAX_PRESSURE as f64))).abs() < 0.5
+        );
+    }
+}
@@ -0,0 +1,334 @@
+// This file implements the attitude estimators that run on the raw inertial sensors, next to the
+// attitude from the DMP of the MPU. They all use the convention of the DMP conversion: the
+// accelerometer at rest reads the direction of gravity in the body frame g, and
+//   pitch = atan2(gx, sqrt(gy^2 + gz^2)), roll = atan2(gy, gz)
+// so for small angles the rates are roll = +x, pitch = -y and yaw = -z of the gyroscope.
+//
+// The complementary filter mixes the integrated gyroscope with the angles from gravity by a fixed
+// weight. The Mahony filter keeps a quaternion, which is turned by the gyroscope and corrected by a
+// PI controller on the angle between the measured and the estimated direction of gravity, the
+// integral learns the gyroscope bias. The quaternion is kept in I32F32, the rotation of one tick is
+// only a few units of I16F16.
+
+use cordic::{atan2, sin_cos, sqrt};
+use fixed::types::{I16F16, I32F32};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Attitude {
+    // Radians.
+    pub yaw: I16F16,
+    pub pitch: I16F16,
+    pub roll: I16F16,
+}
+
+// One sample of the inertial sensors with the calibration offsets removed, except for gravity.
+#[derive(Clone, Copy, Debug)]
+pub struct ImuSample {
+    // The accelerometer in g.
+    pub accel: [I16F16; 3],
+    // The gyroscope in rad/s.
+    pub gyro: [I16F16; 3],
+    // The time since the previous sample in s.
+    pub dt: I16F16,
+}
+
+// The pitch and the roll from the direction of gravity.
+pub fn gravity_angles(accel: [I16F16; 3]) -> (I16F16, I16F16) {
+    let [x, y, z] = accel;
+    let pitch = atan2(x, sqrt(y * y + z * z));
+    let roll = atan2(y, z);
+    (pitch, roll)
+}
+
+// The yaw, pitch and roll rates of the gyroscope, for small angles.
+pub fn euler_rates(gyro: [I16F16; 3]) -> [I16F16; 3] {
+    [-gyro[2], -gyro[1], gyro[0]]
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Complementary {
+    // The share of the gyroscope in every step, the rest comes from gravity.
+    gyro_weight: I16F16,
+    attitude: Attitude,
+    // False until the first sample, which sets the angles from gravity.
+    started: bool,
+}
+
+impl Complementary {
+    pub fn new(gyro_weight: I16F16) -> Self {
+        Complementary {
+            gyro_weight,
+            attitude: Attitude::default(),
+            started: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Complementary::new(self.gyro_weight);
+    }
+
+    pub fn update(&mut self, sample: &ImuSample) -> Attitude {
+        let (pitch, roll) = gravity_angles(sample.accel);
+        if !self.started {
+            self.attitude = Attitude {
+                yaw: I16F16::ZERO,
+                pitch,
+                roll,
+            };
+            self.started = true;
+        }
+        let [yaw_rate, pitch_rate, roll_rate] = euler_rates(sample.gyro);
+        let weight = self.gyro_weight;
+        let attitude = &mut self.attitude;
+        attitude.yaw += yaw_rate * sample.dt;
+        attitude.pitch =
+            weight * (attitude.pitch + pitch_rate * sample.dt) + (I16F16::ONE - weight) * pitch;
+        attitude.roll =
+            weight * (attitude.roll + roll_rate * sample.dt) + (I16F16::ONE - weight) * roll;
+        *attitude
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Mahony {
+    // The gains of the correction in 1/s and 1/s^2.
+    kp: I32F32,
+    ki: I32F32,
+    // w, x, y, z
+    quaternion: [I32F32; 4],
+    // The learned gyroscope bias with the opposite sign, in rad/s.
+    integral: [I32F32; 3],
+    // False until the first sample, which sets the quaternion from gravity.
+    started: bool,
+}
+
+impl Mahony {
+    pub fn new(kp: I32F32, ki: I32F32) -> Self {
+        Mahony {
+            kp,
+            ki,
+            quaternion: [I32F32::ONE, I32F32::ZERO, I32F32::ZERO, I32F32::ZERO],
+            integral: [I32F32::ZERO; 3],
+            started: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Mahony::new(self.kp, self.ki);
+    }
+
+    pub fn update(&mut self, sample: &ImuSample) -> Attitude {
+        if !self.started {
+            self.start(sample.accel);
+        }
+        let dt = I32F32::from(sample.dt);
+        let mut rate = sample.gyro.map(I32F32::from);
+
+        let [ax, ay, az] = sample.accel.map(I32F32::from);
+        let norm = sqrt(ax * ax + ay * ay + az * az);
+        // in free fall there is no direction of gravity to correct with
+        if norm > I32F32::ZERO {
+            let (ax, ay, az) = (ax / norm, ay / norm, az / norm);
+            let [vx, vy, vz] = self.gravity();
+            let error = [ay * vz - az * vy, az * vx - ax * vz, ax * vy - ay * vx];
+            for axis in 0..3 {
+                self.integral[axis] += self.ki * error[axis] * dt;
+                rate[axis] += self.kp * error[axis] + self.integral[axis];
+            }
+        }
+
+        let [w, x, y, z] = self.quaternion;
+        let [gx, gy, gz] = rate;
+        let half_dt = dt / 2;
+        let derivative = [
+            -x * gx - y * gy - z * gz,
+            w * gx + y * gz - z * gy,
+            w * gy - x * gz + z * gx,
+            w * gz + x * gy - y * gx,
+        ];
+        for (value, derivative) in self.quaternion.iter_mut().zip(derivative) {
+            *value += derivative * half_dt;
+        }
+        self.normalize();
+        self.attitude()
+    }
+
+    pub fn attitude(&self) -> Attitude {
+        let [w, x, y, z] = self.quaternion;
+        let [gx, gy, gz] = self.gravity();
+        // the heading, with the sign of the DMP conversion
+        let yaw = -atan2((x * y + w * z) * 2, (w * w + x * x) * 2 - I32F32::ONE);
+        let pitch = atan2(gx, sqrt(gy * gy + gz * gz));
+        let roll = atan2(gy, gz);
+        Attitude {
+            yaw: yaw.saturating_to_num(),
+            pitch: pitch.saturating_to_num(),
+            roll: roll.saturating_to_num(),
+        }
+    }
+
+    // The direction of gravity in the body frame.
+    fn gravity(&self) -> [I32F32; 3] {
+        let [w, x, y, z] = self.quaternion;
+        [
+            (x * z - w * y) * 2,
+            (w * x + y * z) * 2,
+            w * w - x * x - y * y + z * z,
+        ]
+    }
+
+    // The rotation by -pitch about y and then by roll about x, which has the pitch and the roll of
+    // gravity and no yaw.
+    fn start(&mut self, accel: [I16F16; 3]) {
+        let (pitch, roll) = gravity_angles(accel);
+        let (sin_pitch, cos_pitch) = sin_cos(I32F32::from(-pitch) / 2);
+        let (sin_roll, cos_roll) = sin_cos(I32F32::from(roll) / 2);
+        self.quaternion = [
+            cos_pitch * cos_roll,
+            cos_pitch * sin_roll,
+            sin_pitch * cos_roll,
+            -sin_pitch * sin_roll,
+        ];
+        self.started = true;
+    }
+
+    fn normalize(&mut self) {
+        let norm = sqrt(
+            self.quaternion
+                .iter()
+                .map(|value| *value * *value)
+                .sum::<I32F32>(),
+        );
+        if norm > I32F32::ZERO {
+            for value in self.quaternion.iter_mut() {
+                *value /= norm;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f64 = 1.0 / 150.0;
+
+    // The reading of a drone at rest.
+    fn at_rest(pitch: f64, roll: f64) -> [I16F16; 3] {
+        [
+            pitch.sin(),
+            pitch.cos() * roll.sin(),
+            pitch.cos() * roll.cos(),
+        ]
+        .map(I16F16::from_num)
+    }
+
+    fn sample(accel: [I16F16; 3], gyro: [f64; 3]) -> ImuSample {
+        ImuSample {
+            accel,
+            gyro: gyro.map(I16F16::from_num),
+            dt: I16F16::from_num(DT),
+        }
+    }
+
+    fn close(attitude: Attitude, pitch: f64, roll: f64, tolerance: f64) -> bool {
+        (attitude.pitch.to_num::<f64>() - pitch).abs() < tolerance
+            && (attitude.roll.to_num::<f64>() - roll).abs() < tolerance
+    }
+
+    #[test]
+    fn gravity_gives_the_angles() {
+        for (pitch, roll) in [(0.0, 0.0), (0.4, 0.0), (0.0, -0.6), (-0.3, 0.5)] {
+            let (measured_pitch, measured_roll) = gravity_angles(at_rest(pitch, roll));
+            assert!((measured_pitch.to_num::<f64>() - pitch).abs() < 0.005);
+            assert!((measured_roll.to_num::<f64>() - roll).abs() < 0.005);
+        }
+    }
+
+    #[test]
+    fn complementary_starts_from_gravity() {
+        let mut filter = Complementary::new(I16F16::from_num(0.98));
+        let attitude = filter.update(&sample(at_rest(0.3, -0.2), [0.0; 3]));
+        assert!(close(attitude, 0.3, -0.2, 0.01));
+    }
+
+    #[test]
+    fn complementary_follows_a_roll() {
+        let mut filter = Complementary::new(I16F16::from_num(0.98));
+        let mut roll: f64 = 0.0;
+        for _ in 0..150 {
+            roll += 0.5 * DT;
+            let attitude = filter.update(&sample(at_rest(0.0, roll), [0.5, 0.0, 0.0]));
+            assert!(close(attitude, 0.0, roll, 0.02), "{}", roll);
+        }
+    }
+
+    #[test]
+    fn complementary_drifts_back_to_gravity() {
+        let mut filter = Complementary::new(I16F16::from_num(0.98));
+        // a gyroscope bias on pitch
+        for _ in 0..1500 {
+            filter.update(&sample(at_rest(0.0, 0.0), [0.0, 0.05, 0.0]));
+        }
+        let attitude = filter.update(&sample(at_rest(0.0, 0.0), [0.0, 0.05, 0.0]));
+        // the bias only leaves a small offset of dt * weight / (1 - weight) * bias
+        assert!(close(attitude, -0.02, 0.0, 0.01));
+    }
+
+    #[test]
+    fn mahony_starts_from_gravity() {
+        let mut filter = Mahony::new(I32F32::from_num(2), I32F32::from_num(0.1));
+        for (pitch, roll) in [(0.3, -0.2), (-0.5, 0.4), (0.0, 1.0)] {
+            filter.reset();
+            let attitude = filter.update(&sample(at_rest(pitch, roll), [0.0; 3]));
+            assert!(close(attitude, pitch, roll, 0.01), "{} {}", pitch, roll);
+            assert!(attitude.yaw.abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn mahony_follows_rotations_in_the_dmp_convention() {
+        let mut filter = Mahony::new(I32F32::from_num(2), I32F32::from_num(0.1));
+        let mut roll: f64 = 0.0;
+        for _ in 0..150 {
+            roll += 0.5 * DT;
+            let attitude = filter.update(&sample(at_rest(0.0, roll), [0.5, 0.0, 0.0]));
+            assert!(close(attitude, 0.0, roll, 0.02), "{}", roll);
+        }
+        filter.reset();
+        let mut pitch: f64 = 0.0;
+        for _ in 0..150 {
+            pitch += 0.5 * DT;
+            let attitude = filter.update(&sample(at_rest(pitch, 0.0), [0.0, -0.5, 0.0]));
+            assert!(close(attitude, pitch, 0.0, 0.02), "{}", pitch);
+        }
+        filter.reset();
+        // yaw cannot be seen by gravity, it follows the gyroscope alone
+        let mut attitude = Attitude::default();
+        for _ in 0..150 {
+            attitude = filter.update(&sample(at_rest(0.0, 0.0), [0.0, 0.0, -0.5]));
+        }
+        assert!((attitude.yaw.to_num::<f64>() - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn mahony_converges_and_learns_the_bias() {
+        let mut filter = Mahony::new(I32F32::from_num(2), I32F32::from_num(0.5));
+        filter.update(&sample(at_rest(0.0, 0.0), [0.0; 3]));
+        // tilted while the gyroscope missed it, and the gyroscope has a roll bias
+        for _ in 0..3000 {
+            filter.update(&sample(at_rest(0.2, -0.3), [0.03, 0.0, 0.0]));
+        }
+        let attitude = filter.update(&sample(at_rest(0.2, -0.3), [0.03, 0.0, 0.0]));
+        assert!(close(attitude, 0.2, -0.3, 0.005));
+        assert!((filter.integral[0].to_num::<f64>() + 0.03).abs() < 0.005);
+    }
+
+    #[test]
+    fn mahony_ignores_free_fall() {
+        let mut filter = Mahony::new(I32F32::from_num(2), I32F32::from_num(0.1));
+        filter.update(&sample(at_rest(0.1, 0.1), [0.0; 3]));
+        let attitude = filter.update(&sample([I16F16::ZERO; 3], [0.0; 3]));
+        assert!(close(attitude, 0.1, 0.1, 0.01));
+    }
+}
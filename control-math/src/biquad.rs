@@ -0,0 +1,304 @@
+// This file implements the Butterworth low-pass filters of the first and the second order and the
+// notch filter as a biquad, with the coefficients computed from the sample rate and the frequency.
+// The analog low-pass is mapped by the bilinear transform, prewarped so the cutoff stays at -3 dB:
+//   K = tan(pi * cutoff / sample rate)
+//   first order   b = [K, K, 0] / (K + 1),                 a = [(K - 1) / (K + 1), 0]
+//   second order  b = [K^2, 2 K^2, K^2] / n,               a = [2 (K^2 - 1) / n, (K^2 - sqrt2 K + 1) / n]
+//                 with n = K^2 + sqrt2 K + 1
+// The notch removes a narrow band around its centre w = 2 pi centre / sample rate, the quality is
+// the centre over the width of the band:
+//   notch         b = [1, -2 cos w, 1] / n,                 a = [-2 cos w / n, (1 - alpha) / n]
+//                 with alpha = sin w / (2 quality) and n = 1 + alpha
+// and every sample is y = b0 x + b1 x[-1] + b2 x[-2] - a1 y[-1] - a2 y[-2], computed in the
+// transposed direct form II. The coefficients and the state are kept in I32F32, a low cutoff puts
+// the poles close to 1 where the resolution of I16F16 moves the response.
+
+use cordic::{sin_cos, tan};
+use fixed::types::{I16F16, I32F32};
+
+// The cutoff is kept just below half the sample rate, where the tangent goes to infinity.
+const MAX_CUTOFF_RATIO: f64 = 0.49;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    // -20 dB per decade above the cutoff, no overshoot.
+    First,
+    // -40 dB per decade above the cutoff, with a small overshoot on a step.
+    Second,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BiquadCoefficients {
+    pub b: [I32F32; 3],
+    pub a: [I32F32; 2],
+}
+
+impl BiquadCoefficients {
+    // The Butterworth low-pass of the order for a sample rate and a cutoff, both in Hz.
+    pub fn low_pass(order: Order, sample_rate: I16F16, cutoff: I16F16) -> Self {
+        let ratio = (I32F32::from(cutoff) / I32F32::from(sample_rate))
+            .clamp(I32F32::ZERO, I32F32::from_num(MAX_CUTOFF_RATIO));
+        let k = tan(I32F32::PI * ratio);
+        match order {
+            Order::First => {
+                let norm = I32F32::ONE / (k + I32F32::ONE);
+                BiquadCoefficients {
+                    b: [k * norm, k * norm, I32F32::ZERO],
+                    a: [(k - I32F32::ONE) * norm, I32F32::ZERO],
+                }
+            }
+            Order::Second => {
+                let k2 = k * k;
+                let sqrt2_k = I32F32::SQRT_2 * k;
+                let norm = I32F32::ONE / (k2 + sqrt2_k + I32F32::ONE);
+                BiquadCoefficients {
+                    b: [k2 * norm, k2 * norm * 2, k2 * norm],
+                    a: [
+                        (k2 - I32F32::ONE) * norm * 2,
+                        (k2 - sqrt2_k + I32F32::ONE) * norm,
+                    ],
+                }
+            }
+        }
+    }
+
+    // The notch at a centre frequency, with the sample rate and the centre in Hz.
+    pub fn notch(sample_rate: I16F16, centre: I16F16, quality: I16F16) -> Self {
+        let ratio = (I32F32::from(centre) / I32F32::from(sample_rate))
+            .clamp(I32F32::ZERO, I32F32::from_num(MAX_CUTOFF_RATIO));
+        let (sin, cos) = sin_cos(I32F32::PI * 2 * ratio);
+        let alpha = sin / (I32F32::from(quality) * 2);
+        let norm = I32F32::ONE / (I32F32::ONE + alpha);
+        BiquadCoefficients {
+            b: [norm, -cos * 2 * norm, norm],
+            a: [-cos * 2 * norm, (I32F32::ONE - alpha) * norm],
+        }
+    }
+}
+
+// Both the low-pass and the notch filters pass a constant signal unchanged.
+#[derive(Clone, Copy, Debug)]
+pub struct Biquad {
+    coefficients: BiquadCoefficients,
+    state: [I32F32; 2],
+    output: I16F16,
+    // False until the first sample, which the filter is settled on.
+    started: bool,
+}
+
+impl Biquad {
+    pub fn new(coefficients: BiquadCoefficients) -> Self {
+        Biquad {
+            coefficients,
+            state: [I32F32::ZERO; 2],
+            output: I16F16::ZERO,
+            started: false,
+        }
+    }
+
+    pub fn low_pass(order: Order, sample_rate: I16F16, cutoff: I16F16) -> Self {
+        Biquad::new(BiquadCoefficients::low_pass(order, sample_rate, cutoff))
+    }
+
+    pub fn notch(sample_rate: I16F16, centre: I16F16, quality: I16F16) -> Self {
+        Biquad::new(BiquadCoefficients::notch(sample_rate, centre, quality))
+    }
+
+    pub fn reset(&mut self) {
+        *self = Biquad::new(self.coefficients);
+    }
+
+    pub fn get_coefficients(&self) -> BiquadCoefficients {
+        self.coefficients
+    }
+
+    // Use other coefficients from the next sample on, the state is kept.
+    pub fn set_coefficients(&mut self, coefficients: BiquadCoefficients) {
+        self.coefficients = coefficients;
+    }
+
+    pub fn update(&mut self, input: I16F16) -> I16F16 {
+        let BiquadCoefficients { b, a } = self.coefficients;
+        let input = I32F32::from(input);
+        if !self.started {
+            // the state of a filter that has seen this sample forever, the gain at 0 Hz is 1
+            self.state[1] = (b[2] - a[1]) * input;
+            self.state[0] = (b[1] - a[0]) * input + self.state[1];
+            self.started = true;
+        }
+        let output = b[0] * input + self.state[0];
+        self.state[0] = b[1] * input - a[0] * output + self.state[1];
+        self.state[1] = b[2] * input - a[1] * output;
+        self.output = output.saturating_to_num();
+        self.output
+    }
+
+    pub fn output(&self) -> I16F16 {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const SAMPLE_RATE: f64 = 150.0;
+
+    fn filter(order: Order, cutoff: f64) -> Biquad {
+        Biquad::low_pass(
+            order,
+            I16F16::from_num(SAMPLE_RATE),
+            I16F16::from_num(cutoff),
+        )
+    }
+
+    // The amplitude of the output for a sine of amplitude 1, from the power after the filter
+    // settled. The frequencies fit a whole number of periods in the 1500 measured ticks.
+    fn gain(order: Order, cutoff: f64, frequency: f64) -> f64 {
+        let mut filter = filter(order, cutoff);
+        filter.update(I16F16::ZERO);
+        let mut power = 0.0;
+        for tick in 0..3000 {
+            let input = (2.0 * PI * frequency * tick as f64 / SAMPLE_RATE).sin();
+            let output = filter.update(I16F16::from_num(input)).to_num::<f64>();
+            if tick >= 1500 {
+                power += output * output;
+            }
+        }
+        (2.0 * power / 1500.0).sqrt()
+    }
+
+    // The gain of the bilinear Butterworth filter, the analog response at the warped frequency.
+    fn expected_gain(order: Order, cutoff: f64, frequency: f64) -> f64 {
+        let warp = |frequency: f64| (PI * frequency / SAMPLE_RATE).tan();
+        let ratio = warp(frequency) / warp(cutoff);
+        let power = match order {
+            Order::First => 2,
+            Order::Second => 4,
+        };
+        1.0 / (1.0 + ratio.powi(power)).sqrt()
+    }
+
+    #[test]
+    fn first_order_matches_the_old_low_pass() {
+        // the hand-computed coefficients for 22 Hz at 150 Hz
+        let coefficients = filter(Order::First, 22.0).get_coefficients();
+        assert!((coefficients.b[0].to_num::<f64>() - 0.33179).abs() < 0.001);
+        assert!((coefficients.b[1].to_num::<f64>() - 0.33179).abs() < 0.001);
+        assert!((coefficients.a[0].to_num::<f64>() + 0.33643).abs() < 0.001);
+    }
+
+    #[test]
+    fn passes_a_constant() {
+        for order in [Order::First, Order::Second] {
+            let mut filter = filter(order, 5.0);
+            for _ in 0..500 {
+                filter.update(I16F16::from_num(-1000));
+            }
+            assert!((filter.output().to_num::<f64>() + 1000.0).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn starts_at_the_first_sample() {
+        for order in [Order::First, Order::Second] {
+            let mut filter = filter(order, 10.0);
+            let output = filter.update(I16F16::from_num(0.7)).to_num::<f64>();
+            assert!((output - 0.7).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn follows_the_butterworth_response() {
+        for order in [Order::First, Order::Second] {
+            for (cutoff, frequency) in [(22.0, 5.0), (22.0, 22.0), (22.0, 40.0), (10.0, 30.0)] {
+                let measured = gain(order, cutoff, frequency);
+                let expected = expected_gain(order, cutoff, frequency);
+                assert!(
+                    (measured - expected).abs() < 0.01,
+                    "{:?} {} {}: {} {}",
+                    order,
+                    cutoff,
+                    frequency,
+                    measured,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cutoff_is_at_minus_three_decibels() {
+        for order in [Order::First, Order::Second] {
+            assert!((gain(order, 22.0, 22.0) - 0.5f64.sqrt()).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn second_order_falls_off_faster() {
+        let first = gain(Order::First, 10.0, 40.0);
+        let second = gain(Order::Second, 10.0, 40.0);
+        assert!(second < first / 3.0, "{} {}", first, second);
+    }
+
+    #[test]
+    fn cutoff_above_nyquist_is_limited() {
+        let coefficients = filter(Order::Second, 100.0).get_coefficients();
+        let limited = filter(Order::Second, SAMPLE_RATE * MAX_CUTOFF_RATIO).get_coefficients();
+        assert_eq!(coefficients, limited);
+    }
+
+    // The amplitude of the output of a notch at 30 Hz with a quality of 2.
+    fn notch_gain(frequency: f64) -> f64 {
+        let mut notch = Biquad::notch(
+            I16F16::from_num(SAMPLE_RATE),
+            I16F16::from_num(30),
+            I16F16::from_num(2),
+        );
+        notch.update(I16F16::ZERO);
+        let mut power = 0.0;
+        for tick in 0..3000 {
+            let input = (2.0 * PI * frequency * tick as f64 / SAMPLE_RATE).sin();
+            let output = notch.update(I16F16::from_num(input)).to_num::<f64>();
+            if tick >= 1500 {
+                power += output * output;
+            }
+        }
+        (2.0 * power / 1500.0).sqrt()
+    }
+
+    #[test]
+    fn notch_removes_its_centre() {
+        assert!(notch_gain(30.0) < 0.01);
+        // and passes frequencies away from it
+        assert!(notch_gain(2.0) > 0.98);
+        assert!(notch_gain(70.0) > 0.9);
+        // the band around the centre is damped, less further away from it
+        assert!(notch_gain(27.0) < notch_gain(22.5));
+        assert!(notch_gain(22.5) < 0.9);
+    }
+
+    #[test]
+    fn notch_passes_a_constant() {
+        let mut notch = Biquad::notch(
+            I16F16::from_num(SAMPLE_RATE),
+            I16F16::from_num(40),
+            I16F16::from_num(1),
+        );
+        for _ in 0..200 {
+            notch.update(I16F16::from_num(123));
+        }
+        assert!((notch.output().to_num::<f64>() - 123.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut filter = filter(Order::Second, 10.0);
+        for _ in 0..100 {
+            filter.update(I16F16::from_num(3));
+        }
+        filter.reset();
+        assert!((filter.update(I16F16::from_num(-2)).to_num::<f64>() + 2.0).abs() < 0.001);
+    }
+}
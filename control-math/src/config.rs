@@ -0,0 +1,454 @@
+// This file contains everything the drone keeps in the configuration record in flash and the
+// layout of its payload: the gains, the Kalman noise, the calibration and its settings, the
+// accelerometer correction, the motor limits, the notch filters, the limits of the safety monitor,
+// the length of the panic landing and the battery levels. The record around the payload (the
+// sequence number, the CRC and the two flash sectors) is kept by the storage of the drone.
+//
+// The payload is a fixed number of big endian values. Records of another version are ignored, so
+// `CONFIG_VERSION` is bumped and `PAYLOAD_SIZE` updated whenever a field is added or changed.
+
+use crate::desaturation::{MotorLimits, DEFAULT_MOTOR_LIMITS};
+use crate::kalman::KalmanNoise;
+use crate::notch::{NotchMode, NotchSettings};
+use crate::safety::{SafetyAction, SafetyLimits, DEFAULT_SAFETY_LIMITS};
+use alloc::vec::Vec;
+use fixed::types::{I16F16, I32F32};
+
+/// Records with another version are ignored, bump it whenever the payload changes.
+pub const CONFIG_VERSION: u8 = 10;
+pub const PAYLOAD_SIZE: usize = 225;
+
+/// The gains of one PID controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PidGains {
+    pub kp: I16F16,
+    pub kp1: I16F16,
+    pub kp2: I16F16,
+    pub ki: I16F16,
+    pub kd: I16F16,
+}
+
+impl PidGains {
+    pub const fn new(kp: I16F16, kp1: I16F16, kp2: I16F16, ki: I16F16, kd: I16F16) -> Self {
+        PidGains {
+            kp,
+            kp1,
+            kp2,
+            ki,
+            kd,
+        }
+    }
+}
+
+/// The averaged sensor offsets of a finished calibration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CalibrationOffsets {
+    pub yaw: I16F16,
+    pub pitch: I16F16,
+    pub roll: I16F16,
+    pub lift: i32,
+    /// The gyroscope and accelerometer offsets in raw units.
+    pub gyro: [i16; 3],
+    pub accel: [i16; 3],
+}
+
+/// The bias and scale of every accelerometer axis from the six-position calibration, a reading is
+/// corrected to `(raw - bias) * scale`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccelCorrection {
+    pub bias: [i16; 3],
+    pub scale: [I16F16; 3],
+}
+
+impl AccelCorrection {
+    /// Leaves the readings as they are.
+    pub const IDENTITY: AccelCorrection = AccelCorrection {
+        bias: [0; 3],
+        scale: [I16F16::ONE; 3],
+    };
+}
+
+/// Everything that is kept in flash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub yaw: PidGains,
+    pub pitch: PidGains,
+    pub roll: PidGains,
+    pub height: PidGains,
+    /// The noise of the pitch and roll Kalman filters of raw mode.
+    pub kalman: KalmanNoise,
+    /// None until the drone has been calibrated.
+    pub calibration: Option<CalibrationOffsets>,
+    pub accel_correction: AccelCorrection,
+    /// The motor limits of the controlled modes.
+    pub motor_limits: MotorLimits,
+    /// The notch filters of the gyroscope and the accelerometer against the motor vibration.
+    pub notch: NotchSettings,
+    /// When the safety monitor stops the motors.
+    pub safety: SafetyLimits,
+    /// The window and the variance limits of the calibration.
+    pub calibration_settings: CalibrationSettings,
+    /// The number of ticks panic mode takes to ramp the motors down.
+    pub panic_landing_ticks: u32,
+    /// The warning and critical levels of the battery.
+    pub battery: BatteryLevels,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CalibrationSettings {
+    // The number of samples that are averaged, one per tick.
+    pub window: u32,
+    // The largest variance of any axis in raw units squared, the accelerometer has 16384 per g,
+    // the gyroscope 16.4 per deg/s.
+    pub max_gyro_variance: u32,
+    pub max_accel_variance: u32,
+    // The largest variance of yaw, pitch or roll in milliradians squared.
+    pub max_attitude_variance: u32,
+    // The largest variance of the pressure in Pa squared.
+    pub max_pressure_variance: u32,
+}
+
+pub const DEFAULT_CALIBRATION_SETTINGS: CalibrationSettings = CalibrationSettings {
+    // 1 second at the control loop frequency of 150 Hz.
+    window: 150,
+    // A standard deviation of 0.6 deg/s.
+    max_gyro_variance: 100,
+    // A standard deviation of 12 mg.
+    max_accel_variance: 40_000,
+    // A standard deviation of 10 mrad (0.6 degrees).
+    max_attitude_variance: 100,
+    // A standard deviation of 30 Pa.
+    max_pressure_variance: 900,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatteryLevels {
+    // Flying is refused below the warning level, in 10 mV.
+    pub warning: u16,
+    // The drone lands below the critical level, in 10 mV. Always below the warning level.
+    pub critical: u16,
+    // How far the voltage has to recover before a level is left, in 10 mV.
+    pub hysteresis: u16,
+}
+
+// A 3S LiPo is assumed, the levels are 3.6 V and 3.5 V per cell.
+pub const DEFAULT_BATTERY_LEVELS: BatteryLevels = BatteryLevels {
+    warning: 1080,
+    critical: 1050,
+    hysteresis: 20,
+};
+
+// 3 seconds at the control loop frequency of 150 Hz.
+pub const DEFAULT_PANIC_LANDING_TICKS: u32 = 450;
+
+// The configuration that is used when there is no valid record in flash.
+pub fn default_config() -> Config {
+    Config {
+        yaw: PidGains::new(
+            I16F16::from_num(1),
+            I16F16::from_num(0),
+            I16F16::from_num(0),
+            I16F16::from_num(0),
+            I16F16::from_num(0),
+        ),
+        pitch: PidGains::new(
+            I16F16::from_num(1),
+            I16F16::from_num(0),
+            I16F16::from_num(0),
+            I16F16::from_num(0),
+            I16F16::from_num(0),
+        ),
+        roll: PidGains::new(
+            I16F16::from_num(1),
+            I16F16::from_num(0),
+            I16F16::from_num(0),
+            I16F16::from_num(0),
+            I16F16::from_num(1),
+        ),
+        height: PidGains::new(
+            I16F16::from_num(1.1),
+            I16F16::from_num(0),
+            I16F16::from_num(0),
+            I16F16::from_num(3),
+            I16F16::from_num(5),
+        ),
+        kalman: KalmanNoise {
+            angle: I32F32::from_num(0.001),
+            bias: I32F32::from_num(0.003),
+            measurement: I32F32::from_num(0.03),
+        },
+        calibration: None,
+        accel_correction: AccelCorrection::IDENTITY,
+        motor_limits: DEFAULT_MOTOR_LIMITS,
+        notch: NotchSettings {
+            mode: NotchMode::Off,
+            centre: I16F16::from_num(200),
+            hz_per_command: I16F16::from_num(0.3),
+            quality: I16F16::from_num(2),
+        },
+        safety: DEFAULT_SAFETY_LIMITS,
+        calibration_settings: DEFAULT_CALIBRATION_SETTINGS,
+        panic_landing_ticks: DEFAULT_PANIC_LANDING_TICKS,
+        battery: DEFAULT_BATTERY_LEVELS,
+    }
+}
+
+impl Config {
+    /// The payload of the record in flash, always `PAYLOAD_SIZE` bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PAYLOAD_SIZE);
+        for gains in [self.yaw, self.pitch, self.roll, self.height] {
+            for gain in [gains.kp, gains.kp1, gains.kp2, gains.ki, gains.kd] {
+                bytes.extend_from_slice(&gain.to_bits().to_be_bytes());
+            }
+        }
+        for noise in [self.kalman.angle, self.kalman.bias, self.kalman.measurement] {
+            bytes.extend_from_slice(&noise.to_bits().to_be_bytes());
+        }
+        let calibration = self.calibration.unwrap_or(CalibrationOffsets {
+            yaw: I16F16::from_num(0),
+            pitch: I16F16::from_num(0),
+            roll: I16F16::from_num(0),
+            lift: 0,
+            gyro: [0; 3],
+            accel: [0; 3],
+        });
+        bytes.push(self.calibration.is_some() as u8);
+        bytes.extend_from_slice(&calibration.yaw.to_bits().to_be_bytes());
+        bytes.extend_from_slice(&calibration.pitch.to_bits().to_be_bytes());
+        bytes.extend_from_slice(&calibration.roll.to_bits().to_be_bytes());
+        bytes.extend_from_slice(&calibration.lift.to_be_bytes());
+        for value in calibration.gyro.iter().chain(&calibration.accel) {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        for bias in self.accel_correction.bias {
+            bytes.extend_from_slice(&bias.to_be_bytes());
+        }
+        for scale in self.accel_correction.scale {
+            bytes.extend_from_slice(&scale.to_bits().to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.motor_limits.min.to_be_bytes());
+        bytes.extend_from_slice(&self.motor_limits.max.to_be_bytes());
+        bytes.push(match self.notch.mode {
+            NotchMode::Off => 0,
+            NotchMode::Fixed => 1,
+            NotchMode::Tracking => 2,
+        });
+        for value in [
+            self.notch.centre,
+            self.notch.hz_per_command,
+            self.notch.quality,
+        ] {
+            bytes.extend_from_slice(&value.to_bits().to_be_bytes());
+        }
+        let safety = self.safety;
+        bytes.extend_from_slice(&safety.max_tilt.to_bits().to_be_bytes());
+        bytes.extend_from_slice(&safety.tilt_ticks.to_be_bytes());
+        bytes.push(action_to_byte(safety.tilt_action));
+        bytes.extend_from_slice(&safety.max_rate.to_be_bytes());
+        bytes.extend_from_slice(&safety.tumble_ticks.to_be_bytes());
+        bytes.push(action_to_byte(safety.tumble_action));
+        bytes.extend_from_slice(&safety.impact_accel.to_be_bytes());
+        bytes.extend_from_slice(&safety.impact_ticks.to_be_bytes());
+        bytes.push(action_to_byte(safety.impact_action));
+        let settings = self.calibration_settings;
+        bytes.extend_from_slice(&settings.window.to_be_bytes());
+        bytes.extend_from_slice(&settings.max_gyro_variance.to_be_bytes());
+        bytes.extend_from_slice(&settings.max_accel_variance.to_be_bytes());
+        bytes.extend_from_slice(&settings.max_attitude_variance.to_be_bytes());
+        bytes.extend_from_slice(&settings.max_pressure_variance.to_be_bytes());
+        bytes.extend_from_slice(&self.panic_landing_ticks.to_be_bytes());
+        let battery = self.battery;
+        bytes.extend_from_slice(&battery.warning.to_be_bytes());
+        bytes.extend_from_slice(&battery.critical.to_be_bytes());
+        bytes.extend_from_slice(&battery.hysteresis.to_be_bytes());
+        debug_assert_eq!(bytes.len(), PAYLOAD_SIZE);
+        bytes
+    }
+
+    /// Reads the payload of `to_bytes`, `bytes` has to hold at least `PAYLOAD_SIZE` bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Config {
+        let mut reader = Reader { bytes, index: 0 };
+        let yaw = reader.gains();
+        let pitch = reader.gains();
+        let roll = reader.gains();
+        let height = reader.gains();
+        let kalman = KalmanNoise {
+            angle: reader.wide_fixed(),
+            bias: reader.wide_fixed(),
+            measurement: reader.wide_fixed(),
+        };
+        let calibrated = reader.u8() != 0;
+        let calibration = CalibrationOffsets {
+            yaw: reader.fixed(),
+            pitch: reader.fixed(),
+            roll: reader.fixed(),
+            lift: reader.i32(),
+            gyro: [reader.i16(), reader.i16(), reader.i16()],
+            accel: [reader.i16(), reader.i16(), reader.i16()],
+        };
+        let accel_correction = AccelCorrection {
+            bias: [reader.i16(), reader.i16(), reader.i16()],
+            scale: [reader.fixed(), reader.fixed(), reader.fixed()],
+        };
+        let motor_limits = MotorLimits {
+            min: reader.u16(),
+            max: reader.u16(),
+        };
+        let notch = NotchSettings {
+            mode: match reader.u8() {
+                1 => NotchMode::Fixed,
+                2 => NotchMode::Tracking,
+                _ => NotchMode::Off,
+            },
+            centre: reader.fixed(),
+            hz_per_command: reader.fixed(),
+            quality: reader.fixed(),
+        };
+        let safety = SafetyLimits {
+            max_tilt: reader.fixed(),
+            tilt_ticks: reader.u32(),
+            tilt_action: action_from_byte(reader.u8()),
+            max_rate: reader.i32(),
+            tumble_ticks: reader.u32(),
+            tumble_action: action_from_byte(reader.u8()),
+            impact_accel: reader.i32(),
+            impact_ticks: reader.u32(),
+            impact_action: action_from_byte(reader.u8()),
+        };
+        let calibration_settings = CalibrationSettings {
+            window: reader.u32(),
+            max_gyro_variance: reader.u32(),
+            max_accel_variance: reader.u32(),
+            max_attitude_variance: reader.u32(),
+            max_pressure_variance: reader.u32(),
+        };
+        let panic_landing_ticks = reader.u32();
+        let battery = BatteryLevels {
+            warning: reader.u16(),
+            critical: reader.u16(),
+            hysteresis: reader.u16(),
+        };
+        Config {
+            yaw,
+            pitch,
+            roll,
+            height,
+            kalman,
+            calibration: if calibrated { Some(calibration) } else { None },
+            accel_correction,
+            motor_limits,
+            notch,
+            safety,
+            calibration_settings,
+            panic_landing_ticks,
+            battery,
+        }
+    }
+}
+
+fn action_to_byte(action: SafetyAction) -> u8 {
+    match action {
+        SafetyAction::CutMotors => 0,
+        SafetyAction::Panic => 1,
+    }
+}
+
+// Anything else than panic cuts the motors.
+fn action_from_byte(byte: u8) -> SafetyAction {
+    match byte {
+        1 => SafetyAction::Panic,
+        _ => SafetyAction::CutMotors,
+    }
+}
+
+// Reads big endian values from the payload one after the other.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let mut value = [0; N];
+        value.copy_from_slice(&self.bytes[self.index..self.index + N]);
+        self.index += N;
+        value
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_be_bytes(self.take())
+    }
+
+    fn i16(&mut self) -> i16 {
+        i16::from_be_bytes(self.take())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_be_bytes(self.take())
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_be_bytes(self.take())
+    }
+
+    fn fixed(&mut self) -> I16F16 {
+        I16F16::from_bits(self.i32())
+    }
+
+    fn wide_fixed(&mut self) -> I32F32 {
+        I32F32::from_bits(i64::from_be_bytes(self.take()))
+    }
+
+    fn gains(&mut self) -> PidGains {
+        PidGains::new(
+            self.fixed(),
+            self.fixed(),
+            self.fixed(),
+            self.fixed(),
+            self.fixed(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_round_trips() {
+        let config = default_config();
+        let bytes = config.to_bytes();
+        assert_eq!(bytes.len(), PAYLOAD_SIZE);
+        assert_eq!(Config::from_bytes(&bytes), config);
+    }
+
+    #[test]
+    fn every_field_round_trips() {
+        let mut config = default_config();
+        config.calibration = Some(CalibrationOffsets {
+            yaw: I16F16::from_num(-0.25),
+            pitch: I16F16::from_num(0.125),
+            roll: I16F16::from_num(1.5),
+            lift: -70_000,
+            gyro: [-12, 7, 3],
+            accel: [150, -80, 16_200],
+        });
+        config.accel_correction = AccelCorrection {
+            bias: [5, -6, 7],
+            scale: [I16F16::from_num(0.99), I16F16::ONE, I16F16::from_num(1.01)],
+        };
+        config.notch.mode = NotchMode::Tracking;
+        config.safety.tumble_action = SafetyAction::Panic;
+        config.safety.impact_ticks = 4;
+        config.panic_landing_ticks = 600;
+        config.battery.hysteresis = 35;
+        let bytes = config.to_bytes();
+        assert_eq!(bytes.len(), PAYLOAD_SIZE);
+        assert_eq!(Config::from_bytes(&bytes), config);
+    }
+}
@@ -0,0 +1,189 @@
+// This file implements the desaturation of the mixed motor speeds.
+// When a motor would have to go above its maximum or below its minimum speed, clamping that motor
+// alone destroys the attitude correction. Instead, the commands are limited in order of priority:
+//   1. roll and pitch keep the drone upright, they are only scaled down if they cannot fit at all,
+//   2. yaw gets the room that is left, it is scaled down first,
+//   3. the collective thrust is shifted up or down to move all motors into the allowed range.
+// Everything is calculated in signed arithmetic, so a negative motor speed can never wrap around.
+
+use crate::mixer::{Mixer, MOTOR_COUNT};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MotorLimits {
+    pub min: u16,
+    pub max: u16,
+}
+
+// The motor limits of the controlled modes, the minimum is the speed below which the motors stall.
+pub const DEFAULT_MOTOR_LIMITS: MotorLimits = MotorLimits {
+    min: 220,
+    max: 1000,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DesaturatedOutput {
+    pub motors: [u16; MOTOR_COUNT],
+    /// True if any of the commands had to be limited to fit the motors in the range.
+    pub saturated: bool,
+}
+
+pub fn mix_desaturated(
+    mixer: &Mixer,
+    lift: i32,
+    roll: i32,
+    pitch: i32,
+    yaw: i32,
+    limits: MotorLimits,
+) -> DesaturatedOutput {
+    let min = limits.min as i32;
+    let max = limits.max as i32;
+    let range = max - min;
+    let mut saturated = false;
+
+    // Roll and pitch on their own must fit in the range, otherwise both are scaled down equally.
+    let (mut roll, mut pitch) = (roll, pitch);
+    let attitude_spread = spread(mixer.mix(0, roll, pitch, 0));
+    if attitude_spread > range {
+        roll = scale(roll, range, attitude_spread);
+        pitch = scale(pitch, range, attitude_spread);
+        saturated = true;
+    }
+
+    // Yaw only gets what is left, find the largest yaw in the same direction that still fits.
+    let mut yaw_fit = yaw;
+    if spread(mixer.mix(0, roll, pitch, yaw)) > range {
+        let (mut low, mut high) = (0, yaw.abs());
+        while low < high {
+            let middle = (low + high + 1) / 2;
+            if spread(mixer.mix(0, roll, pitch, yaw.signum() * middle)) <= range {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        yaw_fit = yaw.signum() * low;
+        saturated = true;
+    }
+
+    // Move the collective thrust so that all motors are in the range.
+    let motors = mixer.mix(lift, roll, pitch, yaw_fit);
+    let highest = motors.iter().copied().max().unwrap_or(0);
+    let lowest = motors.iter().copied().min().unwrap_or(0);
+    let shift = if highest > max {
+        max - highest
+    } else if lowest < min {
+        min - lowest
+    } else {
+        0
+    };
+    if shift != 0 {
+        saturated = true;
+    }
+
+    DesaturatedOutput {
+        // The clamp only catches rounding of the scaled roll and pitch.
+        motors: motors.map(|motor| (motor + shift).clamp(min, max) as u16),
+        saturated,
+    }
+}
+
+fn spread(motors: [i32; MOTOR_COUNT]) -> i32 {
+    let highest = motors.iter().copied().max().unwrap_or(0);
+    let lowest = motors.iter().copied().min().unwrap_or(0);
+    highest - lowest
+}
+
+fn scale(value: i32, numerator: i32, denominator: i32) -> i32 {
+    (value as i64 * numerator as i64 / denominator as i64) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mixer::{FrameType, SpinDirection};
+
+    const PLUS: Mixer = Mixer::new(FrameType::Plus, SpinDirection::Clockwise);
+    const LIMITS: MotorLimits = MotorLimits {
+        min: 220,
+        max: 1000,
+    };
+
+    fn differences(motors: [u16; MOTOR_COUNT]) -> (i32, i32) {
+        // pitch difference between back and front, roll difference between left and right
+        (
+            motors[2] as i32 - motors[0] as i32,
+            motors[3] as i32 - motors[1] as i32,
+        )
+    }
+
+    #[test]
+    fn unsaturated_mix_is_unchanged() {
+        let output = mix_desaturated(&PLUS, 500, 10, -20, 30, LIMITS);
+        let expected = PLUS.mix(500, 10, -20, 30).map(|motor| motor as u16);
+        assert_eq!(output.motors, expected);
+        assert!(!output.saturated);
+    }
+
+    #[test]
+    fn negative_speeds_never_wrap() {
+        let limits = MotorLimits { min: 0, max: 400 };
+        let output = mix_desaturated(&PLUS, 10, 0, 200, 300, limits);
+        for motor in output.motors {
+            assert!(motor <= 400);
+        }
+        assert!(output.saturated);
+    }
+
+    #[test]
+    fn motors_stay_within_limits() {
+        for lift in (-500..1500).step_by(97) {
+            for roll in (-800..800).step_by(131) {
+                for pitch in (-800..800).step_by(149) {
+                    for yaw in (-800..800).step_by(173) {
+                        let output = mix_desaturated(&PLUS, lift, roll, pitch, yaw, LIMITS);
+                        for motor in output.motors {
+                            assert!((LIMITS.min..=LIMITS.max).contains(&motor));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn collective_is_shifted_down_to_keep_attitude() {
+        let output = mix_desaturated(&PLUS, 980, 0, 50, 0, LIMITS);
+        assert_eq!(differences(output.motors), (100, 0));
+        assert_eq!(output.motors.iter().copied().max(), Some(1000));
+        assert!(output.saturated);
+    }
+
+    #[test]
+    fn collective_is_shifted_up_above_the_minimum() {
+        let output = mix_desaturated(&PLUS, 240, 40, 0, 0, LIMITS);
+        assert_eq!(differences(output.motors), (0, 80));
+        assert_eq!(output.motors.iter().copied().min(), Some(220));
+        assert!(output.saturated);
+    }
+
+    #[test]
+    fn yaw_is_scaled_before_roll_and_pitch() {
+        let limits = MotorLimits { min: 200, max: 400 };
+        let output = mix_desaturated(&PLUS, 300, 60, 40, 200, limits);
+        // roll and pitch differences are kept, yaw gets what is left of the range
+        assert_eq!(differences(output.motors), (80, 120));
+        let spread = output.motors.iter().max().unwrap() - output.motors.iter().min().unwrap();
+        assert!(spread <= 200);
+        assert!(output.saturated);
+    }
+
+    #[test]
+    fn roll_and_pitch_are_scaled_if_they_cannot_fit() {
+        let limits = MotorLimits { min: 200, max: 400 };
+        let output = mix_desaturated(&PLUS, 300, 0, 200, 50, limits);
+        // the front and back motors are pushed to the limits, yaw has no room left
+        assert_eq!(output.motors[0], 200);
+        assert_eq!(output.motors[2], 400);
+        assert!(output.saturated);
+    }
+}
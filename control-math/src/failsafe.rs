@@ -0,0 +1,152 @@
+// This file implements the decision of the failsafe for when the link to the PC is lost.
+// Landing right away is the safe choice without sensors, but in a stabilised mode the drone can
+// keep itself level. The link then gets a grace period in which the attitude (and the height in
+// height mode) is held with the sticks in the middle, and only if the link does not come back in
+// time the drone descends in panic mode. On the ground there is nothing to hold or to land, a lost
+// link is ignored there, so the drone keeps its calibration until the PC connects.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailsafePolicy {
+    // Go into panic as soon as the link is lost, in every mode.
+    Panic,
+    // In a stabilised mode, hold the attitude for the grace period before going into panic.
+    HoldThenDescend { grace_ticks: u32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailsafeAction {
+    // The link is fine or the drone is on the ground, control as usual.
+    None,
+    // Run the current mode with the sticks in the middle.
+    Hold,
+    // Go into panic to descend.
+    Land,
+}
+
+// A change of the failsafe, for the fault events of the PC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkEvent {
+    // The attitude is held for up to the grace ticks.
+    Holding { grace_ticks: u32 },
+    // The drone descends after holding for a number of ticks, 0 when landing right away.
+    Lost { held_ticks: u32 },
+    // The link came back after holding for a number of ticks.
+    Restored { held_ticks: u32 },
+}
+
+pub struct LinkFailsafe {
+    policy: FailsafePolicy,
+    holding_ticks: u32,
+    event: Option<LinkEvent>,
+}
+
+impl LinkFailsafe {
+    pub fn new(policy: FailsafePolicy) -> Self {
+        LinkFailsafe {
+            policy,
+            holding_ticks: 0,
+            event: None,
+        }
+    }
+
+    // Called on every tick. Flying is true in the modes that drive the motors, stabilised when the
+    // current mode keeps the drone level by itself.
+    pub fn update(&mut self, link_lost: bool, flying: bool, stabilised: bool) -> FailsafeAction {
+        if !flying {
+            self.holding_ticks = 0;
+            return FailsafeAction::None;
+        }
+        if !link_lost {
+            if self.holding_ticks > 0 {
+                self.event = Some(LinkEvent::Restored {
+                    held_ticks: self.holding_ticks,
+                });
+                self.holding_ticks = 0;
+            }
+            return FailsafeAction::None;
+        }
+        let grace_ticks = match self.policy {
+            FailsafePolicy::HoldThenDescend { grace_ticks } if stabilised => grace_ticks,
+            _ => 0,
+        };
+        if self.holding_ticks >= grace_ticks {
+            self.event = Some(LinkEvent::Lost {
+                held_ticks: self.holding_ticks,
+            });
+            self.holding_ticks = 0;
+            return FailsafeAction::Land;
+        }
+        if self.holding_ticks == 0 {
+            self.event = Some(LinkEvent::Holding { grace_ticks });
+        }
+        self.holding_ticks += 1;
+        FailsafeAction::Hold
+    }
+
+    // Returns the last change, if it has not been taken yet.
+    pub fn take_event(&mut self) -> Option<LinkEvent> {
+        self.event.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: FailsafePolicy = FailsafePolicy::HoldThenDescend { grace_ticks: 3 };
+
+    #[test]
+    fn lost_link_on_the_ground_is_ignored() {
+        let mut failsafe = LinkFailsafe::new(POLICY);
+        for _ in 0..1000 {
+            assert_eq!(failsafe.update(true, false, false), FailsafeAction::None);
+            assert_eq!(failsafe.take_event(), None);
+        }
+        let mut failsafe = LinkFailsafe::new(FailsafePolicy::Panic);
+        assert_eq!(failsafe.update(true, false, false), FailsafeAction::None);
+        assert_eq!(failsafe.take_event(), None);
+    }
+
+    #[test]
+    fn stabilised_mode_holds_then_lands() {
+        let mut failsafe = LinkFailsafe::new(POLICY);
+        assert_eq!(failsafe.update(true, true, true), FailsafeAction::Hold);
+        assert_eq!(
+            failsafe.take_event(),
+            Some(LinkEvent::Holding { grace_ticks: 3 })
+        );
+        assert_eq!(failsafe.update(true, true, true), FailsafeAction::Hold);
+        assert_eq!(failsafe.update(true, true, true), FailsafeAction::Hold);
+        assert_eq!(failsafe.take_event(), None);
+        assert_eq!(failsafe.update(true, true, true), FailsafeAction::Land);
+        assert_eq!(
+            failsafe.take_event(),
+            Some(LinkEvent::Lost { held_ticks: 3 })
+        );
+    }
+
+    #[test]
+    fn unstabilised_mode_lands_right_away() {
+        let mut failsafe = LinkFailsafe::new(POLICY);
+        assert_eq!(failsafe.update(true, true, false), FailsafeAction::Land);
+        assert_eq!(
+            failsafe.take_event(),
+            Some(LinkEvent::Lost { held_ticks: 0 })
+        );
+    }
+
+    #[test]
+    fn link_restored_while_holding() {
+        let mut failsafe = LinkFailsafe::new(POLICY);
+        failsafe.update(true, true, true);
+        failsafe.update(true, true, true);
+        failsafe.take_event();
+        assert_eq!(failsafe.update(false, true, true), FailsafeAction::None);
+        assert_eq!(
+            failsafe.take_event(),
+            Some(LinkEvent::Restored { held_ticks: 2 })
+        );
+        // the grace period starts over
+        assert_eq!(failsafe.update(true, true, true), FailsafeAction::Hold);
+    }
+}
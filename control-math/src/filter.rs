@@ -0,0 +1,307 @@
+// This file implements the common interface of the signal filters and the filters that are not a
+// biquad. Every filter takes one sample per tick and keeps its last output, and filters are
+// composed into a pipeline with `chain`, which feeds the output of one filter into the next:
+//   Median::<5>::new().chain(Biquad::low_pass(Order::First, rate, cutoff))
+// The pipelines are built from generic types, so a chain takes exactly the memory of its filters.
+
+use crate::biquad::Biquad;
+use fixed::types::I16F16;
+
+pub trait Filter {
+    // Takes the next sample and returns the filtered one.
+    fn update(&mut self, input: I16F16) -> I16F16;
+    // Forget the samples, the next one starts the filter again.
+    fn reset(&mut self);
+    // The last filtered sample.
+    fn output(&self) -> I16F16;
+
+    // This filter followed by the next one.
+    fn chain<F: Filter>(self, next: F) -> Chain<Self, F>
+    where
+        Self: Sized,
+    {
+        Chain { first: self, next }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Chain<A, B> {
+    first: A,
+    next: B,
+}
+
+impl<A, B> Chain<A, B> {
+    pub fn first_mut(&mut self) -> &mut A {
+        &mut self.first
+    }
+
+    pub fn next_mut(&mut self) -> &mut B {
+        &mut self.next
+    }
+}
+
+impl<A: Filter, B: Filter> Filter for Chain<A, B> {
+    fn update(&mut self, input: I16F16) -> I16F16 {
+        self.next.update(self.first.update(input))
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.next.reset();
+    }
+
+    fn output(&self) -> I16F16 {
+        self.next.output()
+    }
+}
+
+impl Filter for Biquad {
+    fn update(&mut self, input: I16F16) -> I16F16 {
+        Biquad::update(self, input)
+    }
+
+    fn reset(&mut self) {
+        Biquad::reset(self);
+    }
+
+    fn output(&self) -> I16F16 {
+        Biquad::output(self)
+    }
+}
+
+// The average of the last N samples, of fewer until N samples were taken.
+#[derive(Clone, Copy, Debug)]
+pub struct MovingAverage<const N: usize> {
+    buffer: [I16F16; N],
+    // The next slot to overwrite, the oldest sample once the buffer is full.
+    index: usize,
+    count: usize,
+    // The sum of the samples in the buffer in raw I16F16 bits, which cannot overflow.
+    sum: i64,
+    output: I16F16,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    pub fn new() -> Self {
+        MovingAverage {
+            buffer: [I16F16::ZERO; N],
+            index: 0,
+            count: 0,
+            sum: 0,
+            output: I16F16::ZERO,
+        }
+    }
+}
+
+impl<const N: usize> Default for MovingAverage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Filter for MovingAverage<N> {
+    fn update(&mut self, input: I16F16) -> I16F16 {
+        if self.count == N {
+            self.sum -= self.buffer[self.index].to_bits() as i64;
+        } else {
+            self.count += 1;
+        }
+        self.buffer[self.index] = input;
+        self.sum += input.to_bits() as i64;
+        self.index = (self.index + 1) % N;
+        self.output = I16F16::from_bits((self.sum / self.count as i64) as i32);
+        self.output
+    }
+
+    fn reset(&mut self) {
+        *self = MovingAverage::new();
+    }
+
+    fn output(&self) -> I16F16 {
+        self.output
+    }
+}
+
+// An exponential moving average, every sample moves the output by a share of the difference.
+#[derive(Clone, Copy, Debug)]
+pub struct Exponential {
+    // The share of the new sample, between 0 and 1. A smaller share filters more.
+    weight: I16F16,
+    output: I16F16,
+    // False until the first sample, which is taken as the start.
+    started: bool,
+}
+
+impl Exponential {
+    pub fn new(weight: I16F16) -> Self {
+        Exponential {
+            weight,
+            output: I16F16::ZERO,
+            started: false,
+        }
+    }
+}
+
+impl Filter for Exponential {
+    fn update(&mut self, input: I16F16) -> I16F16 {
+        if !self.started {
+            self.output = input;
+            self.started = true;
+        }
+        self.output += (input - self.output) * self.weight;
+        self.output
+    }
+
+    fn reset(&mut self) {
+        *self = Exponential::new(self.weight);
+    }
+
+    fn output(&self) -> I16F16 {
+        self.output
+    }
+}
+
+// The median of the last N samples, of fewer until N samples were taken. A single spike does not
+// move it at all, N should be odd.
+#[derive(Clone, Copy, Debug)]
+pub struct Median<const N: usize> {
+    buffer: [I16F16; N],
+    index: usize,
+    count: usize,
+    output: I16F16,
+}
+
+impl<const N: usize> Median<N> {
+    pub fn new() -> Self {
+        Median {
+            buffer: [I16F16::ZERO; N],
+            index: 0,
+            count: 0,
+            output: I16F16::ZERO,
+        }
+    }
+}
+
+impl<const N: usize> Default for Median<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Filter for Median<N> {
+    fn update(&mut self, input: I16F16) -> I16F16 {
+        self.buffer[self.index] = input;
+        self.index = (self.index + 1) % N;
+        self.count = (self.count + 1).min(N);
+        let mut sorted = self.buffer;
+        let samples = &mut sorted[..self.count];
+        samples.sort_unstable();
+        self.output = samples[(self.count - 1) / 2];
+        self.output
+    }
+
+    fn reset(&mut self) {
+        *self = Median::new();
+    }
+
+    fn output(&self) -> I16F16 {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biquad::Order;
+
+    fn run<F: Filter>(filter: &mut F, samples: &[f64]) -> std::vec::Vec<f64> {
+        samples
+            .iter()
+            .map(|sample| filter.update(I16F16::from_num(*sample)).to_num())
+            .collect()
+    }
+
+    #[test]
+    fn moving_average_averages_the_window() {
+        let mut filter = MovingAverage::<4>::new();
+        let outputs = run(&mut filter, &[4.0, 8.0, 0.0, 4.0, 8.0, 8.0]);
+        assert_eq!(outputs, [4.0, 6.0, 4.0, 4.0, 5.0, 5.0]);
+        assert_eq!(filter.output(), 5);
+    }
+
+    #[test]
+    fn moving_average_does_not_overflow() {
+        let mut filter = MovingAverage::<20>::new();
+        for _ in 0..100 {
+            filter.update(I16F16::from_num(30000));
+        }
+        assert_eq!(filter.output(), 30000);
+    }
+
+    #[test]
+    fn exponential_moves_by_its_weight() {
+        let mut filter = Exponential::new(I16F16::from_num(0.25));
+        let outputs = run(&mut filter, &[4.0, 8.0, 8.0]);
+        assert_eq!(outputs, [4.0, 5.0, 5.75]);
+    }
+
+    #[test]
+    fn median_ignores_a_spike() {
+        let mut filter = Median::<5>::new();
+        let outputs = run(&mut filter, &[1.0, 1.0, 1.0, 50.0, 1.0, 2.0, 2.0, 2.0]);
+        assert_eq!(outputs, [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn reset_forgets_the_samples() {
+        let mut average = MovingAverage::<4>::new();
+        let mut exponential = Exponential::new(I16F16::from_num(0.1));
+        let mut median = Median::<3>::new();
+        for _ in 0..10 {
+            average.update(I16F16::from_num(7));
+            exponential.update(I16F16::from_num(7));
+            median.update(I16F16::from_num(7));
+        }
+        average.reset();
+        exponential.reset();
+        median.reset();
+        assert_eq!(average.update(I16F16::from_num(-3)), -3);
+        assert_eq!(exponential.update(I16F16::from_num(-3)), -3);
+        assert_eq!(median.update(I16F16::from_num(-3)), -3);
+    }
+
+    #[test]
+    fn chain_feeds_one_filter_into_the_next() {
+        // the median removes the spike before the average can smear it out
+        let mut chain = Median::<3>::new().chain(MovingAverage::<2>::new());
+        let outputs = run(&mut chain, &[2.0, 2.0, 40.0, 2.0, 4.0, 4.0]);
+        assert_eq!(outputs, [2.0, 2.0, 2.0, 2.0, 3.0, 4.0]);
+        assert_eq!(chain.output(), 4);
+        chain.reset();
+        assert_eq!(chain.update(I16F16::from_num(9)), 9);
+    }
+
+    #[test]
+    fn chain_of_biquads_filters_more() {
+        let rate = I16F16::from_num(150);
+        let cutoff = I16F16::from_num(10);
+        let mut single = Biquad::low_pass(Order::First, rate, cutoff);
+        let mut double = Biquad::low_pass(Order::First, rate, cutoff).chain(Biquad::low_pass(
+            Order::First,
+            rate,
+            cutoff,
+        ));
+        // a square wave at 37.5 Hz
+        let samples: std::vec::Vec<f64> = (0..400)
+            .map(|tick| if tick % 4 < 2 { 1.0 } else { -1.0 })
+            .collect();
+        let ripple = |outputs: std::vec::Vec<f64>| {
+            outputs[200..]
+                .iter()
+                .fold(0.0f64, |ripple, output| ripple.max(output.abs()))
+        };
+        let single = ripple(run(&mut single, &samples));
+        let double = ripple(run(&mut double, &samples));
+        assert!(double < single / 2.0, "{} {}", single, double);
+    }
+}
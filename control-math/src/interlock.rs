@@ -0,0 +1,75 @@
+// This file implements the interlock of the modes that spin the motors on the ground.
+// Manual mode and the self test both drive the motors straight away, so they are only entered when
+// the battery can take it, the operator has armed the motors and the joystick is in the neutral
+// position, so the motors do not jump to the throttle of the stick.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interlock {
+    pub battery_ready: bool,
+    pub armed: bool,
+    pub joystick_neutral: bool,
+}
+
+// Why the motors may not start, in the order the checks are done.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refusal {
+    LowBattery,
+    NotArmed,
+    JoystickNotNeutral,
+}
+
+impl Interlock {
+    pub fn check(self) -> Result<(), Refusal> {
+        if !self.battery_ready {
+            Err(Refusal::LowBattery)
+        } else if !self.armed {
+            Err(Refusal::NotArmed)
+        } else if !self.joystick_neutral {
+            Err(Refusal::JoystickNotNeutral)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READY: Interlock = Interlock {
+        battery_ready: true,
+        armed: true,
+        joystick_neutral: true,
+    };
+
+    #[test]
+    fn ready_when_everything_passes() {
+        assert_eq!(READY.check(), Ok(()));
+    }
+
+    #[test]
+    fn declined_when_any_check_fails() {
+        let low_battery = Interlock {
+            battery_ready: false,
+            ..READY
+        };
+        assert_eq!(low_battery.check(), Err(Refusal::LowBattery));
+        let disarmed = Interlock {
+            armed: false,
+            ..READY
+        };
+        assert_eq!(disarmed.check(), Err(Refusal::NotArmed));
+        let throttle_up = Interlock {
+            joystick_neutral: false,
+            ..READY
+        };
+        assert_eq!(throttle_up.check(), Err(Refusal::JoystickNotNeutral));
+        // the battery is reported first
+        let nothing = Interlock {
+            battery_ready: false,
+            armed: false,
+            joystick_neutral: false,
+        };
+        assert_eq!(nothing.check(), Err(Refusal::LowBattery));
+    }
+}
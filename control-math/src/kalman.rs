@@ -0,0 +1,228 @@
+// This file implements the Kalman filter of one attitude axis. The state is the angle and the bias
+// of the gyroscope, the gyroscope rate drives the prediction and the angle measured from the
+// direction of gravity is the measurement:
+//   predict  angle += (rate - bias) * dt
+//            P = F P F' + Q * dt, with F = [[1, -dt], [0, 1]] and Q = diag(angle, bias) noise
+//   correct  K = P H' / (H P H' + R), with H = [1, 0]
+//            state += K * (measured - angle), P = (I - K H) P
+// The covariance is propagated in I32F32: it gets as small as 1e-6, below the resolution of I16F16.
+
+use fixed::types::{I16F16, I32F32};
+
+// The variance of the gyroscope bias before the first measurement, (0.1 rad/s)^2.
+const INITIAL_BIAS_VARIANCE: f64 = 0.01;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KalmanNoise {
+    // Process noise of the angle in rad^2 per second, how far the gyroscope integration wanders.
+    pub angle: I32F32,
+    // Process noise of the bias in (rad/s)^2 per second, how fast the gyroscope bias drifts.
+    pub bias: I32F32,
+    // Measurement noise of the angle from the accelerometer in rad^2.
+    pub measurement: I32F32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AxisKalman {
+    noise: KalmanNoise,
+    // Radians.
+    angle: I32F32,
+    // Radians per second.
+    bias: I32F32,
+    covariance: [[I32F32; 2]; 2],
+    // False until the first measurement, which is taken as the start.
+    started: bool,
+}
+
+impl AxisKalman {
+    pub fn new(noise: KalmanNoise) -> Self {
+        AxisKalman {
+            noise,
+            angle: I32F32::ZERO,
+            bias: I32F32::ZERO,
+            covariance: [[I32F32::ZERO; 2]; 2],
+            started: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = AxisKalman::new(self.noise);
+    }
+
+    pub fn get_noise(&self) -> KalmanNoise {
+        self.noise
+    }
+
+    pub fn angle(&self) -> I16F16 {
+        self.angle.saturating_to_num()
+    }
+
+    pub fn bias(&self) -> I16F16 {
+        self.bias.saturating_to_num()
+    }
+
+    pub fn covariance(&self) -> [[I32F32; 2]; 2] {
+        self.covariance
+    }
+
+    // One step of dt seconds with the gyroscope rate in rad/s and the measured angle in rad.
+    // Returns the estimated angle.
+    pub fn update(&mut self, rate: I16F16, measured: I16F16, dt: I16F16) -> I16F16 {
+        let measured = I32F32::from(measured);
+        if !self.started {
+            self.angle = measured;
+            self.covariance = [
+                [self.noise.measurement, I32F32::ZERO],
+                [I32F32::ZERO, I32F32::from_num(INITIAL_BIAS_VARIANCE)],
+            ];
+            self.started = true;
+        }
+        let dt = I32F32::from(dt);
+        let [[p00, p01], [p10, p11]] = self.covariance;
+
+        // predict
+        self.angle += (I32F32::from(rate) - self.bias) * dt;
+        let p00 = p00 + dt * (dt * p11 - p01 - p10 + self.noise.angle);
+        let p01 = p01 - dt * p11;
+        let p10 = p10 - dt * p11;
+        let p11 = p11 + self.noise.bias * dt;
+
+        // correct
+        let innovation = measured - self.angle;
+        let variance = p00 + self.noise.measurement;
+        let gain = [p00 / variance, p10 / variance];
+        self.angle += gain[0] * innovation;
+        self.bias += gain[1] * innovation;
+        self.covariance = [
+            [p00 - gain[0] * p00, p01 - gain[0] * p01],
+            [p10 - gain[1] * p00, p11 - gain[1] * p01],
+        ];
+        self.angle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f64 = 1.0 / 150.0;
+
+    fn noise() -> KalmanNoise {
+        KalmanNoise {
+            angle: I32F32::from_num(0.001),
+            bias: I32F32::from_num(0.003),
+            measurement: I32F32::from_num(0.03),
+        }
+    }
+
+    fn step(filter: &mut AxisKalman, rate: f64, measured: f64) -> f64 {
+        filter
+            .update(
+                I16F16::from_num(rate),
+                I16F16::from_num(measured),
+                I16F16::from_num(DT),
+            )
+            .to_num()
+    }
+
+    // Deterministic noise between -amplitude and amplitude.
+    fn noise_sample(seed: &mut u32, amplitude: f64) -> f64 {
+        *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        ((*seed >> 16) as f64 / 32_768.0 - 1.0) * amplitude
+    }
+
+    #[test]
+    fn starts_at_the_first_measurement() {
+        let mut filter = AxisKalman::new(noise());
+        assert!((step(&mut filter, 0.0, 0.3) - 0.3).abs() < 0.001);
+    }
+
+    #[test]
+    fn converges_to_the_measured_angle() {
+        let mut filter = AxisKalman::new(noise());
+        step(&mut filter, 0.0, 0.0);
+        // the drone is tilted while the gyroscope missed it
+        for _ in 0..1500 {
+            step(&mut filter, 0.0, 0.5);
+        }
+        assert!((filter.angle().to_num::<f64>() - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn estimates_the_gyroscope_bias() {
+        let mut filter = AxisKalman::new(noise());
+        let mut seed = 7;
+        // standing still, the gyroscope reads 0.05 rad/s too much
+        for _ in 0..4500 {
+            step(&mut filter, 0.05, noise_sample(&mut seed, 0.05));
+        }
+        assert!((filter.bias().to_num::<f64>() - 0.05).abs() < 0.01);
+        assert!(filter.angle().to_num::<f64>().abs() < 0.02);
+    }
+
+    #[test]
+    fn tracks_a_rotation_with_a_biased_gyroscope() {
+        let mut filter = AxisKalman::new(noise());
+        let mut seed = 3;
+        let mut angle: f64 = 0.0;
+        for tick in 0..6000 {
+            let rate = 0.4 * (tick as f64 * DT).cos();
+            angle += rate * DT;
+            let estimated = step(
+                &mut filter,
+                rate + 0.02,
+                angle + noise_sample(&mut seed, 0.1),
+            );
+            if tick > 3000 {
+                assert!((estimated - angle).abs() < 0.03, "{}", tick);
+            }
+        }
+    }
+
+    #[test]
+    fn smooths_the_measurement() {
+        let mut filter = AxisKalman::new(noise());
+        let mut seed = 11;
+        let mut measurement_error = 0.0;
+        let mut estimate_error = 0.0;
+        for tick in 0..3000 {
+            let measured = 0.2 + noise_sample(&mut seed, 0.2);
+            let estimated = step(&mut filter, 0.0, measured);
+            if tick >= 1000 {
+                measurement_error += (measured - 0.2) * (measured - 0.2);
+                estimate_error += (estimated - 0.2) * (estimated - 0.2);
+            }
+        }
+        assert!(estimate_error * 10.0 < measurement_error);
+    }
+
+    #[test]
+    fn covariance_settles() {
+        let mut filter = AxisKalman::new(noise());
+        for _ in 0..3000 {
+            step(&mut filter, 0.0, 0.0);
+        }
+        let before = filter.covariance();
+        step(&mut filter, 0.0, 0.0);
+        let after = filter.covariance();
+        for (row_before, row_after) in before.iter().zip(&after) {
+            for (before, after) in row_before.iter().zip(row_after) {
+                assert!((*before - *after).abs() < I32F32::from_num(1e-6));
+            }
+        }
+        // variances stay positive and below the measurement noise
+        assert!(after[0][0] > 0 && after[0][0] < noise().measurement);
+        assert!(after[1][1] > 0);
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut filter = AxisKalman::new(noise());
+        for _ in 0..100 {
+            step(&mut filter, 0.1, 0.4);
+        }
+        filter.reset();
+        assert!((step(&mut filter, 0.0, -0.2) + 0.2).abs() < 0.001);
+        assert_eq!(filter.bias(), 0);
+    }
+}
@@ -0,0 +1,18 @@
+#![cfg_attr(not(test), no_std)]
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+pub mod altitude; // converts the barometer pressure to an altitude
+pub mod attitude; // estimates the attitude from the gyroscope and the accelerometer
+pub mod biquad; // Butterworth low-pass and notch filters with the coefficients from the frequency
+pub mod config; // the configuration the drone keeps in flash and the layout of its record
+pub mod desaturation; // keeps the mixed motor speeds within the limits of the motors
+pub mod failsafe; // decides what to do when the link to the PC is lost in flight
+pub mod filter; // the common interface of the signal filters, composed into pipelines
+pub mod interlock; // the checks before the motors spin on the ground
+pub mod kalman; // estimates the angle and the gyroscope bias of one attitude axis
+pub mod mixer; // maps the lift, roll, pitch and yaw commands to the four motors
+pub mod notch; // the notch against the motor vibration and the energy of the vibration
+pub mod safety; // stops the motors when the drone has flipped, tumbles or crashed
+pub mod vertical; // fuses the barometer altitude with the vertical acceleration
@@ -0,0 +1,263 @@
+// This file implements the motor mixer of the drone.
+// The mixer maps the lift, roll, pitch and yaw commands to the speed of every motor, using one row of
+// factors per motor. The rows follow from where a motor sits on the frame and which way it spins,
+// with the same sign convention as the original hand-written mix of the drone:
+//   positive pitch speeds up the back motors and slows down the front motors,
+//   positive roll speeds up the left motors and slows down the right motors,
+//   positive yaw speeds up the counter-clockwise motors and slows down the clockwise motors.
+
+pub const MOTOR_COUNT: usize = 4;
+
+/// Where a motor is mounted on the frame, seen from above with the front of the drone up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MotorPosition {
+    Front,
+    Right,
+    Back,
+    Left,
+    FrontRight,
+    BackRight,
+    BackLeft,
+    FrontLeft,
+}
+
+/// The direction a propeller spins, seen from above.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpinDirection {
+    Clockwise,
+    CounterClockwise,
+}
+
+impl SpinDirection {
+    pub const fn opposite(self) -> SpinDirection {
+        match self {
+            SpinDirection::Clockwise => SpinDirection::CounterClockwise,
+            SpinDirection::CounterClockwise => SpinDirection::Clockwise,
+        }
+    }
+}
+
+/// The layout of the arms, the motors of a plus frame sit on the axes, those of an X frame in between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameType {
+    Plus,
+    X,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MotorConfig {
+    pub position: MotorPosition,
+    pub spin: SpinDirection,
+}
+
+/// How much a single motor responds to every axis command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MixerRow {
+    pub lift: i32,
+    pub roll: i32,
+    pub pitch: i32,
+    pub yaw: i32,
+}
+
+impl MixerRow {
+    pub const fn from_motor(motor: MotorConfig) -> MixerRow {
+        let (roll, pitch) = match motor.position {
+            MotorPosition::Front => (0, -1),
+            MotorPosition::Right => (-1, 0),
+            MotorPosition::Back => (0, 1),
+            MotorPosition::Left => (1, 0),
+            MotorPosition::FrontRight => (-1, -1),
+            MotorPosition::BackRight => (-1, 1),
+            MotorPosition::BackLeft => (1, 1),
+            MotorPosition::FrontLeft => (1, -1),
+        };
+        let yaw = match motor.spin {
+            SpinDirection::Clockwise => -1,
+            SpinDirection::CounterClockwise => 1,
+        };
+        MixerRow {
+            lift: 1,
+            roll,
+            pitch,
+            yaw,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mixer {
+    rows: [MixerRow; MOTOR_COUNT],
+}
+
+impl Mixer {
+    /// The motors are numbered clockwise, starting at the front (plus frame) or front right (X frame)
+    /// motor, which spins in the given direction. Every next motor spins the other way.
+    pub const fn new(frame: FrameType, first_spin: SpinDirection) -> Mixer {
+        let positions = match frame {
+            FrameType::Plus => [
+                MotorPosition::Front,
+                MotorPosition::Right,
+                MotorPosition::Back,
+                MotorPosition::Left,
+            ],
+            FrameType::X => [
+                MotorPosition::FrontRight,
+                MotorPosition::BackRight,
+                MotorPosition::BackLeft,
+                MotorPosition::FrontLeft,
+            ],
+        };
+        let second_spin = first_spin.opposite();
+        Mixer::from_motors([
+            MotorConfig {
+                position: positions[0],
+                spin: first_spin,
+            },
+            MotorConfig {
+                position: positions[1],
+                spin: second_spin,
+            },
+            MotorConfig {
+                position: positions[2],
+                spin: first_spin,
+            },
+            MotorConfig {
+                position: positions[3],
+                spin: second_spin,
+            },
+        ])
+    }
+
+    /// Any motor order, e.g. when the motors are not wired up clockwise.
+    pub const fn from_motors(motors: [MotorConfig; MOTOR_COUNT]) -> Mixer {
+        Mixer {
+            rows: [
+                MixerRow::from_motor(motors[0]),
+                MixerRow::from_motor(motors[1]),
+                MixerRow::from_motor(motors[2]),
+                MixerRow::from_motor(motors[3]),
+            ],
+        }
+    }
+
+    /// A custom mixing matrix, one row per motor.
+    pub const fn from_matrix(rows: [MixerRow; MOTOR_COUNT]) -> Mixer {
+        Mixer { rows }
+    }
+
+    pub fn get_rows(&self) -> [MixerRow; MOTOR_COUNT] {
+        self.rows
+    }
+
+    /// Combine the axis commands into the speed of every motor.
+    pub fn mix(&self, lift: i32, roll: i32, pitch: i32, yaw: i32) -> [i32; MOTOR_COUNT] {
+        self.rows
+            .map(|row| row.lift * lift + row.roll * roll + row.pitch * pitch + row.yaw * yaw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLUS: Mixer = Mixer::new(FrameType::Plus, SpinDirection::Clockwise);
+    const X: Mixer = Mixer::new(FrameType::X, SpinDirection::Clockwise);
+
+    fn deltas(mixer: &Mixer, roll: i32, pitch: i32, yaw: i32) -> [i32; MOTOR_COUNT] {
+        let base = 300;
+        mixer.mix(base, roll, pitch, yaw).map(|motor| motor - base)
+    }
+
+    #[test]
+    fn lift_moves_all_motors_equally() {
+        assert_eq!(PLUS.mix(250, 0, 0, 0), [250; 4]);
+        assert_eq!(X.mix(250, 0, 0, 0), [250; 4]);
+    }
+
+    #[test]
+    fn plus_frame_matches_original_mix() {
+        // ae1 = lift - pitch - yaw, ae2 = lift - roll + yaw, ae3 = lift + pitch - yaw, ae4 = lift + roll + yaw
+        let (lift, roll, pitch, yaw) = (300, 7, 11, 13);
+        assert_eq!(
+            PLUS.mix(lift, roll, pitch, yaw),
+            [
+                lift - pitch - yaw,
+                lift - roll + yaw,
+                lift + pitch - yaw,
+                lift + roll + yaw
+            ]
+        );
+    }
+
+    #[test]
+    fn plus_frame_axis_deltas() {
+        assert_eq!(deltas(&PLUS, 10, 0, 0), [0, -10, 0, 10]);
+        assert_eq!(deltas(&PLUS, 0, 10, 0), [-10, 0, 10, 0]);
+        assert_eq!(deltas(&PLUS, 0, 0, 10), [-10, 10, -10, 10]);
+    }
+
+    #[test]
+    fn x_frame_axis_deltas() {
+        assert_eq!(deltas(&X, 10, 0, 0), [-10, -10, 10, 10]);
+        assert_eq!(deltas(&X, 0, 10, 0), [-10, 10, 10, -10]);
+        assert_eq!(deltas(&X, 0, 0, 10), [-10, 10, -10, 10]);
+    }
+
+    #[test]
+    fn spin_direction_flips_yaw_only() {
+        let flipped = Mixer::new(FrameType::Plus, SpinDirection::CounterClockwise);
+        assert_eq!(deltas(&flipped, 0, 0, 10), [10, -10, 10, -10]);
+        assert_eq!(deltas(&flipped, 10, 0, 0), deltas(&PLUS, 10, 0, 0));
+        assert_eq!(deltas(&flipped, 0, 10, 0), deltas(&PLUS, 0, 10, 0));
+    }
+
+    #[test]
+    fn motor_order_permutes_outputs() {
+        let reordered = Mixer::from_motors([
+            MotorConfig {
+                position: MotorPosition::Left,
+                spin: SpinDirection::CounterClockwise,
+            },
+            MotorConfig {
+                position: MotorPosition::Front,
+                spin: SpinDirection::Clockwise,
+            },
+            MotorConfig {
+                position: MotorPosition::Right,
+                spin: SpinDirection::CounterClockwise,
+            },
+            MotorConfig {
+                position: MotorPosition::Back,
+                spin: SpinDirection::Clockwise,
+            },
+        ]);
+        let plus = PLUS.mix(300, 3, 5, 7);
+        assert_eq!(
+            reordered.mix(300, 3, 5, 7),
+            [plus[3], plus[0], plus[1], plus[2]]
+        );
+    }
+
+    #[test]
+    fn axis_commands_do_not_change_total_thrust() {
+        for mixer in [PLUS, X] {
+            for (roll, pitch, yaw) in [(10, 0, 0), (0, 10, 0), (0, 0, 10), (-4, 9, 2)] {
+                let sum: i32 = deltas(&mixer, roll, pitch, yaw).iter().sum();
+                assert_eq!(sum, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn custom_matrix_is_used_as_is() {
+        let row = MixerRow {
+            lift: 1,
+            roll: 2,
+            pitch: 0,
+            yaw: 0,
+        };
+        let mixer = Mixer::from_matrix([row; MOTOR_COUNT]);
+        assert_eq!(mixer.get_rows(), [row; MOTOR_COUNT]);
+        assert_eq!(mixer.mix(100, 5, 0, 0), [110; MOTOR_COUNT]);
+    }
+}
@@ -0,0 +1,297 @@
+// This file implements the notch filter against the vibration of the motors and the measure of the
+// vibration energy of a signal. The motors shake the frame at a few hundred Hz, far above the
+// 150 Hz of the control loop, so the vibration shows up in the samples at its alias:
+//   alias = |frequency - n * sample rate| for the n that puts it between 0 and sample rate / 2
+// The centre of the notch is either fixed or tracks the motors: the vibration frequency grows with
+// the rotor speed and is taken as the average motor command times `hz_per_command`. A centre that
+// aliases close to 0 Hz would notch out the motion of the drone itself, the notch then passes the
+// signal unchanged.
+
+use crate::biquad::{Biquad, BiquadCoefficients};
+use crate::filter::Filter;
+use cordic::sqrt;
+use fixed::types::{I16F16, I32F32};
+
+// The lowest centre of the notch after aliasing in Hz, the attitude moves below it.
+const MIN_CENTRE: f64 = 10.0;
+// The share of every sample in the mean of the signal, the mean follows the motion of the drone
+// below about 5 Hz at 150 Hz.
+const MEAN_WEIGHT: f64 = 0.2;
+// The share of every sample in the energy, a time constant of 64 samples.
+const ENERGY_WEIGHT: f64 = 1.0 / 64.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotchMode {
+    Off,
+    // The vibration is at `centre`.
+    Fixed,
+    // The vibration follows the average motor command.
+    Tracking,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotchSettings {
+    pub mode: NotchMode,
+    // The vibration frequency in Hz in fixed mode.
+    pub centre: I16F16,
+    // The vibration frequency in Hz per unit of the average motor command in tracking mode.
+    pub hz_per_command: I16F16,
+    // The centre over the width of the band, a higher quality notches a narrower band.
+    pub quality: I16F16,
+}
+
+impl NotchSettings {
+    // The centre of the notch in the samples, None when the notch passes the signal unchanged.
+    pub fn centre(&self, motor_command: u16, sample_rate: I16F16) -> Option<I16F16> {
+        let frequency = match self.mode {
+            NotchMode::Off => return None,
+            NotchMode::Fixed => self.centre,
+            NotchMode::Tracking => self.hz_per_command.saturating_mul_int(motor_command as i32),
+        };
+        Some(alias(frequency, sample_rate)).filter(|centre| *centre >= I16F16::from_num(MIN_CENTRE))
+    }
+}
+
+// The frequency a sine shows up at when it is sampled at the sample rate, between 0 and half the
+// sample rate.
+pub fn alias(frequency: I16F16, sample_rate: I16F16) -> I16F16 {
+    let folded = frequency.abs() % sample_rate;
+    folded.min(sample_rate - folded)
+}
+
+// A biquad notch that is retuned while it runs, or switched off to pass the signal unchanged.
+#[derive(Clone, Copy, Debug)]
+pub struct Notch {
+    // None while the notch is off.
+    biquad: Option<Biquad>,
+    output: I16F16,
+}
+
+impl Notch {
+    pub fn new() -> Self {
+        Notch {
+            biquad: None,
+            output: I16F16::ZERO,
+        }
+    }
+
+    // Move the notch to new coefficients, None switches it off. A running notch keeps its state,
+    // so a centre that moves with the motors does not start the filter over.
+    pub fn tune(&mut self, coefficients: Option<BiquadCoefficients>) {
+        match (&mut self.biquad, coefficients) {
+            (Some(biquad), Some(coefficients)) => biquad.set_coefficients(coefficients),
+            (biquad, coefficients) => *biquad = coefficients.map(Biquad::new),
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.biquad.is_some()
+    }
+}
+
+impl Default for Notch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filter for Notch {
+    fn update(&mut self, input: I16F16) -> I16F16 {
+        self.output = match &mut self.biquad {
+            Some(biquad) => biquad.update(input),
+            None => input,
+        };
+        self.output
+    }
+
+    fn reset(&mut self) {
+        if let Some(biquad) = &mut self.biquad {
+            biquad.reset();
+        }
+        self.output = I16F16::ZERO;
+    }
+
+    fn output(&self) -> I16F16 {
+        self.output
+    }
+}
+
+// The vibration energy of a signal, the RMS of its deviation from the mean that follows the
+// motion, so gravity and the movements of the drone do not count.
+#[derive(Clone, Copy, Debug)]
+pub struct SignalEnergy {
+    mean: I32F32,
+    // The mean of the squared deviation.
+    power: I32F32,
+    // False until the first sample, which is taken as the mean.
+    started: bool,
+}
+
+impl SignalEnergy {
+    pub fn new() -> Self {
+        SignalEnergy {
+            mean: I32F32::ZERO,
+            power: I32F32::ZERO,
+            started: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = SignalEnergy::new();
+    }
+
+    pub fn update(&mut self, input: I16F16) {
+        let input = I32F32::from(input);
+        if !self.started {
+            self.mean = input;
+            self.started = true;
+        }
+        let deviation = input - self.mean;
+        self.mean += deviation * I32F32::from_num(MEAN_WEIGHT);
+        self.power +=
+            (deviation.saturating_mul(deviation) - self.power) * I32F32::from_num(ENERGY_WEIGHT);
+    }
+
+    // The RMS of the deviation, in the unit of the signal.
+    pub fn rms(&self) -> I16F16 {
+        sqrt(self.power).saturating_to_num()
+    }
+}
+
+impl Default for SignalEnergy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const SAMPLE_RATE: f64 = 150.0;
+
+    fn settings(mode: NotchMode) -> NotchSettings {
+        NotchSettings {
+            mode,
+            centre: I16F16::from_num(200),
+            hz_per_command: I16F16::from_num(0.5),
+            quality: I16F16::from_num(2),
+        }
+    }
+
+    fn centre(settings: &NotchSettings, motor_command: u16) -> Option<f64> {
+        settings
+            .centre(motor_command, I16F16::from_num(SAMPLE_RATE))
+            .map(|centre| centre.to_num())
+    }
+
+    // A sine of the frequency sampled at 150 Hz.
+    fn sine(frequency: f64, tick: usize) -> I16F16 {
+        I16F16::from_num((2.0 * PI * frequency * tick as f64 / SAMPLE_RATE).sin())
+    }
+
+    #[test]
+    fn alias_folds_into_half_the_sample_rate() {
+        let rate = I16F16::from_num(SAMPLE_RATE);
+        let alias = |frequency: f64| alias(I16F16::from_num(frequency), rate).to_num::<f64>();
+        assert_eq!(alias(30.0), 30.0);
+        assert_eq!(alias(120.0), 30.0);
+        assert_eq!(alias(200.0), 50.0);
+        assert_eq!(alias(330.0), 30.0);
+        assert_eq!(alias(450.0), 0.0);
+    }
+
+    #[test]
+    fn centre_follows_the_mode() {
+        assert_eq!(centre(&settings(NotchMode::Off), 500), None);
+        assert_eq!(centre(&settings(NotchMode::Fixed), 0), Some(50.0));
+        // 400 * 0.5 = 200 Hz
+        assert_eq!(centre(&settings(NotchMode::Tracking), 400), Some(50.0));
+        // 250 Hz aliases to 50 Hz too, 280 Hz to 20 Hz
+        assert_eq!(centre(&settings(NotchMode::Tracking), 500), Some(50.0));
+        assert_eq!(centre(&settings(NotchMode::Tracking), 560), Some(20.0));
+    }
+
+    #[test]
+    fn centre_near_zero_is_off() {
+        // the motors are off, and 300 Hz aliases to 0 Hz
+        assert_eq!(centre(&settings(NotchMode::Tracking), 0), None);
+        assert_eq!(centre(&settings(NotchMode::Tracking), 600), None);
+    }
+
+    #[test]
+    fn notch_off_passes_the_signal() {
+        let mut notch = Notch::new();
+        assert!(!notch.is_on());
+        for tick in 0..50 {
+            assert_eq!(notch.update(sine(20.0, tick)), sine(20.0, tick));
+        }
+    }
+
+    #[test]
+    fn notch_removes_the_aliased_vibration() {
+        let rate = I16F16::from_num(SAMPLE_RATE);
+        let settings = settings(NotchMode::Fixed);
+        let mut notch = Notch::new();
+        notch.tune(
+            settings
+                .centre(0, rate)
+                .map(|centre| BiquadCoefficients::notch(rate, centre, settings.quality)),
+        );
+        assert!(notch.is_on());
+        let mut before = SignalEnergy::new();
+        let mut after = SignalEnergy::new();
+        for tick in 0..1500 {
+            // a slow motion with the vibration of 200 Hz on top
+            let input = sine(0.5, tick) / 2 + sine(200.0, tick) / 4;
+            before.update(input);
+            after.update(notch.update(input));
+        }
+        assert!(
+            after.rms() < before.rms() / 2,
+            "{} {}",
+            before.rms(),
+            after.rms()
+        );
+    }
+
+    #[test]
+    fn retuning_keeps_the_output_smooth() {
+        let rate = I16F16::from_num(SAMPLE_RATE);
+        let quality = I16F16::from_num(2);
+        let mut notch = Notch::new();
+        notch.tune(Some(BiquadCoefficients::notch(
+            rate,
+            I16F16::from_num(40),
+            quality,
+        )));
+        for _ in 0..100 {
+            notch.update(I16F16::from_num(0.5));
+        }
+        notch.tune(Some(BiquadCoefficients::notch(
+            rate,
+            I16F16::from_num(41),
+            quality,
+        )));
+        let output = notch.update(I16F16::from_num(0.5)).to_num::<f64>();
+        assert!((output - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn energy_is_the_rms_of_the_vibration() {
+        let mut energy = SignalEnergy::new();
+        for tick in 0..1500 {
+            // the offset of gravity does not count
+            energy.update(I16F16::ONE + sine(30.0, tick));
+        }
+        // the high-pass of the mean lets 30 Hz through with a gain of 1.1
+        let rms = energy.rms().to_num::<f64>();
+        assert!((rms - 1.1 * 0.5f64.sqrt()).abs() < 0.02, "{}", rms);
+        energy.reset();
+        for _ in 0..100 {
+            energy.update(I16F16::ONE);
+        }
+        assert_eq!(energy.rms(), 0);
+    }
+}
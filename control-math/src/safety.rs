@@ -0,0 +1,223 @@
+// This file implements the checks of the safety monitor that stops the motors when the drone is out
+// of control. The controllers keep driving the motors whatever the attitude is, so a flipped drone
+// would keep pushing itself into the ground. While flying, the monitor watches for three signatures:
+//   excessive tilt: pitch or roll beyond the limit for a few ticks, the drone has flipped,
+//   tumbling: an angular rate beyond the limit for a while, the drone spins out of control,
+//   impact: an acceleration beyond the limit on any axis for a few ticks, the drone hit something.
+// Every limit has to be exceeded for a number of ticks in a row, so a single bad sample (a spike of
+// the motor vibration, a glitch of the bus) never trips. Each of them trips with its own action,
+// cutting the motors right away or landing in panic mode.
+
+use fixed::types::I16F16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SafetyAction {
+    // Turn the motors off right away, the drone cannot recover.
+    CutMotors,
+    // Land in panic mode.
+    Panic,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SafetyLimits {
+    // The largest pitch or roll in radians.
+    pub max_tilt: I16F16,
+    // The number of ticks in a row the tilt has to be exceeded.
+    pub tilt_ticks: u32,
+    pub tilt_action: SafetyAction,
+    // The largest angular rate of any axis in raw gyroscope units (16.4 per deg/s).
+    pub max_rate: i32,
+    pub tumble_ticks: u32,
+    pub tumble_action: SafetyAction,
+    // The largest acceleration of any axis in raw units (16384 per g).
+    pub impact_accel: i32,
+    pub impact_ticks: u32,
+    pub impact_action: SafetyAction,
+}
+
+pub const DEFAULT_SAFETY_LIMITS: SafetyLimits = SafetyLimits {
+    // 1.05 rad (60 degrees) in I16F16.
+    max_tilt: I16F16::from_bits(68_813),
+    tilt_ticks: 5,
+    tilt_action: SafetyAction::CutMotors,
+    // 800 deg/s for 100 ms at 150 Hz.
+    max_rate: 13_120,
+    tumble_ticks: 15,
+    tumble_action: SafetyAction::CutMotors,
+    // 1.8 g, close to the end of the 2 g range of the accelerometer, for 13 ms at 150 Hz.
+    impact_accel: 29_500,
+    impact_ticks: 2,
+    impact_action: SafetyAction::Panic,
+};
+
+// What the safety monitor found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hazard {
+    // The value is the tilt in milliradians.
+    ExcessiveTilt,
+    // The value is the largest angular rate in raw gyroscope units.
+    Tumbling,
+    // The value is the largest acceleration in raw accelerometer units.
+    Impact,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SafetyTrip {
+    pub hazard: Hazard,
+    pub action: SafetyAction,
+    pub value: i32,
+}
+
+pub struct SafetyMonitor {
+    limits: SafetyLimits,
+    tilt_ticks: u32,
+    tumble_ticks: u32,
+    impact_ticks: u32,
+}
+
+impl SafetyMonitor {
+    pub fn new(limits: SafetyLimits) -> Self {
+        SafetyMonitor {
+            limits,
+            tilt_ticks: 0,
+            tumble_ticks: 0,
+            impact_ticks: 0,
+        }
+    }
+
+    pub fn get_limits(&self) -> SafetyLimits {
+        self.limits
+    }
+
+    // Replace the limits, counting starts again.
+    pub fn set_limits(&mut self, limits: SafetyLimits) {
+        self.limits = limits;
+        self.reset();
+    }
+
+    // Start counting again, called while not flying.
+    pub fn reset(&mut self) {
+        self.tilt_ticks = 0;
+        self.tumble_ticks = 0;
+        self.impact_ticks = 0;
+    }
+
+    // Check the sensor readings of this tick, returns what tripped (if anything) and what to do.
+    pub fn update(
+        &mut self,
+        pitch: I16F16,
+        roll: I16F16,
+        gyro: [i16; 3],
+        accel: [i16; 3],
+    ) -> Option<SafetyTrip> {
+        let tilt = pitch.abs().max(roll.abs());
+        let rate = gyro
+            .iter()
+            .map(|rate| (*rate as i32).abs())
+            .max()
+            .unwrap_or(0);
+        let acceleration = accel
+            .iter()
+            .map(|acc| (*acc as i32).abs())
+            .max()
+            .unwrap_or(0);
+
+        self.tilt_ticks = count(self.tilt_ticks, tilt > self.limits.max_tilt);
+        self.tumble_ticks = count(self.tumble_ticks, rate > self.limits.max_rate);
+        self.impact_ticks = count(self.impact_ticks, acceleration > self.limits.impact_accel);
+
+        let trip = if self.impact_ticks >= self.limits.impact_ticks {
+            SafetyTrip {
+                hazard: Hazard::Impact,
+                action: self.limits.impact_action,
+                value: acceleration,
+            }
+        } else if self.tilt_ticks >= self.limits.tilt_ticks {
+            SafetyTrip {
+                hazard: Hazard::ExcessiveTilt,
+                action: self.limits.tilt_action,
+                // in milliradians
+                value: (tilt * I16F16::from_num(1000)).to_num::<i32>(),
+            }
+        } else if self.tumble_ticks >= self.limits.tumble_ticks {
+            SafetyTrip {
+                hazard: Hazard::Tumbling,
+                action: self.limits.tumble_action,
+                value: rate,
+            }
+        } else {
+            return None;
+        };
+        self.reset();
+        Some(trip)
+    }
+}
+
+// The number of ticks in a row a limit has been exceeded.
+fn count(ticks: u32, exceeded: bool) -> u32 {
+    if exceeded {
+        ticks.saturating_add(1)
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: SafetyLimits = DEFAULT_SAFETY_LIMITS;
+
+    const LEVEL: I16F16 = I16F16::ZERO;
+    const AT_REST: [i16; 3] = [0, 0, 16_384];
+    const SPIKE: [i16; 3] = [0, 0, 32_000];
+
+    #[test]
+    fn single_acceleration_spike_does_not_trip() {
+        let mut monitor = SafetyMonitor::new(LIMITS);
+        for _ in 0..100 {
+            assert_eq!(monitor.update(LEVEL, LEVEL, [0; 3], SPIKE), None);
+            assert_eq!(monitor.update(LEVEL, LEVEL, [0; 3], AT_REST), None);
+        }
+    }
+
+    #[test]
+    fn impact_trips_after_its_ticks() {
+        let mut monitor = SafetyMonitor::new(LIMITS);
+        assert_eq!(monitor.update(LEVEL, LEVEL, [0; 3], SPIKE), None);
+        assert_eq!(
+            monitor.update(LEVEL, LEVEL, [0; 3], [0, -32_000, 0]),
+            Some(SafetyTrip {
+                hazard: Hazard::Impact,
+                action: SafetyAction::Panic,
+                value: 32_000,
+            })
+        );
+        // counting starts over after a trip
+        assert_eq!(monitor.update(LEVEL, LEVEL, [0; 3], SPIKE), None);
+    }
+
+    #[test]
+    fn tilt_trips_after_its_ticks() {
+        let mut monitor = SafetyMonitor::new(LIMITS);
+        let tilted = I16F16::from_num(1.5);
+        for _ in 0..4 {
+            assert_eq!(monitor.update(LEVEL, -tilted, [0; 3], AT_REST), None);
+        }
+        let trip = monitor.update(LEVEL, -tilted, [0; 3], AT_REST).unwrap();
+        assert_eq!(trip.hazard, Hazard::ExcessiveTilt);
+        assert_eq!(trip.action, SafetyAction::CutMotors);
+        assert_eq!(trip.value, 1500);
+    }
+
+    #[test]
+    fn reset_clears_the_counts() {
+        let mut monitor = SafetyMonitor::new(LIMITS);
+        let tumbling = [0, 20_000, 0];
+        for _ in 0..14 {
+            assert_eq!(monitor.update(LEVEL, LEVEL, tumbling, AT_REST), None);
+        }
+        monitor.reset();
+        assert_eq!(monitor.update(LEVEL, LEVEL, tumbling, AT_REST), None);
+    }
+}
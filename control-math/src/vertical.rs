@@ -0,0 +1,243 @@
+// This file implements the vertical estimator, a third order complementary filter that fuses the
+// barometer altitude with the vertical acceleration. The acceleration is integrated to velocity and
+// altitude, which react immediately but drift. The barometer altitude is noisy but does not drift,
+// so the difference between the two corrects the altitude, the velocity and the bias of the
+// acceleration. With all three poles at the crossover frequency w the gains are 3w, 3w^2 and w^3:
+// below w the estimate follows the barometer, above it the accelerometer.
+// The state is kept in I32F32, the velocity and altitude steps of one tick are far below the
+// resolution of I16F16.
+
+use fixed::types::{I16F16, I32F32};
+
+// Standard gravity in m/s^2.
+pub const GRAVITY: f64 = 9.80665;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerticalGains {
+    pub altitude: I32F32,
+    pub velocity: I32F32,
+    pub bias: I32F32,
+}
+
+impl VerticalGains {
+    // The gains for a crossover frequency in rad/s.
+    pub fn from_crossover(crossover: I32F32) -> Self {
+        VerticalGains {
+            altitude: crossover * 3,
+            velocity: crossover * crossover * 3,
+            bias: crossover * crossover * crossover,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerticalEstimate {
+    // Metres above the reference of the barometer altitude, positive is up.
+    pub altitude: I16F16,
+    // Metres per second, positive is climbing.
+    pub velocity: I16F16,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VerticalEstimator {
+    gains: VerticalGains,
+    altitude: I32F32,
+    velocity: I32F32,
+    // The part of the vertical acceleration that is not motion, in m/s^2.
+    bias: I32F32,
+    // False until the first barometer altitude, which is taken as the start.
+    started: bool,
+}
+
+impl VerticalEstimator {
+    pub fn new(gains: VerticalGains) -> Self {
+        VerticalEstimator {
+            gains,
+            altitude: I32F32::ZERO,
+            velocity: I32F32::ZERO,
+            bias: I32F32::ZERO,
+            started: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = VerticalEstimator::new(self.gains);
+    }
+
+    // One step of dt seconds, with the barometer altitude in metres and the vertical acceleration
+    // without gravity in m/s^2.
+    pub fn update(
+        &mut self,
+        baro_altitude: I16F16,
+        acceleration: I16F16,
+        dt: I16F16,
+    ) -> VerticalEstimate {
+        let baro_altitude = I32F32::from(baro_altitude);
+        if !self.started {
+            self.altitude = baro_altitude;
+            self.started = true;
+        }
+        let dt = I32F32::from(dt);
+        let error = baro_altitude - self.altitude;
+        let acceleration = I32F32::from(acceleration) - self.bias;
+
+        self.bias = self.bias.saturating_sub(self.gains.bias * error * dt);
+        self.altitude = self
+            .altitude
+            .saturating_add((self.velocity + self.gains.altitude * error) * dt);
+        self.velocity = self
+            .velocity
+            .saturating_add((acceleration + self.gains.velocity * error) * dt);
+        self.estimate()
+    }
+
+    pub fn estimate(&self) -> VerticalEstimate {
+        VerticalEstimate {
+            altitude: self.altitude.saturating_to_num(),
+            velocity: self.velocity.saturating_to_num(),
+        }
+    }
+}
+
+// The vertical acceleration without gravity in m/s^2, from the accelerometer reading in g and the
+// sine and cosine of the pitch and the roll in the convention of the DMP conversion. The reading is
+// rotated to the earth frame, where a drone at rest reads exactly 1 g upwards whatever its
+// attitude.
+pub fn vertical_acceleration(
+    accel: [I16F16; 3],
+    (sin_pitch, cos_pitch): (I16F16, I16F16),
+    (sin_roll, cos_roll): (I16F16, I16F16),
+) -> I16F16 {
+    let up =
+        sin_pitch * accel[0] + cos_pitch * sin_roll * accel[1] + cos_pitch * cos_roll * accel[2];
+    (up - I16F16::ONE) * I16F16::from_num(GRAVITY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f64 = 0.01;
+
+    fn estimator() -> VerticalEstimator {
+        VerticalEstimator::new(VerticalGains::from_crossover(I32F32::from_num(1)))
+    }
+
+    fn step(estimator: &mut VerticalEstimator, baro: f64, acceleration: f64) -> (f64, f64) {
+        let estimate = estimator.update(
+            I16F16::from_num(baro),
+            I16F16::from_num(acceleration),
+            I16F16::from_num(DT),
+        );
+        (estimate.altitude.to_num(), estimate.velocity.to_num())
+    }
+
+    // Deterministic noise between -amplitude and amplitude.
+    fn noise(seed: &mut u32, amplitude: f64) -> f64 {
+        *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        ((*seed >> 16) as f64 / 32_768.0 - 1.0) * amplitude
+    }
+
+    #[test]
+    fn starts_at_the_barometer_altitude() {
+        let mut estimator = estimator();
+        let (altitude, velocity) = step(&mut estimator, 12.5, 0.0);
+        assert!((altitude - 12.5).abs() < 0.01);
+        assert!(velocity.abs() < 0.01);
+    }
+
+    #[test]
+    fn follows_a_climb() {
+        let mut estimator = estimator();
+        let mut altitude = 0.0;
+        let mut velocity = 0.0;
+        // accelerate up at 1 m/s^2 for a second, then climb at 1 m/s
+        for tick in 0..1000 {
+            let acceleration = if tick < 100 { 1.0 } else { 0.0 };
+            velocity += acceleration * DT;
+            altitude += velocity * DT;
+            let (estimated_altitude, estimated_velocity) =
+                step(&mut estimator, altitude, acceleration);
+            if tick > 100 {
+                assert!((estimated_velocity - velocity).abs() < 0.05, "{}", tick);
+                assert!((estimated_altitude - altitude).abs() < 0.05, "{}", tick);
+            }
+        }
+    }
+
+    #[test]
+    fn learns_the_acceleration_bias() {
+        let mut estimator = estimator();
+        // standing still, but the accelerometer reads 0.3 m/s^2 too much
+        for _ in 0..3000 {
+            step(&mut estimator, 0.0, 0.3);
+        }
+        let (altitude, velocity) = step(&mut estimator, 0.0, 0.3);
+        assert!(altitude.abs() < 0.01, "{}", altitude);
+        assert!(velocity.abs() < 0.01, "{}", velocity);
+        assert!((estimator.bias.to_num::<f64>() - 0.3).abs() < 0.01);
+    }
+
+    #[test]
+    fn smooths_barometer_noise() {
+        let mut estimator = estimator();
+        let mut seed = 1;
+        let mut baro_error = 0.0;
+        let mut estimate_error = 0.0;
+        for tick in 0..2000 {
+            let baro = noise(&mut seed, 1.0);
+            let (altitude, _) = step(&mut estimator, baro, 0.0);
+            if tick >= 500 {
+                baro_error += baro * baro;
+                estimate_error += altitude * altitude;
+            }
+        }
+        assert!(estimate_error * 10.0 < baro_error);
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut estimator = estimator();
+        for _ in 0..100 {
+            step(&mut estimator, 5.0, 1.0);
+        }
+        estimator.reset();
+        let (altitude, velocity) = step(&mut estimator, -2.0, 0.0);
+        assert!((altitude + 2.0).abs() < 0.01);
+        assert!(velocity.abs() < 0.01);
+    }
+
+    fn sin_cos(angle: f64) -> (I16F16, I16F16) {
+        (I16F16::from_num(angle.sin()), I16F16::from_num(angle.cos()))
+    }
+
+    fn at_rest(pitch: f64, roll: f64) -> [I16F16; 3] {
+        // gravity in the body frame, the reading of a drone at rest
+        [
+            pitch.sin(),
+            pitch.cos() * roll.sin(),
+            pitch.cos() * roll.cos(),
+        ]
+        .map(I16F16::from_num)
+    }
+
+    #[test]
+    fn gravity_is_removed_in_any_attitude() {
+        for (pitch, roll) in [(0.0, 0.0), (0.5, 0.0), (0.0, -0.5), (0.3, 0.4), (-0.7, 0.2)] {
+            let acceleration =
+                vertical_acceleration(at_rest(pitch, roll), sin_cos(pitch), sin_cos(roll));
+            assert!(acceleration.abs() < 0.01, "{} {}", pitch, roll);
+        }
+    }
+
+    #[test]
+    fn thrust_is_rotated_to_vertical() {
+        // 1.5 g along the body z axis, tilted by 0.4 rad in roll
+        let roll: f64 = 0.4;
+        let accel = [0.0, 0.0, 1.5].map(I16F16::from_num);
+        let acceleration =
+            vertical_acceleration(accel, sin_cos(0.0), sin_cos(roll)).to_num::<f64>();
+        let expected = (1.5 * roll.cos() - 1.0) * GRAVITY;
+        assert!((acceleration - expected).abs() < 0.01);
+    }
+}
@@ -1,21 +1,62 @@
 use core::time::Duration;
 
-use crate::control::kalman::LowPassOne;
+use crate::control::arming::{pre_arm_checks, PreArmState};
+use crate::control::attitude::AttitudeEstimators;
+use crate::control::battery::{BatteryLevel, BatterySupervisor};
+use crate::control::black_box::BlackBox;
+use crate::control::deadline_monitor::DeadlineMonitor;
+use crate::control::event_recorder::EventRecorder;
+use crate::control::link_failsafe::{fault_event, DEFAULT_FAILSAFE_POLICY};
+use crate::control::motor_control::{get_motor_limits, set_motor_limits};
 use crate::control::pid_controller::{map_p_to_fixed, PIDController};
+use crate::control::profiler::Profiler;
+use crate::control::safety_monitor::{
+    fault_event as safety_fault_event, set_parameter as set_safety_parameter,
+};
+use crate::control::signal_filters::{notch_mode, SignalFilters};
 use crate::control::state_machine::{execute_state_function, JoystickControl, StateMachine};
-use crate::storage::Storage;
+use crate::crash::{update_context, Snapshot};
+use crate::storage::config::{config_hash, ConfigStore};
+use crate::storage::crash::{clear_crash_record, read_crash_record};
+use crate::storage::event::EventLog;
+use crate::storage::incident::IncidentLog;
+use crate::storage::session::{session_report, start_record_number, SessionDirectory};
+use crate::storage::{
+    Storage, CONFIG_END_ADDRESS, CONFIG_START_ADDRESS, EVENT_END_ADDRESS, EVENT_START_ADDRESS,
+    INCIDENT_END_ADDRESS, INCIDENT_START_ADDRESS, LOG_END_ADDRESS, LOG_START_ADDRESS,
+    SESSION_END_ADDRESS, SESSION_START_ADDRESS,
+};
+use crate::telemetry::ReportQueue;
 use crate::yaw_pitch_roll::YawPitchRoll;
-use alloc::vec;
 use tudelft_quadrupel::led::Green;
 
 use alloc::vec::Vec;
+use control_math::altitude::{Pressure, PressureAltitude};
+use control_math::config::{default_config, AccelCorrection, CalibrationOffsets, Config, PidGains};
+use control_math::failsafe::{FailsafeAction, LinkFailsafe};
+use control_math::kalman::KalmanNoise;
+use control_math::notch::NotchSettings;
+use control_math::safety::{SafetyAction, SafetyMonitor};
+use control_math::vertical::{
+    vertical_acceleration, VerticalEstimate, VerticalEstimator, VerticalGains,
+};
+use cordic::sin_cos;
 use tudelft_quadrupel::flash::FlashError;
 // use heapless::Vec as HVec;
+use protocol::command::{
+    CommandId, HostCommand, COMMAND_END_FLAG, COMMAND_SIZE, COMMAND_START_FLAG,
+};
 use protocol::format::{DeviceProtocol, HostProtocol};
+use protocol::report::{
+    ArmingEvent, AttitudeReport, AttitudeSource, BlackBoxSample, ConfigEvent, ConfigReport,
+    DeviceReport, FaultCode, FaultEvent, IncidentCause, ProfileStage, ReportKind, SessionEvent,
+    SessionReport, StatusReport, VibrationReport, STATUS_ARMED, STATUS_BATTERY_CRITICAL,
+    STATUS_BATTERY_LOW,
+};
 use tudelft_quadrupel::barometer::read_pressure;
 use tudelft_quadrupel::battery::read_battery;
 use tudelft_quadrupel::block;
-use tudelft_quadrupel::fixed::types::I16F16;
+use tudelft_quadrupel::fixed::types::{I16F16, I32F32};
 use tudelft_quadrupel::fixed::{types, FixedI32};
 use tudelft_quadrupel::led::Led::{Blue, Red};
 use tudelft_quadrupel::led::Yellow;
@@ -27,11 +68,53 @@ use tudelft_quadrupel::uart::{receive_bytes, send_bytes};
 
 use self::pid_controller::{map_p1_to_fixed, map_p2_to_fixed, GeneralController};
 use self::state_machine::State;
+mod arming;
+mod attitude;
+pub(crate) mod battery;
+mod black_box;
+pub(crate) mod calibration;
+mod deadline_monitor;
+mod event_recorder;
 mod kalman;
+mod link_failsafe;
 mod motor_control;
+mod panic_landing;
 mod pid_controller;
+mod profiler;
+pub(crate) mod safety_monitor;
+mod self_test;
+mod signal_filters;
 mod state_machine;
 
+// The profiling report is sent once per second, in between two telemetry messages.
+const PROFILING_REPORT_PERIOD: u32 = 150;
+const PROFILING_REPORT_PHASE: u32 = 75;
+// The status report is sent at 7.5 Hz, in between two telemetry messages.
+const STATUS_REPORT_PERIOD: u32 = 20;
+const STATUS_REPORT_PHASE: u32 = 10;
+// The session directory is listed at 30 Hz, so the report queue never fills up.
+const SESSION_LIST_PERIOD: u32 = 5;
+// The incident samples are sent at 75 Hz, in between the session listing.
+const INCIDENT_REPORT_PERIOD: u32 = 2;
+const INCIDENT_REPORT_PHASE: u32 = 1;
+// The events in flash are sent at 75 Hz as well, in the other ticks.
+const EVENT_REPORT_PERIOD: u32 = 2;
+const EVENT_REPORT_PHASE: u32 = 0;
+// The attitude estimators are reported at 7.5 Hz, in between the telemetry and the status report.
+const ATTITUDE_REPORT_PERIOD: u32 = 20;
+const ATTITUDE_REPORT_PHASE: u32 = 5;
+// The vibration is reported at 7.5 Hz, after the status report.
+const VIBRATION_REPORT_PERIOD: u32 = 20;
+const VIBRATION_REPORT_PHASE: u32 = 15;
+
+// The accelerometer has 16384 raw units per g.
+const ACCEL_PER_G: i32 = 16384;
+// The gyroscope has 16.4 raw units per deg/s, 939.65 per rad/s.
+const GYRO_PER_RAD: f32 = 939.65;
+// The crossover of the vertical estimator in rad/s, slower changes of the height follow the
+// barometer and faster ones the accelerometer.
+const VERTICAL_CROSSOVER: f32 = 1.0;
+
 #[allow(unused_assignments)]
 pub fn control_loop() -> ! {
     // Initialize the variables for the control loop
@@ -47,39 +130,37 @@ pub fn control_loop() -> ! {
     // let mut command_buf: HVec<u8, 1024> = HVec::new();
     let mut command_buf: Vec<u8> = Vec::new();
     let mut mode = 0b0000_0000;
+    let mut profiler = Profiler::new();
+    let mut report_queue = ReportQueue::new();
+    let mut deadline_monitor = DeadlineMonitor::new();
+    let mut saturation_counter = SaturationCounter::new();
+    let mut link_failsafe = LinkFailsafe::new(DEFAULT_FAILSAFE_POLICY);
+    let mut black_box = BlackBox::new();
+    let mut in_panic = false;
+    let mut event_recorder = EventRecorder::new();
+
+    // load the configuration from flash, the compiled-in defaults are used when there is none
+    let mut config_store = ConfigStore::new(CONFIG_START_ADDRESS, CONFIG_END_ADDRESS);
+    let loaded_config = config_store.load().ok().flatten();
+    let config = loaded_config.unwrap_or_else(default_config);
+    if let Some(calibration) = config.calibration {
+        sensor_data_calibration_offset.restore_calibration(calibration);
+        state_machine.operation_ready = true;
+    }
+    sensor_data_calibration_offset.set_accel_correction(config.accel_correction);
+    sensor_data.set_kalman_noise(config.kalman);
+    sensor_data.set_notch_settings(config.notch);
+    set_motor_limits(config.motor_limits);
+    let mut safety_monitor = SafetyMonitor::new(config.safety);
+    state_machine.set_calibration_settings(config.calibration_settings);
+    state_machine.set_panic_landing_ticks(config.panic_landing_ticks);
+    let mut battery_supervisor = BatterySupervisor::new(config.battery);
 
     // initialize the struct for stable controls
-    let yaw_pid = PIDController::new(
-        I16F16::from_num(1),
-        I16F16::from_num(0),
-        I16F16::from_num(0),
-        I16F16::from_num(0),
-        I16F16::from_num(0),
-    );
-    let pitch_pid = PIDController::new(
-        I16F16::from_num(1),
-        I16F16::from_num(0),
-        I16F16::from_num(0),
-        I16F16::from_num(0),
-        I16F16::from_num(0),
-    );
-    let roll_pid = PIDController::new(
-        I16F16::from_num(1),
-        I16F16::from_num(0),
-        I16F16::from_num(0),
-        I16F16::from_num(0),
-        I16F16::from_num(1),
-    );
-    let height_pid = PIDController::new(
-        I16F16::from_num(1.1),
-        I16F16::from_num(0),
-        I16F16::from_num(0),
-        I16F16::from_num(3),
-        I16F16::from_num(5),
-    );
-    let lp = LowPassOne::new();
-    let kf = kalman::KalmanFilter::new(I16F16::from_num(1.1), I16F16::from_num(5000));
-    let raw_control = pid_controller::RawController::new(lp, kf);
+    let yaw_pid = pid_from_gains(config.yaw);
+    let pitch_pid = pid_from_gains(config.pitch);
+    let roll_pid = pid_from_gains(config.roll);
+    let height_pid = pid_from_gains(config.height);
     let yaw_control = pid_controller::YawController::new(yaw_pid);
     let pitch_control = pid_controller::PitchController::new(pitch_pid);
     let roll_control = pid_controller::RollController::new(roll_pid);
@@ -89,26 +170,154 @@ pub fn control_loop() -> ! {
         pitch_control,
         roll_control,
         height_control,
-        raw_control,
     );
+    // continue the logs and the incidents of the previous flights, every storage is recovered even
+    // if another one fails, so none of them writes over its old records
     let mut log_data = LogData::new();
+    let mut incident_log = IncidentLog::new(INCIDENT_START_ADDRESS, INCIDENT_END_ADDRESS);
+    let mut event_log = EventLog::new(EVENT_START_ADDRESS, EVENT_END_ADDRESS);
     Green.on();
-    if log_data.storage.erase_flash().is_ok() {
+    let recovered = [
+        log_data.recover(),
+        incident_log.recover(),
+        event_log.recover(),
+    ];
+    if recovered.iter().all(Result::is_ok) {
         Green.off();
     }
-    let mut flag = false;
+    event_recorder.boot();
+    let event = if loaded_config.is_some() {
+        ConfigEvent::Loaded
+    } else {
+        ConfigEvent::Defaults
+    };
+    report_queue.push(
+        ConfigReport {
+            event,
+            sequence: config_store.get_sequence(),
+        }
+        .to_report(),
+    );
     for i in 0.. {
+        profiler.begin_loop();
         // update the sensor data
-        sensor_data.update_all(&mut sensor_data_calibration_offset, &state_machine);
+        sensor_data.update_all(
+            &mut sensor_data_calibration_offset,
+            &state_machine,
+            &mut profiler,
+        );
+
+        // check if the previous ticks were missed, e.g. due to a blocking flash operation
+        deadline_monitor.update(sensor_data.get_dt());
+        if let Some(event) = deadline_monitor.take_deadline_event(i, mode) {
+            event_recorder.fault(&event);
+            report_queue.push(event.to_report());
+        }
+        if deadline_monitor.is_starved() {
+            let event = deadline_monitor.take_starvation_event(i, mode);
+            event_recorder.fault(&event);
+            report_queue.push(event.to_report());
+            // The motors were stuck at their last value for too long, land the drone.
+            if state_machine.is_flying() {
+                state_machine.transition(
+                    State::Panic,
+                    &mut joystick_control,
+                    &mut general_controllers,
+                    &mut sensor_data_calibration_offset,
+                    &mut sensor_data,
+                );
+                mode = map_to_mode(&state_machine.state());
+            }
+        }
+
+        // stop the motors when the drone has flipped, tumbles or crashed
+        if state_machine.is_flying() {
+            let ypr = sensor_data.get_ypr();
+            let trip = safety_monitor.update(
+                ypr.pitch,
+                ypr.roll,
+                sensor_data.get_gyro_data(),
+                sensor_data.get_accel_data(),
+            );
+            if let Some(trip) = trip {
+                let event = safety_fault_event(trip, i, mode);
+                event_recorder.fault(&event);
+                report_queue.push(event.to_report());
+                match trip.action {
+                    SafetyAction::CutMotors => state_machine.cut_motors(
+                        &mut general_controllers,
+                        &mut sensor_data_calibration_offset,
+                        &mut sensor_data,
+                    ),
+                    SafetyAction::Panic => {
+                        state_machine.transition(
+                            State::Panic,
+                            &mut joystick_control,
+                            &mut general_controllers,
+                            &mut sensor_data_calibration_offset,
+                            &mut sensor_data,
+                        );
+                    }
+                }
+                mode = map_to_mode(&state_machine.state());
+            }
+        } else {
+            safety_monitor.reset();
+        }
+
+        // check the battery, flying is not allowed on a low battery and a critical battery lands the drone
+        battery_supervisor.update(sensor_data.get_bat());
+        state_machine.battery_ready = battery_supervisor.is_ready_to_fly();
+        if battery_supervisor.get_level() == BatteryLevel::Critical && state_machine.is_flying() {
+            let event = FaultEvent {
+                code: FaultCode::BatteryCritical,
+                mode,
+                tick: i,
+                value: battery_supervisor.get_filtered_voltage(),
+            };
+            event_recorder.fault(&event);
+            report_queue.push(event.to_report());
+            state_machine.transition(
+                State::Panic,
+                &mut joystick_control,
+                &mut general_controllers,
+                &mut sensor_data_calibration_offset,
+                &mut sensor_data,
+            );
+            mode = map_to_mode(&state_machine.state());
+        }
 
-        if !flag {
-            Red.on();
-            sensor_data_calibration_offset.update_gyro_offset(sensor_data.get_gyro_data());
-            sensor_data_calibration_offset.update_acc_offset(sensor_data.get_accel_data());
-            flag = true;
+        // panic mode lands the drone on every tick, also when no message is received
+        if state_machine.state() == State::Panic {
+            state_machine.land(
+                &mut general_controllers,
+                &mut sensor_data_calibration_offset,
+                &mut sensor_data,
+                &mut profiler,
+            );
+            mode = map_to_mode(&state_machine.state());
         }
+        // the calibration samples the sensors on every tick, the report is sent at the end of a window
+        if state_machine.state() == State::Calibrate {
+            if let Some(report) =
+                state_machine.run_calibration(&sensor_data, &mut sensor_data_calibration_offset)
+            {
+                report_queue.push(report.to_report());
+            }
+        }
+        // the self test also runs on every tick, the report is sent once it is done
+        if state_machine.state() == State::SelfTest {
+            if let Some(report) =
+                state_machine.run_self_test(&sensor_data, battery_supervisor.get_filtered_voltage())
+            {
+                report_queue.push(report.to_report());
+            }
+        }
+
         // the code below is an algorithm for receiving the message from the host
         // first read 'num' bytes from the uart
+        profiler.begin(ProfileStage::UartRx);
+        let mut received_command = None;
         let num = receive_bytes(&mut buf);
         // command_buf.extend_from_slice(&buf[0..num]).unwrap();
         // push what we read into the command buffer
@@ -117,7 +326,13 @@ pub fn control_loop() -> ! {
         if command_buf.len() >= 12 {
             let temp = command_buf.clone();
             // due to the fact that the length of the message is already long enough, we can check if the first and last byte are correct
-            if temp[0] != 0x7b || temp[11] != 0x7d {
+            if temp[0] == COMMAND_START_FLAG && temp[COMMAND_SIZE - 1] == COMMAND_END_FLAG {
+                // a one-off command has the same length as a message, it is handled after the message
+                let (message, rest) = temp.split_at(COMMAND_SIZE);
+                command_buf.clear();
+                command_buf.extend_from_slice(rest);
+                received_command = HostCommand::format_message(message);
+            } else if temp[0] != 0x7b || temp[11] != 0x7d {
                 // if the first or last byte is not correct, we directly flush out everything in the command buffer
                 Red.on();
                 command_buf.clear();
@@ -134,6 +349,7 @@ pub fn control_loop() -> ! {
                 ack = verify_message(&nice_received_message);
             }
         }
+        profiler.end(ProfileStage::UartRx);
 
         // if the code received by the drone is acknowledged, then we transition to the next state, and execute corresponding function
         if ack == 0b1111_1111 {
@@ -160,16 +376,187 @@ pub fn control_loop() -> ! {
 
             let current_state = state_machine.state();
             mode = map_to_mode(&current_state);
+            if nice_received_message.get_mode() != mode {
+                event_recorder.declined(i, nice_received_message.get_mode(), ack);
+            }
             // Reset time out counter, since message was received successfully.
             safety_counter.reset_command_timeout();
             if transition_result && ack != 0b0000_1111 {
-                execute_state_function(
+                let saturated = execute_state_function(
                     &current_state,
                     &joystick_control,
                     &mut general_controllers,
                     &mut sensor_data,
-                    &sensor_data_calibration_offset,
+                    &mut profiler,
                 );
+                saturation_counter.record(saturated);
+            }
+        }
+
+        // arm or disarm the motors on command, the result is reported to the PC
+        if let Some(command) = received_command {
+            match command.get_id() {
+                CommandId::Arm => {
+                    let pre_arm_state = PreArmState {
+                        safe_mode: state_machine.state() == State::Safety,
+                        calibrated: state_machine.operation_ready,
+                        pitch: sensor_data.get_ypr().pitch,
+                        roll: sensor_data.get_ypr().roll,
+                        throttle_zero: joystick_control.is_throttle_zero(),
+                        battery_ready: battery_supervisor.is_ready_to_fly(),
+                        ticks_since_message: safety_counter.command_timeout,
+                        sensors_healthy: sensor_data.is_healthy(),
+                    };
+                    state_machine.arm(pre_arm_checks(&pre_arm_state));
+                }
+                CommandId::Disarm => state_machine.disarm(
+                    joystick_control.is_throttle_zero(),
+                    &mut general_controllers,
+                    &mut sensor_data_calibration_offset,
+                    &mut sensor_data,
+                ),
+                CommandId::SaveConfig => {
+                    // Writing the flash blocks the loop, and the offsets are only averaged in safe mode.
+                    let saved = state_machine.state() == State::Safety
+                        && config_store
+                            .save(&current_config(
+                                &general_controllers,
+                                &state_machine,
+                                &sensor_data,
+                                &sensor_data_calibration_offset,
+                                &safety_monitor,
+                                &battery_supervisor,
+                            ))
+                            .is_ok();
+                    let event = if saved {
+                        ConfigEvent::Saved
+                    } else {
+                        ConfigEvent::SaveFailed
+                    };
+                    report_queue.push(
+                        ConfigReport {
+                            event,
+                            sequence: config_store.get_sequence(),
+                        }
+                        .to_report(),
+                    );
+                }
+                CommandId::ListSessions => log_data.list_sessions(),
+                CommandId::DownloadSession => {
+                    let number = command.get_session();
+                    let report = match log_data.download_session(number) {
+                        Ok(Some(session)) => session,
+                        _ => session_report(SessionEvent::NotFound, number),
+                    };
+                    report_queue.push(report.to_report());
+                }
+                CommandId::EraseSession => {
+                    let number = command.get_session();
+                    let report = match log_data.erase_session(number) {
+                        Ok(Some(session)) => session,
+                        _ => session_report(SessionEvent::NotFound, number),
+                    };
+                    report_queue.push(report.to_report());
+                }
+                CommandId::EraseAllSessions => {
+                    // Erasing every sector blocks the loop for about a second.
+                    let erased = state_machine.state() == State::Safety
+                        && !state_machine.arming.is_armed()
+                        && log_data.erase_all_sessions().is_ok();
+                    let event = if erased {
+                        SessionEvent::AllErased
+                    } else {
+                        SessionEvent::EraseRefused
+                    };
+                    report_queue.push(session_report(event, 0).to_report());
+                }
+                CommandId::ReadIncidents => incident_log.start_reading(),
+                CommandId::ReadEvents => event_log.start_reading(),
+                CommandId::SetAccelCorrection => {
+                    let (axis, bias, scale) = command.get_accel_correction();
+                    sensor_data_calibration_offset.update_accel_correction(
+                        axis as usize,
+                        bias,
+                        I16F16::from_bits((scale as i32) << 1),
+                    );
+                }
+                CommandId::SetNotchFilter => {
+                    if let Some((tuning, centre, hz_per_command, quality)) =
+                        command.get_notch_filter()
+                    {
+                        sensor_data.set_notch_settings(NotchSettings {
+                            mode: notch_mode(tuning),
+                            centre: I16F16::from_num(centre.min(i16::MAX as u16)),
+                            hz_per_command: I16F16::from_num(hz_per_command)
+                                / I16F16::from_num(1000),
+                            // a quality of 0 would divide by zero
+                            quality: I16F16::from_num(quality.max(1)) / I16F16::from_num(10),
+                        });
+                    }
+                }
+                CommandId::SetParameter => {
+                    // The limits and settings are only changed on the ground, the next save keeps
+                    // them.
+                    if let Some((parameter, value)) = command.get_parameter() {
+                        if state_machine.state() == State::Safety
+                            && !set_safety_parameter(&mut safety_monitor, parameter, value)
+                            && !battery_supervisor.set_parameter(parameter, value)
+                        {
+                            state_machine.set_parameter(parameter, value);
+                        }
+                    }
+                }
+                CommandId::SetAttitudeEstimator => {
+                    let (estimator, parallel) = command.get_attitude_estimator();
+                    sensor_data.select_attitude_estimator(estimator, parallel);
+                }
+                id @ (CommandId::FetchCrashDump | CommandId::ClearCrashDump) => {
+                    // Erasing the sector blocks the loop.
+                    if id == CommandId::ClearCrashDump && state_machine.state() == State::Safety {
+                        let _ = clear_crash_record();
+                    }
+                    let payload = read_crash_record().ok().flatten().unwrap_or_default();
+                    report_queue.push(DeviceReport::new(ReportKind::Crash, payload));
+                }
+            }
+            safety_counter.reset_command_timeout();
+        }
+        state_machine.update_arming(joystick_control.is_throttle_zero());
+        mode = map_to_mode(&state_machine.state());
+        if let Some(report) = state_machine.arming.take_report() {
+            // every arm starts a new session in the logs
+            if report.event == ArmingEvent::Armed {
+                let config_hash = config_hash(&current_config(
+                    &general_controllers,
+                    &state_machine,
+                    &sensor_data,
+                    &sensor_data_calibration_offset,
+                    &safety_monitor,
+                    &battery_supervisor,
+                ));
+                if let Ok(session) = log_data.start_session(i, config_hash) {
+                    report_queue.push(session.to_report());
+                }
+            }
+            event_recorder.arming(i, &report);
+            if report.event == ArmingEvent::SafetyCut {
+                let _ = black_box.trigger(&mut incident_log, IncidentCause::SafetyCut, i);
+            }
+            report_queue.push(report.to_report());
+        }
+        if i % SESSION_LIST_PERIOD == 0 {
+            if let Ok(Some(session)) = log_data.next_listed_session() {
+                report_queue.push_bulk(session.to_report());
+            }
+        }
+        if i % INCIDENT_REPORT_PERIOD == INCIDENT_REPORT_PHASE {
+            if let Ok(Some(incident)) = incident_log.next_report() {
+                report_queue.push_bulk(incident.to_report());
+            }
+        }
+        if i % EVENT_REPORT_PERIOD == EVENT_REPORT_PHASE {
+            if let Ok(Some(event)) = event_log.next_event() {
+                report_queue.push_bulk(event.to_report());
             }
         }
 
@@ -177,12 +564,7 @@ pub fn control_loop() -> ! {
             // 5 Hz
             if mode < 10 {
                 // Create an instance of the Drone Protocol struct
-                let mut pressure: i32 = 0;
-                if sensor_data_calibration_offset.get_sample_count() != 0 {
-                    pressure = I16F16::to_num(sensor_data.height_filter.filter_height);
-                } else {
-                    pressure = sensor_data.get_pres();
-                }
+                let pressure = sensor_data.get_pres();
                 let message_to_host = DeviceProtocol::new(
                     mode,
                     sensor_data.get_dt().as_millis() as u16,
@@ -213,61 +595,238 @@ pub fn control_loop() -> ! {
                 let mut log_message: Vec<u8> = Vec::new();
                 message_to_log.form_message(&mut log_message);
                 Green.on();
+                profiler.begin(ProfileStage::FlashLog);
                 if log_data.save_data(&log_message).is_ok() {
                     Green.off();
                 }
+                profiler.end(ProfileStage::FlashLog);
+                profiler.begin(ProfileStage::UartTx);
                 send_bytes(&message);
+                profiler.end(ProfileStage::UartTx);
             } else {
                 Green.on();
+                profiler.begin(ProfileStage::FlashLog);
                 let data = log_data.load_data();
-                if let Ok(data) = data {
+                profiler.end(ProfileStage::FlashLog);
+                if let Ok(Some(data)) = data {
+                    profiler.begin(ProfileStage::UartTx);
                     send_bytes(&data);
+                    profiler.end(ProfileStage::UartTx);
                     Green.off();
                 }
             }
         }
 
+        if i % STATUS_REPORT_PERIOD == STATUS_REPORT_PHASE {
+            let dropped_reports = report_queue.dropped();
+            report_queue.push_bulk(
+                status_report(
+                    &state_machine,
+                    &battery_supervisor,
+                    &mut saturation_counter,
+                    &sensor_data,
+                    dropped_reports,
+                )
+                .to_report(),
+            );
+        }
+        if i % ATTITUDE_REPORT_PERIOD == ATTITUDE_REPORT_PHASE {
+            report_queue.push_bulk(sensor_data.attitude_report().to_report());
+        }
+        if i % VIBRATION_REPORT_PERIOD == VIBRATION_REPORT_PHASE {
+            report_queue.push_bulk(sensor_data.vibration_report().to_report());
+        }
+        if i % PROFILING_REPORT_PERIOD == PROFILING_REPORT_PHASE {
+            report_queue.push_bulk(profiler.take_report().to_report());
+        }
+        // send the queued reports when there is room in the uart buffer
+        profiler.begin(ProfileStage::UartTx);
+        report_queue.flush();
+        profiler.end(ProfileStage::UartTx);
+
         // safety checks
         safety_counter.increment_command_timeout();
         // Check if the time limit has been reached for no message received.
-        if safety_counter.is_command_timeout() {
-            // Panic because connection timed out.
-            state_machine.transition(
-                State::Panic,
-                &mut joystick_control,
-                &mut general_controllers,
-                &mut sensor_data_calibration_offset,
-                &mut sensor_data,
-            );
-            // Reset the timeout counter, since it's going to go back to safe mode.
-            safety_counter.reset_command_timeout();
+        // A stabilised mode holds the attitude for a while, in case the link comes back. On the
+        // ground a lost link is ignored, the runner may simply not be connected yet.
+        let flying = state_machine.is_flying();
+        let stabilised = flying && state_machine.permissions.pitch_roll_control;
+        let failsafe_action =
+            link_failsafe.update(safety_counter.is_command_timeout(), flying, stabilised);
+        if let Some(event) = link_failsafe.take_event() {
+            let event = fault_event(event, i, mode);
+            if event.code != FaultCode::LinkRestored {
+                let _ = black_box.trigger(&mut incident_log, IncidentCause::Failsafe, i);
+            }
+            event_recorder.fault(&event);
+            report_queue.push(event.to_report());
+        }
+        match failsafe_action {
+            FailsafeAction::None => {}
+            FailsafeAction::Hold => {
+                let saturated = execute_state_function(
+                    &state_machine.state(),
+                    &joystick_control.level_hold(),
+                    &mut general_controllers,
+                    &mut sensor_data,
+                    &mut profiler,
+                );
+                saturation_counter.record(saturated);
+            }
+            // Panic because connection timed out, there is nothing to land on the ground.
+            FailsafeAction::Land if flying => {
+                state_machine.transition(
+                    State::Panic,
+                    &mut joystick_control,
+                    &mut general_controllers,
+                    &mut sensor_data_calibration_offset,
+                    &mut sensor_data,
+                );
+                // Reset the timeout counter, since it's going to go back to safe mode.
+                safety_counter.reset_command_timeout();
+            }
+            FailsafeAction::Land => {}
         }
 
-        // Uncomment this when you are using a battery.
-
-        // // Check if battery level is low, if positive then go to panic state.
-        // if sensor_data.get_bat() < 120 {
-        //     safety_counter.increment_battery_danger();
-        // }
-        // if safety_counter.is_battery_danger() {
-        //     state_machine.transition(
-        //         State::Panic,
-        //         &mut joystick_control,
-        //         &mut general_controllers,
-        //         &mut sensor_data_calibration_offset,
-        //         &mut sensor_data,
-        //     );
-        //     // then end the function
-        //     panic!();
-        // }
+        // keep the samples of this tick, and write them to flash when something went wrong
+        black_box.record(i, black_box_sample(&sensor_data, &joystick_control));
+        let panicking = state_machine.state() == State::Panic;
+        if panicking && !in_panic {
+            let _ = black_box.trigger(&mut incident_log, IncidentCause::Panic, i);
+        }
+        in_panic = panicking;
+        profiler.begin(ProfileStage::FlashLog);
+        let _ = black_box.flush(&mut incident_log);
+        // log the events of this tick, they are sent with the reports of the next tick
+        event_recorder.update(
+            i,
+            map_to_mode(&state_machine.state()),
+            state_machine.operation_ready,
+        );
+        while let Some(event) = event_recorder.take_event() {
+            let _ = event_log.write(&event);
+            report_queue.push(event.to_report());
+        }
+        profiler.end(ProfileStage::FlashLog);
+        // leave the state of this tick for the panic handler
+        update_context(i, mode, &crash_snapshot(&sensor_data, &joystick_control));
         Red.off();
         Blue.off();
         Yellow.off();
+        profiler.end_loop();
         wait_for_next_tick();
     }
     unreachable!();
 }
 
+// The configuration that is in use right now, the calibration is only kept when it is finished.
+fn current_config(
+    general_controllers: &GeneralController,
+    state_machine: &StateMachine,
+    sensor_data: &SensorData,
+    sensor_data_offset: &SensorOffset,
+    safety_monitor: &SafetyMonitor,
+    battery_supervisor: &BatterySupervisor,
+) -> Config {
+    let calibrated = state_machine.operation_ready && sensor_data_offset.get_sample_count() != 0;
+    Config {
+        yaw: gains_from_pid(&general_controllers.yaw_control.pid),
+        pitch: gains_from_pid(&general_controllers.pitch_control.pid),
+        roll: gains_from_pid(&general_controllers.roll_control.pid),
+        height: gains_from_pid(&general_controllers.height_control.pid),
+        kalman: sensor_data.get_kalman_noise(),
+        calibration: if calibrated {
+            Some(sensor_data_offset.get_calibration())
+        } else {
+            None
+        },
+        accel_correction: sensor_data_offset.get_accel_correction(),
+        motor_limits: get_motor_limits(),
+        notch: sensor_data.get_notch_settings(),
+        safety: safety_monitor.get_limits(),
+        calibration_settings: state_machine.get_calibration_settings(),
+        panic_landing_ticks: state_machine.get_panic_landing_ticks(),
+        battery: battery_supervisor.get_levels(),
+    }
+}
+
+// The state of this tick as it is kept for the crash record.
+fn crash_snapshot(sensor_data: &SensorData, joystick_control: &JoystickControl) -> Snapshot {
+    let sample = black_box_sample(sensor_data, joystick_control);
+    Snapshot {
+        attitude: sample.attitude,
+        rates: sample.rates,
+        accel: sensor_data.get_accel_data(),
+        motors: sample.motors,
+        battery: sensor_data.get_bat(),
+    }
+}
+
+// The state of this tick as it is kept by the black box.
+fn black_box_sample(
+    sensor_data: &SensorData,
+    joystick_control: &JoystickControl,
+) -> BlackBoxSample {
+    let ypr = sensor_data.get_ypr();
+    let milliradians = |angle: I16F16| {
+        (angle * I16F16::from_num(1000))
+            .to_num::<i32>()
+            .clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    };
+    BlackBoxSample {
+        attitude: [
+            milliradians(ypr.yaw),
+            milliradians(ypr.pitch),
+            milliradians(ypr.roll),
+        ],
+        rates: sensor_data.get_gyro_data(),
+        motors: sensor_data.get_motors(),
+        setpoints: [
+            joystick_control.get_lift(),
+            joystick_control.get_yaw(),
+            joystick_control.get_pitch(),
+            joystick_control.get_roll(),
+        ],
+    }
+}
+
+fn pid_from_gains(gains: PidGains) -> PIDController {
+    PIDController::new(gains.kp, gains.kp1, gains.kp2, gains.ki, gains.kd)
+}
+
+fn gains_from_pid(pid: &PIDController) -> PidGains {
+    PidGains::new(pid.kp, pid.kp1, pid.kp2, pid.ki, pid.kd)
+}
+
+fn status_report(
+    state_machine: &StateMachine,
+    battery_supervisor: &BatterySupervisor,
+    saturation_counter: &mut SaturationCounter,
+    sensor_data: &SensorData,
+    dropped_reports: u32,
+) -> StatusReport {
+    let estimate = sensor_data.get_vertical_estimate();
+    let mut flags = 0;
+    match battery_supervisor.get_level() {
+        BatteryLevel::Warning => flags |= STATUS_BATTERY_LOW,
+        BatteryLevel::Critical => flags |= STATUS_BATTERY_LOW | STATUS_BATTERY_CRITICAL,
+        BatteryLevel::Ok | BatteryLevel::Absent => {}
+    }
+    if state_machine.arming.is_armed() {
+        flags |= STATUS_ARMED;
+    }
+    StatusReport {
+        flags,
+        battery: battery_supervisor.get_filtered_voltage(),
+        saturated_mixes: saturation_counter.take_window(),
+        saturated_total: saturation_counter.total,
+        // metres to mm
+        altitude: (I32F32::from(estimate.altitude) * 1000).to_num::<i32>(),
+        vertical_velocity: (I32F32::from(estimate.velocity) * 1000).saturating_to_num::<i16>(),
+        dropped_reports,
+    }
+}
+
 fn update_joystick_control_and_controller(
     joystick_control: &mut JoystickControl,
     controller: &mut GeneralController,
@@ -277,15 +836,28 @@ fn update_joystick_control_and_controller(
     joystick_control.set_yaw(nice_received_message.get_yaw());
     joystick_control.set_pitch(nice_received_message.get_pitch());
     joystick_control.set_roll(nice_received_message.get_roll());
-    joystick_control.set_p(map_p_to_fixed(nice_received_message.get_p()));
-    joystick_control.set_p1(map_p1_to_fixed(nice_received_message.get_p1()));
-    joystick_control.set_p2(map_p2_to_fixed(nice_received_message.get_p2()));
-
-    controller.yaw_control.set_kp(joystick_control.get_p());
-    controller.pitch_control.set_kp1(joystick_control.get_p1());
-    controller.pitch_control.set_kp2(joystick_control.get_p2());
-    controller.roll_control.set_kp1(joystick_control.get_p1());
-    controller.roll_control.set_kp2(joystick_control.get_p2());
+    // The gains of the controllers come from the stored config. A P value of the runner is only
+    // applied once the user changes it, the first message just gives the values to compare with.
+    let p = map_p_to_fixed(nice_received_message.get_p());
+    let p1 = map_p1_to_fixed(nice_received_message.get_p1());
+    let p2 = map_p2_to_fixed(nice_received_message.get_p2());
+    if joystick_control.gains_received {
+        if p != joystick_control.get_p() {
+            controller.yaw_control.set_kp(p);
+        }
+        if p1 != joystick_control.get_p1() {
+            controller.pitch_control.set_kp1(p1);
+            controller.roll_control.set_kp1(p1);
+        }
+        if p2 != joystick_control.get_p2() {
+            controller.pitch_control.set_kp2(p2);
+            controller.roll_control.set_kp2(p2);
+        }
+    }
+    joystick_control.set_p(p);
+    joystick_control.set_p1(p1);
+    joystick_control.set_p2(p2);
+    joystick_control.gains_received = true;
 }
 
 /// verify the message received from the host
@@ -322,6 +894,7 @@ fn map_to_state(mode_received: u8) -> State {
         0b0000_0110 => State::Raw,
         0b0000_0111 => State::Height,
         0b0000_1000 => State::Wireless,
+        0b0000_1001 => State::SelfTest,
         0b0000_1010 => State::ReadLogs,
         _ => State::Panic,
     }
@@ -339,21 +912,18 @@ fn map_to_mode(current_state: &State) -> u8 {
         State::Raw => 0b0000_0110,
         State::Height => 0b0000_0111,
         State::Wireless => 0b0000_1000,
+        State::SelfTest => 0b0000_1001,
         State::ReadLogs => 0b0000_1010,
     }
 }
 
 pub struct SafetyCounter {
     pub command_timeout: u64,
-    pub battery_danger: u64,
 }
 
 impl SafetyCounter {
     pub fn new() -> Self {
-        SafetyCounter {
-            command_timeout: 0,
-            battery_danger: 0,
-        }
+        SafetyCounter { command_timeout: 0 }
     }
 
     pub fn reset_command_timeout(&mut self) {
@@ -363,122 +933,37 @@ impl SafetyCounter {
     pub fn increment_command_timeout(&mut self) {
         self.command_timeout += 1;
     }
-    #[allow(dead_code)]
-    pub fn increment_battery_danger(&mut self) {
-        self.battery_danger += 1;
-    }
 
     pub fn is_command_timeout(&self) -> bool {
         self.command_timeout > 100
     }
-    #[allow(dead_code)]
-    pub fn is_battery_danger(&self) -> bool {
-        self.battery_danger > 100
-    }
 }
 
-const BUFFER_SIZE: usize = 20;
-pub struct HeightMovingAverageFilter {
-    pub buffer: [I16F16; BUFFER_SIZE], // buffer to store the last n samples
-    pub index: usize,                  // index to keep track of the oldest sample in the buffer
-    pub sum: I16F16,                   // sum of the last n samples
-    pub count: usize,                  // number of samples in the sum
-    pub filter_height: I16F16,         // filtered height
+// Counts how often the motors saturated, in total and since the last status report.
+pub struct SaturationCounter {
+    pub window: u16,
+    pub total: u32,
 }
 
-impl HeightMovingAverageFilter {
+impl SaturationCounter {
     pub fn new() -> Self {
-        HeightMovingAverageFilter {
-            buffer: [I16F16::from_num(0.0); BUFFER_SIZE],
-            index: 0,
-            sum: I16F16::from_num(0.0),
-            count: 0,
-            filter_height: I16F16::from_num(0.0),
-        }
-    }
-
-    pub fn reset(&mut self) {
-        self.buffer = [I16F16::from_num(0.0); BUFFER_SIZE];
-        self.index = 0;
-        self.sum = I16F16::from_num(0.0);
-        self.count = 0;
-        self.filter_height = I16F16::from_num(0.0);
-    }
-
-    pub fn update(&mut self, value: I16F16) {
-        // subtract the oldest sample from the sum
-        self.sum -= self.buffer[self.index];
-        // add the new sample to the buffer and the sum
-        self.buffer[self.index] = value;
-        self.sum += value;
-        // increment the index and wrap around if necessary
-        self.index = (self.index + 1) % BUFFER_SIZE;
-        // increment the count if it hasn't reached BUFFER_SIZE yet
-        if self.count < BUFFER_SIZE {
-            self.count += 1;
-        }
-        // return the filtered output
-        self.filter_height = self.sum / I16F16::from_num(self.count);
+        SaturationCounter {
+            window: 0,
+            total: 0,
+        }
     }
-}
 
-const BUFFER_SIZE2: usize = 20;
-pub struct YprMovingAverageFilter {
-    pub buffer: [(I16F16, I16F16, I16F16); BUFFER_SIZE2], // buffer to store the last n samples
-    pub index: usize, // index to keep track of the oldest sample in the buffer
-    pub sum: (I16F16, I16F16, I16F16), // sum of the last n samples
-    pub count: usize, // number of samples in the sum
-    pub filter_ypr: [I16F16; 3], // filtered ypr
-}
+    pub fn record(&mut self, saturated: bool) {
+        if saturated {
+            self.window = self.window.saturating_add(1);
+            self.total = self.total.saturating_add(1);
+        }
+    }
 
-impl YprMovingAverageFilter {
-    pub fn new() -> Self {
-        YprMovingAverageFilter {
-            buffer: [(
-                I16F16::from_num(0),
-                I16F16::from_num(0),
-                I16F16::from_num(0),
-            ); BUFFER_SIZE2],
-            index: 0,
-            sum: (
-                I16F16::from_num(0),
-                I16F16::from_num(0),
-                I16F16::from_num(0),
-            ),
-            count: 0,
-            filter_ypr: [
-                I16F16::from_num(0),
-                I16F16::from_num(0),
-                I16F16::from_num(0),
-            ],
-        }
-    }
-
-    pub fn update(&mut self, yaw: I16F16, pitch: I16F16, roll: I16F16) -> [I16F16; 3] {
-        // Update the buffer with the new sample
-        self.sum.0 -= self.buffer[self.index].0;
-        self.sum.1 -= self.buffer[self.index].1;
-        self.sum.2 -= self.buffer[self.index].2;
-        self.buffer[self.index] = (yaw, pitch, roll);
-        self.sum.0 += yaw;
-        self.sum.1 += pitch;
-        self.sum.2 += roll;
-
-        // Increment the count of the number of samples in the buffer
-        if self.count < BUFFER_SIZE2 {
-            self.count += 1;
-        }
-
-        // Compute the moving average of the last n samples
-        self.filter_ypr[0] = self.sum.0 / I16F16::from_num(self.count as i32);
-        self.filter_ypr[1] = self.sum.1 / I16F16::from_num(self.count as i32);
-        self.filter_ypr[2] = self.sum.2 / I16F16::from_num(self.count as i32);
-
-        // Increment the index to keep track of the oldest sample in the buffer
-        self.index = (self.index + 1) % BUFFER_SIZE2;
-
-        // Return the filtered yaw, pitch, and roll values
-        self.filter_ypr
+    pub fn take_window(&mut self) -> u16 {
+        let window = self.window;
+        self.window = 0;
+        window
     }
 }
 
@@ -486,6 +971,7 @@ pub struct SensorData {
     motors: [u16; 4],
     quaternion: Quaternion,
     ypr: YawPitchRoll,
+    // The attitude of the active estimator, the controllers fly on it.
     ypr_filter: YawPitchRoll,
     non_offset_ypr: YawPitchRoll,
     accel: Accel,
@@ -493,11 +979,16 @@ pub struct SensorData {
     bat: u16,
     pres: i32,
     non_offset_pres: i32,
+    // Metres above the pressure of the calibration.
+    altitude: I16F16,
     last: Instant,
     now: Instant,
     dt: Duration,
-    height_filter: HeightMovingAverageFilter,
-    ypr_filtered_moving: YprMovingAverageFilter,
+    vertical: VerticalEstimator,
+    vertical_estimate: VerticalEstimate,
+    // The filter pipelines of the raw sensors and the altitude.
+    filters: SignalFilters,
+    attitude: AttitudeEstimators,
 }
 
 #[allow(dead_code)]
@@ -529,8 +1020,9 @@ impl SensorData {
         let last = Instant::now();
         let now = Instant::now();
         let dt = Duration::from_secs(0);
-        let height_filter = HeightMovingAverageFilter::new();
-        let ypr_filtered_moving = YprMovingAverageFilter::new();
+        let vertical = VerticalEstimator::new(VerticalGains::from_crossover(I32F32::from_num(
+            VERTICAL_CROSSOVER,
+        )));
         SensorData {
             motors,
             quaternion,
@@ -542,11 +1034,14 @@ impl SensorData {
             bat,
             pres,
             non_offset_pres: pres,
+            altitude: zero_i6,
             last,
             now,
             dt,
-            height_filter,
-            ypr_filtered_moving,
+            vertical,
+            vertical_estimate: vertical.estimate(),
+            filters: SignalFilters::new(default_config().notch),
+            attitude: AttitudeEstimators::new(default_config().kalman),
         }
     }
 
@@ -578,12 +1073,10 @@ impl SensorData {
         // self.ypr.roll -= sensor_data_offset.roll_offset;
     }
 
-    pub fn update_ypr_filtered(&mut self, ypr: YawPitchRoll) {
-        self.ypr_filter = ypr;
-    }
-
-    pub fn update_accel_gyro(&mut self) {
+    pub fn update_accel_gyro(&mut self, sensor_data_offset: &SensorOffset) {
         (self.accel, self.gyro) = read_raw().unwrap();
+        let accel = sensor_data_offset.correct_accel([self.accel.x, self.accel.y, self.accel.z]);
+        (self.accel.x, self.accel.y, self.accel.z) = (accel[0], accel[1], accel[2]);
     }
 
     pub fn update_bat(&mut self) {
@@ -596,18 +1089,91 @@ impl SensorData {
         self.pres = self
             .non_offset_pres
             .saturating_sub(sensor_data_offset.lift_offset);
+        self.altitude = self.filters.filter_altitude(
+            sensor_data_offset
+                .pressure_altitude
+                .altitude(Pressure::from_num(self.non_offset_pres)),
+        );
     }
 
-    pub fn update_height_filter(&mut self) {
-        self.height_filter.update(I16F16::from_num(self.pres));
+    // Fuse the barometer altitude with the accelerometer, the reading is rotated with the attitude
+    // without the offsets, as those are the tilt of the ground during calibration.
+    pub fn update_vertical_estimate(&mut self) {
+        let accel = [self.accel.x, self.accel.y, self.accel.z]
+            .map(|value| I16F16::from_num(value) / I16F16::from_num(ACCEL_PER_G));
+        let acceleration = vertical_acceleration(
+            accel,
+            sin_cos(self.non_offset_ypr.pitch),
+            sin_cos(self.non_offset_ypr.roll),
+        );
+        self.vertical_estimate =
+            self.vertical
+                .update(self.altitude, acceleration, self.get_dt_seconds());
     }
 
-    pub fn update_ypr_filtered_moving_filter(&mut self) {
-        self.ypr_filtered_moving.update(
-            self.ypr_filter.yaw,
-            self.ypr_filter.pitch,
-            self.ypr_filter.roll,
-        );
+    // Run the attitude estimators on the sensors without the offsets of the calibration, the
+    // accelerometer z axis keeps gravity to measure the angles from its direction.
+    pub fn update_attitude(&mut self, sensor_data_offset: &SensorOffset, raw_mode: bool) {
+        let gyro = [self.gyro.x, self.gyro.y, self.gyro.z];
+        let gyro: [I16F16; 3] = core::array::from_fn(|axis| {
+            I16F16::from_num(gyro[axis].saturating_sub(sensor_data_offset.gyro_offset[axis] as i16))
+                / I16F16::from_num(GYRO_PER_RAD)
+        });
+        let accel = [
+            self.accel
+                .x
+                .saturating_sub(sensor_data_offset.acc_offset[0] as i16),
+            self.accel
+                .y
+                .saturating_sub(sensor_data_offset.acc_offset[1] as i16),
+            self.accel.z,
+        ]
+        .map(|value| I16F16::from_num(value) / I16F16::from_num(ACCEL_PER_G));
+        // the vibration follows the average motor command
+        let motor_average = self.motors.iter().map(|&motor| motor as u32).sum::<u32>() / 4;
+        self.filters.track_notch(motor_average as u16);
+        let accel = self.filters.filter_accel(accel);
+        let gyro = self.filters.filter_gyro(gyro);
+        self.ypr_filter =
+            self.attitude
+                .update(self.ypr, accel, gyro, self.get_dt_seconds(), raw_mode);
+    }
+
+    pub fn select_attitude_estimator(&mut self, estimator: Option<AttitudeSource>, parallel: bool) {
+        self.attitude.select(estimator, parallel);
+    }
+
+    pub fn attitude_report(&self) -> AttitudeReport {
+        self.attitude.report()
+    }
+
+    pub fn get_notch_settings(&self) -> NotchSettings {
+        self.filters.get_notch()
+    }
+
+    pub fn set_notch_settings(&mut self, notch: NotchSettings) {
+        self.filters.set_notch(notch);
+    }
+
+    pub fn vibration_report(&self) -> VibrationReport {
+        self.filters.vibration_report()
+    }
+
+    pub fn get_kalman_noise(&self) -> KalmanNoise {
+        self.attitude.get_kalman_noise()
+    }
+
+    pub fn set_kalman_noise(&mut self, noise: KalmanNoise) {
+        self.attitude.set_kalman_noise(noise);
+    }
+
+    // Start the filters and the vertical and the attitude estimators over, e.g. when the offsets
+    // change.
+    pub fn reset_estimates(&mut self) {
+        self.filters.reset();
+        self.vertical.reset();
+        self.vertical_estimate = self.vertical.estimate();
+        self.attitude.reset();
     }
 
     pub fn get_dt(&self) -> Duration {
@@ -670,25 +1236,59 @@ impl SensorData {
         self.pres
     }
 
+    // The loop period in seconds.
+    pub fn get_dt_seconds(&self) -> I16F16 {
+        I16F16::from_bits((((self.dt.as_micros() as i64) << 16) / 1_000_000) as i32)
+    }
+
+    pub fn get_altitude(&self) -> I16F16 {
+        self.altitude
+    }
+
+    pub fn get_vertical_estimate(&self) -> VerticalEstimate {
+        self.vertical_estimate
+    }
+
+    // The pressure in Pa, without the calibration offset.
+    pub fn get_raw_pres(&self) -> u32 {
+        self.non_offset_pres as u32
+    }
+
     pub fn update_all(
         &mut self,
         sensor_data_offset: &mut SensorOffset,
         state_machine: &StateMachine,
+        profiler: &mut Profiler,
     ) {
+        profiler.begin(ProfileStage::SensorRead);
         self.update_dt();
         self.update_motors();
+        profiler.end(ProfileStage::SensorRead);
+        profiler.begin(ProfileStage::DmpDecode);
         self.update_quaternion();
         self.update_ypr(sensor_data_offset);
-        self.update_accel_gyro();
+        profiler.end(ProfileStage::DmpDecode);
+        profiler.begin(ProfileStage::SensorRead);
+        self.update_accel_gyro(sensor_data_offset);
         self.update_bat();
         self.update_pres(sensor_data_offset);
+        profiler.end(ProfileStage::SensorRead);
         if sensor_data_offset.get_sample_count() != 0 {
-            self.update_height_filter();
-            if state_machine.state() == State::Raw {
-                self.update_ypr_filtered_moving_filter();
-            }
+            profiler.begin(ProfileStage::Filtering);
+            self.update_vertical_estimate();
+            self.update_attitude(sensor_data_offset, state_machine.state() == State::Raw);
+            profiler.end(ProfileStage::Filtering);
         }
-        // self.update_ypr_filtered_moving_filter();
+    }
+
+    // The MPU delivers data, all zeros means that it is not running.
+    pub fn is_healthy(&self) -> bool {
+        let accel_zero = self.accel.x == 0 && self.accel.y == 0 && self.accel.z == 0;
+        let quaternion_zero = self.quaternion.w == 0
+            && self.quaternion.x == 0
+            && self.quaternion.y == 0
+            && self.quaternion.z == 0;
+        !accel_zero && !quaternion_zero
     }
 
     pub fn resume_non_offset(&mut self) {
@@ -707,9 +1307,14 @@ pub struct SensorOffset {
     pitch_offset: I16F16,
     roll_offset: I16F16,
     lift_offset: i32,
+    // Converts the pressure to the altitude above lift_offset.
+    pressure_altitude: PressureAltitude,
     sample_count: u32,
     gyro_offset: [i64; 3],
     acc_offset: [i64; 3],
+    // The six-position correction is applied to every accelerometer reading, before the offsets.
+    // It is not reset with the offsets.
+    accel_correction: AccelCorrection,
 }
 
 impl SensorOffset {
@@ -719,9 +1324,11 @@ impl SensorOffset {
             pitch_offset: I16F16::from_num(0.0),
             roll_offset: I16F16::from_num(0.0),
             lift_offset: 0,
+            pressure_altitude: PressureAltitude::default(),
             sample_count: 0,
             gyro_offset: [0; 3],
             acc_offset: [0; 3],
+            accel_correction: AccelCorrection::IDENTITY,
         }
     }
 
@@ -730,6 +1337,7 @@ impl SensorOffset {
         self.pitch_offset = I16F16::from_num(0.0);
         self.roll_offset = I16F16::from_num(0.0);
         self.lift_offset = 0;
+        self.pressure_altitude = PressureAltitude::default();
         self.sample_count = 0;
         self.gyro_offset = [0; 3];
         self.acc_offset = [0; 3];
@@ -739,79 +1347,147 @@ impl SensorOffset {
         self.sample_count = 0;
     }
 
-    pub fn update_sample_count(&mut self) {
-        self.sample_count += 1;
-    }
-
     pub fn get_sample_count(&self) -> u32 {
         self.sample_count
     }
 
-    pub fn update_yaw_offset(&mut self, yaw_offset: I16F16) {
-        self.yaw_offset += yaw_offset;
-    }
-
-    pub fn update_pitch_offset(&mut self, pitch_offset: I16F16) {
-        self.pitch_offset += pitch_offset;
+    pub fn get_accel_correction(&self) -> AccelCorrection {
+        self.accel_correction
     }
 
-    pub fn update_roll_offset(&mut self, roll_offset: I16F16) {
-        self.roll_offset += roll_offset;
+    pub fn set_accel_correction(&mut self, accel_correction: AccelCorrection) {
+        self.accel_correction = accel_correction;
     }
 
-    pub fn update_lift_offset(&mut self, lift_offset: i32) {
-        self.lift_offset += lift_offset;
+    // Set the bias and scale of one axis, an unknown axis is ignored.
+    pub fn update_accel_correction(&mut self, axis: usize, bias: i16, scale: I16F16) {
+        if axis < 3 {
+            self.accel_correction.bias[axis] = bias;
+            self.accel_correction.scale[axis] = scale;
+        }
     }
 
-    pub fn update_gyro_offset(&mut self, gyro: [i16; 3]) {
-        self.gyro_offset[0] = gyro[0] as i64;
-        self.gyro_offset[1] = gyro[1] as i64;
-        self.gyro_offset[2] = gyro[2] as i64;
+    // The accelerometer reading with the bias removed and the scale corrected.
+    pub fn correct_accel(&self, accel: [i16; 3]) -> [i16; 3] {
+        let correction = &self.accel_correction;
+        [0, 1, 2].map(|axis| {
+            let value = accel[axis] as i32 - correction.bias[axis] as i32;
+            // I16F16 times an integer, back to an integer
+            ((value as i64 * correction.scale[axis].to_bits() as i64) >> 16)
+                .clamp(i16::MIN as i64, i16::MAX as i64) as i16
+        })
     }
 
-    pub fn update_acc_offset(&mut self, acc: [i16; 3]) {
-        self.acc_offset[0] = acc[0] as i64;
-        self.acc_offset[1] = acc[1] as i64;
-        self.acc_offset[2] = acc[2] as i64;
+    // The averaged offsets, only meaningful once the calibration is finished.
+    pub fn get_calibration(&self) -> CalibrationOffsets {
+        CalibrationOffsets {
+            yaw: self.yaw_offset,
+            pitch: self.pitch_offset,
+            roll: self.roll_offset,
+            lift: self.lift_offset,
+            gyro: self.gyro_offset.map(|offset| offset as i16),
+            accel: self.acc_offset.map(|offset| offset as i16),
+        }
     }
 
-    pub fn calculate_offset(&mut self) {
-        self.yaw_offset /= I16F16::from_num(self.sample_count);
-        self.pitch_offset /= I16F16::from_num(self.sample_count);
-        self.roll_offset /= I16F16::from_num(self.sample_count);
-        self.lift_offset /= self.sample_count as i32;
-        // self.gyro_offset[0] /= self.sample_count as i64;
-        // self.gyro_offset[1] /= self.sample_count as i64;
-        // self.gyro_offset[2] /= self.sample_count as i64;
-        // self.acc_offset[0] /= self.sample_count as i64;
-        // self.acc_offset[1] /= self.sample_count as i64;
-        // self.acc_offset[2] /= self.sample_count as i64;
+    // Use the offsets of a finished calibration, of this boot or loaded from flash.
+    pub fn restore_calibration(&mut self, calibration: CalibrationOffsets) {
+        self.yaw_offset = calibration.yaw;
+        self.pitch_offset = calibration.pitch;
+        self.roll_offset = calibration.roll;
+        self.lift_offset = calibration.lift;
+        self.pressure_altitude = PressureAltitude::new(Pressure::from_num(calibration.lift));
+        self.gyro_offset = calibration.gyro.map(i64::from);
+        self.acc_offset = calibration.accel.map(i64::from);
+        self.sample_count = 1;
     }
 }
 
 pub struct LogData {
     storage: Storage,
+    sessions: SessionDirectory,
+    // The session that is sent in read logs mode, None to send all logs.
+    download: Option<u16>,
 }
 
 impl LogData {
     pub fn new() -> Self {
         LogData {
-            storage: Storage::new(0x000000, 0x01FFFF),
+            storage: Storage::new(LOG_START_ADDRESS, LOG_END_ADDRESS),
+            sessions: SessionDirectory::new(SESSION_START_ADDRESS, SESSION_END_ADDRESS),
+            download: None,
         }
     }
 
+    // Continue the logs and the session directory of the previous boots. The directory is
+    // recovered even if the logs fail, so new entries are not written over the old ones.
+    pub fn recover(&mut self) -> Result<(), FlashError> {
+        let logs = self.storage.recover();
+        let sessions = self.sessions.recover();
+        logs.and(sessions)
+    }
+
     pub fn save_data(&mut self, message: &[u8]) -> Result<(), FlashError> {
-        self.storage.write(message)
+        self.storage.write(message).map(|_| ())
+    }
+
+    pub fn start_session(
+        &mut self,
+        start_tick: u32,
+        config_hash: u16,
+    ) -> Result<SessionReport, FlashError> {
+        self.sessions
+            .start(&mut self.storage, start_tick, config_hash)
     }
 
-    pub fn load_data(&mut self) -> Result<Vec<u8>, FlashError> {
-        let mut message: Vec<u8> = vec![0; 52]; // Pre-allocate the buffer with an arbitrary size
-        match self.storage.read(&mut message) {
-            Ok(_bytes_read) => {
-                // message.truncate(bytes_read);
-                Ok(message)
+    pub fn list_sessions(&mut self) {
+        self.sessions.start_listing();
+    }
+
+    pub fn next_listed_session(&mut self) -> Result<Option<SessionReport>, FlashError> {
+        self.sessions.next_listed(&self.storage)
+    }
+
+    // Send only the logs of this session in read logs mode, None if the session was not found.
+    pub fn download_session(&mut self, number: u16) -> Result<Option<SessionReport>, FlashError> {
+        let Some((session, log_address)) = self.sessions.find(&self.storage, number)? else {
+            return Ok(None);
+        };
+        self.storage.seek(log_address);
+        self.download = Some(number);
+        Ok(Some(SessionReport {
+            event: SessionEvent::Downloading,
+            ..session
+        }))
+    }
+
+    pub fn erase_session(&mut self, number: u16) -> Result<Option<SessionReport>, FlashError> {
+        let erased = self.sessions.erase(&self.storage, number)?;
+        Ok(erased.map(|session| SessionReport {
+            event: SessionEvent::Erased,
+            ..session
+        }))
+    }
+
+    pub fn erase_all_sessions(&mut self) -> Result<(), FlashError> {
+        self.download = None;
+        self.sessions.erase_all(&mut self.storage)
+    }
+
+    // The next logged message from the oldest to the newest, None once everything has been read.
+    // The session start records are skipped, a download ends at the start of the next session.
+    pub fn load_data(&mut self) -> Result<Option<Vec<u8>>, FlashError> {
+        while let Some(record) = self.storage.read()? {
+            match (start_record_number(&record), self.download) {
+                (None, _) => return Ok(Some(record)),
+                (Some(number), Some(download)) if number != download => {
+                    self.storage.seek_end();
+                    self.download = None;
+                    return Ok(None);
+                }
+                _ => {}
             }
-            Err(e) => Err(e),
         }
+        Ok(None)
     }
 }
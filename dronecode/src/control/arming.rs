@@ -0,0 +1,148 @@
+// This file implements arming and disarming of the motors.
+// Before the motors may start, the PC has to send an arm command and all pre-arm checks have to
+// pass. Once armed, the motors idle in safe mode, so it is clear that they can start at any moment.
+// The motors are disarmed again on command, when the throttle has been at zero in safe mode for a
+// while, or when the drone goes into panic. Every change (or refusal) is kept as a report for the PC.
+
+use protocol::report::{
+    ArmingEvent, ArmingReport, PREARM_BATTERY_LOW, PREARM_LINK_UNHEALTHY, PREARM_NOT_CALIBRATED,
+    PREARM_NOT_LEVEL, PREARM_NOT_SAFE_MODE, PREARM_SENSORS_UNHEALTHY, PREARM_THROTTLE_NOT_ZERO,
+};
+use tudelft_quadrupel::fixed::types::I16F16;
+
+// 5 seconds at the control loop frequency of 150 Hz.
+const AUTO_DISARM_TICKS: u32 = 750;
+// The link is healthy when the last message from the PC is at most this many ticks old (100 ms).
+const LINK_TIMEOUT_TICKS: u64 = 15;
+// The largest pitch and roll in radians at which the drone is level (about 10 degrees).
+const LEVEL_LIMIT: f32 = 0.17;
+
+// Everything the pre-arm checks look at, gathered by the control loop.
+pub struct PreArmState {
+    pub safe_mode: bool,
+    pub calibrated: bool,
+    pub pitch: I16F16,
+    pub roll: I16F16,
+    pub throttle_zero: bool,
+    pub battery_ready: bool,
+    pub ticks_since_message: u64,
+    pub sensors_healthy: bool,
+}
+
+// Returns the `PREARM_` bits of all checks that fail, 0 when the motors may be armed.
+pub fn pre_arm_checks(state: &PreArmState) -> u16 {
+    let level_limit = I16F16::from_num(LEVEL_LIMIT);
+    let mut failed_checks = 0;
+    if !state.safe_mode {
+        failed_checks |= PREARM_NOT_SAFE_MODE;
+    }
+    if !state.calibrated {
+        failed_checks |= PREARM_NOT_CALIBRATED;
+    }
+    if state.pitch.abs() > level_limit || state.roll.abs() > level_limit {
+        failed_checks |= PREARM_NOT_LEVEL;
+    }
+    if !state.throttle_zero {
+        failed_checks |= PREARM_THROTTLE_NOT_ZERO;
+    }
+    if !state.battery_ready {
+        failed_checks |= PREARM_BATTERY_LOW;
+    }
+    if state.ticks_since_message > LINK_TIMEOUT_TICKS {
+        failed_checks |= PREARM_LINK_UNHEALTHY;
+    }
+    if !state.sensors_healthy {
+        failed_checks |= PREARM_SENSORS_UNHEALTHY;
+    }
+    failed_checks
+}
+
+#[derive(Clone)]
+pub struct Arming {
+    armed: bool,
+    zero_throttle_ticks: u32,
+    report: Option<ArmingReport>,
+}
+
+impl Arming {
+    pub fn new() -> Self {
+        Arming {
+            armed: false,
+            zero_throttle_ticks: 0,
+            report: None,
+        }
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    // Arm the motors if none of the pre-arm checks failed, otherwise report why arming was refused.
+    pub fn arm(&mut self, failed_checks: u16) {
+        if failed_checks == 0 {
+            self.armed = true;
+            self.zero_throttle_ticks = 0;
+            self.set_report(ArmingEvent::Armed, 0);
+        } else {
+            self.set_report(ArmingEvent::Refused, failed_checks);
+        }
+    }
+
+    // Disarm on command, only with the throttle at zero so the drone cannot drop out of the air.
+    // Returns true when the motors are disarmed.
+    pub fn disarm(&mut self, throttle_zero: bool) -> bool {
+        if !throttle_zero {
+            self.set_report(ArmingEvent::Refused, PREARM_THROTTLE_NOT_ZERO);
+            return false;
+        }
+        self.armed = false;
+        self.set_report(ArmingEvent::Disarmed, 0);
+        true
+    }
+
+    // The drone went into panic, once it has landed it has to be armed again.
+    pub fn disarm_by_panic(&mut self) {
+        if self.armed {
+            self.armed = false;
+            self.set_report(ArmingEvent::PanicDisarmed, 0);
+        }
+    }
+
+    // The safety monitor cut the motors.
+    pub fn disarm_by_safety_cut(&mut self) {
+        self.armed = false;
+        self.set_report(ArmingEvent::SafetyCut, 0);
+    }
+
+    // Count the ticks with the throttle at zero on the ground, returns true when the motors were auto
+    // disarmed. The caller passes false while the drone may be in the air.
+    pub fn update(&mut self, throttle_zero: bool) -> bool {
+        if !self.armed {
+            return false;
+        }
+        if throttle_zero {
+            self.zero_throttle_ticks = self.zero_throttle_ticks.saturating_add(1);
+        } else {
+            self.zero_throttle_ticks = 0;
+        }
+        if self.zero_throttle_ticks < AUTO_DISARM_TICKS {
+            return false;
+        }
+        self.armed = false;
+        self.set_report(ArmingEvent::AutoDisarmed, 0);
+        true
+    }
+
+    // The report of the last change, if it has not been taken yet.
+    pub fn take_report(&mut self) -> Option<ArmingReport> {
+        self.report.take()
+    }
+
+    fn set_report(&mut self, event: ArmingEvent, failed_checks: u16) {
+        self.report = Some(ArmingReport {
+            event,
+            armed: self.armed,
+            failed_checks,
+        });
+    }
+}
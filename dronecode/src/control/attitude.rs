@@ -0,0 +1,204 @@
+// This file implements the selection of the attitude the controllers fly on. Every estimator
+// implements `AttitudeEstimator` and gets the same input each tick: the attitude of the DMP and one
+// filtered sample of the raw sensors. The estimator is selected at runtime with a command, or
+// follows the flight mode as before: the Kalman filter in raw mode and the DMP in the others.
+// Only the active estimator runs, unless all of them run in parallel to be compared in the
+// `AttitudeReport`. An estimator starts over when it becomes active without having run, so it does
+// not continue from an old attitude.
+
+use super::kalman::KalmanFilter;
+use crate::yaw_pitch_roll::YawPitchRoll;
+use control_math::attitude::{Complementary, ImuSample, Mahony};
+use control_math::kalman::KalmanNoise;
+use protocol::report::{AttitudeReport, AttitudeSource, ATTITUDE_BY_MODE, ATTITUDE_SOURCE_COUNT};
+use tudelft_quadrupel::fixed::types::{I16F16, I32F32};
+
+// The share of the gyroscope in every step of the complementary filter, a time constant of 0.3 s
+// at 150 Hz.
+const COMPLEMENTARY_GYRO_WEIGHT: f32 = 0.98;
+// The gains of the correction of the Mahony filter in 1/s and 1/s^2.
+const MAHONY_KP: f32 = 1.0;
+const MAHONY_KI: f32 = 0.05;
+
+pub struct AttitudeInput {
+    // The attitude of the DMP without the calibration offsets.
+    pub dmp: YawPitchRoll,
+    pub sample: ImuSample,
+}
+
+pub trait AttitudeEstimator {
+    // One step with the sensors of this tick, returns the attitude.
+    fn update(&mut self, input: &AttitudeInput) -> YawPitchRoll;
+    // Forget the attitude, the next update starts from the sensors again.
+    fn reset(&mut self);
+}
+
+// The DMP integrates the sensors itself, its attitude is passed on.
+pub struct DmpEstimator;
+
+impl AttitudeEstimator for DmpEstimator {
+    fn update(&mut self, input: &AttitudeInput) -> YawPitchRoll {
+        input.dmp
+    }
+
+    fn reset(&mut self) {}
+}
+
+impl AttitudeEstimator for Complementary {
+    fn update(&mut self, input: &AttitudeInput) -> YawPitchRoll {
+        Complementary::update(self, &input.sample).into()
+    }
+
+    fn reset(&mut self) {
+        Complementary::reset(self);
+    }
+}
+
+impl AttitudeEstimator for KalmanFilter {
+    fn update(&mut self, input: &AttitudeInput) -> YawPitchRoll {
+        self.get_kalman_data(&input.sample)
+    }
+
+    fn reset(&mut self) {
+        KalmanFilter::reset(self);
+    }
+}
+
+impl AttitudeEstimator for Mahony {
+    fn update(&mut self, input: &AttitudeInput) -> YawPitchRoll {
+        Mahony::update(self, &input.sample).into()
+    }
+
+    fn reset(&mut self) {
+        Mahony::reset(self);
+    }
+}
+
+pub struct AttitudeEstimators {
+    dmp: DmpEstimator,
+    complementary: Complementary,
+    kalman: KalmanFilter,
+    mahony: Mahony,
+    // None follows the flight mode.
+    selected: Option<AttitudeSource>,
+    parallel: bool,
+    active: AttitudeSource,
+    // The bits of the estimators that ran in the last tick.
+    running: u8,
+    estimates: [YawPitchRoll; ATTITUDE_SOURCE_COUNT],
+}
+
+impl AttitudeEstimators {
+    pub fn new(noise: KalmanNoise) -> Self {
+        let zero = YawPitchRoll {
+            yaw: I16F16::from_num(0),
+            pitch: I16F16::from_num(0),
+            roll: I16F16::from_num(0),
+        };
+        AttitudeEstimators {
+            dmp: DmpEstimator,
+            complementary: Complementary::new(I16F16::from_num(COMPLEMENTARY_GYRO_WEIGHT)),
+            kalman: KalmanFilter::new(noise),
+            mahony: Mahony::new(I32F32::from_num(MAHONY_KP), I32F32::from_num(MAHONY_KI)),
+            selected: None,
+            parallel: false,
+            active: AttitudeSource::Dmp,
+            running: 0,
+            estimates: [zero; ATTITUDE_SOURCE_COUNT],
+        }
+    }
+
+    pub fn select(&mut self, selected: Option<AttitudeSource>, parallel: bool) {
+        self.selected = selected;
+        self.parallel = parallel;
+    }
+
+    pub fn get_kalman_noise(&self) -> KalmanNoise {
+        self.kalman.get_noise()
+    }
+
+    pub fn set_kalman_noise(&mut self, noise: KalmanNoise) {
+        self.kalman = KalmanFilter::new(noise);
+    }
+
+    // Start all estimators over, e.g. after the calibration changed.
+    pub fn reset(&mut self) {
+        for source in AttitudeSource::ALL {
+            self.estimator(source).reset();
+        }
+        self.running = 0;
+    }
+
+    // One tick with the attitude of the DMP and the filtered sensors, without the offsets of the
+    // calibration except for gravity. Returns the attitude of the active estimator.
+    pub fn update(
+        &mut self,
+        dmp: YawPitchRoll,
+        accel: [I16F16; 3],
+        gyro: [I16F16; 3],
+        dt: I16F16,
+        raw_mode: bool,
+    ) -> YawPitchRoll {
+        self.active = match self.selected {
+            Some(source) => source,
+            None if raw_mode => AttitudeSource::Kalman,
+            None => AttitudeSource::Dmp,
+        };
+        let input = AttitudeInput {
+            dmp,
+            sample: ImuSample { accel, gyro, dt },
+        };
+
+        let active_bit = 1 << self.active.index();
+        if self.running & active_bit == 0 {
+            self.estimator(self.active).reset();
+        }
+        self.running = if self.parallel {
+            (1 << ATTITUDE_SOURCE_COUNT) - 1
+        } else {
+            active_bit
+        };
+        for source in AttitudeSource::ALL {
+            if self.running & (1 << source.index()) != 0 {
+                self.estimates[source.index()] = self.estimator(source).update(&input);
+            }
+        }
+        self.estimates[self.active.index()]
+    }
+
+    pub fn report(&self) -> AttitudeReport {
+        let milliradians = |angle: I16F16| {
+            (angle * I16F16::from_num(1000))
+                .to_num::<i32>()
+                .clamp(i16::MIN as i32, i16::MAX as i32) as i16
+        };
+        let mut estimates = [[0; 3]; ATTITUDE_SOURCE_COUNT];
+        for source in AttitudeSource::ALL {
+            if self.running & (1 << source.index()) != 0 {
+                let estimate = self.estimates[source.index()];
+                estimates[source.index()] = [
+                    milliradians(estimate.yaw),
+                    milliradians(estimate.pitch),
+                    milliradians(estimate.roll),
+                ];
+            }
+        }
+        AttitudeReport {
+            selected: self
+                .selected
+                .map_or(ATTITUDE_BY_MODE, |source| source.index() as u8),
+            active: self.active.index() as u8,
+            running: self.running,
+            estimates,
+        }
+    }
+
+    fn estimator(&mut self, source: AttitudeSource) -> &mut dyn AttitudeEstimator {
+        match source {
+            AttitudeSource::Dmp => &mut self.dmp,
+            AttitudeSource::Complementary => &mut self.complementary,
+            AttitudeSource::Kalman => &mut self.kalman,
+            AttitudeSource::Mahony => &mut self.mahony,
+        }
+    }
+}
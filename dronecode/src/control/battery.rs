@@ -0,0 +1,115 @@
+// This file implements the battery supervisor of the drone.
+// The voltage from `read_battery` (in 10 mV) drops briefly whenever the motors speed up, so it is
+// filtered with an exponential moving average first. The filtered voltage is compared to a warning
+// and a critical level, a level is only left again once the voltage has recovered by the hysteresis.
+// The levels are set by the PC with `SetParameter` commands and kept in the configuration in flash.
+
+use control_math::config::BatteryLevels;
+use protocol::command::Parameter;
+
+// Below this voltage the drone is powered over USB only, the motors cannot spin so nothing is checked.
+const NO_BATTERY_LEVEL: u16 = 500;
+// 2 V, also keeps a level plus the hysteresis within a u16.
+const MAX_HYSTERESIS: u16 = 200;
+// The new sample is weighted by 1 / 2^EMA_SHIFT.
+const EMA_SHIFT: u32 = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BatteryLevel {
+    Absent,
+    Ok,
+    Warning,
+    Critical,
+}
+
+pub struct BatterySupervisor {
+    levels: BatteryLevels,
+    // The filtered voltage, scaled up by 2^EMA_SHIFT to keep the fraction.
+    filtered_scaled: u32,
+    initialized: bool,
+    level: BatteryLevel,
+}
+
+impl BatterySupervisor {
+    pub fn new(levels: BatteryLevels) -> Self {
+        BatterySupervisor {
+            levels,
+            filtered_scaled: 0,
+            initialized: false,
+            level: BatteryLevel::Absent,
+        }
+    }
+
+    pub fn get_levels(&self) -> BatteryLevels {
+        self.levels
+    }
+
+    // Set one of the levels of a `SetParameter` command. Returns false if it is not a battery level
+    // or the value is out of range, the warning level has to stay above the critical level.
+    pub fn set_parameter(&mut self, parameter: Parameter, value: i32) -> bool {
+        let levels = &mut self.levels;
+        match parameter {
+            Parameter::BatteryWarningLevel
+                if value > levels.critical as i32
+                    && value <= (u16::MAX - MAX_HYSTERESIS) as i32 =>
+            {
+                levels.warning = value as u16;
+            }
+            Parameter::BatteryCriticalLevel
+                if (NO_BATTERY_LEVEL as i32..levels.warning as i32).contains(&value) =>
+            {
+                levels.critical = value as u16;
+            }
+            Parameter::BatteryHysteresis if (0..=MAX_HYSTERESIS as i32).contains(&value) => {
+                levels.hysteresis = value as u16;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Filter the new battery reading and update the level, returns the new level.
+    pub fn update(&mut self, battery: u16) -> BatteryLevel {
+        if self.initialized {
+            self.filtered_scaled =
+                self.filtered_scaled - (self.filtered_scaled >> EMA_SHIFT) + battery as u32;
+        } else {
+            self.filtered_scaled = (battery as u32) << EMA_SHIFT;
+            self.initialized = true;
+        }
+        let voltage = self.get_filtered_voltage();
+        let levels = self.levels;
+        self.level = if voltage < NO_BATTERY_LEVEL {
+            BatteryLevel::Absent
+        } else {
+            match self.level {
+                BatteryLevel::Critical if voltage < levels.critical + levels.hysteresis => {
+                    BatteryLevel::Critical
+                }
+                _ if voltage < levels.critical => BatteryLevel::Critical,
+                BatteryLevel::Warning | BatteryLevel::Critical
+                    if voltage < levels.warning + levels.hysteresis =>
+                {
+                    BatteryLevel::Warning
+                }
+                _ if voltage < levels.warning => BatteryLevel::Warning,
+                _ => BatteryLevel::Ok,
+            }
+        };
+        self.level
+    }
+
+    pub fn get_filtered_voltage(&self) -> u16 {
+        (self.filtered_scaled >> EMA_SHIFT) as u16
+    }
+
+    pub fn get_level(&self) -> BatteryLevel {
+        self.level
+    }
+
+    // Flying is refused once the battery has reached the warning level.
+    // Without a battery the motors cannot spin, so the modes can still be tried out over USB.
+    pub fn is_ready_to_fly(&self) -> bool {
+        self.level == BatteryLevel::Ok || self.level == BatteryLevel::Absent
+    }
+}
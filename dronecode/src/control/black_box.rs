@@ -0,0 +1,89 @@
+// This file implements the black box. The flash logs only get a message 5 times per second, which
+// misses what happened right before a crash. The black box keeps the last samples of the attitude,
+// the angular rates, the motors and the setpoints in RAM, and when the drone panics, the link is
+// lost or the motors are cut, the samples are frozen and written to the incident log in flash.
+//
+// With 8 KiB of RAM the buffer is small: 40 samples at 50 Hz, the 0.8 s before the incident. The
+// samples are written a record at a time, one record per tick, so the loop is not blocked for long
+// while the drone is landing.
+
+use alloc::vec::Vec;
+use protocol::report::{BlackBoxSample, IncidentCause};
+use tudelft_quadrupel::flash::FlashError;
+
+use crate::storage::incident::IncidentLog;
+
+// Take a sample every third tick, 50 Hz at the control loop frequency of 150 Hz.
+const SAMPLE_PERIOD: u32 = 3;
+const BLACK_BOX_SAMPLES: usize = 40;
+// 196 bytes per record.
+const SAMPLES_PER_RECORD: usize = 8;
+
+pub struct BlackBox {
+    samples: [BlackBoxSample; BLACK_BOX_SAMPLES],
+    // Where the next sample goes, the oldest sample once the buffer is full.
+    next: usize,
+    count: usize,
+    // The number of the incident that is being written and the number of samples written so far.
+    // No samples are taken until everything has been written.
+    flushing: Option<(u16, usize)>,
+}
+
+impl BlackBox {
+    pub fn new() -> Self {
+        BlackBox {
+            samples: [BlackBoxSample::default(); BLACK_BOX_SAMPLES],
+            next: 0,
+            count: 0,
+            flushing: None,
+        }
+    }
+
+    // Keep the sample of this tick, the oldest sample is overwritten once the buffer is full.
+    pub fn record(&mut self, tick: u32, sample: BlackBoxSample) {
+        if self.flushing.is_some() || !tick.is_multiple_of(SAMPLE_PERIOD) {
+            return;
+        }
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % BLACK_BOX_SAMPLES;
+        self.count = (self.count + 1).min(BLACK_BOX_SAMPLES);
+    }
+
+    // Freeze the samples and start writing them as a new incident. An incident that happens while
+    // the previous one is being written is part of it.
+    pub fn trigger(
+        &mut self,
+        log: &mut IncidentLog,
+        cause: IncidentCause,
+        tick: u32,
+    ) -> Result<(), FlashError> {
+        if self.flushing.is_some() || self.count == 0 {
+            return Ok(());
+        }
+        let number = log.start(cause, tick, self.count as u8, SAMPLE_PERIOD as u8)?;
+        self.flushing = Some((number, 0));
+        Ok(())
+    }
+
+    // Write the next record of the frozen samples, called on every tick.
+    pub fn flush(&mut self, log: &mut IncidentLog) -> Result<(), FlashError> {
+        let Some((number, written)) = self.flushing else {
+            return Ok(());
+        };
+        let oldest = (self.next + BLACK_BOX_SAMPLES - self.count) % BLACK_BOX_SAMPLES;
+        let end = (written + SAMPLES_PER_RECORD).min(self.count);
+        let samples: Vec<BlackBoxSample> = (written..end)
+            .map(|index| self.samples[(oldest + index) % BLACK_BOX_SAMPLES])
+            .collect();
+        // A failed write is not retried, the incident would never finish.
+        let result = log.write_samples(number, written as u8, &samples);
+        if end == self.count {
+            // Start over, the next incident only holds samples taken after this one.
+            self.flushing = None;
+            self.count = 0;
+        } else {
+            self.flushing = Some((number, end));
+        }
+        result
+    }
+}
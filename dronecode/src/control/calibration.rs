@@ -0,0 +1,207 @@
+// This file implements the calibration. While in calibration mode, the gyroscope, the accelerometer,
+// the attitude and the pressure are sampled on every tick for a window of samples. At the end of
+// the window the averages become the offsets, unless the variance of a sensor shows that the drone
+// was moved or the readings were too noisy. A rejected calibration starts over right away, so the
+// drone only has to be held still for a whole window. Once accepted, no more samples are taken.
+// The window and the variance limits are set by the PC with `SetParameter` commands and kept in the
+// configuration in flash.
+
+use protocol::command::Parameter;
+use protocol::report::{
+    CalibrationReport, CALIBRATION_ACCEL, CALIBRATION_ATTITUDE, CALIBRATION_GYRO,
+    CALIBRATION_PRESSURE,
+};
+use tudelft_quadrupel::fixed::types::I16F16;
+
+use control_math::config::{CalibrationOffsets, CalibrationSettings};
+
+// The sum and the sum of squares of every axis. The values are taken relative to the first sample,
+// so the sums stay small and the variance is exact.
+#[derive(Clone, Copy)]
+struct Statistics<const N: usize> {
+    reference: [i64; N],
+    sum: [i64; N],
+    squares: [i64; N],
+}
+
+impl<const N: usize> Statistics<N> {
+    fn new() -> Self {
+        Statistics {
+            reference: [0; N],
+            sum: [0; N],
+            squares: [0; N],
+        }
+    }
+
+    fn add(&mut self, count: u32, sample: [i64; N]) {
+        if count == 0 {
+            self.reference = sample;
+        }
+        for (axis, value) in sample.iter().enumerate() {
+            let value = value - self.reference[axis];
+            self.sum[axis] += value;
+            self.squares[axis] += value * value;
+        }
+    }
+
+    fn mean(&self, count: u32, axis: usize) -> i64 {
+        self.reference[axis] + self.sum[axis] / count as i64
+    }
+
+    // The largest variance of the axes.
+    fn variance(&self, count: u32) -> i64 {
+        let count = count as i64;
+        (0..N)
+            .map(|axis| (self.squares[axis] - self.sum[axis] * self.sum[axis] / count) / count)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Clone)]
+pub struct Calibration {
+    settings: CalibrationSettings,
+    count: u32,
+    accepted: bool,
+    gyro: Statistics<3>,
+    accel: Statistics<3>,
+    // Yaw, pitch and roll as I16F16 bits.
+    attitude: Statistics<3>,
+    pressure: Statistics<1>,
+}
+
+impl Calibration {
+    pub fn new(settings: CalibrationSettings) -> Self {
+        Calibration {
+            settings,
+            count: 0,
+            accepted: false,
+            gyro: Statistics::new(),
+            accel: Statistics::new(),
+            attitude: Statistics::new(),
+            pressure: Statistics::new(),
+        }
+    }
+
+    pub fn get_settings(&self) -> CalibrationSettings {
+        self.settings
+    }
+
+    // The settings are used from the next window on.
+    pub fn set_settings(&mut self, settings: CalibrationSettings) {
+        self.settings = settings;
+    }
+
+    // Set one of the settings of a `SetParameter` command. Returns false if it is not a setting of
+    // the calibration or the value is out of range, the settings are kept as they are then.
+    pub fn set_parameter(&mut self, parameter: Parameter, value: i32) -> bool {
+        let settings = &mut self.settings;
+        match parameter {
+            // The number of samples is reported as a u16.
+            Parameter::CalibrationWindow if (2..=u16::MAX as i32).contains(&value) => {
+                settings.window = value as u32;
+            }
+            Parameter::MaxGyroVariance if value >= 0 => settings.max_gyro_variance = value as u32,
+            Parameter::MaxAccelVariance if value >= 0 => settings.max_accel_variance = value as u32,
+            Parameter::MaxAttitudeVariance if value >= 0 => {
+                settings.max_attitude_variance = value as u32;
+            }
+            Parameter::MaxPressureVariance if value >= 0 => {
+                settings.max_pressure_variance = value as u32;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Start a new window, the samples taken so far are dropped.
+    pub fn start(&mut self) {
+        *self = Calibration::new(self.settings);
+    }
+
+    // Add the readings of this tick, without offsets. Returns the offsets and the report at the end
+    // of the window, the offsets are None when the calibration was rejected.
+    pub fn add_sample(
+        &mut self,
+        gyro: [i16; 3],
+        accel: [i16; 3],
+        attitude: [I16F16; 3],
+        pressure: i32,
+    ) -> Option<(Option<CalibrationOffsets>, CalibrationReport)> {
+        if self.accepted {
+            return None;
+        }
+        self.gyro.add(self.count, gyro.map(i64::from));
+        self.accel.add(self.count, accel.map(i64::from));
+        self.attitude
+            .add(self.count, attitude.map(|angle| angle.to_bits() as i64));
+        self.pressure.add(self.count, [pressure as i64]);
+        self.count += 1;
+        if self.count < self.settings.window {
+            return None;
+        }
+        let (offsets, report) = self.finish();
+        self.start();
+        self.accepted = offsets.is_some();
+        Some((offsets, report))
+    }
+
+    fn finish(&self) -> (Option<CalibrationOffsets>, CalibrationReport) {
+        let count = self.count;
+        let gyro = [0, 1, 2].map(|axis| self.gyro.mean(count, axis) as i16);
+        let accel = [0, 1, 2].map(|axis| self.accel.mean(count, axis) as i16);
+        let attitude =
+            [0, 1, 2].map(|axis| I16F16::from_bits(self.attitude.mean(count, axis) as i32));
+        let pressure = self.pressure.mean(count, 0) as i32;
+
+        let gyro_variance = clamp_variance(self.gyro.variance(count));
+        let accel_variance = clamp_variance(self.accel.variance(count));
+        // From I16F16 bits (1/65536 rad) squared to milliradians squared.
+        let attitude_variance = clamp_variance((self.attitude.variance(count) * 1_000_000) >> 32);
+        let pressure_variance = clamp_variance(self.pressure.variance(count));
+
+        let mut failed_checks = 0;
+        if gyro_variance > self.settings.max_gyro_variance {
+            failed_checks |= CALIBRATION_GYRO;
+        }
+        if accel_variance > self.settings.max_accel_variance {
+            failed_checks |= CALIBRATION_ACCEL;
+        }
+        if attitude_variance > self.settings.max_attitude_variance {
+            failed_checks |= CALIBRATION_ATTITUDE;
+        }
+        if pressure_variance > self.settings.max_pressure_variance {
+            failed_checks |= CALIBRATION_PRESSURE;
+        }
+
+        let report = CalibrationReport {
+            failed_checks,
+            samples: count.min(u16::MAX as u32) as u16,
+            gyro,
+            accel,
+            attitude: attitude.map(|angle| {
+                (angle * I16F16::from_num(1000))
+                    .to_num::<i32>()
+                    .clamp(i16::MIN as i32, i16::MAX as i32) as i16
+            }),
+            pressure,
+            gyro_variance,
+            accel_variance,
+            attitude_variance,
+            pressure_variance,
+        };
+        let offsets = CalibrationOffsets {
+            yaw: attitude[0],
+            pitch: attitude[1],
+            roll: attitude[2],
+            lift: pressure,
+            gyro,
+            accel,
+        };
+        (report.is_accepted().then_some(offsets), report)
+    }
+}
+
+fn clamp_variance(variance: i64) -> u32 {
+    variance.clamp(0, u32::MAX as i64) as u32
+}
@@ -0,0 +1,94 @@
+// This file implements the deadline monitor of the control loop.
+// `wait_for_next_tick` returns immediately when a tick has already passed, so a blocking flash
+// erase or motor ramp does not stop the loop, it silently skips ticks with the motors at their last
+// value. The monitor derives the number of missed ticks from the measured dt of every tick, keeps
+// a starvation budget that is filled by missed ticks and drained by ticks that are on time, and
+// tells the control loop when the drone has been starved for too long and has to land.
+
+use core::time::Duration;
+use protocol::report::{FaultCode, FaultEvent};
+
+use super::profiler::LOOP_BUDGET_US;
+
+// A single tick that takes longer than this starves the loop right away.
+const EXCESSIVE_DT_US: u32 = 200_000;
+// The number of missed ticks, minus the ticks on time since, that starves the loop (~200 ms).
+const STARVATION_LIMIT: u32 = 30;
+// Deadline misses are reported at most this often, the missed ticks in between are summed up.
+const REPORT_INTERVAL_TICKS: u32 = 15;
+
+pub struct DeadlineMonitor {
+    warmed_up: bool,
+    unreported_missed_ticks: u32,
+    ticks_since_report: u32,
+    starvation: u32,
+    starved: bool,
+}
+
+impl DeadlineMonitor {
+    pub fn new() -> Self {
+        DeadlineMonitor {
+            warmed_up: false,
+            unreported_missed_ticks: 0,
+            ticks_since_report: 0,
+            starvation: 0,
+            starved: false,
+        }
+    }
+
+    // Check the dt of the current tick, returns the number of ticks that were missed before it.
+    pub fn update(&mut self, dt: Duration) -> u32 {
+        self.ticks_since_report = self.ticks_since_report.saturating_add(1);
+        // The first dt includes the initialization (e.g. the flash erase), so it is not checked.
+        if !self.warmed_up {
+            self.warmed_up = true;
+            return 0;
+        }
+        let dt_us = dt.as_micros() as u32;
+        // Round to the nearest number of ticks, so jitter of a single tick is not counted as a miss.
+        let missed_ticks = ((dt_us + LOOP_BUDGET_US / 2) / LOOP_BUDGET_US).saturating_sub(1);
+        if missed_ticks == 0 {
+            self.starvation = self.starvation.saturating_sub(1);
+            return 0;
+        }
+        self.unreported_missed_ticks = self.unreported_missed_ticks.saturating_add(missed_ticks);
+        self.starvation = self.starvation.saturating_add(missed_ticks);
+        if dt_us > EXCESSIVE_DT_US || self.starvation > STARVATION_LIMIT {
+            self.starved = true;
+        }
+        missed_ticks
+    }
+
+    pub fn is_starved(&self) -> bool {
+        self.starved
+    }
+
+    // Returns the fault event for the escalation and starts a new starvation budget.
+    pub fn take_starvation_event(&mut self, tick: u32, mode: u8) -> FaultEvent {
+        let event = FaultEvent {
+            code: FaultCode::LoopStarved,
+            mode,
+            tick,
+            value: self.starvation.min(u16::MAX as u32) as u16,
+        };
+        self.starved = false;
+        self.starvation = 0;
+        event
+    }
+
+    // Returns the deadline misses since the last event, if any, at most once per report interval.
+    pub fn take_deadline_event(&mut self, tick: u32, mode: u8) -> Option<FaultEvent> {
+        if self.unreported_missed_ticks == 0 || self.ticks_since_report < REPORT_INTERVAL_TICKS {
+            return None;
+        }
+        let event = FaultEvent {
+            code: FaultCode::DeadlineMiss,
+            mode,
+            tick,
+            value: self.unreported_missed_ticks.min(u16::MAX as u32) as u16,
+        };
+        self.unreported_missed_ticks = 0;
+        self.ticks_since_report = 0;
+        Some(event)
+    }
+}
@@ -0,0 +1,82 @@
+// This file collects the events of the event log. Mode changes are noticed by comparing the mode
+// at the end of every tick, so a transition is logged wherever it came from (the PC, a failsafe, a
+// finished panic landing). The PC sends the mode it wants 100 times per second, so a declined
+// transition is only logged once until the request, the answer or the mode changes.
+
+use alloc::collections::VecDeque;
+use protocol::report::{ArmingReport, EventKind, EventReport, FaultEvent};
+
+// The mode of the calibration, see `map_to_mode`.
+const CALIBRATION_MODE: u8 = 0b0000_0011;
+
+pub struct EventRecorder {
+    mode: u8,
+    // The requested mode and the ack byte of the last declined transition that was logged.
+    declined: Option<(u8, u8)>,
+    pending: VecDeque<EventReport>,
+}
+
+impl EventRecorder {
+    pub fn new() -> Self {
+        EventRecorder {
+            mode: 0,
+            declined: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    // The PC asked for another mode, but it was not allowed.
+    pub fn declined(&mut self, tick: u32, requested: u8, ack: u8) {
+        if self.declined == Some((requested, ack)) {
+            return;
+        }
+        self.declined = Some((requested, ack));
+        self.push(EventKind::Declined, requested, ack, tick);
+    }
+
+    pub fn fault(&mut self, fault: &FaultEvent) {
+        self.push(
+            EventKind::Fault,
+            self.mode,
+            fault.code.to_byte(),
+            fault.tick,
+        );
+    }
+
+    // The drone was powered on, the ticks of the events after it start again from 0.
+    pub fn boot(&mut self) {
+        self.push(EventKind::Boot, 0, 0, 0);
+    }
+
+    pub fn arming(&mut self, tick: u32, report: &ArmingReport) {
+        self.push(EventKind::Arming, self.mode, report.event.to_byte(), tick);
+    }
+
+    // Log the transition if the mode changed during this tick, and the outcome of a calibration.
+    pub fn update(&mut self, tick: u32, mode: u8, calibrated: bool) {
+        if mode == self.mode {
+            return;
+        }
+        self.push(EventKind::Transition, mode, 0, tick);
+        if self.mode == CALIBRATION_MODE {
+            self.push(EventKind::Calibration, mode, calibrated as u8, tick);
+        }
+        self.mode = mode;
+        self.declined = None;
+    }
+
+    // The events that were not logged yet, the oldest first.
+    pub fn take_event(&mut self) -> Option<EventReport> {
+        self.pending.pop_front()
+    }
+
+    fn push(&mut self, kind: EventKind, to: u8, reason: u8, tick: u32) {
+        self.pending.push_back(EventReport {
+            kind,
+            from: self.mode,
+            to,
+            reason,
+            tick,
+        });
+    }
+}
@@ -0,0 +1,24 @@
+// This file implements the failsafe for when the link to the PC is lost, the decision is made by
+// `control_math::failsafe`. Every change is reported as a fault event.
+
+use control_math::failsafe::{FailsafePolicy, LinkEvent};
+use protocol::report::{FaultCode, FaultEvent};
+
+// 2 seconds at the control loop frequency of 150 Hz.
+pub const DEFAULT_FAILSAFE_POLICY: FailsafePolicy =
+    FailsafePolicy::HoldThenDescend { grace_ticks: 300 };
+
+pub fn fault_event(event: LinkEvent, tick: u32, mode: u8) -> FaultEvent {
+    let (code, value) = match event {
+        LinkEvent::Holding { grace_ticks } => (FaultCode::LinkHolding, grace_ticks),
+        // The value is how long the attitude was held, 0 when landing right away.
+        LinkEvent::Lost { held_ticks } => (FaultCode::LinkLost, held_ticks),
+        LinkEvent::Restored { held_ticks } => (FaultCode::LinkRestored, held_ticks),
+    };
+    FaultEvent {
+        code,
+        mode,
+        tick,
+        value: value.min(u16::MAX as u32) as u16,
+    }
+}
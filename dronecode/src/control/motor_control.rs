@@ -1,24 +1,41 @@
+use core::sync::atomic::{AtomicU16, Ordering};
 use tudelft_quadrupel::{
     fixed::types::I16F16,
     motor::{get_motors, set_motor_max, set_motors},
-    time::delay_us_assembly,
 };
 
-pub fn set_motor_speeds_manual(lift: i16, yaw: i16, pitch: i16, roll: i16) {
+use control_math::desaturation::{mix_desaturated, MotorLimits, DEFAULT_MOTOR_LIMITS};
+use control_math::mixer::{FrameType, Mixer, SpinDirection};
+
+// Motor 0 is at the front and spins clockwise, the others follow clockwise: right, back and left.
+const MIXER: Mixer = Mixer::new(FrameType::Plus, SpinDirection::Clockwise);
+// The limits can be changed by the configuration in flash, see `set_motor_limits`.
+static MOTOR_MINIMUM: AtomicU16 = AtomicU16::new(DEFAULT_MOTOR_LIMITS.min);
+static MOTOR_MAXIMUM: AtomicU16 = AtomicU16::new(DEFAULT_MOTOR_LIMITS.max);
+// Yaw mode does not stabilise pitch and roll, so it is kept slower.
+const YAW_MODE_MAXIMUM: u16 = 600;
+
+pub fn set_motor_limits(limits: MotorLimits) {
+    MOTOR_MINIMUM.store(limits.min, Ordering::Relaxed);
+    MOTOR_MAXIMUM.store(limits.max, Ordering::Relaxed);
+}
+
+pub fn get_motor_limits() -> MotorLimits {
+    MotorLimits {
+        min: MOTOR_MINIMUM.load(Ordering::Relaxed),
+        max: MOTOR_MAXIMUM.load(Ordering::Relaxed),
+    }
+}
+
+// The set_motor_speeds functions return true when the motors saturated and a command had to be limited.
+pub fn set_motor_speeds_manual(lift: i16, yaw: i16, pitch: i16, roll: i16) -> bool {
+    set_motor_max(400);
     if lift == 200 {
-        let ae1_safe: u16 = 0;
-        let ae2_safe: u16 = 0;
-        let ae3_safe: u16 = 0;
-        let ae4_safe: u16 = 0;
-        set_motor_max(400);
-        set_motors([ae1_safe, ae2_safe, ae3_safe, ae4_safe]);
+        set_motors_off();
+        false
     } else {
-        let ae1: u16 = (lift - pitch - yaw) as u16;
-        let ae2: u16 = (lift - roll + yaw) as u16;
-        let ae3: u16 = (lift + pitch - yaw) as u16;
-        let ae4: u16 = (lift + roll + yaw) as u16;
-        set_motor_max(400);
-        set_motors([ae1, ae2, ae3, ae4]);
+        let limits = MotorLimits { min: 0, max: 400 };
+        set_mixed_motor_speeds(lift as i32, roll as i32, pitch as i32, yaw as i32, limits)
     }
 }
 
@@ -90,34 +107,30 @@ pub fn determine_lift_compensate(old: I16F16, new: I16F16) -> i16 {
     }
 }
 
-pub fn set_motor_speeds_yaw(lift: i16, yaw: i16, pitch: i16, roll: i16, yaw_compensate: i16) {
+pub fn set_motor_speeds_yaw(
+    lift: i16,
+    yaw: i16,
+    pitch: i16,
+    roll: i16,
+    yaw_compensate: i16,
+) -> bool {
     if lift == 200 {
-        let ae1_safe: u16 = 0;
-        let ae2_safe: u16 = 0;
-        let ae3_safe: u16 = 0;
-        let ae4_safe: u16 = 0;
-        set_motors([ae1_safe, ae2_safe, ae3_safe, ae4_safe]);
+        set_motors_off();
+        false
     } else {
-        let mut ae1: u16 = (lift - pitch - yaw - yaw_compensate) as u16;
-        let mut ae2: u16 = (lift - roll + yaw + yaw_compensate) as u16;
-        let mut ae3: u16 = (lift + pitch - yaw - yaw_compensate) as u16;
-        let mut ae4: u16 = (lift + roll + yaw + yaw_compensate) as u16;
-
-        let motor_minimum = 220;
-        if ae1 < motor_minimum {
-            ae1 = motor_minimum;
-        }
-        if ae2 < motor_minimum {
-            ae2 = motor_minimum;
-        }
-        if ae3 < motor_minimum {
-            ae3 = motor_minimum;
-        }
-        if ae4 < motor_minimum {
-            ae4 = motor_minimum;
-        }
-        set_motor_max(600);
-        set_motors([ae1, ae2, ae3, ae4]);
+        let limits = get_motor_limits();
+        let limits = MotorLimits {
+            min: limits.min,
+            max: limits.max.min(YAW_MODE_MAXIMUM),
+        };
+        set_motor_max(limits.max);
+        set_mixed_motor_speeds(
+            lift as i32,
+            roll as i32,
+            pitch as i32,
+            yaw as i32 + yaw_compensate as i32,
+            limits,
+        )
     }
 }
 
@@ -129,70 +142,53 @@ pub fn set_motor_speeds_full(
     yaw_compensate: i16,
     pitch_compensate: i16,
     roll_compensate: i16,
-) {
+) -> bool {
     if lift == 200 {
-        let ae1_safe: u16 = 0;
-        let ae2_safe: u16 = 0;
-        let ae3_safe: u16 = 0;
-        let ae4_safe: u16 = 0;
-        set_motors([ae1_safe, ae2_safe, ae3_safe, ae4_safe]);
+        set_motors_off();
+        false
     } else {
-        let mut ae1: u16 = (lift - pitch + pitch_compensate - yaw - yaw_compensate) as u16;
-        let mut ae2: u16 = (lift - roll - roll_compensate + yaw + yaw_compensate) as u16;
-        let mut ae3: u16 = (lift + pitch - pitch_compensate - yaw - yaw_compensate) as u16;
-        let mut ae4: u16 = (lift + roll + roll_compensate + yaw + yaw_compensate) as u16;
-
-        let motor_minimum = 220;
-        if ae1 < motor_minimum {
-            ae1 = motor_minimum;
-        }
-        if ae2 < motor_minimum {
-            ae2 = motor_minimum;
-        }
-        if ae3 < motor_minimum {
-            ae3 = motor_minimum;
-        }
-        if ae4 < motor_minimum {
-            ae4 = motor_minimum;
-        }
-        set_motor_max(1000);
-        set_motors([ae1, ae2, ae3, ae4]);
+        let limits = get_motor_limits();
+        set_motor_max(limits.max);
+        set_mixed_motor_speeds(
+            lift as i32,
+            roll as i32 + roll_compensate as i32,
+            pitch as i32 - pitch_compensate as i32,
+            yaw as i32 + yaw_compensate as i32,
+            limits,
+        )
     }
 }
 
-pub fn set_motor_speeds_lift(lift: i16, yaw: i16, pitch: i16, roll: i16, lift_compensate: i16) {
+pub fn set_motor_speeds_lift(
+    lift: i16,
+    yaw: i16,
+    pitch: i16,
+    roll: i16,
+    lift_compensate: i16,
+) -> bool {
     if lift == 200 {
-        let ae1_safe: u16 = 0;
-        let ae2_safe: u16 = 0;
-        let ae3_safe: u16 = 0;
-        let ae4_safe: u16 = 0;
-        set_motors([ae1_safe, ae2_safe, ae3_safe, ae4_safe]);
+        set_motors_off();
+        false
     } else {
-        let lift_temp = lift - lift_compensate;
-
-        let mut ae1: u16 = (lift_temp - pitch - yaw) as u16;
-        let mut ae2: u16 = (lift_temp - roll + yaw) as u16;
-        let mut ae3: u16 = (lift_temp + pitch - yaw) as u16;
-        let mut ae4: u16 = (lift_temp + roll + yaw) as u16;
-
-        let motor_minimum = 220;
-        if ae1 < motor_minimum {
-            ae1 = motor_minimum;
-        }
-        if ae2 < motor_minimum {
-            ae2 = motor_minimum;
-        }
-        if ae3 < motor_minimum {
-            ae3 = motor_minimum;
-        }
-        if ae4 < motor_minimum {
-            ae4 = motor_minimum;
-        }
-        set_motor_max(1000);
-        set_motors([ae1, ae2, ae3, ae4]);
+        let limits = get_motor_limits();
+        set_motor_max(limits.max);
+        set_mixed_motor_speeds(
+            lift as i32 - lift_compensate as i32,
+            roll as i32,
+            pitch as i32,
+            yaw as i32,
+            limits,
+        )
     }
 }
 
+// Mix the axis commands, keep the motors within the limits and set them, returns true when saturated.
+fn set_mixed_motor_speeds(lift: i32, roll: i32, pitch: i32, yaw: i32, limits: MotorLimits) -> bool {
+    let output = mix_desaturated(&MIXER, lift, roll, pitch, yaw, limits);
+    set_motors(output.motors);
+    output.saturated
+}
+
 pub fn map_lift_command_manual(command: u8) -> i16 {
     // the mapping might be wrong, for now, I will assume the lift from the joystick starts at -1, and goes to 1
     if command == 90 {
@@ -690,21 +686,37 @@ pub fn map_lift_command(command: u8) -> I16F16 {
     }
 }
 
-pub fn gradually_slow_down_motors() {
-    let motors_speed = get_motors();
-    let mut motor0 = motors_speed[0];
-    let mut motor1 = motors_speed[1];
-    let mut motor2 = motors_speed[2];
-    let mut motor3 = motors_speed[3];
+// Used while landing in panic mode, there is no minimum speed so the motors can ramp down to zero.
+pub fn set_motor_speeds_landing(
+    lift: i16,
+    yaw_compensate: i16,
+    pitch_compensate: i16,
+    roll_compensate: i16,
+) {
+    set_motor_max(1000);
+    let limits = MotorLimits { min: 0, max: 1000 };
+    // Saturation while landing is expected as the lift goes to zero, so it is not counted.
+    set_mixed_motor_speeds(
+        lift as i32,
+        roll_compensate as i32,
+        -(pitch_compensate as i32),
+        yaw_compensate as i32,
+        limits,
+    );
+}
 
-    while (motor0 > 0) || (motor1 > 0) || (motor2 > 0) || (motor3 > 0) {
-        motor0 = motor0.saturating_sub(1);
-        motor1 = motor1.saturating_sub(1);
-        motor2 = motor2.saturating_sub(1);
-        motor3 = motor3.saturating_sub(1);
+// Set every motor on its own without mixing, used while landing in panic mode without trusted
+// sensors (every motor follows its own ramp) and by the self test.
+pub fn set_motor_speeds_direct(motors: [u16; 4]) {
+    set_motor_max(1000);
+    set_motors(motors);
+}
 
-        delay_us_assembly(5000);
+pub fn set_motors_off() {
+    set_motors([0, 0, 0, 0]);
+}
 
-        set_motors([motor0, motor1, motor2, motor3]);
-    }
+// Armed in safe mode, the motors turn at the lowest speed at which they keep spinning.
+pub fn set_motors_idle() {
+    set_motors([get_motor_limits().min; 4]);
 }
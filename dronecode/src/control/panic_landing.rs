@@ -0,0 +1,79 @@
+// This file implements the landing of the drone in panic mode.
+// Instead of blocking the control loop until the motors are off, the thrust is ramped down a little
+// on every tick, so the sensors are still read, the attitude can still be stabilised and the
+// telemetry keeps flowing while the drone comes down. The length of the ramp is set by the PC with a
+// `SetParameter` command and kept in the configuration in flash.
+
+use tudelft_quadrupel::motor::get_motors;
+
+#[derive(Clone)]
+pub struct PanicLanding {
+    duration_ticks: u32,
+    elapsed_ticks: u32,
+    start_motors: [u16; 4],
+    stabilise: bool,
+}
+
+impl PanicLanding {
+    pub fn new(duration_ticks: u32) -> Self {
+        PanicLanding {
+            duration_ticks,
+            elapsed_ticks: 0,
+            start_motors: [0; 4],
+            stabilise: false,
+        }
+    }
+
+    pub fn get_duration_ticks(&self) -> u32 {
+        self.duration_ticks
+    }
+
+    // At most u16::MAX ticks, so the ramp of a motor speed cannot overflow. Returns false and keeps
+    // the duration if it is out of range.
+    pub fn set_duration_ticks(&mut self, duration_ticks: u32) -> bool {
+        if !(1..=u16::MAX as u32).contains(&duration_ticks) {
+            return false;
+        }
+        self.duration_ticks = duration_ticks;
+        true
+    }
+
+    // Start ramping down from the current motor speeds.
+    // Stabilise should only be true when the sensors are calibrated and can be trusted.
+    pub fn start(&mut self, stabilise: bool) {
+        self.start_motors = get_motors();
+        self.elapsed_ticks = 0;
+        self.stabilise = stabilise;
+    }
+
+    pub fn is_stabilised(&self) -> bool {
+        self.stabilise
+    }
+
+    // The landing is done when the ramp has reached zero, or when the motors were off to begin with.
+    pub fn is_finished(&self) -> bool {
+        self.elapsed_ticks >= self.duration_ticks || self.start_motors == [0; 4]
+    }
+
+    // Go to the next tick of the ramp.
+    pub fn step(&mut self) {
+        self.elapsed_ticks = self.elapsed_ticks.saturating_add(1);
+    }
+
+    // The speed of every motor, ramped down from the speed it had when the landing started.
+    pub fn ramped_motors(&self) -> [u16; 4] {
+        self.start_motors
+            .map(|motor| self.ramp(motor as u32) as u16)
+    }
+
+    // The average speed of the motors, ramped down from the average when the landing started.
+    pub fn ramped_lift(&self) -> i16 {
+        let sum: u32 = self.start_motors.iter().map(|motor| *motor as u32).sum();
+        self.ramp(sum / 4) as i16
+    }
+
+    fn ramp(&self, start: u32) -> u32 {
+        let remaining_ticks = self.duration_ticks.saturating_sub(self.elapsed_ticks);
+        start * remaining_ticks / self.duration_ticks.max(1)
+    }
+}
@@ -2,17 +2,13 @@ use core::u8;
 // use micromath::F32;
 use tudelft_quadrupel::fixed::types::I16F16;
 
-use super::{
-    kalman::{KalmanFilter, LowPassOne},
-    SensorData,
-};
+use super::SensorData;
 
 pub struct GeneralController {
     pub yaw_control: YawController,
     pub pitch_control: PitchController,
     pub roll_control: RollController,
     pub height_control: HeightController,
-    pub raw_control: RawController,
 }
 
 impl GeneralController {
@@ -21,14 +17,12 @@ impl GeneralController {
         pitch_control: PitchController,
         roll_control: RollController,
         height_control: HeightController,
-        raw_control: RawController,
     ) -> GeneralController {
         GeneralController {
             yaw_control,
             pitch_control,
             roll_control,
             height_control,
-            raw_control,
         }
     }
 }
@@ -149,7 +143,7 @@ impl YawController {
     }
 
     pub fn update_phi(&mut self, sensor_data: &SensorData) {
-        self.phi = sensor_data.ypr.yaw;
+        self.phi = sensor_data.ypr_filter.yaw;
     }
 
     pub fn update_prev_phi(&mut self) {
@@ -266,10 +260,6 @@ impl PitchController {
     }
 
     pub fn update_theta(&mut self, sensor_data: &SensorData) {
-        self.theta = sensor_data.ypr.pitch;
-    }
-
-    pub fn update_theta_raw(&mut self, sensor_data: &SensorData) {
         self.theta = sensor_data.ypr_filter.pitch;
     }
 
@@ -322,18 +312,6 @@ impl PitchController {
         self.update_prev_theta();
         self.update_prev_error();
     }
-
-    pub fn go_through_process_raw(&mut self, command: I16F16, sensor_data: &SensorData) {
-        self.update_pitch(command);
-        self.update_theta_raw(sensor_data);
-        self.update_dt(sensor_data);
-        self.error();
-        self.update_proportional1();
-        self.update_proportional2();
-        self.update_new_pitch();
-        self.update_prev_theta();
-        self.update_prev_error();
-    }
 }
 
 pub struct RollController {
@@ -397,10 +375,6 @@ impl RollController {
     }
 
     pub fn update_psi(&mut self, sensor_data: &SensorData) {
-        self.psi = sensor_data.ypr.roll;
-    }
-
-    pub fn update_psi_raw(&mut self, sensor_data: &SensorData) {
         self.psi = sensor_data.ypr_filter.roll;
     }
 
@@ -453,23 +427,12 @@ impl RollController {
         self.update_new_roll();
         self.update_prev_psi();
     }
-
-    pub fn go_through_process_raw(&mut self, command: I16F16, sensor_data: &SensorData) {
-        self.update_roll(command);
-        self.update_psi_raw(sensor_data);
-        self.update_dt(sensor_data);
-        self.error();
-        self.update_proportional1();
-        self.update_prev_error();
-        self.update_proportional2();
-        self.update_new_roll();
-        self.update_prev_psi();
-    }
 }
 
 pub struct HeightController {
     pub pid: PIDController,
-    pub pressure: I16F16,
+    pub altitude: I16F16,
+    pub velocity: I16F16,
     pub target_altitude: I16F16,
     pub proportional: I16F16,
     pub integral: I16F16,
@@ -485,7 +448,8 @@ impl HeightController {
     pub fn new(pid: PIDController) -> HeightController {
         HeightController {
             pid,
-            pressure: I16F16::from_num(0),
+            altitude: I16F16::from_num(0),
+            velocity: I16F16::from_num(0),
             target_altitude: I16F16::from_num(0),
             proportional: I16F16::from_num(0),
             integral: I16F16::from_num(0),
@@ -498,7 +462,8 @@ impl HeightController {
     }
 
     pub fn reset_values(&mut self) {
-        self.pressure = I16F16::from_num(0);
+        self.altitude = I16F16::from_num(0);
+        self.velocity = I16F16::from_num(0);
         self.target_altitude = I16F16::from_num(0);
         self.proportional = I16F16::from_num(0);
         self.integral = I16F16::from_num(0);
@@ -509,22 +474,15 @@ impl HeightController {
         self.new_throttle = I16F16::from_num(0);
     }
 
-    pub fn update_target_altitude(&mut self, target_pressure: I16F16) {
-        // let pressure: u32 = I16F16::to_num(target_pressure);
-        // let mut pressure_temp = F32::from_bits(pressure);
-        // let sea_level = F32::from_bits(101325);
-        // pressure_temp = micromath::F32::powf(pressure_temp/sea_level, micromath::F32(0.1903));
-        // let altitude = (1.0 - pressure_temp) * 44330.8;
-        // let altitude_temp = F32::to_bits(altitude) as i32;
-        // let altitude_fixed = I16F16::from_bits(altitude_temp);
-        // self.target_altitude = altitude_fixed;
-
-        self.target_altitude = target_pressure;
+    // The target in metres above the pressure of the calibration.
+    pub fn update_target_altitude(&mut self, target_altitude: I16F16) {
+        self.target_altitude = target_altitude;
     }
 
-    pub fn update_pressure(&mut self, sensor_data: &SensorData) {
-        // self.pressure = I16F16::from_num(sensor_data.get_pres());
-        self.pressure = sensor_data.height_filter.filter_height;
+    pub fn update_altitude(&mut self, sensor_data: &SensorData) {
+        let estimate = sensor_data.get_vertical_estimate();
+        self.altitude = estimate.altitude;
+        self.velocity = estimate.velocity;
     }
 
     pub fn update_dt(&mut self, sensor_data: &SensorData) {
@@ -532,18 +490,7 @@ impl HeightController {
     }
 
     pub fn error(&mut self) {
-        // let pressure: u32 = I16F16::to_num(self.pressure);
-        // let mut pressure_temp = F32::from_bits(pressure);
-        // let sea_level = F32::from_bits(101325);
-        // pressure_temp = micromath::F32::powf(pressure_temp/sea_level, micromath::F32(0.1903));
-        // let altitude = (1.0 - pressure_temp) * 44330.8;
-        // let altitude_temp = F32::to_bits(altitude) as i32;
-        // let altitude_fixed = I16F16::from_bits(altitude_temp);
-        // self.error = self.target_altitude - altitude_fixed;
-        self.error = self.target_altitude - self.pressure;
-        // if self.error.abs() < I16F16::from_num(5) {
-        //     self.error = I16F16::from_num(0);
-        // }
+        self.error = self.target_altitude - self.altitude;
     }
 
     pub fn update_prev_error(&mut self) {
@@ -558,8 +505,10 @@ impl HeightController {
         self.integral += self.pid.get_ki() * self.error * self.dt;
     }
 
+    // The error changes with the vertical velocity of the estimator, which is far less noisy than
+    // the difference of two altitudes one tick apart.
     pub fn update_derivative(&mut self) {
-        self.derivative = self.pid.get_kd() * (self.error - self.prev_error) / self.dt;
+        self.derivative = -self.pid.get_kd() * self.velocity;
     }
 
     pub fn update_new_throttle(&mut self) {
@@ -567,7 +516,7 @@ impl HeightController {
     }
 
     pub fn go_through_process(&mut self, command: I16F16, sensor_data: &SensorData) {
-        self.update_pressure(sensor_data);
+        self.update_altitude(sensor_data);
         self.update_target_altitude(command);
         self.update_dt(sensor_data);
         self.error();
@@ -607,17 +556,3 @@ pub fn map_p2_to_fixed(p: u8) -> I16F16 {
 
     (max_new - min_new) / (max_old - min_old) * (p_old - min_old) + min_new
 }
-
-pub struct RawController {
-    pub low_pass_filter: LowPassOne,
-    pub kalman_filter: KalmanFilter,
-}
-
-impl RawController {
-    pub fn new(lp: LowPassOne, kf: KalmanFilter) -> Self {
-        Self {
-            low_pass_filter: lp,
-            kalman_filter: kf,
-        }
-    }
-}
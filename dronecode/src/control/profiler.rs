@@ -0,0 +1,129 @@
+// This file implements the execution time profiler of the control loop.
+// Every stage of the loop is timed between `begin` and `end`, a stage may be timed several times in
+// one tick (e.g. filtering happens while reading the sensors and again in raw mode), the time is
+// then summed up for that tick. At the end of every tick the stage times are folded into the
+// statistics, which are sent to the PC as a `ProfilingReport` once per report window.
+// Note that `Instant` has a resolution of about 30 us, so short stages will mostly read 0 or 30 us.
+
+use protocol::report::{ProfileStage, ProfilingReport, StageTiming, PROFILE_STAGE_COUNT};
+use tudelft_quadrupel::time::Instant;
+
+// The time available for one tick of the control loop at 150 Hz.
+pub const LOOP_BUDGET_US: u32 = 1_000_000 / 150;
+
+// The time every stage is allowed to take in a single tick, indexed by `ProfileStage::index`.
+const STAGE_BUDGET_US: [u32; PROFILE_STAGE_COUNT] = [
+    1500, // sensor read
+    2500, // dmp decode
+    500,  // filtering
+    500,  // control
+    200,  // mixing
+    300,  // uart rx
+    500,  // uart tx
+    1500, // flash log
+];
+
+#[derive(Clone, Copy)]
+struct StageStatistics {
+    max_us: u32,
+    total_us: u32,
+    samples: u32,
+    overruns: u16,
+}
+
+impl StageStatistics {
+    fn new() -> Self {
+        StageStatistics {
+            max_us: 0,
+            total_us: 0,
+            samples: 0,
+            overruns: 0,
+        }
+    }
+
+    fn add_sample(&mut self, elapsed_us: u32, budget_us: u32) {
+        self.max_us = self.max_us.max(elapsed_us);
+        self.total_us = self.total_us.saturating_add(elapsed_us);
+        self.samples += 1;
+        if elapsed_us > budget_us {
+            self.overruns = self.overruns.saturating_add(1);
+        }
+    }
+
+    // Summarize the current window and start a new one, the overruns are kept since boot.
+    fn take_timing(&mut self) -> StageTiming {
+        let avg_us = self.total_us.checked_div(self.samples).unwrap_or(0);
+        let timing = StageTiming {
+            avg_us: avg_us.min(u16::MAX as u32) as u16,
+            max_us: self.max_us.min(u16::MAX as u32) as u16,
+            overruns: self.overruns,
+        };
+        self.max_us = 0;
+        self.total_us = 0;
+        self.samples = 0;
+        timing
+    }
+}
+
+pub struct Profiler {
+    stages: [StageStatistics; PROFILE_STAGE_COUNT],
+    whole_loop: StageStatistics,
+    stage_start: [Option<Instant>; PROFILE_STAGE_COUNT],
+    tick_us: [Option<u32>; PROFILE_STAGE_COUNT],
+    loop_start: Instant,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            stages: [StageStatistics::new(); PROFILE_STAGE_COUNT],
+            whole_loop: StageStatistics::new(),
+            stage_start: [None; PROFILE_STAGE_COUNT],
+            tick_us: [None; PROFILE_STAGE_COUNT],
+            loop_start: Instant::now(),
+        }
+    }
+
+    // Call this at the start of every tick, right after waiting for the tick.
+    pub fn begin_loop(&mut self) {
+        self.loop_start = Instant::now();
+    }
+
+    // Call this at the end of every tick, right before waiting for the next tick.
+    pub fn end_loop(&mut self) {
+        for stage in ProfileStage::ALL {
+            let index = stage.index();
+            if let Some(elapsed_us) = self.tick_us[index].take() {
+                self.stages[index].add_sample(elapsed_us, STAGE_BUDGET_US[index]);
+            }
+        }
+        let elapsed_us = elapsed_us_since(self.loop_start);
+        self.whole_loop.add_sample(elapsed_us, LOOP_BUDGET_US);
+    }
+
+    pub fn begin(&mut self, stage: ProfileStage) {
+        self.stage_start[stage.index()] = Some(Instant::now());
+    }
+
+    pub fn end(&mut self, stage: ProfileStage) {
+        let index = stage.index();
+        if let Some(start) = self.stage_start[index].take() {
+            let elapsed_us = elapsed_us_since(start);
+            self.tick_us[index] = Some(self.tick_us[index].unwrap_or(0) + elapsed_us);
+        }
+    }
+
+    // Summarize the statistics of the current window and start a new window.
+    pub fn take_report(&mut self) -> ProfilingReport {
+        let mut report = ProfilingReport::default();
+        for (timing, statistics) in report.stages.iter_mut().zip(self.stages.iter_mut()) {
+            *timing = statistics.take_timing();
+        }
+        report.whole_loop = self.whole_loop.take_timing();
+        report
+    }
+}
+
+fn elapsed_us_since(start: Instant) -> u32 {
+    Instant::now().duration_since(start).as_micros() as u32
+}
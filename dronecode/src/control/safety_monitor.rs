@@ -0,0 +1,50 @@
+// This file connects the safety monitor of `control_math::safety` to the drone.
+// The limits and the actions are set by the PC with `SetParameter` commands and kept in the
+// configuration in flash, a trip is reported to the PC as a fault event.
+
+use control_math::safety::{Hazard, SafetyAction, SafetyMonitor, SafetyTrip};
+use protocol::command::{Parameter, SAFETY_ACTION_CUT_MOTORS, SAFETY_ACTION_PANIC};
+use protocol::report::{FaultCode, FaultEvent};
+use tudelft_quadrupel::fixed::types::I16F16;
+
+// Set one of the limits of a `SetParameter` command. Returns false if it is not a limit of the
+// safety monitor or the value is out of range, the limits are kept as they are then.
+pub fn set_parameter(safety_monitor: &mut SafetyMonitor, parameter: Parameter, value: i32) -> bool {
+    let action = match value {
+        SAFETY_ACTION_CUT_MOTORS => Some(SafetyAction::CutMotors),
+        SAFETY_ACTION_PANIC => Some(SafetyAction::Panic),
+        _ => None,
+    };
+    let mut limits = safety_monitor.get_limits();
+    match (parameter, action) {
+        // Up to pi, in milliradians.
+        (Parameter::MaxTilt, _) if (1..=3142).contains(&value) => {
+            limits.max_tilt = I16F16::from_num(value) / I16F16::from_num(1000);
+        }
+        (Parameter::TiltTicks, _) if value >= 1 => limits.tilt_ticks = value as u32,
+        (Parameter::TiltAction, Some(action)) => limits.tilt_action = action,
+        (Parameter::MaxRate, _) if value >= 1 => limits.max_rate = value,
+        (Parameter::TumbleTicks, _) if value >= 1 => limits.tumble_ticks = value as u32,
+        (Parameter::TumbleAction, Some(action)) => limits.tumble_action = action,
+        (Parameter::ImpactAccel, _) if value >= 1 => limits.impact_accel = value,
+        (Parameter::ImpactTicks, _) if value >= 1 => limits.impact_ticks = value as u32,
+        (Parameter::ImpactAction, Some(action)) => limits.impact_action = action,
+        _ => return false,
+    }
+    safety_monitor.set_limits(limits);
+    true
+}
+
+// The fault event of a trip for the PC.
+pub fn fault_event(trip: SafetyTrip, tick: u32, mode: u8) -> FaultEvent {
+    FaultEvent {
+        code: match trip.hazard {
+            Hazard::ExcessiveTilt => FaultCode::ExcessiveTilt,
+            Hazard::Tumbling => FaultCode::Tumbling,
+            Hazard::Impact => FaultCode::Impact,
+        },
+        mode,
+        tick,
+        value: trip.value.clamp(0, u16::MAX as i32) as u16,
+    }
+}
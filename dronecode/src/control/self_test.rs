@@ -0,0 +1,178 @@
+// This file implements the pre-flight self test.
+// The test runs a little on every tick while in self test mode, so the control loop keeps running
+// and the drone can still panic. First the IMU is sampled with the motors off, the readings must
+// change (not stuck) but not by much (not noisy). Then every motor spins on its own at a low speed,
+// a motor passes when the accelerometer picks up its vibration. At the end the barometer and the
+// battery are checked against a plausible range.
+
+use protocol::report::{
+    SelfTestReport, SELFTEST_ACCEL, SELFTEST_BAROMETER, SELFTEST_BATTERY, SELFTEST_GYRO,
+    SELFTEST_MOTORS,
+};
+
+use control_math::config::DEFAULT_BATTERY_LEVELS;
+
+// 0.5 seconds at the control loop frequency of 150 Hz.
+const REST_TICKS: u32 = 75;
+const MOTOR_TICKS: u32 = 75;
+// The vibration is only sampled once the motor has spun up.
+const SPIN_UP_TICKS: u32 = 25;
+const MOTOR_SPEED: u16 = 300;
+// Peak-to-peak limits in raw units, the accelerometer has 16384 per g, the gyroscope 16.4 per deg/s.
+const ACCEL_NOISE_MAX: u16 = 500;
+const GYRO_NOISE_MAX: u16 = 300;
+// A spinning motor has to shake the drone at least this much more than the noise at rest.
+const MOTOR_VIBRATION_MIN: u16 = 150;
+// The air pressure in Pa, from well above sea level to a deep low.
+const PRESSURE_MIN: u32 = 80_000;
+const PRESSURE_MAX: u32 = 110_000;
+// A fully charged 3S battery is 12.6 V.
+const BATTERY_MAX: u16 = 1300;
+
+// The smallest and largest reading of every axis.
+#[derive(Clone)]
+struct SampleRange {
+    min: [i16; 3],
+    max: [i16; 3],
+    count: u32,
+}
+
+impl SampleRange {
+    fn new() -> Self {
+        SampleRange {
+            min: [i16::MAX; 3],
+            max: [i16::MIN; 3],
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, sample: [i16; 3]) {
+        for (axis, value) in sample.iter().enumerate() {
+            self.min[axis] = self.min[axis].min(*value);
+            self.max[axis] = self.max[axis].max(*value);
+        }
+        self.count += 1;
+    }
+
+    // The largest peak-to-peak value of the axes.
+    fn spread(&self) -> u16 {
+        if self.count == 0 {
+            return 0;
+        }
+        (0..3)
+            .map(|axis| (self.max[axis] as i32 - self.min[axis] as i32) as u16)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Rest,
+    Motor(usize),
+    Done,
+}
+
+#[derive(Clone)]
+pub struct SelfTest {
+    phase: Phase,
+    ticks: u32,
+    accel: SampleRange,
+    gyro: SampleRange,
+    report: SelfTestReport,
+}
+
+impl SelfTest {
+    pub fn new() -> Self {
+        SelfTest {
+            phase: Phase::Done,
+            ticks: 0,
+            accel: SampleRange::new(),
+            gyro: SampleRange::new(),
+            report: SelfTestReport::default(),
+        }
+    }
+
+    pub fn start(&mut self) {
+        *self = SelfTest::new();
+        self.phase = Phase::Rest;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.phase == Phase::Done
+    }
+
+    // Run one tick of the motor and IMU part of the test, returns the speed for every motor.
+    pub fn step(&mut self, accel: [i16; 3], gyro: [i16; 3]) -> [u16; 4] {
+        let mut motors = [0; 4];
+        match self.phase {
+            Phase::Rest => {
+                self.accel.add(accel);
+                self.gyro.add(gyro);
+                self.ticks += 1;
+                if self.ticks >= REST_TICKS {
+                    self.finish_rest();
+                    self.next_phase(Phase::Motor(0));
+                }
+            }
+            Phase::Motor(motor) => {
+                motors[motor] = MOTOR_SPEED;
+                if self.ticks >= SPIN_UP_TICKS {
+                    self.accel.add(accel);
+                }
+                self.ticks += 1;
+                if self.ticks >= MOTOR_TICKS {
+                    self.finish_motor(motor);
+                    self.next_phase(if motor + 1 < motors.len() {
+                        Phase::Motor(motor + 1)
+                    } else {
+                        Phase::Done
+                    });
+                    motors[motor] = 0;
+                }
+            }
+            Phase::Done => {}
+        }
+        motors
+    }
+
+    // Check the barometer and battery and return the report, once the motors have been tested.
+    pub fn finish(&mut self, pressure: u32, battery: u16) -> SelfTestReport {
+        self.report.pressure = pressure;
+        self.report.battery = battery;
+        if !(PRESSURE_MIN..=PRESSURE_MAX).contains(&pressure) {
+            self.report.failed_checks |= SELFTEST_BAROMETER;
+        }
+        if !(DEFAULT_BATTERY_LEVELS.warning..=BATTERY_MAX).contains(&battery) {
+            self.report.failed_checks |= SELFTEST_BATTERY;
+        }
+        self.report
+    }
+
+    fn finish_rest(&mut self) {
+        self.report.accel_noise = self.accel.spread();
+        self.report.gyro_noise = self.gyro.spread();
+        // A real sensor always has some noise, a reading that does not change at all is stuck.
+        if self.report.accel_noise == 0 || self.report.accel_noise > ACCEL_NOISE_MAX {
+            self.report.failed_checks |= SELFTEST_ACCEL;
+        }
+        if self.report.gyro_noise == 0 || self.report.gyro_noise > GYRO_NOISE_MAX {
+            self.report.failed_checks |= SELFTEST_GYRO;
+        }
+    }
+
+    fn finish_motor(&mut self, motor: usize) {
+        let vibration = self.accel.spread();
+        self.report.motor_vibration[motor] = vibration;
+        if vibration < self.report.accel_noise.saturating_add(MOTOR_VIBRATION_MIN) {
+            self.report.failed_checks |= SELFTEST_MOTORS[motor];
+        }
+    }
+
+    fn next_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.ticks = 0;
+        self.accel = SampleRange::new();
+        self.gyro = SampleRange::new();
+    }
+}
@@ -0,0 +1,187 @@
+// This file implements the filter pipelines of the signals in `SensorData`. Every signal goes
+// through a chain of `Filter`s, given by its type and its constructor below, so a stage is added by
+// chaining it into both. The accelerometer and the gyroscope are low-passed per axis and notched
+// against the motor vibration before the attitude estimators, the altitude of the barometer goes
+// through a median against its spikes.
+//
+// The notches of all six axes share one centre, which is fixed or follows the average motor
+// command, see `control_math::notch`. The vibration of every axis is measured before and after its
+// pipeline for the `VibrationReport`, so the effect of the notch can be seen.
+
+use control_math::biquad::{Biquad, BiquadCoefficients, Order};
+use control_math::filter::{Chain, Filter, Median};
+use control_math::notch::{Notch, NotchMode, NotchSettings, SignalEnergy};
+use protocol::report::{NotchTuning, VibrationReport};
+use tudelft_quadrupel::fixed::types::I16F16;
+
+// The control loop runs at 150 Hz.
+const SAMPLE_RATE: f32 = 150.0;
+const LOW_PASS_ORDER: Order = Order::First;
+const LOW_PASS_CUTOFF: f32 = 22.0;
+// The number of altitude samples in the median, 2 ticks of delay.
+const ALTITUDE_MEDIAN: usize = 5;
+
+pub type ImuPipeline = Chain<Biquad, Notch>;
+pub type AltitudePipeline = Median<ALTITUDE_MEDIAN>;
+
+fn imu_pipeline() -> ImuPipeline {
+    Biquad::low_pass(
+        LOW_PASS_ORDER,
+        I16F16::from_num(SAMPLE_RATE),
+        I16F16::from_num(LOW_PASS_CUTOFF),
+    )
+    .chain(Notch::new())
+}
+
+fn altitude_pipeline() -> AltitudePipeline {
+    Median::new()
+}
+
+pub fn notch_tuning(mode: NotchMode) -> NotchTuning {
+    match mode {
+        NotchMode::Off => NotchTuning::Off,
+        NotchMode::Fixed => NotchTuning::Fixed,
+        NotchMode::Tracking => NotchTuning::Tracking,
+    }
+}
+
+pub fn notch_mode(tuning: NotchTuning) -> NotchMode {
+    match tuning {
+        NotchTuning::Off => NotchMode::Off,
+        NotchTuning::Fixed => NotchMode::Fixed,
+        NotchTuning::Tracking => NotchMode::Tracking,
+    }
+}
+
+// The pipelines of the three axes of a sensor, and their vibration before and after.
+struct ImuFilters {
+    pipelines: [ImuPipeline; 3],
+    before: [SignalEnergy; 3],
+    after: [SignalEnergy; 3],
+}
+
+impl ImuFilters {
+    fn new() -> Self {
+        ImuFilters {
+            pipelines: [imu_pipeline(); 3],
+            before: [SignalEnergy::new(); 3],
+            after: [SignalEnergy::new(); 3],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pipelines.iter_mut().for_each(Filter::reset);
+        self.before.iter_mut().for_each(SignalEnergy::reset);
+        self.after.iter_mut().for_each(SignalEnergy::reset);
+    }
+
+    fn tune(&mut self, coefficients: Option<BiquadCoefficients>) {
+        for pipeline in self.pipelines.iter_mut() {
+            pipeline.next_mut().tune(coefficients);
+        }
+    }
+
+    fn update(&mut self, samples: [I16F16; 3]) -> [I16F16; 3] {
+        core::array::from_fn(|axis| {
+            self.before[axis].update(samples[axis]);
+            let output = self.pipelines[axis].update(samples[axis]);
+            self.after[axis].update(output);
+            output
+        })
+    }
+
+    // The RMS before and after of every axis, times the scale and saturated to u16.
+    fn vibration(&self, scale: I16F16) -> [[u16; 2]; 3] {
+        let scaled = |energy: &SignalEnergy| {
+            energy
+                .rms()
+                .saturating_mul(scale)
+                .to_num::<i32>()
+                .clamp(0, u16::MAX as i32) as u16
+        };
+        core::array::from_fn(|axis| [scaled(&self.before[axis]), scaled(&self.after[axis])])
+    }
+}
+
+pub struct SignalFilters {
+    accel: ImuFilters,
+    gyro: ImuFilters,
+    altitude: AltitudePipeline,
+    notch: NotchSettings,
+    // The centre of the notches in the samples, None while they are off.
+    centre: Option<I16F16>,
+    // The notches are tuned again on the next tick, after the settings changed.
+    retune: bool,
+}
+
+impl SignalFilters {
+    pub fn new(notch: NotchSettings) -> Self {
+        SignalFilters {
+            accel: ImuFilters::new(),
+            gyro: ImuFilters::new(),
+            altitude: altitude_pipeline(),
+            notch,
+            centre: None,
+            retune: true,
+        }
+    }
+
+    // Forget the samples, e.g. when the offsets change and the signals jump.
+    pub fn reset(&mut self) {
+        self.accel.reset();
+        self.gyro.reset();
+        self.altitude.reset();
+    }
+
+    pub fn get_notch(&self) -> NotchSettings {
+        self.notch
+    }
+
+    pub fn set_notch(&mut self, notch: NotchSettings) {
+        self.notch = notch;
+        self.retune = true;
+    }
+
+    // Move the notches to the vibration of the average motor command, the coefficients are only
+    // computed again when the centre moved.
+    pub fn track_notch(&mut self, motor_command: u16) {
+        let sample_rate = I16F16::from_num(SAMPLE_RATE);
+        let centre = self.notch.centre(motor_command, sample_rate);
+        if centre == self.centre && !self.retune {
+            return;
+        }
+        let coefficients =
+            centre.map(|centre| BiquadCoefficients::notch(sample_rate, centre, self.notch.quality));
+        self.accel.tune(coefficients);
+        self.gyro.tune(coefficients);
+        self.centre = centre;
+        self.retune = false;
+    }
+
+    // The accelerometer in g.
+    pub fn filter_accel(&mut self, accel: [I16F16; 3]) -> [I16F16; 3] {
+        self.accel.update(accel)
+    }
+
+    // The gyroscope in rad/s.
+    pub fn filter_gyro(&mut self, gyro: [I16F16; 3]) -> [I16F16; 3] {
+        self.gyro.update(gyro)
+    }
+
+    // The altitude in metres.
+    pub fn filter_altitude(&mut self, altitude: I16F16) -> I16F16 {
+        self.altitude.update(altitude)
+    }
+
+    pub fn vibration_report(&self) -> VibrationReport {
+        let thousand = I16F16::from_num(1000);
+        VibrationReport {
+            tuning: notch_tuning(self.notch.mode).index() as u8,
+            centre: self.centre.map_or(0, |centre| {
+                (centre * I16F16::from_num(10)).to_num::<i32>() as u16
+            }),
+            gyro: self.gyro.vibration(thousand),
+            accel: self.accel.vibration(thousand),
+        }
+    }
+}
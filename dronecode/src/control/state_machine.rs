@@ -7,9 +7,19 @@ use tudelft_quadrupel::{
 };
 
 use crate::control::state_machine::State::Safety;
+use control_math::config::{
+    CalibrationSettings, DEFAULT_CALIBRATION_SETTINGS, DEFAULT_PANIC_LANDING_TICKS,
+};
+use control_math::interlock::{Interlock, Refusal};
 use core::clone::Clone;
+use protocol::command::Parameter;
+use protocol::report::{CalibrationReport, ProfileStage, SelfTestReport};
 
-use super::{motor_control::*, pid_controller::GeneralController, SensorData, SensorOffset};
+use super::{
+    arming::Arming, calibration::Calibration, motor_control::*, panic_landing::PanicLanding,
+    pid_controller::GeneralController, profiler::Profiler, self_test::SelfTest, SensorData,
+    SensorOffset,
+};
 
 // Define the possible states of the state machine.
 #[derive(Clone, PartialEq)]
@@ -17,7 +27,7 @@ pub enum State {
     /// Mode 0: Ignore all PC commands, keep motors off.
     Safety,
 
-    /// Mode 1: Moderate RPM to land safely, then enter safe mode.
+    /// Mode 1: Ramp the RPM down over a few seconds to land safely, then enter safe mode.
     Panic,
 
     /// Mode 2: Pass joystick and keyboard commands to drone.
@@ -41,6 +51,9 @@ pub enum State {
     /// Mode 8: Wireless communication with ground station.
     Wireless,
 
+    /// Mode 9: Spin every motor on its own and check the sensors before flight.
+    SelfTest,
+
     // Mode extra: Read logs from the Flash
     ReadLogs,
 }
@@ -51,7 +64,12 @@ pub struct StateMachine {
     state: State,
     pub operation_ready: bool,
     pub controller_ready: bool,
+    pub battery_ready: bool,
     pub permissions: Permissions,
+    pub arming: Arming,
+    panic_landing: PanicLanding,
+    self_test: SelfTest,
+    calibration: Calibration,
     // Add more fields here if needed such as data to be stored in the state machine.
 }
 
@@ -79,6 +97,8 @@ pub struct JoystickControl {
     pub p: I16F16,
     pub p1: I16F16,
     pub p2: I16F16,
+    // False until the first message, whose P values are the ones the runner starts with.
+    pub gains_received: bool,
 }
 
 impl JoystickControl {
@@ -91,6 +111,7 @@ impl JoystickControl {
             p: I16F16::from_num(50),
             p1: I16F16::from_num(50),
             p2: I16F16::from_num(50),
+            gains_received: false,
         }
     }
 
@@ -150,6 +171,20 @@ impl JoystickControl {
         self.p2
     }
 
+    // The same lift with yaw, pitch and roll in the middle, to hold the attitude without the PC.
+    pub fn level_hold(&self) -> JoystickControl {
+        let mut hold = self.clone();
+        hold.set_yaw(50);
+        hold.set_pitch(50);
+        hold.set_roll(50);
+        hold
+    }
+
+    // The lift stick is all the way down, the motors are off in every mode.
+    pub fn is_throttle_zero(&self) -> bool {
+        self.get_lift() >= 90
+    }
+
     // Check if lift, yaw, pitch and roll are all neutral on the controller.
     pub fn joystick_neutral_check(&mut self, state_machine: &mut StateMachine) {
         let flag = (self.get_lift() <= 90 && self.get_lift() >= 75)
@@ -172,6 +207,7 @@ impl StateMachine {
             state: State::Safety,
             operation_ready: false,
             controller_ready: false,
+            battery_ready: false,
             permissions: Permissions {
                 controller: false,
                 calibration: false,
@@ -181,6 +217,10 @@ impl StateMachine {
                 wireless: false,
                 sensors: false,
             },
+            arming: Arming::new(),
+            panic_landing: PanicLanding::new(DEFAULT_PANIC_LANDING_TICKS),
+            self_test: SelfTest::new(),
+            calibration: Calibration::new(DEFAULT_CALIBRATION_SETTINGS),
         }
     }
 
@@ -189,6 +229,73 @@ impl StateMachine {
         self.state.clone()
     }
 
+    // Check if the state machine is in one of the modes in which the motors are driven by the pilot.
+    pub fn is_flying(&self) -> bool {
+        matches!(
+            self.state,
+            State::Manual | State::Yaw | State::Full | State::Raw | State::Height | State::Wireless
+        )
+    }
+
+    pub fn get_calibration_settings(&self) -> CalibrationSettings {
+        self.calibration.get_settings()
+    }
+
+    pub fn set_calibration_settings(&mut self, settings: CalibrationSettings) {
+        self.calibration.set_settings(settings);
+    }
+
+    pub fn get_panic_landing_ticks(&self) -> u32 {
+        self.panic_landing.get_duration_ticks()
+    }
+
+    pub fn set_panic_landing_ticks(&mut self, ticks: u32) -> bool {
+        self.panic_landing.set_duration_ticks(ticks)
+    }
+
+    // Set one of the settings of the modes of a `SetParameter` command, returns false if it is not
+    // one of them or the value is out of range.
+    pub fn set_parameter(&mut self, parameter: Parameter, value: i32) -> bool {
+        match parameter {
+            Parameter::PanicLandingTicks if value > 0 => self.set_panic_landing_ticks(value as u32),
+            _ => self.calibration.set_parameter(parameter, value),
+        }
+    }
+
+    // Arm the motors if none of the pre-arm checks failed, the result is kept in `arming` for the PC.
+    pub fn arm(&mut self, failed_checks: u16) {
+        self.arming.arm(failed_checks);
+    }
+
+    // Disarm the motors on command. The drone may still be in the air in a flying mode, so the
+    // motors are not cut there but ramped down by the panic landing.
+    pub fn disarm(
+        &mut self,
+        throttle_zero: bool,
+        general_controllers: &mut GeneralController,
+        sensor_data_offset: &mut SensorOffset,
+        sensor_data: &mut SensorData,
+    ) {
+        if self.arming.disarm(throttle_zero) && self.is_flying() {
+            self.transition_panic(general_controllers, sensor_data_offset, sensor_data);
+        }
+    }
+
+    // Called on every tick: disarms after inactivity and lets the motors idle while armed in safe mode.
+    // Only the idling motors in safe mode are on the ground for sure, so only there the ticks with the
+    // throttle at zero are counted.
+    pub fn update_arming(&mut self, throttle_zero: bool) {
+        if self
+            .arming
+            .update(throttle_zero && self.state == State::Safety)
+        {
+            set_motors_off();
+        }
+        if self.arming.is_armed() && self.state == State::Safety {
+            set_motors_idle();
+        }
+    }
+
     // Transition the state machine to a new state.
     // Returns true for a proper transition and false for an illegal transition.
     // This value can then be communicated back to the PC.
@@ -201,6 +308,10 @@ impl StateMachine {
         sensor_data: &mut SensorData,
     ) -> (bool, u8) {
         joystick.joystick_neutral_check(self);
+        // The drone has to land before anything else is allowed, the PC is told to stay in panic.
+        if self.state() == State::Panic && next_state != State::Panic {
+            return (false, 0b0000_1111);
+        }
         if self.state() != next_state {
             match next_state {
                 State::Safety => {
@@ -211,7 +322,7 @@ impl StateMachine {
                         && self.state() != State::Height
                         && self.state() != State::Wireless
                     {
-                        self.transition_safe(false, sensor_data_offset)
+                        self.transition_safe(false)
                     } else {
                         (false, 0b0000_0001)
                     }
@@ -222,6 +333,7 @@ impl StateMachine {
                 State::Manual => self.transition_manual(),
                 State::Calibrate => self.transition_calibrate(sensor_data_offset, sensor_data),
                 State::ReadLogs => self.transition_read_logs(),
+                State::SelfTest => self.transition_self_test(),
                 State::Yaw | State::Full | State::Raw | State::Height | State::Wireless => {
                     self.transition_operation(next_state)
                 } // | State::Manual => self.transition_operation(next_state, joystick),
@@ -242,15 +354,8 @@ impl StateMachine {
     }
 
     // Safe mode should do nothing so everything is false.
-    fn transition_safe(
-        &mut self,
-        through_panic: bool,
-        sensor_data_offset: &mut SensorOffset,
-    ) -> (bool, u8) {
+    fn transition_safe(&mut self, through_panic: bool) -> (bool, u8) {
         self.state = State::Safety;
-        if sensor_data_offset.get_sample_count() > 0 {
-            sensor_data_offset.calculate_offset();
-        }
         self.permissions.controller = false;
         self.permissions.calibration = false;
         self.permissions.yaw_control = false;
@@ -266,62 +371,128 @@ impl StateMachine {
         }
     }
 
-    // Panic mode should also do nothing from the controller, might need the sensors (for now false).
+    // Panic mode ignores the controller, the motors are ramped down on every tick by `land`.
+    // The attitude is kept level while landing if the sensors were calibrated and in use.
     fn transition_panic(
         &mut self,
         general_controllers: &mut GeneralController,
         sensor_data_offset: &mut SensorOffset,
         sensor_data: &mut SensorData,
     ) -> (bool, u8) {
+        let stabilise = self.operation_ready && self.permissions.sensors;
+        self.arming.disarm_by_panic();
         self.state = State::Panic;
         Blue.on();
         self.permissions.controller = false;
         self.permissions.calibration = false;
-        self.permissions.yaw_control = false;
-        self.permissions.pitch_roll_control = false;
+        self.permissions.yaw_control = stabilise;
+        self.permissions.pitch_roll_control = stabilise;
         self.permissions.height_control = false;
         self.permissions.wireless = false;
-        self.permissions.sensors = false;
-        // Reset the calibration flag if there was a panic.
-        self.operation_ready = false;
-        // Power down motors, due to panic.
-        gradually_slow_down_motors();
+        self.permissions.sensors = stabilise;
+        self.panic_landing.start(stabilise);
+        // With the motors already off there is nothing to land, go back to safe mode directly.
+        if self.panic_landing.is_finished() {
+            self.finish_panic(false, general_controllers, sensor_data_offset, sensor_data)
+        } else {
+            (true, 0b0011_1100)
+        }
+    }
+
+    // Ramp the motors down for one tick, called on every tick while in panic mode.
+    // Goes back to safe mode once the motors have reached zero.
+    pub fn land(
+        &mut self,
+        general_controllers: &mut GeneralController,
+        sensor_data_offset: &mut SensorOffset,
+        sensor_data: &mut SensorData,
+        profiler: &mut Profiler,
+    ) {
+        if self.panic_landing.is_finished() {
+            self.finish_panic(true, general_controllers, sensor_data_offset, sensor_data);
+            return;
+        }
+        Blue.on();
+        panic_mode(
+            &self.panic_landing,
+            general_controllers,
+            sensor_data,
+            profiler,
+        );
+        self.panic_landing.step();
+    }
+
+    // Turn the motors off right away and go back to safe mode, as if a panic landing just finished.
+    pub fn cut_motors(
+        &mut self,
+        general_controllers: &mut GeneralController,
+        sensor_data_offset: &mut SensorOffset,
+        sensor_data: &mut SensorData,
+    ) {
+        self.arming.disarm_by_safety_cut();
+        self.finish_panic(true, general_controllers, sensor_data_offset, sensor_data);
+    }
+
+    // Landed is false when the motors were already off, the calibration is then kept.
+    fn finish_panic(
+        &mut self,
+        landed: bool,
+        general_controllers: &mut GeneralController,
+        sensor_data_offset: &mut SensorOffset,
+        sensor_data: &mut SensorData,
+    ) -> (bool, u8) {
+        set_motors_off();
         // Reset all the values in the general PID controllers
         general_controllers.yaw_control.reset_values();
         general_controllers.pitch_control.reset_values();
         general_controllers.roll_control.reset_values();
         general_controllers.height_control.reset_values();
-        sensor_data.height_filter.reset();
-        sensor_data_offset.reset_sample_count();
-        sensor_data_offset.reset_offset();
-        sensor_data.resume_non_offset();
+        sensor_data.reset_estimates();
+        if landed {
+            // Reset the calibration flag if there was a panic.
+            self.operation_ready = false;
+            sensor_data_offset.reset_sample_count();
+            sensor_data_offset.reset_offset();
+            sensor_data.resume_non_offset();
+        }
         // Automatically go back to safe mode.
-        self.transition_safe(true, sensor_data_offset)
+        self.transition_safe(true)
     }
 
     // Manual mode should accept all controller movements, but not use any sensor data.
     fn transition_manual(&mut self) -> (bool, u8) {
-        // Can only go into manual mode from safe mode.
-        if self.controller_ready {
-            if self.state == State::Safety {
-                self.state = State::Manual;
-                self.permissions.controller = true;
-                self.permissions.calibration = false;
-                self.permissions.yaw_control = false;
-                self.permissions.pitch_roll_control = false;
-                self.permissions.height_control = false;
-                self.permissions.wireless = false;
-                self.permissions.sensors = false;
-                (true, 0b0011_1100)
-            } else {
-                (false, 0b0000_1111)
+        // Never start flying on a low battery, unarmed or with the joystick out of neutral.
+        if let Err(refusal) = self.interlock().check() {
+            if refusal == Refusal::JoystickNotNeutral {
+                self.state = State::Safety;
             }
+            return refusal_code(refusal);
+        }
+        // Can only go into manual mode from safe mode.
+        if self.state == State::Safety {
+            self.state = State::Manual;
+            self.permissions.controller = true;
+            self.permissions.calibration = false;
+            self.permissions.yaw_control = false;
+            self.permissions.pitch_roll_control = false;
+            self.permissions.height_control = false;
+            self.permissions.wireless = false;
+            self.permissions.sensors = false;
+            (true, 0b0011_1100)
         } else {
-            self.state = State::Safety;
             (false, 0b0000_1111)
         }
     }
 
+    // The checks of the modes that spin the motors on the ground.
+    fn interlock(&self) -> Interlock {
+        Interlock {
+            battery_ready: self.battery_ready,
+            armed: self.arming.is_armed(),
+            joystick_neutral: self.controller_ready,
+        }
+    }
+
     // Calibration mode should only accept sensor data, no controller movements.
     fn transition_calibrate(
         &mut self,
@@ -330,6 +501,8 @@ impl StateMachine {
     ) -> (bool, u8) {
         // Can only go into calibration mode from safe mode.
         if self.state == State::Safety {
+            // The idling motors would shake the drone while calibrating.
+            self.disarm_on_ground();
             self.state = State::Calibrate;
             self.permissions.controller = false;
             self.permissions.calibration = true;
@@ -338,10 +511,12 @@ impl StateMachine {
             self.permissions.height_control = false;
             self.permissions.wireless = false;
             self.permissions.sensors = true;
-            if calibrate_mode(sensor_data_offset, sensor_data) {
-                self.operation_ready = true;
-            }
-            // The code below is the original code
+            // Sample the sensors without the offsets of an earlier calibration.
+            sensor_data.reset_estimates();
+            sensor_data.resume_non_offset();
+            sensor_data_offset.reset_offset();
+            self.operation_ready = false;
+            self.calibration.start();
             (true, 0b0011_1100)
         } else {
             Red.on();
@@ -352,8 +527,11 @@ impl StateMachine {
     // Checks whether calibration is done and then redirects to the required transition.
     // All operating modes use the sensors for control loops.
     fn transition_operation(&mut self, next_state: State) -> (bool, u8) {
-        // Calibration flag check
-        if self.operation_ready && self.state == Safety {
+        if self.state == Safety && !self.arming.is_armed() {
+            return (false, 0b0000_0100);
+        }
+        // Calibration flag and battery check
+        if self.operation_ready && self.battery_ready && self.state == Safety {
             if self.controller_ready {
                 match next_state {
                     State::Manual => self.transition_manual(),
@@ -440,10 +618,86 @@ impl StateMachine {
         (true, 0b0011_1100)
     }
 
+    // Modes on the ground stop the idling motors.
+    fn disarm_on_ground(&mut self) {
+        if self.arming.is_armed() {
+            self.arming.disarm(true);
+            set_motors_off();
+        }
+    }
+
+    // The self test drives the motors itself, it is run on every tick by `run_self_test`.
+    fn transition_self_test(&mut self) -> (bool, u8) {
+        // Can only start the self test from safe mode, it stays in self test mode when done.
+        if self.state == State::Safety {
+            // The motors spin during the test, so it needs the same checks as manual mode. Once
+            // accepted the idling of the armed motors is stopped, the test drives them one by one.
+            if let Err(refusal) = self.interlock().check() {
+                return refusal_code(refusal);
+            }
+            self.disarm_on_ground();
+            self.state = State::SelfTest;
+            self.permissions.controller = false;
+            self.permissions.calibration = false;
+            self.permissions.yaw_control = false;
+            self.permissions.pitch_roll_control = false;
+            self.permissions.height_control = false;
+            self.permissions.wireless = false;
+            self.permissions.sensors = false;
+            self.self_test.start();
+            (true, 0b0011_1100)
+        } else {
+            (false, 0b0000_1111)
+        }
+    }
+
+    // Take the samples of this tick, called on every tick while in calibration mode.
+    // Returns the report at the end of every window, the offsets are only used when it is accepted.
+    pub fn run_calibration(
+        &mut self,
+        sensor_data: &SensorData,
+        sensor_data_offset: &mut SensorOffset,
+    ) -> Option<CalibrationReport> {
+        let (offsets, report) = self.calibration.add_sample(
+            sensor_data.get_gyro_data(),
+            sensor_data.get_accel_data(),
+            sensor_data.get_ypr_data(),
+            sensor_data.get_pres(),
+        )?;
+        if let Some(offsets) = offsets {
+            sensor_data_offset.restore_calibration(offsets);
+            self.operation_ready = true;
+        }
+        Some(report)
+    }
+
+    // Run one tick of the self test, called on every tick while in self test mode.
+    // Returns the report once, when the test has finished.
+    pub fn run_self_test(
+        &mut self,
+        sensor_data: &SensorData,
+        battery: u16,
+    ) -> Option<SelfTestReport> {
+        if self.self_test.is_finished() {
+            return None;
+        }
+        let motors = self
+            .self_test
+            .step(sensor_data.get_accel_data(), sensor_data.get_gyro_data());
+        set_motor_speeds_direct(motors);
+        if self.self_test.is_finished() {
+            set_motors_off();
+            Some(self.self_test.finish(sensor_data.get_raw_pres(), battery))
+        } else {
+            None
+        }
+    }
+
     fn transition_read_logs(&mut self) -> (bool, u8) {
         // Can only go into read logs mode from safe mode.
         // Return back to safe mode after reading logs.
         if self.state == State::Safety {
+            self.disarm_on_ground();
             self.state = State::ReadLogs;
             self.permissions.controller = false;
             self.permissions.calibration = false;
@@ -459,48 +713,39 @@ impl StateMachine {
     }
 }
 
+// Returns true when the motors saturated and the commands of the mode had to be limited.
 pub fn execute_state_function(
     current_state: &State,
     command: &JoystickControl,
     general_controllers: &mut GeneralController,
     sensor_data: &mut SensorData,
-    sensor_data_offset: &SensorOffset,
-) {
+    profiler: &mut Profiler,
+) -> bool {
     match current_state {
         State::Safety => {
             safety_mode();
+            false
         }
-        State::Manual => {
-            manual_mode(command);
-        }
+        State::Manual => manual_mode(command, profiler),
         // State::Calibrate => {
         //     calibrate_mode(sensor_data_offset);
         // }
         State::ReadLogs => {
             // read_logs_mode();
+            false
         }
-        State::Yaw => {
-            yaw_mode(command, general_controllers, sensor_data);
-        }
-        State::Full => {
-            full_mode(command, general_controllers, sensor_data);
-        }
-        State::Raw => {
-            raw_mode(
-                command,
-                general_controllers,
-                sensor_data,
-                sensor_data_offset,
-            );
-        }
-        State::Height => {
-            height_mode(command, general_controllers, sensor_data);
-        }
+        State::Yaw => yaw_mode(command, general_controllers, sensor_data, profiler),
+        State::Full => full_mode(command, general_controllers, sensor_data, profiler),
+        // raw mode flies like full mode, on the Kalman filter unless another estimator is selected
+        State::Raw => full_mode(command, general_controllers, sensor_data, profiler),
+        State::Height => height_mode(command, general_controllers, sensor_data, profiler),
         State::Wireless => {
             wireless_mode();
+            false
         }
         _ => {
-            // mode such as Panic is dealt during transition
+            // Panic is executed on every tick by `StateMachine::land`, not only when a message is received
+            false
         }
     }
 }
@@ -510,53 +755,47 @@ fn safety_mode() {
     // TODO: Nothing to implement in safety mode
 }
 
-fn manual_mode(command: &JoystickControl) {
+fn manual_mode(command: &JoystickControl, profiler: &mut Profiler) -> bool {
     let lift: i16 = map_lift_command_manual(command.get_lift());
     let yaw: i16 = map_yaw_command_manual(command.get_yaw());
     let pitch: i16 = map_pitch_command_manual(command.get_pitch());
     let roll: i16 = map_roll_command_manual(command.get_roll());
-    set_motor_speeds_manual(lift, yaw, pitch, roll);
-}
-
-fn calibrate_mode(sensor_data_offset: &mut SensorOffset, sensor_data: &mut SensorData) -> bool {
-    if sensor_data_offset.get_sample_count() == 0 {
-        sensor_data.height_filter.reset();
-        sensor_data.resume_non_offset();
-        sensor_data_offset.reset_offset();
-    }
-    sensor_data_offset.update_sample_count();
-    sensor_data_offset.update_yaw_offset(sensor_data.get_ypr().yaw);
-    sensor_data_offset.update_pitch_offset(sensor_data.get_ypr().pitch);
-    sensor_data_offset.update_roll_offset(sensor_data.get_ypr().roll);
-    sensor_data_offset.update_lift_offset(sensor_data.get_pres());
-    // sensor_data_offset.update_gyro_offset(sensor_data.get_gyro_data());
-    // sensor_data_offset.update_acc_offset(sensor_data.get_accel_data());
-    true
+    profiler.begin(ProfileStage::Mixing);
+    let saturated = set_motor_speeds_manual(lift, yaw, pitch, roll);
+    profiler.end(ProfileStage::Mixing);
+    saturated
 }
 
 fn yaw_mode(
     command: &JoystickControl,
     general_controllers: &mut GeneralController,
     sensor_data: &SensorData,
-) {
+    profiler: &mut Profiler,
+) -> bool {
     let lift: i16 = map_lift_command_control(command.get_lift()); // this should be the value that keeps the drone in the air stable
     let yaw: i16 = map_yaw_command_manual(command.get_yaw());
     let yaw_rate: I16F16 = map_yaw_command(command.get_yaw());
     let pitch: i16 = map_pitch_command_manual(command.get_pitch());
     let roll: i16 = map_roll_command_manual(command.get_roll());
+    profiler.begin(ProfileStage::Control);
     general_controllers
         .yaw_control
         .go_through_process(yaw_rate, sensor_data);
     let yaw_compensate: i16 =
         determine_yaw_compensate(yaw_rate, general_controllers.yaw_control.new_yaw);
-    set_motor_speeds_yaw(lift, yaw, pitch, roll, yaw_compensate);
+    profiler.end(ProfileStage::Control);
+    profiler.begin(ProfileStage::Mixing);
+    let saturated = set_motor_speeds_yaw(lift, yaw, pitch, roll, yaw_compensate);
+    profiler.end(ProfileStage::Mixing);
+    saturated
 }
 
 fn full_mode(
     command: &JoystickControl,
     general_controllers: &mut GeneralController,
     sensor_data: &SensorData,
-) {
+    profiler: &mut Profiler,
+) -> bool {
     // directly map the lift to the motor speeds
     let lift: i16 = map_lift_command_control(command.get_lift()); // this should be the value that keeps the drone in the air stable
     let yaw: i16 = map_yaw_command_manual(command.get_yaw());
@@ -566,6 +805,7 @@ fn full_mode(
     let pitch_angle: I16F16 = map_pitch_command(command.get_pitch());
     let roll_angle: I16F16 = map_roll_command(command.get_roll());
 
+    profiler.begin(ProfileStage::Control);
     general_controllers
         .yaw_control
         .go_through_process(yaw_rate, sensor_data);
@@ -585,8 +825,10 @@ fn full_mode(
     let roll_compensate: i16 =
         determine_roll_compensate(roll_angle, general_controllers.roll_control.new_roll);
     // let roll_compensate: i16 = 0;
+    profiler.end(ProfileStage::Control);
 
-    set_motor_speeds_full(
+    profiler.begin(ProfileStage::Mixing);
+    let saturated = set_motor_speeds_full(
         lift,
         yaw,
         pitch,
@@ -595,114 +837,86 @@ fn full_mode(
         pitch_compensate,
         roll_compensate,
     );
+    profiler.end(ProfileStage::Mixing);
+    saturated
 }
 
 #[allow(unused_variables)]
-fn raw_mode(
+fn height_mode(
     command: &JoystickControl,
     general_controllers: &mut GeneralController,
-    sensor_data: &mut SensorData,
-    sensor_data_offset: &SensorOffset,
-) {
-    let offseted_gyro = [
-        I16F16::from_num(
-            sensor_data.get_gyro_data()[0].saturating_sub(sensor_data_offset.gyro_offset[0] as i16),
-        ),
-        I16F16::from_num(
-            sensor_data.get_gyro_data()[1].saturating_sub(sensor_data_offset.gyro_offset[1] as i16),
-        ),
-        I16F16::from_num(
-            sensor_data.get_gyro_data()[2].saturating_sub(sensor_data_offset.gyro_offset[2] as i16),
-        ),
-    ];
-
-    let offseted_acc = [
-        I16F16::from_num(
-            sensor_data.get_accel_data()[0].saturating_sub(sensor_data_offset.acc_offset[0] as i16),
-        ),
-        I16F16::from_num(
-            sensor_data.get_accel_data()[1].saturating_sub(sensor_data_offset.acc_offset[1] as i16),
-        ),
-        I16F16::from_num(
-            sensor_data.get_accel_data()[2].saturating_sub(sensor_data_offset.acc_offset[2] as i16),
-        ),
-    ];
-
-    let (acc, gyro) = general_controllers
-        .raw_control
-        .low_pass_filter
-        .low_pass_one(offseted_gyro, offseted_acc);
-    let kf_ypr = general_controllers
-        .raw_control
-        .kalman_filter
-        .get_kalman_data(acc, gyro, sensor_data_offset);
-    sensor_data.update_ypr_filtered(kf_ypr);
-
-    // directly map the lift to the motor speeds
-    let lift: i16 = map_lift_command_control(command.get_lift()); // this should be the value that keeps the drone in the air stable
+    sensor_data: &SensorData,
+    profiler: &mut Profiler,
+) -> bool {
+    let lift: i16 = map_lift_command_height(command.get_lift()); // this should be the value that keeps the drone in the air stable
+    let target_lift: I16F16 = map_lift_command(command.get_lift());
     let yaw: i16 = map_yaw_command_manual(command.get_yaw());
-    let yaw_rate: I16F16 = map_yaw_command(command.get_yaw());
     let pitch: i16 = map_pitch_command_manual(command.get_pitch());
     let roll: i16 = map_roll_command_manual(command.get_roll());
-    let pitch_angle: I16F16 = map_pitch_command(command.get_pitch());
-    let roll_angle: I16F16 = map_roll_command(command.get_roll());
-
-    // TODO: Everything below might not be perfectly correct
-
-    // set the yaw rate from kalman to the dmp yaw value first
-    sensor_data.ypr_filter.yaw = sensor_data.ypr.yaw;
+    profiler.begin(ProfileStage::Control);
+    general_controllers
+        .height_control
+        .go_through_process(target_lift, sensor_data);
+    let lift_compensate: i16 =
+        determine_lift_compensate(target_lift, general_controllers.height_control.new_throttle);
+    profiler.end(ProfileStage::Control);
+    profiler.begin(ProfileStage::Mixing);
+    let saturated = set_motor_speeds_lift(lift, yaw, pitch, roll, lift_compensate);
+    profiler.end(ProfileStage::Mixing);
+    saturated
+}
 
+fn panic_mode(
+    landing: &PanicLanding,
+    general_controllers: &mut GeneralController,
+    sensor_data: &SensorData,
+    profiler: &mut Profiler,
+) {
+    if !landing.is_stabilised() {
+        profiler.begin(ProfileStage::Mixing);
+        set_motor_speeds_direct(landing.ramped_motors());
+        profiler.end(ProfileStage::Mixing);
+        return;
+    }
+    // Keep the drone level and stop it from spinning while the lift goes down.
+    let level = I16F16::from_num(0);
+    profiler.begin(ProfileStage::Control);
     general_controllers
         .yaw_control
-        .go_through_process(yaw_rate, sensor_data);
+        .go_through_process(level, sensor_data);
     let yaw_compensate: i16 =
-        determine_yaw_compensate(yaw_rate, general_controllers.yaw_control.new_yaw);
-    // let yaw_compensate: i16 = 0;
-
+        determine_yaw_compensate(level, general_controllers.yaw_control.new_yaw);
     general_controllers
         .pitch_control
-        .go_through_process_raw(pitch_angle, sensor_data);
+        .go_through_process(level, sensor_data);
     let pitch_compensate: i16 =
-        determine_pitch_compensate(pitch_angle, general_controllers.pitch_control.new_pitch);
-
+        determine_pitch_compensate(level, general_controllers.pitch_control.new_pitch);
     general_controllers
         .roll_control
-        .go_through_process_raw(roll_angle, sensor_data);
+        .go_through_process(level, sensor_data);
     let roll_compensate: i16 =
-        determine_roll_compensate(roll_angle, general_controllers.roll_control.new_roll);
-    // let roll_compensate: i16 = 0;
+        determine_roll_compensate(level, general_controllers.roll_control.new_roll);
+    profiler.end(ProfileStage::Control);
 
-    set_motor_speeds_full(
-        lift,
-        yaw,
-        pitch,
-        roll,
+    profiler.begin(ProfileStage::Mixing);
+    set_motor_speeds_landing(
+        landing.ramped_lift(),
         yaw_compensate,
         pitch_compensate,
         roll_compensate,
     );
-}
-
-#[allow(unused_variables)]
-fn height_mode(
-    command: &JoystickControl,
-    general_controllers: &mut GeneralController,
-    sensor_data: &SensorData,
-) {
-    let lift: i16 = map_lift_command_height(command.get_lift()); // this should be the value that keeps the drone in the air stable
-    let target_lift: I16F16 = map_lift_command(command.get_lift());
-    let yaw: i16 = map_yaw_command_manual(command.get_yaw());
-    let pitch: i16 = map_pitch_command_manual(command.get_pitch());
-    let roll: i16 = map_roll_command_manual(command.get_roll());
-    general_controllers
-        .height_control
-        .go_through_process(target_lift, sensor_data);
-    let lift_compensate: i16 =
-        determine_lift_compensate(target_lift, general_controllers.height_control.new_throttle);
-    set_motor_speeds_lift(lift, yaw, pitch, roll, lift_compensate);
+    profiler.end(ProfileStage::Mixing);
 }
 
 #[allow(unused_variables)]
 fn wireless_mode() {
     // TODO
 }
+
+// The answer to the PC when the interlock declines a mode, the PC stays in its mode and can arm.
+fn refusal_code(refusal: Refusal) -> (bool, u8) {
+    match refusal {
+        Refusal::NotArmed => (false, 0b0000_0100),
+        Refusal::LowBattery | Refusal::JoystickNotNeutral => (false, 0b0000_1111),
+    }
+}
@@ -0,0 +1,129 @@
+// This file keeps what the panic handler needs to write a crash record. The panic handler cannot
+// reach the state of the control loop, so the loop leaves the tick, the mode and a snapshot of the
+// sensors and motors in statics at the end of every tick. A crash before the first tick (while the
+// drivers are initialized) is not recorded, the flash is not ready yet at that point.
+//
+// Nothing here allocates, the crash may be an allocation failure.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU8, Ordering};
+use protocol::report::CrashCause;
+
+use crate::storage::crash::{
+    write_crash_record, MAX_CRASH_FILE_LENGTH, MAX_CRASH_MESSAGE_LENGTH, MAX_CRASH_PAYLOAD,
+};
+
+// attitude (3), rates (3), accel (3), motors (4), battery
+const SNAPSHOT_SIZE: usize = 14;
+
+static LOOP_RUNNING: AtomicBool = AtomicBool::new(false);
+static RECORDED: AtomicBool = AtomicBool::new(false);
+static TICK: AtomicU32 = AtomicU32::new(0);
+static MODE: AtomicU8 = AtomicU8::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU16 = AtomicU16::new(0);
+static SNAPSHOT: [AtomicU16; SNAPSHOT_SIZE] = [ZERO; SNAPSHOT_SIZE];
+
+/// The sensors and motors at the end of a tick.
+pub struct Snapshot {
+    /// Yaw, pitch and roll in milliradians.
+    pub attitude: [i16; 3],
+    pub rates: [i16; 3],
+    pub accel: [i16; 3],
+    pub motors: [u16; 4],
+    pub battery: u16,
+}
+
+/// The payload of a `CrashReport`, kept on the stack.
+pub struct CrashPayload {
+    bytes: [u8; MAX_CRASH_PAYLOAD],
+    length: usize,
+}
+
+impl CrashPayload {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let length = bytes.len().min(MAX_CRASH_PAYLOAD - self.length);
+        self.bytes[self.length..self.length + length].copy_from_slice(&bytes[..length]);
+        self.length += length;
+    }
+}
+
+// Formats into a fixed buffer, whatever does not fit is dropped.
+struct TextBuffer {
+    bytes: [u8; MAX_CRASH_MESSAGE_LENGTH],
+    length: usize,
+}
+
+impl Write for TextBuffer {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        let length = text.len().min(MAX_CRASH_MESSAGE_LENGTH - self.length);
+        self.bytes[self.length..self.length + length].copy_from_slice(&text.as_bytes()[..length]);
+        self.length += length;
+        Ok(())
+    }
+}
+
+// Called by the control loop at the end of every tick.
+pub fn update_context(tick: u32, mode: u8, snapshot: &Snapshot) {
+    TICK.store(tick, Ordering::Relaxed);
+    MODE.store(mode, Ordering::Relaxed);
+    let values = snapshot
+        .attitude
+        .iter()
+        .chain(&snapshot.rates)
+        .chain(&snapshot.accel)
+        .map(|value| *value as u16)
+        .chain(snapshot.motors)
+        .chain([snapshot.battery]);
+    for (slot, value) in SNAPSHOT.iter().zip(values) {
+        slot.store(value, Ordering::Relaxed);
+    }
+    LOOP_RUNNING.store(true, Ordering::Relaxed);
+}
+
+// Write the crash record for the first crash, later calls (e.g. the panic after an allocation
+// failure) are ignored. Returns the payload, so it can be sent over UART as well.
+pub fn record_crash(
+    cause: CrashCause,
+    file: &str,
+    line: u32,
+    message: fmt::Arguments,
+) -> Option<CrashPayload> {
+    if RECORDED.load(Ordering::Relaxed) {
+        return None;
+    }
+    // Also when writing the record panics, there is no second attempt.
+    RECORDED.store(true, Ordering::Relaxed);
+
+    let mut payload = CrashPayload {
+        bytes: [0; MAX_CRASH_PAYLOAD],
+        length: 0,
+    };
+    payload.push(&[cause.to_byte()]);
+    payload.push(&TICK.load(Ordering::Relaxed).to_be_bytes());
+    payload.push(&[MODE.load(Ordering::Relaxed)]);
+    payload.push(&line.to_be_bytes());
+    for value in SNAPSHOT.iter() {
+        payload.push(&value.load(Ordering::Relaxed).to_be_bytes());
+    }
+    // The end of the path tells the most.
+    let file = &file.as_bytes()[file.len().saturating_sub(MAX_CRASH_FILE_LENGTH)..];
+    payload.push(&[file.len() as u8]);
+    payload.push(file);
+    let mut text = TextBuffer {
+        bytes: [0; MAX_CRASH_MESSAGE_LENGTH],
+        length: 0,
+    };
+    let _ = text.write_fmt(message);
+    payload.push(&[text.length as u8]);
+    payload.push(&text.bytes[..text.length]);
+
+    if LOOP_RUNNING.load(Ordering::Relaxed) {
+        let _ = write_crash_record(payload.as_bytes());
+    }
+    Some(payload)
+}
@@ -5,10 +5,13 @@
 extern crate alloc;
 
 use crate::control::control_loop;
+use crate::crash::record_crash;
 use alloc::format;
+use alloc::vec::Vec;
 use core::alloc::Layout;
 use core::mem::MaybeUninit;
 use core::panic::PanicInfo;
+use protocol::report::{CrashCause, DeviceReport, ReportKind};
 
 use tudelft_quadrupel::initialize::initialize;
 use tudelft_quadrupel::led::Led::{Green, Red};
@@ -17,7 +20,9 @@ use tudelft_quadrupel::uart::send_bytes;
 use tudelft_quadrupel::{entry, uart};
 
 mod control;
+mod crash;
 mod storage;
+mod telemetry;
 mod yaw_pitch_roll;
 
 /// The heap size of your drone code in bytes.
@@ -50,19 +55,39 @@ fn main() -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     // On panic:
+    // * write the crash record to flash
     // * try and write the panic message on UART
-    // * blink the red light
+    // * blink the red light, and keep sending the crash report for a runner that connects later
 
     // use tudelft_quadrupel::uart;
 
+    let (file, line) = match info.location() {
+        Some(location) => (location.file(), location.line()),
+        None => ("", 0),
+    };
+    let payload = record_crash(
+        CrashCause::Panic,
+        file,
+        line,
+        format_args!("{}", info.message()),
+    );
+
+    let mut crash_message = Vec::new();
     if uart::is_initialized() {
         let msg = format!("{info}\n");
         send_bytes(msg.as_bytes());
+        if let Some(payload) = payload {
+            DeviceReport::new(ReportKind::Crash, payload.as_bytes().to_vec())
+                .form_message(&mut crash_message);
+        }
     }
 
     // Start blinking red
     loop {
         let _ = Red.toggle();
+        if !crash_message.is_empty() {
+            send_bytes(&crash_message);
+        }
         assembly_delay(1_000_000)
     }
 }
@@ -79,6 +104,14 @@ fn alloc_error(layout: Layout) -> ! {
     // (together with blinking red of the panic)
     Green.on();
 
+    // The record is written without allocating, the panic that follows does not write another one.
+    record_crash(
+        CrashCause::OutOfMemory,
+        "",
+        0,
+        format_args!("out of memory: {layout:?}"),
+    );
+
     // Safety: after this we panic and go into an infinite loop
     unsafe { uart::uninitialize() };
 
@@ -0,0 +1,176 @@
+// This file implements the configuration record in flash, so the gains, the Kalman noise, the
+// calibration and its settings, the accelerometer correction, the motor limits, the notch filters,
+// the limits of the safety monitor, the length of the panic landing and the battery levels do not
+// have to be compiled in or redone after every power-off.
+//
+// The region has two sectors that are used in turn, records are never overwritten. Every save
+// writes a new record into the next blank slot with a higher sequence number, and the previous
+// record stays valid until the new one has been written and read back. Once a sector is full, the
+// other sector (which only holds older records) is erased and used. A record that was cut off by a
+// power cut fails its CRC and is skipped, so loading always finds the last complete record.
+//
+// Every slot holds `[magic u16][version u8][length u8][sequence u32][payload][crc16]`, all big
+// endian. Erased flash reads 0xFF. The payload and its version are `control_math::config`.
+//
+// The chip is not erased at boot (see vendor/tudelft-quadrupel), so after a reset `load` finds the
+// record that was saved last, the control loop applies it before the first tick and reports
+// `ConfigEvent::Loaded` with its sequence number. The defaults are only used when no valid record
+// of this version is found.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use control_math::config::{Config, CONFIG_VERSION, PAYLOAD_SIZE};
+use tudelft_quadrupel::flash::{flash_read_bytes, flash_write_bytes, FlashError};
+
+use super::calculate_crc16;
+use super::flash::{flash_sector_erase, SECTOR_SIZE};
+
+const CONFIG_MAGIC: u16 = 0xC0F1;
+const SLOT_SIZE: u32 = 256;
+const HEADER_SIZE: usize = 8;
+const RECORD_SIZE: usize = HEADER_SIZE + PAYLOAD_SIZE + 2;
+
+/// A CRC16 over everything that is kept in flash, to tell configurations apart.
+pub fn config_hash(config: &Config) -> u16 {
+    calculate_crc16(&config.to_bytes())
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Reading, writing or erasing the flash failed.
+    Flash,
+    /// The record read back differs from what was written.
+    VerifyFailed,
+}
+
+impl From<FlashError> for ConfigError {
+    fn from(_: FlashError) -> Self {
+        ConfigError::Flash
+    }
+}
+
+pub struct ConfigStore {
+    start_address: u32,
+    slot_count: u32,
+    // The slot of the record in use, None when the defaults are in use.
+    current_slot: Option<u32>,
+    // The blank slot the next record is written to, None when the other sector has to be erased.
+    next_slot: Option<u32>,
+    sequence: u32,
+}
+
+impl ConfigStore {
+    /// Creates a store in the flash region from `start_address` up to and including `end_address`,
+    /// the region has to be two sectors.
+    pub fn new(start_address: u32, end_address: u32) -> Self {
+        ConfigStore {
+            start_address,
+            slot_count: (end_address + 1 - start_address) / SLOT_SIZE,
+            current_slot: None,
+            next_slot: None,
+            sequence: 0,
+        }
+    }
+
+    /// The sequence number of the record in use, 0 when nothing has been loaded or saved.
+    pub fn get_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Scans every slot and returns the newest valid record, None if there is none.
+    pub fn load(&mut self) -> Result<Option<Config>, ConfigError> {
+        let mut newest = None;
+        for slot in 0..self.slot_count {
+            let record = self.read_slot(slot)?;
+            if let Some((sequence, config)) = parse_record(&record) {
+                if newest.is_none() || sequence > self.sequence {
+                    self.sequence = sequence;
+                    self.current_slot = Some(slot);
+                    newest = Some(config);
+                }
+            }
+        }
+
+        // Continue in the first blank slot after the record in use, in the same sector.
+        self.next_slot = None;
+        if let Some(current_slot) = self.current_slot {
+            let sector_end = (current_slot / self.slots_per_sector() + 1) * self.slots_per_sector();
+            for slot in current_slot + 1..sector_end {
+                if self.read_slot(slot)?.iter().all(|byte| *byte == 0xFF) {
+                    self.next_slot = Some(slot);
+                    break;
+                }
+            }
+        }
+        Ok(newest)
+    }
+
+    /// Writes the configuration as a new record, returns its sequence number.
+    pub fn save(&mut self, config: &Config) -> Result<u32, ConfigError> {
+        let slot = match self.next_slot {
+            Some(slot) => slot,
+            None => {
+                // The sector in use is full, the other one only holds older records.
+                let sector = match self.current_slot {
+                    Some(current_slot) => 1 - current_slot / self.slots_per_sector(),
+                    None => 0,
+                };
+                let slot = sector * self.slots_per_sector();
+                flash_sector_erase(self.slot_address(slot))?;
+                slot
+            }
+        };
+        // The slot is used from now on, also when the write fails halfway.
+        self.next_slot = if (slot + 1) % self.slots_per_sector() != 0 {
+            Some(slot + 1)
+        } else {
+            None
+        };
+
+        let sequence = self.sequence + 1;
+        let payload = config.to_bytes();
+        let mut record = Vec::with_capacity(RECORD_SIZE);
+        record.extend_from_slice(&CONFIG_MAGIC.to_be_bytes());
+        record.push(CONFIG_VERSION);
+        record.push(payload.len() as u8);
+        record.extend_from_slice(&sequence.to_be_bytes());
+        record.extend_from_slice(&payload);
+        record.extend_from_slice(&calculate_crc16(&record).to_be_bytes());
+        flash_write_bytes(self.slot_address(slot), &record)?;
+        if self.read_slot(slot)? != record {
+            return Err(ConfigError::VerifyFailed);
+        }
+
+        self.sequence = sequence;
+        self.current_slot = Some(slot);
+        Ok(sequence)
+    }
+
+    fn read_slot(&self, slot: u32) -> Result<Vec<u8>, FlashError> {
+        let mut record = vec![0u8; RECORD_SIZE];
+        flash_read_bytes(self.slot_address(slot), &mut record)?;
+        Ok(record)
+    }
+
+    fn slots_per_sector(&self) -> u32 {
+        SECTOR_SIZE / SLOT_SIZE
+    }
+
+    fn slot_address(&self, slot: u32) -> u32 {
+        self.start_address + slot * SLOT_SIZE
+    }
+}
+
+// Returns the sequence number and the configuration if the record is complete and of this version.
+fn parse_record(record: &[u8]) -> Option<(u32, Config)> {
+    let magic = u16::from_be_bytes([record[0], record[1]]);
+    if magic != CONFIG_MAGIC || record[2] != CONFIG_VERSION || record[3] as usize != PAYLOAD_SIZE {
+        return None;
+    }
+    let crc = u16::from_be_bytes([record[RECORD_SIZE - 2], record[RECORD_SIZE - 1]]);
+    if crc != calculate_crc16(&record[..RECORD_SIZE - 2]) {
+        return None;
+    }
+    let sequence = u32::from_be_bytes([record[4], record[5], record[6], record[7]]);
+    Some((sequence, Config::from_bytes(&record[HEADER_SIZE..])))
+}
@@ -0,0 +1,68 @@
+// This file implements the crash record, the sector at `CRASH_ADDRESS` holds at most one record:
+// `[magic u16][length u8][payload][crc16]`. The payload is the payload of a `CrashReport`. The
+// record is written from the panic handler, where the heap may be full, so writing it does not
+// allocate.
+//
+// The record survives a reset: the runner fetches it when it connects, after the drone has been
+// reset too, and it stays until it is cleared. Before the reset the panic handler also keeps
+// sending the crash report over UART.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use protocol::report::CRASH_HEADER_SIZE;
+use tudelft_quadrupel::flash::{flash_read_bytes, flash_write_bytes, FlashError};
+
+use super::flash::flash_sector_erase;
+use super::{calculate_crc16, CRASH_ADDRESS};
+
+const CRASH_MAGIC: u16 = 0xC7A5;
+const RECORD_HEADER_SIZE: usize = 3;
+/// The longest file name and message that are kept, longer ones are cut off.
+pub const MAX_CRASH_FILE_LENGTH: usize = 40;
+pub const MAX_CRASH_MESSAGE_LENGTH: usize = 96;
+pub const MAX_CRASH_PAYLOAD: usize =
+    CRASH_HEADER_SIZE + 2 + MAX_CRASH_FILE_LENGTH + MAX_CRASH_MESSAGE_LENGTH;
+
+/// Replaces the crash record with a new one.
+///
+/// Note: This erases a sector, which takes about 25ms and blocks!
+pub fn write_crash_record(payload: &[u8]) -> Result<(), FlashError> {
+    let length = payload.len().min(MAX_CRASH_PAYLOAD);
+    let mut record = [0u8; RECORD_HEADER_SIZE + MAX_CRASH_PAYLOAD + 2];
+    record[..2].copy_from_slice(&CRASH_MAGIC.to_be_bytes());
+    record[2] = length as u8;
+    record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + length].copy_from_slice(&payload[..length]);
+    let end = RECORD_HEADER_SIZE + length;
+    let crc = calculate_crc16(&record[..end]);
+    record[end..end + 2].copy_from_slice(&crc.to_be_bytes());
+
+    flash_sector_erase(CRASH_ADDRESS)?;
+    flash_write_bytes(CRASH_ADDRESS, &record[..end + 2])
+}
+
+/// The payload of the crash record, None if there is no complete record.
+pub fn read_crash_record() -> Result<Option<Vec<u8>>, FlashError> {
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    flash_read_bytes(CRASH_ADDRESS, &mut header)?;
+    let length = header[2] as usize;
+    if u16::from_be_bytes([header[0], header[1]]) != CRASH_MAGIC || length > MAX_CRASH_PAYLOAD {
+        return Ok(None);
+    }
+    let mut record = vec![0u8; RECORD_HEADER_SIZE + length + 2];
+    flash_read_bytes(CRASH_ADDRESS, &mut record)?;
+    let (content, crc) = record.split_at(RECORD_HEADER_SIZE + length);
+    if calculate_crc16(content) != u16::from_be_bytes([crc[0], crc[1]]) {
+        return Ok(None);
+    }
+    Ok(Some(content[RECORD_HEADER_SIZE..].to_vec()))
+}
+
+/// Erases the crash record, if there is anything to erase.
+pub fn clear_crash_record() -> Result<(), FlashError> {
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    flash_read_bytes(CRASH_ADDRESS, &mut header)?;
+    if header.iter().all(|byte| *byte == 0xFF) {
+        return Ok(());
+    }
+    flash_sector_erase(CRASH_ADDRESS)
+}
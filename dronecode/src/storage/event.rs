@@ -0,0 +1,75 @@
+// This file implements the event log in flash. Every event is a record of its own in a
+// log-structured storage, the oldest events are dropped a sector at a time once it is full. With
+// 16 bytes per record, one sector holds the last 256 events or so. The events survive a reset, a
+// boot event is logged at every power-up because the ticks start again from 0.
+
+use protocol::report::{EventKind, EventReport};
+use tudelft_quadrupel::flash::FlashError;
+
+use super::Storage;
+
+pub struct EventLog {
+    storage: Storage,
+    // True while the events are sent one by one after a read command.
+    reading: bool,
+}
+
+impl EventLog {
+    /// Creates a log in the flash region from `start_address` up to and including `end_address`,
+    /// `recover` has to be called before it is used.
+    pub fn new(start_address: u32, end_address: u32) -> Self {
+        EventLog {
+            storage: Storage::new(start_address, end_address),
+            reading: false,
+        }
+    }
+
+    /// Continues the events of the previous boots.
+    pub fn recover(&mut self) -> Result<(), FlashError> {
+        self.storage.recover()
+    }
+
+    pub fn write(&mut self, event: &EventReport) -> Result<(), FlashError> {
+        let mut record = [
+            event.kind.to_byte(),
+            event.from,
+            event.to,
+            event.reason,
+            0,
+            0,
+            0,
+            0,
+        ];
+        record[4..].copy_from_slice(&event.tick.to_be_bytes());
+        self.storage.write(&record).map(|_| ())
+    }
+
+    /// Starts sending the events from the oldest one, `next_event` returns them one by one.
+    pub fn start_reading(&mut self) {
+        self.storage.rewind();
+        self.reading = true;
+    }
+
+    /// The next event in flash, None when everything has been sent.
+    pub fn next_event(&mut self) -> Result<Option<EventReport>, FlashError> {
+        while self.reading {
+            let Some(record) = self.storage.read()? else {
+                self.reading = false;
+                break;
+            };
+            if record.len() != 8 {
+                continue;
+            }
+            if let Some(kind) = EventKind::from_byte(record[0]) {
+                return Ok(Some(EventReport {
+                    kind,
+                    from: record[1],
+                    to: record[2],
+                    reason: record[3],
+                    tick: u32::from_be_bytes([record[4], record[5], record[6], record[7]]),
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
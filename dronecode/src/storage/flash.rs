@@ -0,0 +1,6 @@
+// This file holds the layout of the flash chip (SST25VF010A) that the storage builds on. A single
+// sector is erased with `flash_sector_erase` of the patched library, see vendor/tudelft-quadrupel.
+
+pub use tudelft_quadrupel::flash::flash_sector_erase;
+
+pub const SECTOR_SIZE: u32 = 4096;
@@ -0,0 +1,151 @@
+// This file implements the incident log, where the black box keeps its samples after a panic, a
+// failsafe or a safety cut. It is a log-structured storage of its own, so the incidents are not
+// pushed out by the regular logs. With three sectors, of which one is always erased ahead, the
+// last eight or so incidents are kept, also across a reset, and their numbers continue.
+//
+// An incident is a header record `[marker u8][number u16][cause u8][trigger tick u32][count u8]
+// [period u8]`, followed by sample records `[marker u8][number u16][first index u8][samples..]`
+// that hold a few samples each, from the oldest to the newest.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use protocol::report::{BlackBoxSample, IncidentCause, IncidentReport, BLACK_BOX_SAMPLE_SIZE};
+use tudelft_quadrupel::flash::FlashError;
+
+use super::Storage;
+
+// "I" and "D" in ASCII.
+const HEADER_MARKER: u8 = 0x49;
+const SAMPLES_MARKER: u8 = 0x44;
+const HEADER_SIZE: usize = 10;
+const SAMPLES_HEADER_SIZE: usize = 4;
+
+pub struct IncidentLog {
+    storage: Storage,
+    // The number of the next incident, one more than the highest number in flash.
+    next_number: u16,
+    // True while the incidents are sent sample by sample after a read command.
+    reading: bool,
+    // The header of the incident that is being read, the sample fields are filled in per sample.
+    header: Option<IncidentReport>,
+    // The samples of the last record that was read and have not been sent yet.
+    pending: VecDeque<IncidentReport>,
+}
+
+impl IncidentLog {
+    /// Creates a log in the flash region from `start_address` up to and including `end_address`,
+    /// `recover` has to be called before it is used.
+    pub fn new(start_address: u32, end_address: u32) -> Self {
+        IncidentLog {
+            storage: Storage::new(start_address, end_address),
+            next_number: 1,
+            reading: false,
+            header: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Continues the incidents of the previous boots.
+    pub fn recover(&mut self) -> Result<(), FlashError> {
+        self.storage.recover()?;
+        self.storage.rewind();
+        while let Some(record) = self.storage.read()? {
+            if record.len() == HEADER_SIZE && record[0] == HEADER_MARKER {
+                let number = u16::from_be_bytes([record[1], record[2]]);
+                self.next_number = self.next_number.max(number.wrapping_add(1));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the header of a new incident with `count` samples, returns its number.
+    pub fn start(
+        &mut self,
+        cause: IncidentCause,
+        trigger_tick: u32,
+        count: u8,
+        period: u8,
+    ) -> Result<u16, FlashError> {
+        let number = self.next_number;
+        let mut record = Vec::with_capacity(HEADER_SIZE);
+        record.push(HEADER_MARKER);
+        record.extend_from_slice(&number.to_be_bytes());
+        record.push(cause.to_byte());
+        record.extend_from_slice(&trigger_tick.to_be_bytes());
+        record.push(count);
+        record.push(period);
+        self.storage.write(&record)?;
+        self.next_number = number.wrapping_add(1).max(1);
+        Ok(number)
+    }
+
+    /// Writes the next samples of the incident, `first_index` is the index of the first of them.
+    pub fn write_samples(
+        &mut self,
+        number: u16,
+        first_index: u8,
+        samples: &[BlackBoxSample],
+    ) -> Result<(), FlashError> {
+        let mut record =
+            Vec::with_capacity(SAMPLES_HEADER_SIZE + samples.len() * BLACK_BOX_SAMPLE_SIZE);
+        record.push(SAMPLES_MARKER);
+        record.extend_from_slice(&number.to_be_bytes());
+        record.push(first_index);
+        for sample in samples {
+            record.extend_from_slice(&sample.to_bytes());
+        }
+        self.storage.write(&record).map(|_| ())
+    }
+
+    /// Starts sending the incidents from the oldest one, `next_report` returns the samples.
+    pub fn start_reading(&mut self) {
+        self.storage.rewind();
+        self.reading = true;
+        self.header = None;
+        self.pending.clear();
+    }
+
+    /// The next sample of the incidents in flash, None when everything has been sent.
+    pub fn next_report(&mut self) -> Result<Option<IncidentReport>, FlashError> {
+        while self.reading {
+            if let Some(report) = self.pending.pop_front() {
+                return Ok(Some(report));
+            }
+            match self.storage.read()? {
+                Some(record) => self.parse_record(&record),
+                None => self.reading = false,
+            }
+        }
+        Ok(None)
+    }
+
+    fn parse_record(&mut self, record: &[u8]) {
+        if record.len() == HEADER_SIZE && record[0] == HEADER_MARKER {
+            self.header = IncidentCause::from_byte(record[3]).map(|cause| IncidentReport {
+                number: u16::from_be_bytes([record[1], record[2]]),
+                cause,
+                trigger_tick: u32::from_be_bytes([record[4], record[5], record[6], record[7]]),
+                index: 0,
+                count: record[8],
+                period: record[9],
+                sample: BlackBoxSample::default(),
+            });
+        } else if record.len() >= SAMPLES_HEADER_SIZE && record[0] == SAMPLES_MARKER {
+            // The samples of an incident whose header was dropped with the oldest sector are skipped.
+            let Some(header) = self.header else {
+                return;
+            };
+            if header.number != u16::from_be_bytes([record[1], record[2]]) {
+                return;
+            }
+            let samples = record[SAMPLES_HEADER_SIZE..].chunks_exact(BLACK_BOX_SAMPLE_SIZE);
+            for (offset, bytes) in samples.enumerate() {
+                self.pending.push_back(IncidentReport {
+                    index: record[3].wrapping_add(offset as u8),
+                    sample: BlackBoxSample::from_bytes(bytes),
+                    ..header
+                });
+            }
+        }
+    }
+}
@@ -1,120 +1,357 @@
-use tudelft_quadrupel::flash::{flash_chip_erase, flash_read_bytes, flash_write_bytes, FlashError};
+use alloc::vec;
+use alloc::vec::Vec;
+use crc16::{State, XMODEM};
+use tudelft_quadrupel::flash::{flash_read_bytes, flash_write_bytes, FlashError};
 
-/// A storage structure that manages reading and writing data to flash memory.
+use self::flash::{flash_sector_erase, SECTOR_SIZE};
+
+pub mod config;
+pub mod crash;
+pub mod event;
+pub mod flash;
+pub mod incident;
+pub mod session;
+
+// The flash chip holds 128 KiB, the last 40 KiB are reserved for the crash record, the event log,
+// the black-box incidents, the session directory and the configuration. All survive a reboot, the
+// patched library in vendor/tudelft-quadrupel does not erase the chip when it starts.
+pub const LOG_START_ADDRESS: u32 = 0x000000;
+pub const LOG_END_ADDRESS: u32 = 0x015FFF;
+pub const CRASH_ADDRESS: u32 = 0x016000;
+pub const EVENT_START_ADDRESS: u32 = 0x017000;
+pub const EVENT_END_ADDRESS: u32 = 0x018FFF;
+pub const INCIDENT_START_ADDRESS: u32 = 0x019000;
+pub const INCIDENT_END_ADDRESS: u32 = 0x01BFFF;
+pub const SESSION_START_ADDRESS: u32 = 0x01C000;
+pub const SESSION_END_ADDRESS: u32 = 0x01DFFF;
+pub const CONFIG_START_ADDRESS: u32 = 0x01E000;
+pub const CONFIG_END_ADDRESS: u32 = 0x01FFFF;
+
+// Every record is `[magic u8][length u8][sequence u32][payload][crc16]`, the CRC covers everything
+// in front of it. Records never cross a sector boundary.
+const RECORD_MAGIC: u8 = 0xA5;
+const HEADER_SIZE: u32 = 6;
+const RECORD_OVERHEAD: u32 = HEADER_SIZE + 2;
+
+// What was found at an address while walking through the records.
+enum Record {
+    // Erased flash, nothing has been written here yet.
+    Blank,
+    // A complete record with the given sequence number and payload.
+    Valid(u32, Vec<u8>),
+    // A record with a plausible header of the given size, but the CRC does not match (e.g. the
+    // power was cut while writing it). It is skipped.
+    Corrupt(u32),
+    // Something that is not a record, the rest of the sector cannot be trusted.
+    Garbage,
+}
+
+/// A log-structured storage in flash, the records survive a reboot and the oldest records are
+/// dropped a sector at a time when the storage is full.
+///
+/// The sector after the one that is written is always erased ahead of time, so a record can always
+/// be written and the erase never touches the records in front of the write address.
 ///
 /// # Fields
 ///
-/// * `start_address` - A u32 representing the starting address of the flash memory
-/// * `end_address` - A u32 representing the ending address of the flash memory
-/// * `current_address` - A u32 representing the current write address in the flash memory
-/// * `read_address` - A u32 representing the current read address in the flash memory
-
+/// * `start_address` - A u32 representing the first address of the storage, at a sector boundary
+/// * `sector_count` - A u32 representing the number of sectors in the storage
+/// * `write_address` - A u32 representing the address of the next record that is written
+/// * `sequence` - A u32 representing the sequence number of the next record that is written
+/// * `read_address` - A u32 representing the address of the next record that is read
 pub struct Storage {
     start_address: u32,
-    end_address: u32,
-    current_address: u32,
+    sector_count: u32,
+    write_address: u32,
+    sequence: u32,
     read_address: u32,
 }
 
 impl Storage {
     /// Creates a new Storage instance with the specified start and end addresses.
+    /// `recover` has to be called before the storage is used.
     ///
     /// # Arguments
     ///
     /// * `start_address` - A u32 representing the starting address of the flash memory
-    /// * `end_address` - A u32 representing the ending address of the flash memory
+    /// * `end_address` - A u32 representing the last address of the flash memory
     ///
     /// # Returns
     ///
     /// * A Storage instance
-
     pub fn new(start_address: u32, end_address: u32) -> Self {
         Storage {
             start_address,
-            end_address,
-            current_address: start_address,
+            sector_count: (end_address + 1 - start_address) / SECTOR_SIZE,
+            write_address: start_address,
+            sequence: 0,
             read_address: start_address,
         }
     }
 
-    /// Erases the entire flash memory.
+    /// Finds the newest record to continue writing after it, and the oldest record to start
+    /// reading from, so the records of the previous boots are kept. On an empty storage, the first
+    /// two sectors are erased.
     ///
     /// # Returns
     ///
     /// * A Result indicating success or failure (FlashError)
+    pub fn recover(&mut self) -> Result<(), FlashError> {
+        // The first record of every sector tells which sector was written last and which is oldest.
+        let mut newest: Option<(u32, u32)> = None;
+        let mut oldest: Option<(u32, u32)> = None;
+        for sector in 0..self.sector_count {
+            if let Record::Valid(sequence, _) = read_record(self.sector_address(sector))? {
+                if newest.is_none_or(|(_, newest)| sequence > newest) {
+                    newest = Some((sector, sequence));
+                }
+                if oldest.is_none_or(|(_, oldest)| sequence < oldest) {
+                    oldest = Some((sector, sequence));
+                }
+            }
+        }
 
-    pub fn erase_flash(&mut self) -> Result<(), FlashError> {
-        flash_chip_erase()
+        let (sector, sequence) = match (newest, oldest) {
+            (Some(newest), Some(oldest)) => {
+                self.read_address = self.sector_address(oldest.0);
+                newest
+            }
+            _ => {
+                self.write_address = self.start_address;
+                self.read_address = self.start_address;
+                self.sequence = 0;
+                flash_sector_erase(self.sector_address(0))?;
+                return self.erase_ahead();
+            }
+        };
+
+        // Walk through the newest sector up to the first blank space.
+        let sector_end = self.sector_address(sector) + SECTOR_SIZE;
+        let mut address = self.sector_address(sector);
+        self.sequence = sequence;
+        loop {
+            match read_record(address)? {
+                Record::Blank => break,
+                Record::Valid(sequence, payload) => {
+                    self.sequence = self.sequence.max(sequence);
+                    address += RECORD_OVERHEAD + payload.len() as u32;
+                }
+                Record::Corrupt(size) => address += size,
+                Record::Garbage => {
+                    // Written halfway, continue in the next sector.
+                    address = sector_end;
+                    break;
+                }
+            }
+            if address + RECORD_OVERHEAD > sector_end {
+                break;
+            }
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        if address + RECORD_OVERHEAD > sector_end {
+            self.write_address = self.sector_address(sector);
+            self.next_sector()
+        } else {
+            self.write_address = address;
+            self.erase_ahead()
+        }
     }
 
-    /// Writes data to the flash memory at the current address.
+    /// Erases every sector and starts again with an empty storage.
+    ///
+    /// Note: This takes about 25ms per sector, and blocks!
+    ///
+    /// # Returns
+    ///
+    /// * A Result indicating success or failure (FlashError)
+    pub fn erase_all(&mut self) -> Result<(), FlashError> {
+        for sector in 0..self.sector_count {
+            flash_sector_erase(self.sector_address(sector))?;
+        }
+        self.write_address = self.start_address;
+        self.read_address = self.start_address;
+        self.sequence = 0;
+        Ok(())
+    }
+
+    /// Writes the data as a new record after the newest one.
     ///
     /// # Arguments
     ///
-    /// * `data` - A byte slice containing the data to be written
+    /// * `data` - A byte slice containing the data to be written, at most 255 bytes
     ///
     /// # Returns
     ///
-    /// * A Result indicating success or failure (FlashError)
+    /// * A Result containing the address of the record, which can be passed to `seek`
+    pub fn write(&mut self, data: &[u8]) -> Result<u32, FlashError> {
+        let size = RECORD_OVERHEAD + data.len() as u32;
+        if data.len() > u8::MAX as usize {
+            return Err(FlashError::OutOfSpace);
+        }
+        // Records do not cross sectors, start the next sector if the rest of this one is too small.
+        if self.sector_offset(self.write_address) + size > SECTOR_SIZE {
+            self.next_sector()?;
+        }
 
-    pub fn write(&mut self, data: &[u8]) -> Result<(), FlashError> {
-        let length = data.len();
+        let mut record = Vec::with_capacity(size as usize);
+        record.push(RECORD_MAGIC);
+        record.push(data.len() as u8);
+        record.extend_from_slice(&self.sequence.to_be_bytes());
+        record.extend_from_slice(data);
+        record.extend_from_slice(&calculate_crc16(&record).to_be_bytes());
+        let address = self.write_address;
+        flash_write_bytes(address, &record)?;
+        self.write_address += size;
+        self.sequence = self.sequence.wrapping_add(1);
 
-        // Check if the new address will overflow the end address,
-        // and erase the flash memory if necessary.
-        let new_address = self.current_address + length as u32;
-        if new_address > self.end_address {
-            self.erase_flash()?;
-            self.current_address = self.start_address;
+        // The record filled the sector exactly, continue in the sector that was erased ahead.
+        if self.sector_offset(self.write_address) == 0 {
+            if self.sector_of(self.write_address) == self.sector_count {
+                self.write_address = self.start_address;
+            }
+            self.erase_ahead()?;
         }
+        Ok(address)
+    }
 
-        flash_write_bytes(self.current_address, data)?;
-        self.update_current_address(length)?;
-
-        Ok(())
+    /// Starts reading again from the oldest record.
+    pub fn rewind(&mut self) {
+        // The oldest records are in the sector after the one that was erased ahead, blank sectors
+        // are skipped while reading.
+        let ahead = self.next_sector_address(self.write_address);
+        self.read_address = self.next_sector_address(ahead);
     }
 
-    /// Reads data from the flash memory at the current read address into the provided buffer.
+    /// Continues reading at the record at `address`, as returned by `write`. If the record has
+    /// been dropped since, reading continues with whatever is found there.
     ///
     /// # Arguments
     ///
-    /// * `buffer` - A mutable byte slice to store the read data
+    /// * `address` - A u32 representing the address of a record in this storage
+    pub fn seek(&mut self, address: u32) {
+        if address >= self.start_address && self.sector_of(address) < self.sector_count {
+            self.read_address = address;
+        }
+    }
+
+    /// Reads the record at `address` without moving on, as returned by `write`.
     ///
     /// # Returns
     ///
-    /// * A Result containing the number of bytes read, or a FlashError on failure
-
-    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FlashError> {
-        let length = buffer.len();
-        flash_read_bytes(self.read_address, buffer)?;
-        self.update_read_address(length)?;
-
-        Ok(length)
+    /// * A Result containing the data of the record, or None if there is no valid record
+    pub fn read_at(&self, address: u32) -> Result<Option<Vec<u8>>, FlashError> {
+        if address < self.start_address || self.sector_of(address) >= self.sector_count {
+            return Ok(None);
+        }
+        match read_record(address)? {
+            Record::Valid(_, payload) => Ok(Some(payload)),
+            _ => Ok(None),
+        }
     }
 
-    // Update the current address
-    fn update_current_address(&mut self, length: usize) -> Result<(), FlashError> {
-        let new_address = self.current_address + length as u32;
+    /// Stops reading, `read` returns None until `rewind` or `seek` is called.
+    pub fn seek_end(&mut self) {
+        self.read_address = self.write_address;
+    }
 
-        if new_address > self.end_address {
-            self.current_address = self.start_address;
-        } else {
-            self.current_address = new_address;
+    /// Reads the next record, from the oldest to the newest.
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the data of the record, or None if all records have been read
+    pub fn read(&mut self) -> Result<Option<Vec<u8>>, FlashError> {
+        while self.read_address != self.write_address {
+            match read_record(self.read_address)? {
+                Record::Valid(_, payload) => {
+                    self.read_address += RECORD_OVERHEAD + payload.len() as u32;
+                    self.wrap_read_address();
+                    return Ok(Some(payload));
+                }
+                Record::Corrupt(size) => {
+                    self.read_address += size;
+                    self.wrap_read_address();
+                }
+                Record::Blank | Record::Garbage => {
+                    // The rest of the sector is unused, the records continue in the next sector.
+                    if self.sector_of(self.read_address) == self.sector_of(self.write_address) {
+                        self.read_address = self.write_address;
+                    } else {
+                        self.read_address = self.next_sector_address(self.read_address);
+                    }
+                }
+            }
         }
+        Ok(None)
+    }
 
-        Ok(())
+    // Move the write address to the start of the next sector, which was already erased.
+    fn next_sector(&mut self) -> Result<(), FlashError> {
+        self.write_address = self.next_sector_address(self.write_address);
+        self.erase_ahead()
     }
 
-    // Update the read address
-    fn update_read_address(&mut self, length: usize) -> Result<(), FlashError> {
-        let new_address = self.read_address + length as u32;
+    // Erase the sector after the one that is written, it holds the oldest records.
+    fn erase_ahead(&mut self) -> Result<(), FlashError> {
+        let ahead = self.next_sector_address(self.write_address);
+        if self.sector_of(self.read_address) == self.sector_of(ahead) {
+            self.read_address = self.next_sector_address(ahead);
+        }
+        // After a reboot the sector is usually erased already.
+        if let Record::Blank = read_record(ahead)? {
+            return Ok(());
+        }
+        flash_sector_erase(ahead)
+    }
 
-        if new_address > self.end_address {
+    // A record may end exactly at the end of the last sector.
+    fn wrap_read_address(&mut self) {
+        if self.sector_of(self.read_address) == self.sector_count {
             self.read_address = self.start_address;
-        } else {
-            self.read_address = new_address;
         }
+    }
 
-        Ok(())
+    fn sector_address(&self, sector: u32) -> u32 {
+        self.start_address + sector * SECTOR_SIZE
+    }
+
+    fn sector_of(&self, address: u32) -> u32 {
+        (address - self.start_address) / SECTOR_SIZE
+    }
+
+    fn sector_offset(&self, address: u32) -> u32 {
+        (address - self.start_address) % SECTOR_SIZE
+    }
+
+    fn next_sector_address(&self, address: u32) -> u32 {
+        self.sector_address((self.sector_of(address) + 1) % self.sector_count)
     }
 }
+
+fn read_record(address: u32) -> Result<Record, FlashError> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    flash_read_bytes(address, &mut header)?;
+    if header.iter().all(|byte| *byte == 0xFF) {
+        return Ok(Record::Blank);
+    }
+    let size = RECORD_OVERHEAD + header[1] as u32;
+    if header[0] != RECORD_MAGIC || (address % SECTOR_SIZE) + size > SECTOR_SIZE {
+        return Ok(Record::Garbage);
+    }
+    let mut record = vec![0u8; size as usize];
+    flash_read_bytes(address, &mut record)?;
+    let (content, crc) = record.split_at(size as usize - 2);
+    if calculate_crc16(content) != u16::from_be_bytes([crc[0], crc[1]]) {
+        return Ok(Record::Corrupt(size));
+    }
+    let sequence = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+    Ok(Record::Valid(
+        sequence,
+        content[HEADER_SIZE as usize..].to_vec(),
+    ))
+}
+
+fn calculate_crc16(bytes: &[u8]) -> u16 {
+    let mut state = State::<XMODEM>::new();
+    state.update(bytes);
+    state.get()
+}
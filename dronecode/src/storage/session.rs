@@ -0,0 +1,263 @@
+// This file implements the flight sessions in the logs. Every arm of the motors starts a new
+// session: a short start record is written into the logs, and an entry that points at it is
+// written into the session directory, a separate log-structured storage. The logs of a session are
+// everything from its start record up to the start record of the next session.
+//
+// Directory entries are never changed. Erasing a session appends an `erased` entry, the logs of the
+// session stay in flash until the log storage wraps around and drops them. Since the log storage
+// drops its oldest records on its own, an entry whose start record is gone is not listed either.
+// The directory and the logs survive a reset, so the numbers continue from the previous boots and
+// the sessions of earlier flights can still be listed and downloaded.
+//
+// A start entry is `[kind u8][number u16][start tick u32][firmware version 3 bytes][config hash u16]
+// [log address u32]`, an erased entry is `[kind u8][number u16]`. The start record in the logs is
+// `[marker u8][number u16]`, the marker tells it apart from the logged `DeviceProtocol` messages.
+
+use alloc::vec::Vec;
+use protocol::report::{SessionEvent, SessionReport};
+use tudelft_quadrupel::flash::FlashError;
+
+use super::Storage;
+
+/// The first byte of a session start record in the logs, "S" in ASCII.
+pub const SESSION_MARKER: u8 = 0x53;
+/// Major, minor and patch version of this firmware, from Cargo.toml.
+pub const FIRMWARE_VERSION: [u8; 3] = [
+    parse_version(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_version(env!("CARGO_PKG_VERSION_MINOR")),
+    parse_version(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+const ENTRY_STARTED: u8 = 0x01;
+const ENTRY_ERASED: u8 = 0x02;
+const STARTED_ENTRY_SIZE: usize = 16;
+const ERASED_ENTRY_SIZE: usize = 3;
+
+const fn parse_version(part: &str) -> u8 {
+    let bytes = part.as_bytes();
+    let mut value = 0u8;
+    let mut index = 0;
+    while index < bytes.len() {
+        value = value * 10 + (bytes[index] - b'0');
+        index += 1;
+    }
+    value
+}
+
+/// Returns the session number if the logged record is a session start record.
+pub fn start_record_number(record: &[u8]) -> Option<u16> {
+    if record.len() == 3 && record[0] == SESSION_MARKER {
+        Some(u16::from_be_bytes([record[1], record[2]]))
+    } else {
+        None
+    }
+}
+
+/// A report about a session that has no directory entry (or all of them), the other fields are 0.
+pub fn session_report(event: SessionEvent, number: u16) -> SessionReport {
+    SessionReport {
+        event,
+        number,
+        start_tick: 0,
+        firmware_version: [0; 3],
+        config_hash: 0,
+    }
+}
+
+// A directory entry as it is stored in flash.
+enum Entry {
+    // The session was started, its start record is at `log_address` in the logs.
+    Started(SessionReport, u32),
+    Erased(u16),
+}
+
+impl Entry {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(STARTED_ENTRY_SIZE);
+        match self {
+            Entry::Started(session, log_address) => {
+                bytes.push(ENTRY_STARTED);
+                bytes.extend_from_slice(&session.number.to_be_bytes());
+                bytes.extend_from_slice(&session.start_tick.to_be_bytes());
+                bytes.extend_from_slice(&session.firmware_version);
+                bytes.extend_from_slice(&session.config_hash.to_be_bytes());
+                bytes.extend_from_slice(&log_address.to_be_bytes());
+            }
+            Entry::Erased(number) => {
+                bytes.push(ENTRY_ERASED);
+                bytes.extend_from_slice(&number.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Entry> {
+        match (bytes.first()?, bytes.len()) {
+            (&ENTRY_STARTED, STARTED_ENTRY_SIZE) => Some(Entry::Started(
+                SessionReport {
+                    event: SessionEvent::Listed,
+                    number: u16::from_be_bytes([bytes[1], bytes[2]]),
+                    start_tick: u32::from_be_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]),
+                    firmware_version: [bytes[7], bytes[8], bytes[9]],
+                    config_hash: u16::from_be_bytes([bytes[10], bytes[11]]),
+                },
+                u32::from_be_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            )),
+            (&ENTRY_ERASED, ERASED_ENTRY_SIZE) => {
+                Some(Entry::Erased(u16::from_be_bytes([bytes[1], bytes[2]])))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct SessionDirectory {
+    storage: Storage,
+    // The number of the next session, one more than the highest number in the directory.
+    next_number: u16,
+    // The sessions with an erased entry, so they are skipped without reading the directory twice.
+    erased: Vec<u16>,
+    // True while the entries are sent one by one after a list command.
+    listing: bool,
+}
+
+impl SessionDirectory {
+    /// Creates a directory in the flash region from `start_address` up to and including
+    /// `end_address`, `recover` has to be called before it is used.
+    pub fn new(start_address: u32, end_address: u32) -> Self {
+        SessionDirectory {
+            storage: Storage::new(start_address, end_address),
+            next_number: 1,
+            erased: Vec::new(),
+            listing: false,
+        }
+    }
+
+    /// Continues the directory of the previous boots.
+    pub fn recover(&mut self) -> Result<(), FlashError> {
+        self.storage.recover()?;
+        self.storage.rewind();
+        while let Some(bytes) = self.storage.read()? {
+            match Entry::from_bytes(&bytes) {
+                Some(Entry::Started(session, _)) => {
+                    self.next_number = self.next_number.max(session.number.wrapping_add(1));
+                }
+                Some(Entry::Erased(number)) => self.erased.push(number),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a new session: writes its start record into the logs and its entry into the
+    /// directory. Returns the report of the new session.
+    pub fn start(
+        &mut self,
+        log: &mut Storage,
+        start_tick: u32,
+        config_hash: u16,
+    ) -> Result<SessionReport, FlashError> {
+        let number = self.next_number;
+        let mut record = Vec::with_capacity(3);
+        record.push(SESSION_MARKER);
+        record.extend_from_slice(&number.to_be_bytes());
+        let log_address = log.write(&record)?;
+
+        let session = SessionReport {
+            event: SessionEvent::Started,
+            number,
+            start_tick,
+            firmware_version: FIRMWARE_VERSION,
+            config_hash,
+        };
+        self.storage
+            .write(&Entry::Started(session, log_address).to_bytes())?;
+        self.next_number = number.wrapping_add(1).max(1);
+        Ok(session)
+    }
+
+    /// Starts sending the directory, `next_listed` returns the entries one by one.
+    pub fn start_listing(&mut self) {
+        self.storage.rewind();
+        self.listing = true;
+    }
+
+    /// The next session of the directory that still has its logs, None when the listing is done.
+    pub fn next_listed(&mut self, log: &Storage) -> Result<Option<SessionReport>, FlashError> {
+        while self.listing {
+            match self.storage.read()? {
+                Some(bytes) => {
+                    if let Some(Entry::Started(session, log_address)) = Entry::from_bytes(&bytes) {
+                        if self.is_available(log, session.number, log_address)? {
+                            return Ok(Some(session));
+                        }
+                    }
+                }
+                None => self.listing = false,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the session, returns its entry and the address of its start record in the logs, or
+    /// None if it was erased or its logs are gone. Stops a listing that is in progress.
+    pub fn find(
+        &mut self,
+        log: &Storage,
+        number: u16,
+    ) -> Result<Option<(SessionReport, u32)>, FlashError> {
+        self.listing = false;
+        self.storage.rewind();
+        while let Some(bytes) = self.storage.read()? {
+            if let Some(Entry::Started(session, log_address)) = Entry::from_bytes(&bytes) {
+                if session.number == number && self.is_available(log, number, log_address)? {
+                    return Ok(Some((session, log_address)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Removes the session from the directory, returns its entry or None if it was not found.
+    pub fn erase(
+        &mut self,
+        log: &Storage,
+        number: u16,
+    ) -> Result<Option<SessionReport>, FlashError> {
+        let Some((session, _)) = self.find(log, number)? else {
+            return Ok(None);
+        };
+        self.storage.write(&Entry::Erased(number).to_bytes())?;
+        self.erased.push(number);
+        Ok(Some(session))
+    }
+
+    /// Erases the directory and the logs, the next session is number 1 again.
+    ///
+    /// Note: This erases every sector of both, and blocks for about a second!
+    pub fn erase_all(&mut self, log: &mut Storage) -> Result<(), FlashError> {
+        self.listing = false;
+        log.erase_all()?;
+        self.storage.erase_all()?;
+        self.erased.clear();
+        self.next_number = 1;
+        Ok(())
+    }
+
+    // The session was not erased and its start record is still in the logs.
+    fn is_available(
+        &self,
+        log: &Storage,
+        number: u16,
+        log_address: u32,
+    ) -> Result<bool, FlashError> {
+        if self.erased.contains(&number) {
+            return Ok(false);
+        }
+        Ok(log
+            .read_at(log_address)?
+            .as_deref()
+            .and_then(start_record_number)
+            == Some(number))
+    }
+}
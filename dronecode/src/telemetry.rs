@@ -0,0 +1,49 @@
+// This file implements the queue of reports waiting to be sent to the PC.
+// The UART TX buffer only holds 256 bytes and the `DeviceProtocol` telemetry already takes 52 of
+// them, so reports are not sent directly but queued and sent once there is room in the buffer.
+// Faults, arming and acknowledgements are urgent, the periodic reports and the log streams are
+// bulk and give way to them, see `protocol::queue`.
+
+use protocol::queue::{Priority, ReportQueue as PendingReports};
+use protocol::report::DeviceReport;
+use tudelft_quadrupel::uart::send_bytes;
+
+// With only 4 KB of heap, a report is dropped once this many reports are waiting.
+const MAX_PENDING_REPORTS: usize = 4;
+
+pub struct ReportQueue {
+    pending: PendingReports,
+}
+
+impl ReportQueue {
+    pub fn new() -> Self {
+        ReportQueue {
+            pending: PendingReports::new(MAX_PENDING_REPORTS),
+        }
+    }
+
+    // Queue a report the PC must see, e.g. a fault or the answer to a command.
+    pub fn push(&mut self, report: DeviceReport) {
+        self.pending.push(report, Priority::Urgent);
+    }
+
+    // Queue a periodic report or one of a log stream.
+    pub fn push_bulk(&mut self, report: DeviceReport) {
+        self.pending.push(report, Priority::Bulk);
+    }
+
+    // The number of reports that were dropped since boot.
+    pub fn dropped(&self) -> u32 {
+        self.pending.dropped()
+    }
+
+    // Send as many of the waiting reports as fit in the UART buffer, the urgent ones first.
+    pub fn flush(&mut self) {
+        while let Some(message) = self.pending.front() {
+            if !send_bytes(message) {
+                break;
+            }
+            self.pending.pop_front();
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use control_math::attitude::Attitude;
 use fixed_trigonometry::{atan, sqrt};
 use tudelft_quadrupel::{fixed::types::I16F16, mpu::structs::Quaternion};
 
@@ -45,3 +46,11 @@ impl From<Quaternion> for YawPitchRoll {
         Self { yaw, pitch, roll }
     }
 }
+
+// The estimators on the raw sensors use the same convention as the conversion above.
+impl From<Attitude> for YawPitchRoll {
+    fn from(attitude: Attitude) -> Self {
+        let Attitude { yaw, pitch, roll } = attitude;
+        Self { yaw, pitch, roll }
+    }
+}
@@ -0,0 +1,513 @@
+// This file contains the commands that the PC sends to the drone next to the `HostProtocol`
+// messages. A command is sent once instead of in every message, e.g. to arm the motors. Every
+// command is framed as `[ id args.. crc16 ]` and has the same length as a `HostProtocol` message,
+// so the drone can read both from the same buffer and tell them apart by the start flag.
+
+use crate::report::{AttitudeSource, NotchTuning, ATTITUDE_BY_MODE};
+use alloc::vec::Vec;
+use crc16::{State, XMODEM};
+
+pub const COMMAND_START_FLAG: u8 = 0x5b; // In ASCII, it is "["
+pub const COMMAND_END_FLAG: u8 = 0x5d; // In ASCII, it is "]"
+pub const COMMAND_SIZE: usize = 12;
+pub const COMMAND_ARGS_SIZE: usize = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandId {
+    /// Run the pre-arm checks and arm the motors if they pass.
+    Arm,
+    /// Stop the motors, only accepted with the throttle at zero.
+    Disarm,
+    /// Write the current gains, calibration and motor limits to flash.
+    SaveConfig,
+    /// Send the session directory, one `SessionReport` per entry.
+    ListSessions,
+    /// Send the logs of the session in `args[0..2]` in read logs mode.
+    DownloadSession,
+    /// Remove the session in `args[0..2]` from the directory.
+    EraseSession,
+    /// Erase all sessions and logs, only accepted in safe mode.
+    EraseAllSessions,
+    /// Send the black-box incidents in flash, one `IncidentReport` per sample.
+    ReadIncidents,
+    /// Send the event log in flash, one `EventReport` per event.
+    ReadEvents,
+    /// Send the crash record in flash.
+    FetchCrashDump,
+    /// Erase the crash record, only accepted in safe mode. The record is sent again afterwards.
+    ClearCrashDump,
+    /// Set the accelerometer bias and scale of one axis, from the six-position calibration.
+    SetAccelCorrection,
+    /// Select the attitude estimator the controllers fly on, and whether all estimators run to be
+    /// compared in the `AttitudeReport`.
+    SetAttitudeEstimator,
+    /// Set the notch filters of the gyroscope and the accelerometer against the motor vibration.
+    SetNotchFilter,
+    /// Set the `Parameter` in `args[0]` to the value in `args[1..5]`, only accepted in safe mode.
+    /// Like the other settings it is kept in flash by the next save.
+    SetParameter,
+}
+
+impl CommandId {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            CommandId::Arm => 0x01,
+            CommandId::Disarm => 0x02,
+            CommandId::SaveConfig => 0x03,
+            CommandId::ListSessions => 0x04,
+            CommandId::DownloadSession => 0x05,
+            CommandId::EraseSession => 0x06,
+            CommandId::EraseAllSessions => 0x07,
+            CommandId::ReadIncidents => 0x08,
+            CommandId::ReadEvents => 0x09,
+            CommandId::FetchCrashDump => 0x0A,
+            CommandId::ClearCrashDump => 0x0B,
+            CommandId::SetAccelCorrection => 0x0C,
+            CommandId::SetAttitudeEstimator => 0x0D,
+            CommandId::SetNotchFilter => 0x0E,
+            CommandId::SetParameter => 0x0F,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<CommandId> {
+        match byte {
+            0x01 => Some(CommandId::Arm),
+            0x02 => Some(CommandId::Disarm),
+            0x03 => Some(CommandId::SaveConfig),
+            0x04 => Some(CommandId::ListSessions),
+            0x05 => Some(CommandId::DownloadSession),
+            0x06 => Some(CommandId::EraseSession),
+            0x07 => Some(CommandId::EraseAllSessions),
+            0x08 => Some(CommandId::ReadIncidents),
+            0x09 => Some(CommandId::ReadEvents),
+            0x0A => Some(CommandId::FetchCrashDump),
+            0x0B => Some(CommandId::ClearCrashDump),
+            0x0C => Some(CommandId::SetAccelCorrection),
+            0x0D => Some(CommandId::SetAttitudeEstimator),
+            0x0E => Some(CommandId::SetNotchFilter),
+            0x0F => Some(CommandId::SetParameter),
+            _ => None,
+        }
+    }
+}
+
+// The values of the `*Action` parameters.
+pub const SAFETY_ACTION_CUT_MOTORS: i32 = 0;
+pub const SAFETY_ACTION_PANIC: i32 = 1;
+
+/// A limit or setting of the drone that is set with a `SetParameter` command, every value is an i32.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parameter {
+    /// The largest pitch or roll of the safety monitor in milliradians.
+    MaxTilt,
+    /// The number of ticks in a row the tilt has to be beyond the limit.
+    TiltTicks,
+    /// What the safety monitor does when the drone is tilted too far, a `SAFETY_ACTION_` value.
+    TiltAction,
+    /// The largest angular rate of any axis in raw gyroscope units (16.4 per deg/s).
+    MaxRate,
+    /// The number of ticks in a row the rate has to be beyond the limit.
+    TumbleTicks,
+    /// What the safety monitor does when the drone tumbles, a `SAFETY_ACTION_` value.
+    TumbleAction,
+    /// The largest acceleration of any axis in raw accelerometer units (16384 per g).
+    ImpactAccel,
+    /// The number of ticks in a row the acceleration has to be beyond the limit.
+    ImpactTicks,
+    /// What the safety monitor does on an impact, a `SAFETY_ACTION_` value.
+    ImpactAction,
+    /// The number of samples the calibration averages, one per tick.
+    CalibrationWindow,
+    /// The largest variance of a gyroscope axis during the calibration, in raw units squared.
+    MaxGyroVariance,
+    /// The largest variance of an accelerometer axis during the calibration, in raw units squared.
+    MaxAccelVariance,
+    /// The largest variance of yaw, pitch or roll during the calibration, in milliradians squared.
+    MaxAttitudeVariance,
+    /// The largest variance of the pressure during the calibration, in Pa squared.
+    MaxPressureVariance,
+    /// The number of ticks panic mode takes to ramp the motors down.
+    PanicLandingTicks,
+    /// The battery voltage below which flying is refused, in 10 mV.
+    BatteryWarningLevel,
+    /// The battery voltage below which the drone lands, in 10 mV. Below the warning level.
+    BatteryCriticalLevel,
+    /// How far the battery has to recover before a level is left, in 10 mV.
+    BatteryHysteresis,
+}
+
+impl Parameter {
+    pub const ALL: [Parameter; 18] = [
+        Parameter::MaxTilt,
+        Parameter::TiltTicks,
+        Parameter::TiltAction,
+        Parameter::MaxRate,
+        Parameter::TumbleTicks,
+        Parameter::TumbleAction,
+        Parameter::ImpactAccel,
+        Parameter::ImpactTicks,
+        Parameter::ImpactAction,
+        Parameter::CalibrationWindow,
+        Parameter::MaxGyroVariance,
+        Parameter::MaxAccelVariance,
+        Parameter::MaxAttitudeVariance,
+        Parameter::MaxPressureVariance,
+        Parameter::PanicLandingTicks,
+        Parameter::BatteryWarningLevel,
+        Parameter::BatteryCriticalLevel,
+        Parameter::BatteryHysteresis,
+    ];
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Parameter::MaxTilt => 0x01,
+            Parameter::TiltTicks => 0x02,
+            Parameter::TiltAction => 0x03,
+            Parameter::MaxRate => 0x04,
+            Parameter::TumbleTicks => 0x05,
+            Parameter::TumbleAction => 0x06,
+            Parameter::ImpactAccel => 0x07,
+            Parameter::ImpactAction => 0x08,
+            Parameter::CalibrationWindow => 0x09,
+            Parameter::MaxGyroVariance => 0x0A,
+            Parameter::MaxAccelVariance => 0x0B,
+            Parameter::MaxAttitudeVariance => 0x0C,
+            Parameter::MaxPressureVariance => 0x0D,
+            Parameter::PanicLandingTicks => 0x0E,
+            Parameter::BatteryWarningLevel => 0x0F,
+            Parameter::BatteryCriticalLevel => 0x10,
+            Parameter::BatteryHysteresis => 0x11,
+            Parameter::ImpactTicks => 0x12,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Parameter> {
+        match byte {
+            0x01 => Some(Parameter::MaxTilt),
+            0x02 => Some(Parameter::TiltTicks),
+            0x03 => Some(Parameter::TiltAction),
+            0x04 => Some(Parameter::MaxRate),
+            0x05 => Some(Parameter::TumbleTicks),
+            0x06 => Some(Parameter::TumbleAction),
+            0x07 => Some(Parameter::ImpactAccel),
+            0x08 => Some(Parameter::ImpactAction),
+            0x09 => Some(Parameter::CalibrationWindow),
+            0x0A => Some(Parameter::MaxGyroVariance),
+            0x0B => Some(Parameter::MaxAccelVariance),
+            0x0C => Some(Parameter::MaxAttitudeVariance),
+            0x0D => Some(Parameter::MaxPressureVariance),
+            0x0E => Some(Parameter::PanicLandingTicks),
+            0x0F => Some(Parameter::BatteryWarningLevel),
+            0x10 => Some(Parameter::BatteryCriticalLevel),
+            0x11 => Some(Parameter::BatteryHysteresis),
+            0x12 => Some(Parameter::ImpactTicks),
+            _ => None,
+        }
+    }
+
+    /// The name of the parameter in a parameter file of the runner.
+    pub fn name(self) -> &'static str {
+        match self {
+            Parameter::MaxTilt => "max_tilt",
+            Parameter::TiltTicks => "tilt_ticks",
+            Parameter::TiltAction => "tilt_action",
+            Parameter::MaxRate => "max_rate",
+            Parameter::TumbleTicks => "tumble_ticks",
+            Parameter::TumbleAction => "tumble_action",
+            Parameter::ImpactAccel => "impact_accel",
+            Parameter::ImpactTicks => "impact_ticks",
+            Parameter::ImpactAction => "impact_action",
+            Parameter::CalibrationWindow => "calibration_window",
+            Parameter::MaxGyroVariance => "max_gyro_variance",
+            Parameter::MaxAccelVariance => "max_accel_variance",
+            Parameter::MaxAttitudeVariance => "max_attitude_variance",
+            Parameter::MaxPressureVariance => "max_pressure_variance",
+            Parameter::PanicLandingTicks => "panic_landing_ticks",
+            Parameter::BatteryWarningLevel => "battery_warning_level",
+            Parameter::BatteryCriticalLevel => "battery_critical_level",
+            Parameter::BatteryHysteresis => "battery_hysteresis",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Parameter> {
+        Parameter::ALL
+            .iter()
+            .copied()
+            .find(|parameter| parameter.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HostCommand {
+    id: CommandId,
+    args: [u8; COMMAND_ARGS_SIZE], // The meaning depends on the command, unused bytes are 0
+}
+
+impl HostCommand {
+    pub fn new(id: CommandId) -> Self {
+        Self::with_args(id, [0; COMMAND_ARGS_SIZE])
+    }
+
+    pub fn with_args(id: CommandId, args: [u8; COMMAND_ARGS_SIZE]) -> Self {
+        Self { id, args }
+    }
+
+    // A command about a single session, the session number is the first two argument bytes
+    pub fn with_session(id: CommandId, number: u16) -> Self {
+        let mut args = [0; COMMAND_ARGS_SIZE];
+        args[..2].copy_from_slice(&number.to_be_bytes());
+        Self::with_args(id, args)
+    }
+
+    // The accelerometer correction of one axis (0 is x): the bias in raw units, and the scale that is
+    // applied after subtracting the bias in units of 1/32768
+    pub fn with_accel_correction(axis: u8, bias: i16, scale: u16) -> Self {
+        let mut args = [0; COMMAND_ARGS_SIZE];
+        args[0] = axis;
+        args[1..3].copy_from_slice(&bias.to_be_bytes());
+        args[3..5].copy_from_slice(&scale.to_be_bytes());
+        Self::with_args(CommandId::SetAccelCorrection, args)
+    }
+
+    // The attitude estimator to fly on, None to follow the flight mode, and whether all estimators
+    // run in parallel
+    pub fn with_attitude_estimator(estimator: Option<AttitudeSource>, parallel: bool) -> Self {
+        let mut args = [0; COMMAND_ARGS_SIZE];
+        args[0] = estimator.map_or(ATTITUDE_BY_MODE, |estimator| estimator.index() as u8);
+        args[1] = parallel as u8;
+        Self::with_args(CommandId::SetAttitudeEstimator, args)
+    }
+
+    // The tuning of the notch filters, the vibration frequency in Hz when it is fixed, the vibration
+    // frequency per unit of the average motor command in mHz when it is tracked, and the quality in
+    // units of 0.1
+    pub fn with_notch_filter(
+        tuning: NotchTuning,
+        centre: u16,
+        hz_per_command: u16,
+        quality: u8,
+    ) -> Self {
+        let mut args = [0; COMMAND_ARGS_SIZE];
+        args[0] = tuning.index() as u8;
+        args[1..3].copy_from_slice(&centre.to_be_bytes());
+        args[3..5].copy_from_slice(&hz_per_command.to_be_bytes());
+        args[5] = quality;
+        Self::with_args(CommandId::SetNotchFilter, args)
+    }
+
+    // One of the limits of the drone, see `Parameter` for the unit of the value
+    pub fn with_parameter(parameter: Parameter, value: i32) -> Self {
+        let mut args = [0; COMMAND_ARGS_SIZE];
+        args[0] = parameter.to_byte();
+        args[1..5].copy_from_slice(&value.to_be_bytes());
+        Self::with_args(CommandId::SetParameter, args)
+    }
+
+    // Form the message to be sent to the drone in bytes, the size of the message is 12 bytes
+    pub fn form_message(&self, message: &mut Vec<u8>) {
+        message.push(COMMAND_START_FLAG);
+        message.push(self.id.to_byte());
+        message.extend_from_slice(&self.args);
+        message.extend_from_slice(&self.calculate_crc16().to_be_bytes());
+        message.push(COMMAND_END_FLAG);
+    }
+
+    // Parse and verify a complete frame, returns None if the flags, the CRC or the id are wrong
+    pub fn format_message(message: &[u8]) -> Option<HostCommand> {
+        if message.len() != COMMAND_SIZE
+            || message[0] != COMMAND_START_FLAG
+            || message[COMMAND_SIZE - 1] != COMMAND_END_FLAG
+        {
+            return None;
+        }
+        let mut args = [0; COMMAND_ARGS_SIZE];
+        args.copy_from_slice(&message[2..2 + COMMAND_ARGS_SIZE]);
+        let command = HostCommand {
+            id: CommandId::from_byte(message[1])?,
+            args,
+        };
+        let crc = u16::from_be_bytes([message[9], message[10]]);
+        if crc != command.calculate_crc16() {
+            return None;
+        }
+        Some(command)
+    }
+
+    pub fn calculate_crc16(&self) -> u16 {
+        let mut state = State::<XMODEM>::new();
+        state.update(&[self.id.to_byte()]);
+        state.update(&self.args);
+        state.get()
+    }
+
+    pub fn get_id(&self) -> CommandId {
+        self.id
+    }
+
+    pub fn get_args(&self) -> [u8; COMMAND_ARGS_SIZE] {
+        self.args
+    }
+
+    pub fn get_session(&self) -> u16 {
+        u16::from_be_bytes([self.args[0], self.args[1]])
+    }
+
+    // The axis, the bias and the scale of a `SetAccelCorrection` command
+    pub fn get_accel_correction(&self) -> (u8, i16, u16) {
+        (
+            self.args[0],
+            i16::from_be_bytes([self.args[1], self.args[2]]),
+            u16::from_be_bytes([self.args[3], self.args[4]]),
+        )
+    }
+
+    // The estimator and the parallel flag of a `SetAttitudeEstimator` command, an unknown estimator
+    // follows the flight mode
+    pub fn get_attitude_estimator(&self) -> (Option<AttitudeSource>, bool) {
+        (
+            AttitudeSource::from_index(self.args[0] as usize),
+            self.args[1] != 0,
+        )
+    }
+
+    // The tuning, the centre, the frequency per motor command and the quality of a
+    // `SetNotchFilter` command, None for an unknown tuning
+    pub fn get_notch_filter(&self) -> Option<(NotchTuning, u16, u16, u8)> {
+        Some((
+            NotchTuning::from_index(self.args[0] as usize)?,
+            u16::from_be_bytes([self.args[1], self.args[2]]),
+            u16::from_be_bytes([self.args[3], self.args[4]]),
+            self.args[5],
+        ))
+    }
+
+    // The parameter and the value of a `SetParameter` command, None for an unknown parameter
+    pub fn get_parameter(&self) -> Option<(Parameter, i32)> {
+        Some((
+            Parameter::from_byte(self.args[0])?,
+            i32::from_be_bytes([self.args[1], self.args[2], self.args[3], self.args[4]]),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(command: &HostCommand) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        command.form_message(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn command_ids_round_trip() {
+        for byte in 0..=u8::MAX {
+            if let Some(id) = CommandId::from_byte(byte) {
+                assert_eq!(id.to_byte(), byte);
+            }
+        }
+    }
+
+    #[test]
+    fn host_command_round_trips() {
+        let commands = [
+            HostCommand::new(CommandId::Arm),
+            HostCommand::with_args(CommandId::Disarm, [1, 2, 3, 4, 5, 6, 7]),
+            HostCommand::with_session(CommandId::DownloadSession, 0x1234),
+            HostCommand::with_accel_correction(2, -300, 32_900),
+            HostCommand::with_attitude_estimator(Some(AttitudeSource::Mahony), true),
+            HostCommand::with_attitude_estimator(None, false),
+            HostCommand::with_notch_filter(NotchTuning::Tracking, 200, 500, 25),
+            HostCommand::with_parameter(Parameter::MaxRate, 13_120),
+        ];
+        for command in commands {
+            let bytes = encode(&command);
+            assert_eq!(bytes.len(), COMMAND_SIZE);
+            assert_eq!(HostCommand::format_message(&bytes), Some(command));
+        }
+    }
+
+    #[test]
+    fn arguments_are_read_back() {
+        let args = [1, 2, 3, 4, 5, 6, 7];
+        let command = HostCommand::with_args(CommandId::Disarm, args);
+        assert_eq!(command.get_id(), CommandId::Disarm);
+        assert_eq!(command.get_args(), args);
+        assert_eq!(
+            HostCommand::with_session(CommandId::EraseSession, 513).get_session(),
+            513
+        );
+        assert_eq!(
+            HostCommand::with_accel_correction(1, -300, 32_900).get_accel_correction(),
+            (1, -300, 32_900)
+        );
+        assert_eq!(
+            HostCommand::with_attitude_estimator(Some(AttitudeSource::Kalman), true)
+                .get_attitude_estimator(),
+            (Some(AttitudeSource::Kalman), true)
+        );
+        assert_eq!(
+            HostCommand::with_attitude_estimator(None, false).get_attitude_estimator(),
+            (None, false)
+        );
+        assert_eq!(
+            HostCommand::with_notch_filter(NotchTuning::Fixed, 180, 0, 30).get_notch_filter(),
+            Some((NotchTuning::Fixed, 180, 0, 30))
+        );
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        for parameter in Parameter::ALL {
+            assert_eq!(Parameter::from_byte(parameter.to_byte()), Some(parameter));
+            assert_eq!(Parameter::from_name(parameter.name()), Some(parameter));
+            for value in [0, 1, -1, i32::MAX, i32::MIN] {
+                let command = HostCommand::with_parameter(parameter, value);
+                let decoded = HostCommand::format_message(&encode(&command));
+                assert_eq!(
+                    decoded.and_then(|command| command.get_parameter()),
+                    Some((parameter, value))
+                );
+            }
+        }
+        assert_eq!(Parameter::from_byte(0x00), None);
+        assert_eq!(Parameter::from_name("max_speed"), None);
+    }
+
+    #[test]
+    fn crc_mismatch_is_rejected() {
+        let mut bytes = encode(&HostCommand::with_args(
+            CommandId::Arm,
+            [0, 0, 3, 0, 0, 0, 0],
+        ));
+        bytes[3] ^= 0x01;
+        assert_eq!(HostCommand::format_message(&bytes), None);
+    }
+
+    #[test]
+    fn broken_frames_are_rejected() {
+        let bytes = encode(&HostCommand::new(CommandId::Disarm));
+
+        let mut wrong_end = bytes.clone();
+        wrong_end[COMMAND_SIZE - 1] = 0x7d;
+        assert_eq!(HostCommand::format_message(&wrong_end), None);
+
+        let mut wrong_start = bytes.clone();
+        wrong_start[0] = 0x7b;
+        assert_eq!(HostCommand::format_message(&wrong_start), None);
+
+        assert_eq!(
+            HostCommand::format_message(&bytes[..COMMAND_SIZE - 1]),
+            None
+        );
+
+        // an unknown id with a matching CRC
+        let mut unknown = bytes;
+        unknown[1] = 0x7f;
+        let mut state = State::<XMODEM>::new();
+        state.update(&unknown[1..2 + COMMAND_ARGS_SIZE]);
+        unknown[9..11].copy_from_slice(&state.get().to_be_bytes());
+        assert_eq!(HostCommand::format_message(&unknown), None);
+    }
+}
@@ -3,7 +3,7 @@ extern crate alloc;
 #[cfg(test)]
 extern crate std;
 
+pub mod command; // one-off commands from the PC, e.g. arming the motors
 pub mod format; // this is to load the data_format.rs file and the structs in it
-
-#[cfg(test)]
-mod tests {}
+pub mod queue; // the reports waiting on the drone, the urgent ones go first
+pub mod report; // variable-length reports from the drone, e.g. the loop profile
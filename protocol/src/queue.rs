@@ -0,0 +1,146 @@
+// This file contains the queue of reports that wait on the drone until there is room in the UART
+// buffer. The heap only fits a few reports, so when the queue is full a report has to go. Reports
+// the PC must see (faults, arming, acknowledgements) are urgent, the periodic telemetry and the log
+// streams are bulk: urgent reports are sent first and a full queue drops bulk reports before
+// urgent ones. Every dropped report is counted, the count is sent in the `StatusReport`.
+
+use crate::report::DeviceReport;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Sent before any bulk report, only dropped when the queue holds nothing else.
+    Urgent,
+    /// Periodic or streamed, the next one follows anyway.
+    Bulk,
+}
+
+pub struct ReportQueue {
+    urgent: VecDeque<Vec<u8>>,
+    bulk: VecDeque<Vec<u8>>,
+    capacity: usize,
+    dropped: u32,
+}
+
+impl ReportQueue {
+    /// A queue that holds at most `capacity` reports of both priorities together.
+    pub fn new(capacity: usize) -> Self {
+        ReportQueue {
+            urgent: VecDeque::new(),
+            bulk: VecDeque::new(),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    /// Form the message of the report and queue it. When the queue is full the oldest bulk
+    /// report is dropped, or the new report itself if it is bulk and everything queued is urgent.
+    /// Only a queue full of urgent reports drops its oldest urgent report.
+    pub fn push(&mut self, report: DeviceReport, priority: Priority) {
+        let mut message = Vec::new();
+        report.form_message(&mut message);
+        if self.len() >= self.capacity {
+            self.dropped = self.dropped.saturating_add(1);
+            if self.bulk.pop_front().is_none() {
+                if priority == Priority::Bulk {
+                    return;
+                }
+                self.urgent.pop_front();
+            }
+        }
+        match priority {
+            Priority::Urgent => self.urgent.push_back(message),
+            Priority::Bulk => self.bulk.push_back(message),
+        }
+    }
+
+    /// The message to send next, the urgent ones first and in the order they were queued.
+    pub fn front(&self) -> Option<&[u8]> {
+        self.urgent.front().or(self.bulk.front()).map(Vec::as_slice)
+    }
+
+    /// Remove the message of `front` once it has been sent.
+    pub fn pop_front(&mut self) {
+        if self.urgent.pop_front().is_none() {
+            self.bulk.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.urgent.len() + self.bulk.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of reports that were dropped since boot.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::ReportKind;
+    use alloc::vec;
+
+    fn report(kind: ReportKind, tag: u8) -> DeviceReport {
+        DeviceReport::new(kind, vec![tag])
+    }
+
+    // The tags of the queued messages in the order they are sent.
+    fn drain(queue: &mut ReportQueue) -> Vec<u8> {
+        let mut tags = Vec::new();
+        while let Some(message) = queue.front() {
+            tags.push(message[3]);
+            queue.pop_front();
+        }
+        tags
+    }
+
+    #[test]
+    fn urgent_reports_are_sent_first() {
+        let mut queue = ReportQueue::new(4);
+        queue.push(report(ReportKind::Status, 1), Priority::Bulk);
+        queue.push(report(ReportKind::Fault, 2), Priority::Urgent);
+        queue.push(report(ReportKind::Attitude, 3), Priority::Bulk);
+        queue.push(report(ReportKind::Arming, 4), Priority::Urgent);
+        assert_eq!(drain(&mut queue), vec![2, 4, 1, 3]);
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn streaming_never_evicts_urgent_reports() {
+        let mut queue = ReportQueue::new(4);
+        queue.push(report(ReportKind::Fault, 1), Priority::Urgent);
+        queue.push(report(ReportKind::Config, 2), Priority::Urgent);
+        for tag in 10..30 {
+            queue.push(report(ReportKind::Incident, tag), Priority::Bulk);
+        }
+        // the newest bulk reports are kept next to the urgent ones
+        assert_eq!(queue.dropped(), 18);
+        assert_eq!(drain(&mut queue), vec![1, 2, 28, 29]);
+    }
+
+    #[test]
+    fn urgent_report_evicts_bulk_first() {
+        let mut queue = ReportQueue::new(2);
+        queue.push(report(ReportKind::Event, 1), Priority::Bulk);
+        queue.push(report(ReportKind::Event, 2), Priority::Bulk);
+        queue.push(report(ReportKind::Fault, 3), Priority::Urgent);
+        queue.push(report(ReportKind::Arming, 4), Priority::Urgent);
+        assert_eq!(queue.dropped(), 2);
+        // a bulk report into a queue full of urgent reports is dropped itself
+        queue.push(report(ReportKind::Status, 5), Priority::Bulk);
+        assert_eq!(queue.dropped(), 3);
+        assert_eq!(queue.len(), 2);
+        // only then the oldest urgent report goes
+        queue.push(report(ReportKind::Fault, 6), Priority::Urgent);
+        assert_eq!(queue.dropped(), 4);
+        assert_eq!(drain(&mut queue), vec![4, 6]);
+        assert!(queue.is_empty());
+    }
+}
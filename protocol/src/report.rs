@@ -0,0 +1,1802 @@
+// This file contains the variable-length reports that the drone sends to the PC next to the
+// fixed-size `DeviceProtocol` telemetry. Every report is framed as
+// `[ kind length payload.. crc16 ]`, so the PC can tell them apart from `{ .. }` telemetry frames.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crc16::{State, XMODEM};
+
+pub const REPORT_START_FLAG: u8 = 0x5b; // In ASCII, it is "["
+pub const REPORT_END_FLAG: u8 = 0x5d; // In ASCII, it is "]"
+
+// start flag + kind + length in front of the payload, crc + end flag behind it
+const REPORT_HEADER_SIZE: usize = 3;
+const REPORT_FOOTER_SIZE: usize = 3;
+
+/// The type of payload carried by a `DeviceReport`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportKind {
+    /// Execution time of every control loop stage, see `ProfilingReport`.
+    Profiling,
+    /// Something went wrong on the drone, see `FaultEvent`.
+    Fault,
+    /// Health of the drone, see `StatusReport`.
+    Status,
+    /// The motors were armed or disarmed, or arming was refused, see `ArmingReport`.
+    Arming,
+    /// The outcome of the pre-flight self test, see `SelfTestReport`.
+    SelfTest,
+    /// The configuration in flash was loaded or saved, see `ConfigReport`.
+    Config,
+    /// A flight session in the flash logs, see `SessionReport`.
+    Session,
+    /// One sample of a black-box incident in flash, see `IncidentReport`.
+    Incident,
+    /// An entry of the event log, see `EventReport`.
+    Event,
+    /// The record the panic handler left in flash, see `CrashReport`. An empty payload means
+    /// there is no record.
+    Crash,
+    /// The offsets and the quality of a finished calibration, see `CalibrationReport`.
+    Calibration,
+    /// The output of the attitude estimators, see `AttitudeReport`.
+    Attitude,
+    /// The notch filters and the vibration before and after the filters, see `VibrationReport`.
+    Vibration,
+}
+
+impl ReportKind {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            ReportKind::Profiling => 0x01,
+            ReportKind::Fault => 0x02,
+            ReportKind::Status => 0x03,
+            ReportKind::Arming => 0x04,
+            ReportKind::SelfTest => 0x05,
+            ReportKind::Config => 0x06,
+            ReportKind::Session => 0x07,
+            ReportKind::Incident => 0x08,
+            ReportKind::Event => 0x09,
+            ReportKind::Crash => 0x0A,
+            ReportKind::Calibration => 0x0B,
+            ReportKind::Attitude => 0x0C,
+            ReportKind::Vibration => 0x0D,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<ReportKind> {
+        match byte {
+            0x01 => Some(ReportKind::Profiling),
+            0x02 => Some(ReportKind::Fault),
+            0x03 => Some(ReportKind::Status),
+            0x04 => Some(ReportKind::Arming),
+            0x05 => Some(ReportKind::SelfTest),
+            0x06 => Some(ReportKind::Config),
+            0x07 => Some(ReportKind::Session),
+            0x08 => Some(ReportKind::Incident),
+            0x09 => Some(ReportKind::Event),
+            0x0A => Some(ReportKind::Crash),
+            0x0B => Some(ReportKind::Calibration),
+            0x0C => Some(ReportKind::Attitude),
+            0x0D => Some(ReportKind::Vibration),
+            _ => None,
+        }
+    }
+}
+
+pub struct DeviceReport {
+    start_flag: u8,   // By default, this would be set to 0x5b
+    kind: u8,         // The `ReportKind` of the payload
+    payload: Vec<u8>, // At most 255 bytes, the length is sent in front of the payload
+    crc: u16,         // Cyclic redundancy check over kind, length and payload
+    end_flag: u8,     // By default, this would be set to 0x5d
+}
+
+impl DeviceReport {
+    pub fn new(kind: ReportKind, payload: Vec<u8>) -> Self {
+        assert!(payload.len() <= u8::MAX as usize);
+        Self {
+            start_flag: REPORT_START_FLAG,
+            kind: kind.to_byte(),
+            payload,
+            crc: 0x0000, // The CRC is calculated when the message is formed
+            end_flag: REPORT_END_FLAG,
+        }
+    }
+
+    // Form the message to be sent to the host in bytes
+    pub fn form_message(&self, message: &mut Vec<u8>) {
+        message.push(self.start_flag);
+        message.push(self.kind);
+        message.push(self.payload.len() as u8);
+        message.extend_from_slice(&self.payload);
+        message.extend_from_slice(&self.calculate_crc16().to_be_bytes());
+        message.push(self.end_flag);
+    }
+
+    // Parse a complete frame, the length of `message` must match the length byte
+    pub fn format_message(message: &[u8]) -> DeviceReport {
+        let length = message[2] as usize;
+        let payload_end = REPORT_HEADER_SIZE + length;
+        DeviceReport {
+            start_flag: message[0],
+            kind: message[1],
+            payload: message[REPORT_HEADER_SIZE..payload_end].to_vec(),
+            crc: u16::from_be_bytes([message[payload_end], message[payload_end + 1]]),
+            end_flag: message[payload_end + 2],
+        }
+    }
+
+    /// True if the flags and the CRC of a received report are right.
+    pub fn is_valid(&self) -> bool {
+        self.start_flag == REPORT_START_FLAG
+            && self.end_flag == REPORT_END_FLAG
+            && self.crc == self.calculate_crc16()
+    }
+
+    pub fn calculate_crc16(&self) -> u16 {
+        let mut state = State::<XMODEM>::new();
+        state.update(&[self.kind]);
+        state.update(&[self.payload.len() as u8]);
+        state.update(&self.payload);
+        state.get()
+    }
+
+    pub fn get_start_flag(&self) -> u8 {
+        self.start_flag
+    }
+
+    pub fn get_kind(&self) -> Option<ReportKind> {
+        ReportKind::from_byte(self.kind)
+    }
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn get_crc(&self) -> u16 {
+        self.crc
+    }
+
+    pub fn get_end_flag(&self) -> u8 {
+        self.end_flag
+    }
+}
+
+/// Collects report frames byte by byte from a serial stream.
+///
+/// A frame whose kind is unknown, or whose CRC or end flag is wrong, is dropped and the decoder
+/// starts again at the next start flag inside it, so a corrupted length byte does not swallow the
+/// reports behind it.
+pub struct ReportDecoder {
+    buffer: Vec<u8>,
+}
+
+impl ReportDecoder {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    /// True while a frame has been started but is not complete yet.
+    pub fn is_receiving(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// Feed the next byte, returns the report once its last byte has been received and it is
+    /// valid.
+    pub fn push(&mut self, byte: u8) -> Option<DeviceReport> {
+        if self.buffer.is_empty() && byte != REPORT_START_FLAG {
+            return None;
+        }
+        self.buffer.push(byte);
+        loop {
+            if self.buffer.len() >= 2 && ReportKind::from_byte(self.buffer[1]).is_none() {
+                self.resync();
+                continue;
+            }
+            if self.buffer.len() < REPORT_HEADER_SIZE {
+                return None;
+            }
+            let frame_size = REPORT_HEADER_SIZE + self.buffer[2] as usize + REPORT_FOOTER_SIZE;
+            if self.buffer.len() < frame_size {
+                return None;
+            }
+            let report = DeviceReport::format_message(&self.buffer[..frame_size]);
+            if !report.is_valid() {
+                self.resync();
+                continue;
+            }
+            // After a resync, the bytes behind the frame are kept from the next start flag on.
+            self.buffer.drain(..frame_size);
+            let next_start = self
+                .buffer
+                .iter()
+                .position(|byte| *byte == REPORT_START_FLAG)
+                .unwrap_or(self.buffer.len());
+            self.buffer.drain(..next_start);
+            return Some(report);
+        }
+    }
+
+    // Drop the frame at the front of the buffer and start again at the next start flag after it.
+    fn resync(&mut self) {
+        let next_start = self.buffer[1..]
+            .iter()
+            .position(|byte| *byte == REPORT_START_FLAG)
+            .map_or(self.buffer.len(), |position| position + 1);
+        self.buffer.drain(..next_start);
+    }
+}
+
+impl Default for ReportDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The stages of the drone control loop that are timed by the profiler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileStage {
+    SensorRead,
+    DmpDecode,
+    Filtering,
+    Control,
+    Mixing,
+    UartRx,
+    UartTx,
+    FlashLog,
+}
+
+pub const PROFILE_STAGE_COUNT: usize = 8;
+
+impl ProfileStage {
+    pub const ALL: [ProfileStage; PROFILE_STAGE_COUNT] = [
+        ProfileStage::SensorRead,
+        ProfileStage::DmpDecode,
+        ProfileStage::Filtering,
+        ProfileStage::Control,
+        ProfileStage::Mixing,
+        ProfileStage::UartRx,
+        ProfileStage::UartTx,
+        ProfileStage::FlashLog,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProfileStage::SensorRead => "sensor read",
+            ProfileStage::DmpDecode => "dmp decode",
+            ProfileStage::Filtering => "filtering",
+            ProfileStage::Control => "control",
+            ProfileStage::Mixing => "mixing",
+            ProfileStage::UartRx => "uart rx",
+            ProfileStage::UartTx => "uart tx",
+            ProfileStage::FlashLog => "flash log",
+        }
+    }
+}
+
+/// Execution time of a single stage (or the whole loop) in microseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StageTiming {
+    pub avg_us: u16,
+    pub max_us: u16,
+    pub overruns: u16,
+}
+
+impl StageTiming {
+    fn form_payload(&self, payload: &mut Vec<u8>) {
+        payload.extend_from_slice(&self.avg_us.to_be_bytes());
+        payload.extend_from_slice(&self.max_us.to_be_bytes());
+        payload.extend_from_slice(&self.overruns.to_be_bytes());
+    }
+
+    fn format_payload(payload: &[u8]) -> StageTiming {
+        StageTiming {
+            avg_us: u16::from_be_bytes([payload[0], payload[1]]),
+            max_us: u16::from_be_bytes([payload[2], payload[3]]),
+            overruns: u16::from_be_bytes([payload[4], payload[5]]),
+        }
+    }
+}
+
+const STAGE_TIMING_SIZE: usize = 6;
+
+/// Timing of every control loop stage, indexed by `ProfileStage::index`, plus the whole loop.
+/// Averages and maxima cover the last report window, overruns are counted since boot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProfilingReport {
+    pub stages: [StageTiming; PROFILE_STAGE_COUNT],
+    pub whole_loop: StageTiming,
+}
+
+impl ProfilingReport {
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::new();
+        for stage in self.stages.iter() {
+            stage.form_payload(&mut payload);
+        }
+        self.whole_loop.form_payload(&mut payload);
+        DeviceReport::new(ReportKind::Profiling, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<ProfilingReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Profiling)
+            || payload.len() != (PROFILE_STAGE_COUNT + 1) * STAGE_TIMING_SIZE
+        {
+            return None;
+        }
+        let mut profiling = ProfilingReport::default();
+        for (i, chunk) in payload.chunks(STAGE_TIMING_SIZE).enumerate() {
+            if i < PROFILE_STAGE_COUNT {
+                profiling.stages[i] = StageTiming::format_payload(chunk);
+            } else {
+                profiling.whole_loop = StageTiming::format_payload(chunk);
+            }
+        }
+        Some(profiling)
+    }
+}
+
+/// The reason of a `FaultEvent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultCode {
+    /// The control loop missed one or more ticks, the value is the number of missed ticks.
+    DeadlineMiss,
+    /// The control loop missed so many ticks that the drone went into panic, the value is the number of missed ticks.
+    LoopStarved,
+    /// The battery reached the critical level and the drone went into panic, the value is the battery voltage in 10 mV.
+    BatteryCritical,
+    /// The link to the PC was lost, the attitude is held, the value is the grace period in ticks.
+    LinkHolding,
+    /// The link to the PC was lost and the drone went into panic, the value is how long the attitude was held in ticks.
+    LinkLost,
+    /// The link to the PC came back during the grace period, the value is how long it was lost in ticks.
+    LinkRestored,
+    /// Pitch or roll was beyond the limit, the value is the tilt in milliradians.
+    ExcessiveTilt,
+    /// The angular rate was beyond the limit for too long, the value is the rate in raw gyroscope units.
+    Tumbling,
+    /// An acceleration spike, the value is the acceleration in raw accelerometer units.
+    Impact,
+}
+
+impl FaultCode {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            FaultCode::DeadlineMiss => 0x01,
+            FaultCode::LoopStarved => 0x02,
+            FaultCode::BatteryCritical => 0x03,
+            FaultCode::LinkHolding => 0x04,
+            FaultCode::LinkLost => 0x05,
+            FaultCode::LinkRestored => 0x06,
+            FaultCode::ExcessiveTilt => 0x07,
+            FaultCode::Tumbling => 0x08,
+            FaultCode::Impact => 0x09,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<FaultCode> {
+        match byte {
+            0x01 => Some(FaultCode::DeadlineMiss),
+            0x02 => Some(FaultCode::LoopStarved),
+            0x03 => Some(FaultCode::BatteryCritical),
+            0x04 => Some(FaultCode::LinkHolding),
+            0x05 => Some(FaultCode::LinkLost),
+            0x06 => Some(FaultCode::LinkRestored),
+            0x07 => Some(FaultCode::ExcessiveTilt),
+            0x08 => Some(FaultCode::Tumbling),
+            0x09 => Some(FaultCode::Impact),
+            _ => None,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            FaultCode::DeadlineMiss => "deadline miss",
+            FaultCode::LoopStarved => "loop starved",
+            FaultCode::BatteryCritical => "battery critical",
+            FaultCode::LinkHolding => "link lost, hold",
+            FaultCode::LinkLost => "link lost, land",
+            FaultCode::LinkRestored => "link restored",
+            FaultCode::ExcessiveTilt => "excessive tilt",
+            FaultCode::Tumbling => "tumbling",
+            FaultCode::Impact => "impact",
+        }
+    }
+}
+
+/// A fault detected by the drone, together with the mode and the control loop tick it happened in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultEvent {
+    pub code: FaultCode,
+    pub mode: u8,
+    pub tick: u32,
+    pub value: u16,
+}
+
+impl FaultEvent {
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::new();
+        payload.push(self.code.to_byte());
+        payload.push(self.mode);
+        payload.extend_from_slice(&self.tick.to_be_bytes());
+        payload.extend_from_slice(&self.value.to_be_bytes());
+        DeviceReport::new(ReportKind::Fault, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<FaultEvent> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Fault) || payload.len() != 8 {
+            return None;
+        }
+        Some(FaultEvent {
+            code: FaultCode::from_byte(payload[0])?,
+            mode: payload[1],
+            tick: u32::from_be_bytes([payload[2], payload[3], payload[4], payload[5]]),
+            value: u16::from_be_bytes([payload[6], payload[7]]),
+        })
+    }
+}
+
+// The bits of `StatusReport::flags`.
+pub const STATUS_BATTERY_LOW: u16 = 0b0000_0000_0000_0001;
+pub const STATUS_BATTERY_CRITICAL: u16 = 0b0000_0000_0000_0010;
+pub const STATUS_ARMED: u16 = 0b0000_0000_0000_0100;
+
+/// The health of the drone, sent a few times per second.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatusReport {
+    pub flags: u16,
+    /// Filtered battery voltage in 10 mV.
+    pub battery: u16,
+    /// Number of motor mixes that saturated since the previous status report.
+    pub saturated_mixes: u16,
+    /// Number of motor mixes that saturated since boot.
+    pub saturated_total: u32,
+    /// Estimated altitude above the pressure of the calibration in mm, 0 until calibrated.
+    pub altitude: i32,
+    /// Estimated vertical velocity in mm/s, positive is climbing.
+    pub vertical_velocity: i16,
+    /// Number of reports the drone dropped since boot because its queue was full.
+    pub dropped_reports: u32,
+}
+
+impl StatusReport {
+    pub fn has_flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&self.flags.to_be_bytes());
+        payload.extend_from_slice(&self.battery.to_be_bytes());
+        payload.extend_from_slice(&self.saturated_mixes.to_be_bytes());
+        payload.extend_from_slice(&self.saturated_total.to_be_bytes());
+        payload.extend_from_slice(&self.altitude.to_be_bytes());
+        payload.extend_from_slice(&self.vertical_velocity.to_be_bytes());
+        payload.extend_from_slice(&self.dropped_reports.to_be_bytes());
+        DeviceReport::new(ReportKind::Status, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<StatusReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Status) || payload.len() != 20 {
+            return None;
+        }
+        Some(StatusReport {
+            flags: u16::from_be_bytes([payload[0], payload[1]]),
+            battery: u16::from_be_bytes([payload[2], payload[3]]),
+            saturated_mixes: u16::from_be_bytes([payload[4], payload[5]]),
+            saturated_total: u32::from_be_bytes([payload[6], payload[7], payload[8], payload[9]]),
+            altitude: i32::from_be_bytes([payload[10], payload[11], payload[12], payload[13]]),
+            vertical_velocity: i16::from_be_bytes([payload[14], payload[15]]),
+            dropped_reports: u32::from_be_bytes([
+                payload[16],
+                payload[17],
+                payload[18],
+                payload[19],
+            ]),
+        })
+    }
+}
+
+// The bits of `ArmingReport::failed_checks`, every bit is a pre-arm check that did not pass.
+pub const PREARM_NOT_SAFE_MODE: u16 = 0b0000_0000_0000_0001;
+pub const PREARM_NOT_CALIBRATED: u16 = 0b0000_0000_0000_0010;
+pub const PREARM_NOT_LEVEL: u16 = 0b0000_0000_0000_0100;
+pub const PREARM_THROTTLE_NOT_ZERO: u16 = 0b0000_0000_0000_1000;
+pub const PREARM_BATTERY_LOW: u16 = 0b0000_0000_0001_0000;
+pub const PREARM_LINK_UNHEALTHY: u16 = 0b0000_0000_0010_0000;
+pub const PREARM_SENSORS_UNHEALTHY: u16 = 0b0000_0000_0100_0000;
+
+pub const PREARM_CHECKS: [(u16, &str); 7] = [
+    (PREARM_NOT_SAFE_MODE, "not in safe mode"),
+    (PREARM_NOT_CALIBRATED, "not calibrated"),
+    (PREARM_NOT_LEVEL, "not level"),
+    (PREARM_THROTTLE_NOT_ZERO, "throttle not zero"),
+    (PREARM_BATTERY_LOW, "battery low"),
+    (PREARM_LINK_UNHEALTHY, "link unhealthy"),
+    (PREARM_SENSORS_UNHEALTHY, "sensors unhealthy"),
+];
+
+/// What happened to the arming state of the motors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArmingEvent {
+    Armed,
+    Disarmed,
+    /// The arm or disarm command was refused, the failed checks tell why.
+    Refused,
+    /// The throttle was at zero for too long.
+    AutoDisarmed,
+    /// The drone went into panic, it has to be armed again after landing.
+    PanicDisarmed,
+    /// The safety monitor cut the motors.
+    SafetyCut,
+}
+
+impl ArmingEvent {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            ArmingEvent::Armed => 0x01,
+            ArmingEvent::Disarmed => 0x02,
+            ArmingEvent::Refused => 0x03,
+            ArmingEvent::AutoDisarmed => 0x04,
+            ArmingEvent::PanicDisarmed => 0x05,
+            ArmingEvent::SafetyCut => 0x06,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<ArmingEvent> {
+        match byte {
+            0x01 => Some(ArmingEvent::Armed),
+            0x02 => Some(ArmingEvent::Disarmed),
+            0x03 => Some(ArmingEvent::Refused),
+            0x04 => Some(ArmingEvent::AutoDisarmed),
+            0x05 => Some(ArmingEvent::PanicDisarmed),
+            0x06 => Some(ArmingEvent::SafetyCut),
+            _ => None,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ArmingEvent::Armed => "armed",
+            ArmingEvent::Disarmed => "disarmed",
+            ArmingEvent::Refused => "refused",
+            ArmingEvent::AutoDisarmed => "auto disarmed",
+            ArmingEvent::PanicDisarmed => "disarmed by panic",
+            ArmingEvent::SafetyCut => "safety cut",
+        }
+    }
+}
+
+/// Sent whenever the motors are armed or disarmed, or when arming is refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArmingReport {
+    pub event: ArmingEvent,
+    pub armed: bool,
+    /// The `PREARM_` bits of the checks that did not pass, only set when the event is `Refused`.
+    pub failed_checks: u16,
+}
+
+impl ArmingReport {
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::new();
+        payload.push(self.event.to_byte());
+        payload.push(self.armed as u8);
+        payload.extend_from_slice(&self.failed_checks.to_be_bytes());
+        DeviceReport::new(ReportKind::Arming, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<ArmingReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Arming) || payload.len() != 4 {
+            return None;
+        }
+        Some(ArmingReport {
+            event: ArmingEvent::from_byte(payload[0])?,
+            armed: payload[1] != 0,
+            failed_checks: u16::from_be_bytes([payload[2], payload[3]]),
+        })
+    }
+
+    pub fn has_failed(&self, check: u16) -> bool {
+        self.failed_checks & check != 0
+    }
+}
+
+// The bits of `SelfTestReport::failed_checks`, every bit is a part of the self test that did not pass.
+pub const SELFTEST_MOTOR_1: u16 = 0b0000_0000_0000_0001;
+pub const SELFTEST_MOTOR_2: u16 = 0b0000_0000_0000_0010;
+pub const SELFTEST_MOTOR_3: u16 = 0b0000_0000_0000_0100;
+pub const SELFTEST_MOTOR_4: u16 = 0b0000_0000_0000_1000;
+pub const SELFTEST_ACCEL: u16 = 0b0000_0000_0001_0000;
+pub const SELFTEST_GYRO: u16 = 0b0000_0000_0010_0000;
+pub const SELFTEST_BAROMETER: u16 = 0b0000_0000_0100_0000;
+pub const SELFTEST_BATTERY: u16 = 0b0000_0000_1000_0000;
+
+pub const SELFTEST_MOTORS: [u16; 4] = [
+    SELFTEST_MOTOR_1,
+    SELFTEST_MOTOR_2,
+    SELFTEST_MOTOR_3,
+    SELFTEST_MOTOR_4,
+];
+
+/// The measurements of the pre-flight self test and the checks that failed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// The `SELFTEST_` bits of the checks that did not pass, 0 when everything passed.
+    pub failed_checks: u16,
+    /// Largest peak-to-peak accelerometer reading of an axis with the motors off, in raw units.
+    pub accel_noise: u16,
+    /// Largest peak-to-peak gyroscope reading of an axis with the motors off, in raw units.
+    pub gyro_noise: u16,
+    /// Largest peak-to-peak accelerometer reading while only that motor was spinning.
+    pub motor_vibration: [u16; 4],
+    /// Barometer reading in Pa.
+    pub pressure: u32,
+    /// Filtered battery voltage in 10 mV.
+    pub battery: u16,
+}
+
+impl SelfTestReport {
+    pub fn has_failed(&self, check: u16) -> bool {
+        self.failed_checks & check != 0
+    }
+
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&self.failed_checks.to_be_bytes());
+        payload.extend_from_slice(&self.accel_noise.to_be_bytes());
+        payload.extend_from_slice(&self.gyro_noise.to_be_bytes());
+        for vibration in self.motor_vibration.iter() {
+            payload.extend_from_slice(&vibration.to_be_bytes());
+        }
+        payload.extend_from_slice(&self.pressure.to_be_bytes());
+        payload.extend_from_slice(&self.battery.to_be_bytes());
+        DeviceReport::new(ReportKind::SelfTest, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<SelfTestReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::SelfTest) || payload.len() != 20 {
+            return None;
+        }
+        let mut motor_vibration = [0; 4];
+        for (i, vibration) in motor_vibration.iter_mut().enumerate() {
+            *vibration = u16::from_be_bytes([payload[6 + 2 * i], payload[7 + 2 * i]]);
+        }
+        Some(SelfTestReport {
+            failed_checks: u16::from_be_bytes([payload[0], payload[1]]),
+            accel_noise: u16::from_be_bytes([payload[2], payload[3]]),
+            gyro_noise: u16::from_be_bytes([payload[4], payload[5]]),
+            motor_vibration,
+            pressure: u32::from_be_bytes([payload[14], payload[15], payload[16], payload[17]]),
+            battery: u16::from_be_bytes([payload[18], payload[19]]),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigEvent {
+    /// A valid configuration record was found at boot and is in use.
+    Loaded,
+    /// No valid configuration record was found at boot, the compiled-in defaults are in use.
+    Defaults,
+    Saved,
+    /// The configuration could not be written, the previous record is still in use.
+    SaveFailed,
+}
+
+impl ConfigEvent {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            ConfigEvent::Loaded => 0x01,
+            ConfigEvent::Defaults => 0x02,
+            ConfigEvent::Saved => 0x03,
+            ConfigEvent::SaveFailed => 0x04,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<ConfigEvent> {
+        match byte {
+            0x01 => Some(ConfigEvent::Loaded),
+            0x02 => Some(ConfigEvent::Defaults),
+            0x03 => Some(ConfigEvent::Saved),
+            0x04 => Some(ConfigEvent::SaveFailed),
+            _ => None,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ConfigEvent::Loaded => "loaded",
+            ConfigEvent::Defaults => "defaults",
+            ConfigEvent::Saved => "saved",
+            ConfigEvent::SaveFailed => "save failed",
+        }
+    }
+}
+
+/// Sent when the configuration was loaded at boot and after every save command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfigReport {
+    pub event: ConfigEvent,
+    /// The sequence number of the record in use, 0 when the defaults are in use.
+    pub sequence: u32,
+}
+
+impl ConfigReport {
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::new();
+        payload.push(self.event.to_byte());
+        payload.extend_from_slice(&self.sequence.to_be_bytes());
+        DeviceReport::new(ReportKind::Config, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<ConfigReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Config) || payload.len() != 5 {
+            return None;
+        }
+        Some(ConfigReport {
+            event: ConfigEvent::from_byte(payload[0])?,
+            sequence: u32::from_be_bytes([payload[1], payload[2], payload[3], payload[4]]),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// The motors were armed and a new session was started in the logs.
+    Started,
+    /// An entry of the session directory, sent one by one after a list command.
+    Listed,
+    /// The session is sent as log messages from now on.
+    Downloading,
+    /// The session was removed from the directory.
+    Erased,
+    /// The requested session is not in the directory, or its logs were overwritten.
+    NotFound,
+    /// All sessions and their logs were erased, the other fields are 0.
+    AllErased,
+    /// Erasing all sessions is only done in safe mode with the motors disarmed.
+    EraseRefused,
+}
+
+impl SessionEvent {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            SessionEvent::Started => 0x01,
+            SessionEvent::Listed => 0x02,
+            SessionEvent::Downloading => 0x03,
+            SessionEvent::Erased => 0x04,
+            SessionEvent::NotFound => 0x05,
+            SessionEvent::AllErased => 0x06,
+            SessionEvent::EraseRefused => 0x07,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<SessionEvent> {
+        match byte {
+            0x01 => Some(SessionEvent::Started),
+            0x02 => Some(SessionEvent::Listed),
+            0x03 => Some(SessionEvent::Downloading),
+            0x04 => Some(SessionEvent::Erased),
+            0x05 => Some(SessionEvent::NotFound),
+            0x06 => Some(SessionEvent::AllErased),
+            0x07 => Some(SessionEvent::EraseRefused),
+            _ => None,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            SessionEvent::Started => "started",
+            SessionEvent::Listed => "listed",
+            SessionEvent::Downloading => "downloading",
+            SessionEvent::Erased => "erased",
+            SessionEvent::NotFound => "not found",
+            SessionEvent::AllErased => "all erased",
+            SessionEvent::EraseRefused => "erase refused",
+        }
+    }
+}
+
+/// A flight session, every arm of the motors starts a new one in the flash logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionReport {
+    pub event: SessionEvent,
+    /// Counts up from 1, the numbers of erased sessions are not reused until all are erased.
+    pub number: u16,
+    /// The control loop iteration at which the motors were armed.
+    pub start_tick: u32,
+    /// Major, minor and patch version of the firmware that flew the session.
+    pub firmware_version: [u8; 3],
+    /// CRC16 of the configuration (gains, calibration, motor limits) at the start of the session.
+    pub config_hash: u16,
+}
+
+impl SessionReport {
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::new();
+        payload.push(self.event.to_byte());
+        payload.extend_from_slice(&self.number.to_be_bytes());
+        payload.extend_from_slice(&self.start_tick.to_be_bytes());
+        payload.extend_from_slice(&self.firmware_version);
+        payload.extend_from_slice(&self.config_hash.to_be_bytes());
+        DeviceReport::new(ReportKind::Session, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<SessionReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Session) || payload.len() != 12 {
+            return None;
+        }
+        Some(SessionReport {
+            event: SessionEvent::from_byte(payload[0])?,
+            number: u16::from_be_bytes([payload[1], payload[2]]),
+            start_tick: u32::from_be_bytes([payload[3], payload[4], payload[5], payload[6]]),
+            firmware_version: [payload[7], payload[8], payload[9]],
+            config_hash: u16::from_be_bytes([payload[10], payload[11]]),
+        })
+    }
+}
+
+pub const BLACK_BOX_SAMPLE_SIZE: usize = 24;
+
+/// The state of the drone at one moment, as kept by the black box before an incident.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlackBoxSample {
+    /// Yaw, pitch and roll in milliradians.
+    pub attitude: [i16; 3],
+    /// The raw angular rates of the gyroscope (16.4 per deg/s).
+    pub rates: [i16; 3],
+    pub motors: [u16; 4],
+    /// Lift, yaw, pitch and roll as last sent by the PC.
+    pub setpoints: [u8; 4],
+}
+
+impl BlackBoxSample {
+    pub fn to_bytes(&self) -> [u8; BLACK_BOX_SAMPLE_SIZE] {
+        let mut bytes = [0; BLACK_BOX_SAMPLE_SIZE];
+        for (index, value) in self.attitude.iter().chain(self.rates.iter()).enumerate() {
+            bytes[index * 2..index * 2 + 2].copy_from_slice(&value.to_be_bytes());
+        }
+        for (index, motor) in self.motors.iter().enumerate() {
+            bytes[12 + index * 2..14 + index * 2].copy_from_slice(&motor.to_be_bytes());
+        }
+        bytes[20..].copy_from_slice(&self.setpoints);
+        bytes
+    }
+
+    /// `bytes` has to hold at least `BLACK_BOX_SAMPLE_SIZE` bytes.
+    pub fn from_bytes(bytes: &[u8]) -> BlackBoxSample {
+        let i16_at = |index: usize| i16::from_be_bytes([bytes[index], bytes[index + 1]]);
+        let u16_at = |index: usize| u16::from_be_bytes([bytes[index], bytes[index + 1]]);
+        BlackBoxSample {
+            attitude: [i16_at(0), i16_at(2), i16_at(4)],
+            rates: [i16_at(6), i16_at(8), i16_at(10)],
+            motors: [u16_at(12), u16_at(14), u16_at(16), u16_at(18)],
+            setpoints: [bytes[20], bytes[21], bytes[22], bytes[23]],
+        }
+    }
+}
+
+/// What made the black box keep its samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IncidentCause {
+    /// The drone went into panic mode.
+    Panic,
+    /// The link to the PC was lost while flying.
+    Failsafe,
+    /// The safety monitor cut the motors.
+    SafetyCut,
+}
+
+impl IncidentCause {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            IncidentCause::Panic => 0x01,
+            IncidentCause::Failsafe => 0x02,
+            IncidentCause::SafetyCut => 0x03,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<IncidentCause> {
+        match byte {
+            0x01 => Some(IncidentCause::Panic),
+            0x02 => Some(IncidentCause::Failsafe),
+            0x03 => Some(IncidentCause::SafetyCut),
+            _ => None,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            IncidentCause::Panic => "panic",
+            IncidentCause::Failsafe => "failsafe",
+            IncidentCause::SafetyCut => "safety cut",
+        }
+    }
+}
+
+/// One sample of an incident, the incidents in flash are sent sample by sample after a read
+/// incidents command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IncidentReport {
+    /// Counts up from 1 over all incidents in flash.
+    pub number: u16,
+    pub cause: IncidentCause,
+    /// The control loop iteration at which the incident happened.
+    pub trigger_tick: u32,
+    /// The samples are sent from the oldest (0) to the last one before the incident (count - 1).
+    pub index: u8,
+    pub count: u8,
+    /// The number of ticks between two samples.
+    pub period: u8,
+    pub sample: BlackBoxSample,
+}
+
+impl IncidentReport {
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&self.number.to_be_bytes());
+        payload.push(self.cause.to_byte());
+        payload.extend_from_slice(&self.trigger_tick.to_be_bytes());
+        payload.push(self.index);
+        payload.push(self.count);
+        payload.push(self.period);
+        payload.extend_from_slice(&self.sample.to_bytes());
+        DeviceReport::new(ReportKind::Incident, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<IncidentReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Incident)
+            || payload.len() != 10 + BLACK_BOX_SAMPLE_SIZE
+        {
+            return None;
+        }
+        Some(IncidentReport {
+            number: u16::from_be_bytes([payload[0], payload[1]]),
+            cause: IncidentCause::from_byte(payload[2])?,
+            trigger_tick: u32::from_be_bytes([payload[3], payload[4], payload[5], payload[6]]),
+            index: payload[7],
+            count: payload[8],
+            period: payload[9],
+            sample: BlackBoxSample::from_bytes(&payload[10..]),
+        })
+    }
+
+    /// The time of the sample relative to the incident in ticks, negative before it.
+    pub fn tick_offset(&self) -> i32 {
+        (self.index as i32 - self.count as i32) * self.period as i32
+    }
+}
+
+/// What an `EventReport` is about, this also tells what its reason code means.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// The mode changed from `from` to `to`, the reason is 0.
+    Transition,
+    /// The PC asked for mode `to` in mode `from`, the reason is the ack byte that was sent back.
+    Declined,
+    /// A fault event, the reason is its `FaultCode`.
+    Fault,
+    /// The motors were armed or disarmed, or arming was refused, the reason is the `ArmingEvent`.
+    Arming,
+    /// The calibration mode was left, the reason is 1 if the offsets were calculated and 0 if not.
+    Calibration,
+    /// The drone was powered on, the tick counts from here. The modes and the reason are 0.
+    Boot,
+}
+
+impl EventKind {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            EventKind::Transition => 0x01,
+            EventKind::Declined => 0x02,
+            EventKind::Fault => 0x03,
+            EventKind::Arming => 0x04,
+            EventKind::Calibration => 0x05,
+            EventKind::Boot => 0x06,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<EventKind> {
+        match byte {
+            0x01 => Some(EventKind::Transition),
+            0x02 => Some(EventKind::Declined),
+            0x03 => Some(EventKind::Fault),
+            0x04 => Some(EventKind::Arming),
+            0x05 => Some(EventKind::Calibration),
+            0x06 => Some(EventKind::Boot),
+            _ => None,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            EventKind::Transition => "transition",
+            EventKind::Declined => "declined",
+            EventKind::Fault => "fault",
+            EventKind::Arming => "arming",
+            EventKind::Calibration => "calibration",
+            EventKind::Boot => "boot",
+        }
+    }
+}
+
+/// An entry of the event log. Every event is sent as it happens and kept in flash, so the events
+/// of a flight can be read again afterwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventReport {
+    pub kind: EventKind,
+    /// The mode before the event.
+    pub from: u8,
+    /// The mode after the event, or the mode that was asked for when it was declined.
+    pub to: u8,
+    pub reason: u8,
+    pub tick: u32,
+}
+
+impl EventReport {
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::with_capacity(8);
+        payload.extend_from_slice(&[self.kind.to_byte(), self.from, self.to, self.reason]);
+        payload.extend_from_slice(&self.tick.to_be_bytes());
+        DeviceReport::new(ReportKind::Event, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<EventReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Event) || payload.len() != 8 {
+            return None;
+        }
+        Some(EventReport {
+            kind: EventKind::from_byte(payload[0])?,
+            from: payload[1],
+            to: payload[2],
+            reason: payload[3],
+            tick: u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashCause {
+    /// The firmware panicked.
+    Panic,
+    /// An allocation failed, the 4 KB heap is full.
+    OutOfMemory,
+}
+
+impl CrashCause {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            CrashCause::Panic => 0x01,
+            CrashCause::OutOfMemory => 0x02,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<CrashCause> {
+        match byte {
+            0x01 => Some(CrashCause::Panic),
+            0x02 => Some(CrashCause::OutOfMemory),
+            _ => None,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            CrashCause::Panic => "panic",
+            CrashCause::OutOfMemory => "out of memory",
+        }
+    }
+}
+
+/// The size of the fixed part of the crash payload, the file name and the message follow it.
+pub const CRASH_HEADER_SIZE: usize = 38;
+
+/// What the drone was doing when the firmware crashed, as the panic handler wrote it to flash.
+///
+/// The payload is `[cause u8][tick u32][mode u8][line u32][attitude 3 x i16][rates 3 x i16]
+/// [accel 3 x i16][motors 4 x u16][battery u16][file length u8][file][message length u8][message]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrashReport {
+    pub cause: CrashCause,
+    /// The control loop iteration of the last completed tick.
+    pub tick: u32,
+    pub mode: u8,
+    /// Where the panic happened, 0 and empty when it is not known (e.g. out of memory).
+    pub line: u32,
+    pub file: String,
+    /// The panic message, cut off when it is too long for the record.
+    pub message: String,
+    /// Yaw, pitch and roll in milliradians.
+    pub attitude: [i16; 3],
+    pub rates: [i16; 3],
+    pub accel: [i16; 3],
+    pub motors: [u16; 4],
+    pub battery: u16,
+}
+
+impl CrashReport {
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::with_capacity(CRASH_HEADER_SIZE);
+        payload.push(self.cause.to_byte());
+        payload.extend_from_slice(&self.tick.to_be_bytes());
+        payload.push(self.mode);
+        payload.extend_from_slice(&self.line.to_be_bytes());
+        for value in self.attitude.iter().chain(&self.rates).chain(&self.accel) {
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        for value in self.motors.iter().chain(core::iter::once(&self.battery)) {
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        for text in [&self.file, &self.message] {
+            let bytes = &text.as_bytes()[..text.len().min(u8::MAX as usize)];
+            payload.push(bytes.len() as u8);
+            payload.extend_from_slice(bytes);
+        }
+        DeviceReport::new(ReportKind::Crash, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<CrashReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Crash) || payload.len() < CRASH_HEADER_SIZE + 2 {
+            return None;
+        }
+        let u16_at = |index: usize| u16::from_be_bytes([payload[index], payload[index + 1]]);
+        let i16_at = |index: usize| u16_at(index) as i16;
+        let file_length = payload[CRASH_HEADER_SIZE] as usize;
+        let file = payload.get(CRASH_HEADER_SIZE + 1..CRASH_HEADER_SIZE + 1 + file_length)?;
+        let message_start = CRASH_HEADER_SIZE + 1 + file_length;
+        let message_length = *payload.get(message_start)? as usize;
+        let message = payload.get(message_start + 1..message_start + 1 + message_length)?;
+        Some(CrashReport {
+            cause: CrashCause::from_byte(payload[0])?,
+            tick: u32::from_be_bytes([payload[1], payload[2], payload[3], payload[4]]),
+            mode: payload[5],
+            line: u32::from_be_bytes([payload[6], payload[7], payload[8], payload[9]]),
+            file: String::from_utf8_lossy(file).into_owned(),
+            message: String::from_utf8_lossy(message).into_owned(),
+            attitude: [i16_at(10), i16_at(12), i16_at(14)],
+            rates: [i16_at(16), i16_at(18), i16_at(20)],
+            accel: [i16_at(22), i16_at(24), i16_at(26)],
+            motors: [u16_at(28), u16_at(30), u16_at(32), u16_at(34)],
+            battery: u16_at(36),
+        })
+    }
+}
+
+// The bits of `CalibrationReport::failed_checks`, every bit is a sensor that moved or was too noisy.
+pub const CALIBRATION_GYRO: u8 = 0b0000_0001;
+pub const CALIBRATION_ACCEL: u8 = 0b0000_0010;
+pub const CALIBRATION_ATTITUDE: u8 = 0b0000_0100;
+pub const CALIBRATION_PRESSURE: u8 = 0b0000_1000;
+
+pub const CALIBRATION_CHECKS: [u8; 4] = [
+    CALIBRATION_GYRO,
+    CALIBRATION_ACCEL,
+    CALIBRATION_ATTITUDE,
+    CALIBRATION_PRESSURE,
+];
+
+/// The averaged offsets of a calibration window and the variance of the samples. The offsets are
+/// only used when no check failed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CalibrationReport {
+    /// The `CALIBRATION_` bits of the sensors whose variance was too high, 0 when accepted.
+    pub failed_checks: u8,
+    /// The number of samples that were averaged.
+    pub samples: u16,
+    /// Gyroscope offset of every axis in raw units.
+    pub gyro: [i16; 3],
+    /// Accelerometer offset of every axis in raw units.
+    pub accel: [i16; 3],
+    /// Yaw, pitch and roll offset in milliradians.
+    pub attitude: [i16; 3],
+    /// Barometer offset in Pa.
+    pub pressure: i32,
+    /// Largest variance of a gyroscope axis, in raw units squared.
+    pub gyro_variance: u32,
+    /// Largest variance of an accelerometer axis, in raw units squared.
+    pub accel_variance: u32,
+    /// Largest variance of yaw, pitch or roll, in milliradians squared.
+    pub attitude_variance: u32,
+    /// Variance of the barometer, in Pa squared.
+    pub pressure_variance: u32,
+}
+
+impl CalibrationReport {
+    pub fn is_accepted(&self) -> bool {
+        self.failed_checks == 0
+    }
+
+    pub fn has_failed(&self, check: u8) -> bool {
+        self.failed_checks & check != 0
+    }
+
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::with_capacity(41);
+        payload.push(self.failed_checks);
+        payload.extend_from_slice(&self.samples.to_be_bytes());
+        for value in self.gyro.iter().chain(&self.accel).chain(&self.attitude) {
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        payload.extend_from_slice(&self.pressure.to_be_bytes());
+        for variance in [
+            self.gyro_variance,
+            self.accel_variance,
+            self.attitude_variance,
+            self.pressure_variance,
+        ] {
+            payload.extend_from_slice(&variance.to_be_bytes());
+        }
+        DeviceReport::new(ReportKind::Calibration, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<CalibrationReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Calibration) || payload.len() != 41 {
+            return None;
+        }
+        let i16_at = |index: usize| i16::from_be_bytes([payload[index], payload[index + 1]]);
+        let u32_at = |index: usize| {
+            u32::from_be_bytes([
+                payload[index],
+                payload[index + 1],
+                payload[index + 2],
+                payload[index + 3],
+            ])
+        };
+        Some(CalibrationReport {
+            failed_checks: payload[0],
+            samples: u16::from_be_bytes([payload[1], payload[2]]),
+            gyro: [i16_at(3), i16_at(5), i16_at(7)],
+            accel: [i16_at(9), i16_at(11), i16_at(13)],
+            attitude: [i16_at(15), i16_at(17), i16_at(19)],
+            pressure: u32_at(21) as i32,
+            gyro_variance: u32_at(25),
+            accel_variance: u32_at(29),
+            attitude_variance: u32_at(33),
+            pressure_variance: u32_at(37),
+        })
+    }
+}
+
+/// The attitude estimators of the drone. The controllers fly on the selected one, the others only
+/// run when they are compared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttitudeSource {
+    /// The quaternion of the DMP of the MPU.
+    Dmp,
+    /// The gyroscope mixed with the angles from gravity by a fixed weight.
+    Complementary,
+    /// A Kalman filter of the angle and the gyroscope bias per axis.
+    Kalman,
+    /// A quaternion corrected by a PI controller on the direction of gravity.
+    Mahony,
+}
+
+pub const ATTITUDE_SOURCE_COUNT: usize = 4;
+
+impl AttitudeSource {
+    pub const ALL: [AttitudeSource; ATTITUDE_SOURCE_COUNT] = [
+        AttitudeSource::Dmp,
+        AttitudeSource::Complementary,
+        AttitudeSource::Kalman,
+        AttitudeSource::Mahony,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn from_index(index: usize) -> Option<AttitudeSource> {
+        AttitudeSource::ALL.get(index).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AttitudeSource::Dmp => "dmp",
+            AttitudeSource::Complementary => "complementary",
+            AttitudeSource::Kalman => "kalman",
+            AttitudeSource::Mahony => "mahony",
+        }
+    }
+}
+
+// The selection byte of an `AttitudeReport` when the estimator follows the flight mode.
+pub const ATTITUDE_BY_MODE: u8 = 0xFF;
+
+/// The attitude of every estimator that ran in the last tick, in milliradians.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AttitudeReport {
+    /// The index of the selected estimator, or `ATTITUDE_BY_MODE`.
+    pub selected: u8,
+    /// The index of the estimator the controllers fly on.
+    pub active: u8,
+    /// The estimators that ran, bit n is the estimator with index n.
+    pub running: u8,
+    /// Yaw, pitch and roll of every estimator in the order of `AttitudeSource::ALL`, zero when
+    /// it did not run.
+    pub estimates: [[i16; 3]; ATTITUDE_SOURCE_COUNT],
+}
+
+impl AttitudeReport {
+    pub fn is_running(&self, estimator: AttitudeSource) -> bool {
+        self.running & (1 << estimator.index()) != 0
+    }
+
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::with_capacity(27);
+        payload.push(self.selected);
+        payload.push(self.active);
+        payload.push(self.running);
+        for estimate in self.estimates.iter() {
+            for angle in estimate {
+                payload.extend_from_slice(&angle.to_be_bytes());
+            }
+        }
+        DeviceReport::new(ReportKind::Attitude, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<AttitudeReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Attitude) || payload.len() != 27 {
+            return None;
+        }
+        let i16_at = |index: usize| i16::from_be_bytes([payload[index], payload[index + 1]]);
+        let mut estimates = [[0; 3]; ATTITUDE_SOURCE_COUNT];
+        for (estimator, estimate) in estimates.iter_mut().enumerate() {
+            for (axis, angle) in estimate.iter_mut().enumerate() {
+                *angle = i16_at(3 + 6 * estimator + 2 * axis);
+            }
+        }
+        Some(AttitudeReport {
+            selected: payload[0],
+            active: payload[1],
+            running: payload[2],
+            estimates,
+        })
+    }
+}
+
+/// How the centre of the notch filters against the motor vibration is chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotchTuning {
+    /// The notch filters pass the signals unchanged.
+    Off,
+    /// The vibration is at a fixed frequency.
+    Fixed,
+    /// The vibration frequency follows the average motor command.
+    Tracking,
+}
+
+impl NotchTuning {
+    pub const ALL: [NotchTuning; 3] = [NotchTuning::Off, NotchTuning::Fixed, NotchTuning::Tracking];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn from_index(index: usize) -> Option<NotchTuning> {
+        NotchTuning::ALL.get(index).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NotchTuning::Off => "off",
+            NotchTuning::Fixed => "fixed",
+            NotchTuning::Tracking => "tracking",
+        }
+    }
+}
+
+/// The notch filters of the gyroscope and the accelerometer, and the vibration of every axis as the
+/// RMS of the signal above the motion of the drone, before and after the filters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VibrationReport {
+    /// The index of the `NotchTuning`.
+    pub tuning: u8,
+    /// The centre of the notch in the samples in 0.1 Hz, 0 while the notch is off.
+    pub centre: u16,
+    /// Before and after the filters in mrad/s, for x, y and z.
+    pub gyro: [[u16; 2]; 3],
+    /// Before and after the filters in mg, for x, y and z.
+    pub accel: [[u16; 2]; 3],
+}
+
+impl VibrationReport {
+    pub fn to_report(&self) -> DeviceReport {
+        let mut payload = Vec::with_capacity(27);
+        payload.push(self.tuning);
+        payload.extend_from_slice(&self.centre.to_be_bytes());
+        for axis in self.gyro.iter().chain(&self.accel) {
+            for rms in axis {
+                payload.extend_from_slice(&rms.to_be_bytes());
+            }
+        }
+        DeviceReport::new(ReportKind::Vibration, payload)
+    }
+
+    pub fn from_report(report: &DeviceReport) -> Option<VibrationReport> {
+        let payload = report.get_payload();
+        if report.get_kind() != Some(ReportKind::Vibration) || payload.len() != 27 {
+            return None;
+        }
+        let u16_at = |index: usize| u16::from_be_bytes([payload[index], payload[index + 1]]);
+        let axis_at = |index: usize| [u16_at(index), u16_at(index + 2)];
+        Some(VibrationReport {
+            tuning: payload[0],
+            centre: u16_at(1),
+            gyro: [axis_at(3), axis_at(7), axis_at(11)],
+            accel: [axis_at(15), axis_at(19), axis_at(23)],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn encode(report: &DeviceReport) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        report.form_message(&mut bytes);
+        bytes
+    }
+
+    // Every report the decoder returns for the bytes, in order.
+    fn decode(bytes: &[u8]) -> Vec<DeviceReport> {
+        let mut decoder = ReportDecoder::new();
+        bytes
+            .iter()
+            .filter_map(|byte| decoder.push(*byte))
+            .collect()
+    }
+
+    // Sends the report through the encoder and the decoder.
+    fn round_trip(report: DeviceReport) -> DeviceReport {
+        let mut decoded = decode(&encode(&report));
+        assert_eq!(decoded.len(), 1);
+        decoded.remove(0)
+    }
+
+    fn config_report(sequence: u32) -> ConfigReport {
+        ConfigReport {
+            event: ConfigEvent::Loaded,
+            sequence,
+        }
+    }
+
+    #[test]
+    fn device_report_round_trips() {
+        let report = round_trip(DeviceReport::new(
+            ReportKind::Status,
+            vec![1, 2, 0x5b, 0x5d],
+        ));
+        assert!(report.is_valid());
+        assert_eq!(report.get_kind(), Some(ReportKind::Status));
+        assert_eq!(report.get_payload(), &[1, 2, 0x5b, 0x5d]);
+
+        let empty = round_trip(DeviceReport::new(ReportKind::Crash, Vec::new()));
+        assert_eq!(empty.get_kind(), Some(ReportKind::Crash));
+        assert!(empty.get_payload().is_empty());
+    }
+
+    #[test]
+    fn report_kinds_round_trip() {
+        for byte in 0..=u8::MAX {
+            if let Some(kind) = ReportKind::from_byte(byte) {
+                assert_eq!(kind.to_byte(), byte);
+            }
+        }
+        assert_eq!(ReportKind::from_byte(REPORT_START_FLAG), None);
+    }
+
+    #[test]
+    fn crc_mismatch_is_rejected() {
+        let mut bytes = encode(&config_report(7).to_report());
+        bytes[4] ^= 0x01;
+        assert!(decode(&bytes).is_empty());
+
+        let report = DeviceReport::format_message(&bytes);
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn wrong_end_flag_is_rejected() {
+        let mut bytes = encode(&config_report(7).to_report());
+        let last = bytes.len() - 1;
+        bytes[last] = 0x00;
+        assert!(decode(&bytes).is_empty());
+    }
+
+    #[test]
+    fn decoder_skips_bytes_before_the_start_flag() {
+        let mut bytes = vec![0x00, 0x7d, 0xff];
+        bytes.extend(encode(&config_report(1).to_report()));
+        bytes.extend(encode(&config_report(2).to_report()));
+        let decoded = decode(&bytes);
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(
+            ConfigReport::from_report(&decoded[0]),
+            Some(config_report(1))
+        );
+        assert_eq!(
+            ConfigReport::from_report(&decoded[1]),
+            Some(config_report(2))
+        );
+    }
+
+    #[test]
+    fn decoder_resyncs_after_a_crc_mismatch() {
+        let mut bytes = encode(&config_report(1).to_report());
+        bytes[4] ^= 0x01;
+        bytes.extend(encode(&config_report(2).to_report()));
+        let decoded = decode(&bytes);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(
+            ConfigReport::from_report(&decoded[0]),
+            Some(config_report(2))
+        );
+    }
+
+    #[test]
+    fn decoder_resyncs_after_a_corrupted_length() {
+        // the length claims more bytes than the frame has, the next report is inside them
+        let mut bytes = encode(&config_report(1).to_report());
+        bytes[2] = 200;
+        bytes.extend(encode(&config_report(2).to_report()));
+        bytes.extend([0x00; 250]);
+        bytes.extend(encode(&config_report(3).to_report()));
+        let decoded = decode(&bytes);
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(
+            ConfigReport::from_report(&decoded[0]),
+            Some(config_report(2))
+        );
+        assert_eq!(
+            ConfigReport::from_report(&decoded[1]),
+            Some(config_report(3))
+        );
+    }
+
+    #[test]
+    fn decoder_resyncs_after_an_unknown_kind() {
+        let mut bytes = vec![REPORT_START_FLAG, 0x7f];
+        bytes.extend(encode(&config_report(4).to_report()));
+        let decoded = decode(&bytes);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(
+            ConfigReport::from_report(&decoded[0]),
+            Some(config_report(4))
+        );
+    }
+
+    #[test]
+    fn reports_of_another_kind_are_not_parsed() {
+        let report = round_trip(config_report(1).to_report());
+        assert_eq!(StatusReport::from_report(&report), None);
+        assert_eq!(EventReport::from_report(&report), None);
+    }
+
+    #[test]
+    fn profiling_report_round_trips() {
+        let mut profiling = ProfilingReport::default();
+        for (i, stage) in profiling.stages.iter_mut().enumerate() {
+            *stage = StageTiming {
+                avg_us: i as u16,
+                max_us: 100 + i as u16,
+                overruns: 1000 + i as u16,
+            };
+        }
+        profiling.whole_loop = StageTiming {
+            avg_us: 5000,
+            max_us: 6500,
+            overruns: 3,
+        };
+        let report = round_trip(profiling.to_report());
+        assert_eq!(ProfilingReport::from_report(&report), Some(profiling));
+    }
+
+    #[test]
+    fn fault_and_status_reports_round_trip() {
+        let fault = FaultEvent {
+            code: FaultCode::Impact,
+            mode: 5,
+            tick: 123_456,
+            value: 4000,
+        };
+        assert_eq!(
+            FaultEvent::from_report(&round_trip(fault.to_report())),
+            Some(fault)
+        );
+
+        let status = StatusReport {
+            flags: STATUS_ARMED | STATUS_BATTERY_LOW,
+            battery: 1080,
+            saturated_mixes: 3,
+            saturated_total: 70_000,
+            altitude: -1250,
+            vertical_velocity: -300,
+            dropped_reports: 12,
+        };
+        assert_eq!(
+            StatusReport::from_report(&round_trip(status.to_report())),
+            Some(status)
+        );
+    }
+
+    #[test]
+    fn arming_and_self_test_reports_round_trip() {
+        let arming = ArmingReport {
+            event: ArmingEvent::Refused,
+            armed: false,
+            failed_checks: PREARM_NOT_LEVEL | PREARM_BATTERY_LOW,
+        };
+        assert_eq!(
+            ArmingReport::from_report(&round_trip(arming.to_report())),
+            Some(arming)
+        );
+
+        let self_test = SelfTestReport {
+            failed_checks: SELFTEST_MOTOR_3 | SELFTEST_BAROMETER,
+            accel_noise: 40,
+            gyro_noise: 12,
+            motor_vibration: [100, 200, 0, 400],
+            pressure: 101_325,
+            battery: 1110,
+        };
+        assert_eq!(
+            SelfTestReport::from_report(&round_trip(self_test.to_report())),
+            Some(self_test)
+        );
+    }
+
+    #[test]
+    fn config_and_session_reports_round_trip() {
+        for event in [
+            ConfigEvent::Loaded,
+            ConfigEvent::Defaults,
+            ConfigEvent::Saved,
+            ConfigEvent::SaveFailed,
+        ] {
+            let config = ConfigReport {
+                event,
+                sequence: 42,
+            };
+            assert_eq!(
+                ConfigReport::from_report(&round_trip(config.to_report())),
+                Some(config)
+            );
+        }
+
+        let session = SessionReport {
+            event: SessionEvent::Listed,
+            number: 17,
+            start_tick: 9000,
+            firmware_version: [0, 1, 0],
+            config_hash: 0xbeef,
+        };
+        assert_eq!(
+            SessionReport::from_report(&round_trip(session.to_report())),
+            Some(session)
+        );
+    }
+
+    #[test]
+    fn incident_and_event_reports_round_trip() {
+        let incident = IncidentReport {
+            number: 3,
+            cause: IncidentCause::SafetyCut,
+            trigger_tick: 77_000,
+            index: 5,
+            count: 32,
+            period: 2,
+            sample: BlackBoxSample {
+                attitude: [-100, 200, -300],
+                rates: [16, -32, 64],
+                motors: [300, 310, 320, 330],
+                setpoints: [80, 50, 45, 55],
+            },
+        };
+        assert_eq!(
+            IncidentReport::from_report(&round_trip(incident.to_report())),
+            Some(incident)
+        );
+        assert_eq!(incident.tick_offset(), -54);
+
+        let event = EventReport {
+            kind: EventKind::Boot,
+            from: 0,
+            to: 0,
+            reason: 0,
+            tick: 0,
+        };
+        assert_eq!(
+            EventReport::from_report(&round_trip(event.to_report())),
+            Some(event)
+        );
+    }
+
+    #[test]
+    fn crash_report_round_trips() {
+        let crash = CrashReport {
+            cause: CrashCause::Panic,
+            tick: 31_337,
+            mode: 4,
+            line: 812,
+            file: "src/control.rs".to_string(),
+            message: "attempt to divide by zero".to_string(),
+            attitude: [1, -2, 3],
+            rates: [-4, 5, -6],
+            accel: [7, -8, 9],
+            motors: [400, 401, 402, 403],
+            battery: 1050,
+        };
+        assert_eq!(
+            CrashReport::from_report(&round_trip(crash.to_report())),
+            Some(crash)
+        );
+
+        // a payload cut off inside the file name
+        let mut payload = vec![0; CRASH_HEADER_SIZE];
+        payload[0] = CrashCause::Panic.to_byte();
+        payload.extend([10, b's', b'r', b'c']);
+        let cut = DeviceReport::new(ReportKind::Crash, payload);
+        assert_eq!(CrashReport::from_report(&cut), None);
+    }
+
+    #[test]
+    fn calibration_attitude_and_vibration_reports_round_trip() {
+        let calibration = CalibrationReport {
+            failed_checks: CALIBRATION_ACCEL,
+            samples: 300,
+            gyro: [-5, 6, -7],
+            accel: [120, -130, 16_000],
+            attitude: [10, -20, 30],
+            pressure: -40_000,
+            gyro_variance: 3,
+            accel_variance: 250_000,
+            attitude_variance: 12,
+            pressure_variance: 90,
+        };
+        assert_eq!(
+            CalibrationReport::from_report(&round_trip(calibration.to_report())),
+            Some(calibration)
+        );
+
+        let attitude = AttitudeReport {
+            selected: ATTITUDE_BY_MODE,
+            active: AttitudeSource::Kalman.index() as u8,
+            running: 0b1111,
+            estimates: [[1, 2, 3], [-4, -5, -6], [7, 8, 9], [-10, 11, -12]],
+        };
+        assert_eq!(
+            AttitudeReport::from_report(&round_trip(attitude.to_report())),
+            Some(attitude)
+        );
+
+        let vibration = VibrationReport {
+            tuning: NotchTuning::Tracking.index() as u8,
+            centre: 500,
+            gyro: [[900, 100], [800, 90], [700, 80]],
+            accel: [[60, 10], [50, 9], [40, 8]],
+        };
+        assert_eq!(
+            VibrationReport::from_report(&round_trip(vibration.to_report())),
+            Some(vibration)
+        );
+    }
+}
@@ -0,0 +1,159 @@
+// The six-position accelerometer calibration. The drone is put on each of its six faces in turn,
+// and the accelerometer readings of the telemetry are averaged in every position. Every axis then
+// has one position where it points up (+1 g) and one where it points down (-1 g): the bias is the
+// middle of the two and the scale makes them exactly 2 g apart. Which position is which is found
+// from the readings, so the faces can be done in any order.
+//
+// The procedure starts by resetting the correction on the drone, so the telemetry holds the plain
+// readings, and ends by uploading the correction of every axis.
+
+use protocol::command::HostCommand;
+
+// The accelerometer has 16384 raw units per g.
+const ONE_G: f64 = 16384.0;
+// The telemetry arrives at 7.5 Hz, 2 seconds per position.
+const SAMPLES_PER_POSITION: usize = 15;
+pub const POSITIONS: usize = 6;
+
+const INSTRUCTIONS: [&str; POSITIONS] = [
+    "flat, top up",
+    "upside down",
+    "nose up",
+    "nose down",
+    "left side up",
+    "right side up",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Idle,
+    // Waiting for the drone to be put in the position, until the key is pressed.
+    Waiting,
+    Sampling,
+}
+
+pub struct AccelCalibration {
+    phase: Phase,
+    position: usize,
+    samples: Vec<[i16; 3]>,
+    averages: Vec<[f64; 3]>,
+    status: String,
+}
+
+impl AccelCalibration {
+    pub fn new() -> Self {
+        AccelCalibration {
+            phase: Phase::Idle,
+            position: 0,
+            samples: Vec::new(),
+            averages: Vec::new(),
+            status: String::from("Press s to start"),
+        }
+    }
+
+    // What the user has to do next.
+    pub fn get_status(&self) -> &str {
+        &self.status
+    }
+
+    // The key was pressed: start the procedure, or sample the position the drone is in now.
+    // Returns the commands to send to the drone.
+    pub fn on_key(&mut self) -> Vec<HostCommand> {
+        match self.phase {
+            Phase::Idle => {
+                self.position = 0;
+                self.averages.clear();
+                self.wait_for_position();
+                // measure without the correction of an earlier calibration
+                (0..3)
+                    .map(|axis| HostCommand::with_accel_correction(axis, 0, 1 << 15))
+                    .collect()
+            }
+            Phase::Waiting => {
+                self.samples.clear();
+                self.phase = Phase::Sampling;
+                self.status = format!(
+                    "{}/{}: hold still ({})",
+                    self.position + 1,
+                    POSITIONS,
+                    INSTRUCTIONS[self.position]
+                );
+                Vec::new()
+            }
+            Phase::Sampling => Vec::new(),
+        }
+    }
+
+    // An accelerometer reading of the telemetry. Returns the commands to send to the drone once
+    // the last position has been sampled.
+    pub fn add_sample(&mut self, accel: [i16; 3]) -> Vec<HostCommand> {
+        if self.phase != Phase::Sampling {
+            return Vec::new();
+        }
+        self.samples.push(accel);
+        if self.samples.len() < SAMPLES_PER_POSITION {
+            return Vec::new();
+        }
+        let mut average = [0.0; 3];
+        for sample in &self.samples {
+            for axis in 0..3 {
+                average[axis] += sample[axis] as f64 / self.samples.len() as f64;
+            }
+        }
+        self.averages.push(average);
+        self.position += 1;
+        if self.position < POSITIONS {
+            self.wait_for_position();
+            return Vec::new();
+        }
+        self.phase = Phase::Idle;
+        match self.correction() {
+            Some(correction) => {
+                self.status = format!("Done, bias/scale {:?}", correction);
+                correction
+                    .iter()
+                    .enumerate()
+                    .map(|(axis, (bias, scale))| {
+                        HostCommand::with_accel_correction(axis as u8, *bias, *scale)
+                    })
+                    .collect()
+            }
+            None => {
+                self.status = String::from("Failed, every face has to be down once. Press s");
+                Vec::new()
+            }
+        }
+    }
+
+    fn wait_for_position(&mut self) {
+        self.phase = Phase::Waiting;
+        self.status = format!(
+            "{}/{}: put the drone {}, press s",
+            self.position + 1,
+            POSITIONS,
+            INSTRUCTIONS[self.position]
+        );
+    }
+
+    // The bias in raw units and the scale in 1/32768 of every axis, None when an axis was not
+    // pointed up and down.
+    fn correction(&self) -> Option<[(i16, u16); 3]> {
+        let mut correction = [(0, 0); 3];
+        for (axis, result) in correction.iter_mut().enumerate() {
+            let readings = self.averages.iter().map(|average| average[axis]);
+            let up = readings.clone().fold(f64::MIN, f64::max);
+            let down = readings.fold(f64::MAX, f64::min);
+            // up and down should be about 2 g apart, the other positions read about 0 g
+            if up < ONE_G / 2.0 || down > -ONE_G / 2.0 {
+                return None;
+            }
+            let bias = (up + down) / 2.0;
+            let scale = 2.0 * ONE_G / (up - down);
+            *result = (
+                bias.round() as i16,
+                (scale * 32768.0).round().min(u16::MAX as f64) as u16,
+            );
+        }
+        Some(correction)
+    }
+}
@@ -1,3 +1,8 @@
+use protocol::report::{
+    ArmingReport, AttitudeReport, CalibrationReport, ConfigReport, CrashReport, EventReport,
+    FaultCode, FaultEvent, IncidentReport, ProfilingReport, SelfTestReport, SessionEvent,
+    SessionReport, StatusReport, VibrationReport,
+};
 // use rand::{
 //     distributions::{Distribution, Uniform},
 //     rngs::ThreadRng,
@@ -65,6 +70,9 @@ impl<'a> TabsState<'a> {
 //     }
 // }
 
+const MAX_SHOWN_FAULTS: usize = 8;
+const MAX_SHOWN_EVENTS: usize = 12;
+
 pub struct App<'a> {
     pub title: &'a str,
     pub should_quit: bool,
@@ -92,6 +100,31 @@ pub struct App<'a> {
     pub pres: i32,
     pub crc: u16,
     pub ack: u8,
+    pub profiling: ProfilingReport,
+    pub status: StatusReport,
+    pub faults: Vec<FaultEvent>,
+    pub missed_ticks: u32,
+    pub arming: Option<ArmingReport>,
+    pub self_test: Option<SelfTestReport>,
+    // The outcome of the last calibration window.
+    pub calibration: Option<CalibrationReport>,
+    // What to do next in the six-position accelerometer calibration.
+    pub accel_calibration: String,
+    // The attitude of the estimators on the drone, to compare them.
+    pub attitude: Option<AttitudeReport>,
+    // The notch filters on the drone and the vibration before and after the filters.
+    pub vibration: Option<VibrationReport>,
+    pub config: Option<ConfigReport>,
+    // The sessions in flash by number, as far as they have been listed or started.
+    pub sessions: Vec<SessionReport>,
+    pub selected_session: u16,
+    // The answer to the last download or erase command.
+    pub session_event: Option<SessionReport>,
+    // The last black-box sample that was read, it is written to the incident log as well.
+    pub incident: Option<IncidentReport>,
+    pub events: Vec<EventReport>,
+    // The crash record the drone left in flash, fetched when the runner starts.
+    pub crash: Option<CrashReport>,
 }
 
 impl<'a> App<'a> {
@@ -129,6 +162,23 @@ impl<'a> App<'a> {
             mode_sent: 0,
             crc: 0,
             ack: 0b1000_0001, // this is a redundant ack byte
+            profiling: ProfilingReport::default(),
+            status: StatusReport::default(),
+            faults: Vec::new(),
+            missed_ticks: 0,
+            arming: None,
+            self_test: None,
+            calibration: None,
+            accel_calibration: String::from("Press s to start"),
+            attitude: None,
+            vibration: None,
+            config: None,
+            sessions: Vec::new(),
+            selected_session: 1,
+            session_event: None,
+            incident: None,
+            events: Vec::new(),
+            crash: None,
         }
     }
 
@@ -178,6 +228,44 @@ impl<'a> App<'a> {
     //     }
     // }
 
+    // Keep the latest fault events for the fault table, the newest one first.
+    pub fn on_fault(&mut self, fault: FaultEvent) {
+        if fault.code == FaultCode::DeadlineMiss {
+            self.missed_ticks += fault.value as u32;
+        }
+        self.faults.insert(0, fault);
+        self.faults.truncate(MAX_SHOWN_FAULTS);
+    }
+
+    // Keep the latest events for the timeline, the newest one first.
+    pub fn on_event(&mut self, event: EventReport) {
+        self.events.insert(0, event);
+        self.events.truncate(MAX_SHOWN_EVENTS);
+    }
+
+    // Keep the session table in line with the directory on the drone.
+    pub fn on_session(&mut self, session: SessionReport) {
+        match session.event {
+            SessionEvent::Started | SessionEvent::Listed | SessionEvent::Downloading => {
+                match self
+                    .sessions
+                    .binary_search_by_key(&session.number, |s| s.number)
+                {
+                    Ok(index) => self.sessions[index] = session,
+                    Err(index) => self.sessions.insert(index, session),
+                }
+            }
+            SessionEvent::Erased | SessionEvent::NotFound => {
+                self.sessions.retain(|s| s.number != session.number)
+            }
+            SessionEvent::AllErased => self.sessions.clear(),
+            SessionEvent::EraseRefused => {}
+        }
+        if session.event != SessionEvent::Listed {
+            self.session_event = Some(session);
+        }
+    }
+
     pub fn on_tick(&mut self) {
         // Update progress
         self.progress += 0.001;
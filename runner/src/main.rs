@@ -1,7 +1,9 @@
 mod runner_thread_layer;
 use app::App;
 use gilrs::Gilrs;
+use protocol::command::HostCommand;
 use protocol::format::{DeviceProtocol, HostProtocol};
+use protocol::report::DeviceReport;
 use runner_thread_layer::{
     joystick_monitor, keyboard_monitor, uart_handler, user_input, JoystickControl, KeyboardControl,
 };
@@ -17,8 +19,10 @@ use tudelft_serial_upload::{upload_file_or_stop, PortSelector};
 use tui::backend::TermionBackend;
 use tui::Terminal;
 
+mod accel_calibration;
 mod app;
 mod file_writer;
+mod parameters;
 mod termion_ui;
 mod ui;
 
@@ -32,11 +36,16 @@ fn main() {
     sleep(Duration::from_millis(1000));
 
     let (user_input_tx, user_input_rx) = channel::<HostProtocol>();
+    let (command_input_tx, command_input_rx) = channel::<HostCommand>();
     let (keyboard_input_tx, keyboard_input_rx) = channel::<KeyboardControl>();
     let (joystick_input_tx, joystick_input_rx) = channel::<JoystickControl>();
     let (user_input_gui_tx, user_input_gui_rx) = channel::<HostProtocol>();
     let (device_data_tx, device_data_rx) = channel::<DeviceProtocol>();
+    let (device_report_tx, device_report_rx) = channel::<DeviceReport>();
     let (ack_tx, ack_rx) = channel::<bool>();
+    let (session_gui_tx, session_gui_rx) = channel::<u16>();
+    let (accel_calibration_tx, accel_calibration_rx) = channel::<()>();
+    let (accel_status_tx, accel_status_rx) = channel::<String>();
 
     let stdout = io::stdout().into_raw_mode().unwrap();
     let backend = TermionBackend::new(stdout);
@@ -44,16 +53,28 @@ fn main() {
     let app = App::new(" Group 5 Drone Demo!!!", true);
 
     let uart_handler = thread::spawn(move || {
-        uart_handler(serial, user_input_rx, ack_tx, device_data_tx);
+        uart_handler(
+            serial,
+            user_input_rx,
+            command_input_rx,
+            ack_tx,
+            device_data_tx,
+            device_report_tx,
+            accel_calibration_rx,
+            accel_status_tx,
+        );
     });
 
     let user_input = thread::spawn(move || {
         user_input(
             user_input_tx,
+            command_input_tx,
             keyboard_input_rx,
             joystick_input_rx,
             ack_rx,
             user_input_gui_tx,
+            session_gui_tx,
+            accel_calibration_tx,
         );
     });
 
@@ -67,7 +88,16 @@ fn main() {
 
     let gui = thread::spawn(move || {
         // run( true, user_input_gui_rx, device_data_rx).unwrap();
-        run_app(&mut terminal, app, user_input_gui_rx, device_data_rx).unwrap();
+        run_app(
+            &mut terminal,
+            app,
+            user_input_gui_rx,
+            device_data_rx,
+            device_report_rx,
+            session_gui_rx,
+            accel_status_rx,
+        )
+        .unwrap();
     });
 
     uart_handler.join().unwrap();
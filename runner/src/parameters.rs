@@ -0,0 +1,32 @@
+// The limits and settings of the drone in a parameter file, so they can be tuned without building
+// the firmware again. Every line is `name value` with the name of a `Parameter`, e.g.
+// `max_tilt 1050`. Empty lines and lines starting with `#` are skipped. The drone only takes them
+// in safe mode, and keeps them in flash once the configuration is saved.
+
+use protocol::command::{HostCommand, Parameter};
+use std::fs;
+
+pub const PARAMETER_FILE: &str = "parameters.txt";
+
+// One `SetParameter` command per line of the file, or what is wrong with it.
+pub fn read_parameters(path: &str) -> Result<Vec<HostCommand>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut commands = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |what: &str| format!("{} line {}: {}", path, index + 1, what);
+        let mut words = line.split_whitespace();
+        let (Some(name), Some(value), None) = (words.next(), words.next(), words.next()) else {
+            return Err(error("expected a name and a value"));
+        };
+        let parameter = Parameter::from_name(name).ok_or_else(|| error("unknown parameter"))?;
+        let value = value
+            .parse::<i32>()
+            .map_err(|_| error("the value is not a whole number"))?;
+        commands.push(HostCommand::with_parameter(parameter, value));
+    }
+    Ok(commands)
+}
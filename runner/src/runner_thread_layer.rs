@@ -1,6 +1,13 @@
+use crate::accel_calibration::AccelCalibration;
 use crate::file_writer::FileWriter;
+use crate::parameters::{read_parameters, PARAMETER_FILE};
 use gilrs::{Event, Gilrs};
+use protocol::command::{CommandId, HostCommand};
 use protocol::format::{DeviceProtocol, HostProtocol};
+use protocol::report::{
+    AttitudeSource, DeviceReport, IncidentReport, NotchTuning, ReportDecoder, ReportKind,
+    REPORT_START_FLAG,
+};
 use serial2::SerialPort;
 use std::io::{stdin, stdout, Write};
 use std::{
@@ -12,6 +19,12 @@ use termion::event::Key;
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
 
+// The notch filters that are sent with every tuning: the vibration at 200 Hz when it is fixed, or
+// 0.3 Hz per unit of the average motor command when it is tracked, with a quality of 2.
+const NOTCH_CENTRE: u16 = 200;
+const NOTCH_MILLIHERTZ_PER_COMMAND: u16 = 300;
+const NOTCH_QUALITY_TENTHS: u8 = 20;
+
 pub struct JoystickControl {
     lift: u8,
     yaw: u8,
@@ -70,6 +83,23 @@ pub enum KeyboardControl {
     RollPitchP2Up,
     RollPitchP2Down,
     ReadLogs,
+    Arm,
+    Disarm,
+    SaveConfig,
+    ListSessions,
+    PreviousSession,
+    NextSession,
+    DownloadSession,
+    EraseSession,
+    EraseAllSessions,
+    ReadIncidents,
+    ReadEvents,
+    ClearCrashDump,
+    AccelCalibration,
+    NextAttitudeEstimator,
+    ToggleAttitudeComparison,
+    NextNotchTuning,
+    SendParameters,
 }
 
 #[allow(dead_code)]
@@ -82,16 +112,23 @@ pub enum AckByteCorespondingState {
     RemainingOnTheSameMode,  // 0b0000_0001, 1
     FromPanic,               // 0b0000_0010, 2
     FromReadLogs,            // 0b0000_0011, 3
+    NotArmed,                // 0b0000_0100, 4
     NotDefined,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn uart_handler(
     serial: SerialPort,
     user_input: Receiver<HostProtocol>,
+    command_input: Receiver<HostCommand>,
     ack: Sender<bool>,
     device_data_to_gui: Sender<DeviceProtocol>,
+    device_report_to_gui: Sender<DeviceReport>,
+    accel_calibration_input: Receiver<()>,
+    accel_calibration_to_gui: Sender<String>,
 ) {
     let mut buf = [0u8; 255];
+    let mut report_decoder = ReportDecoder::new();
     let mut received_bytes_count = 0; // the size of the message should be exactly 40 bytes, since we are using fixed size
     let mut message_buffer = Vec::new();
     let mut file_writer = match FileWriter::new("log_file.csv") {
@@ -101,6 +138,17 @@ pub fn uart_handler(
             return;
         }
     };
+    // the black-box incidents go to a log of their own
+    let mut incident_writer = match FileWriter::new("incident_log.csv") {
+        Ok(writer) => writer,
+        Err(e) => {
+            println!("Error creating FileWriter: {}", e);
+            return;
+        }
+    };
+    // the six-position calibration runs here, it needs the telemetry and sends its own commands
+    let mut accel_calibration = AccelCalibration::new();
+    let mut accel_commands: Vec<HostCommand> = Vec::new();
     let mut start_receiving = false;
     // let mut command_ready = true;
     let mut repeat_flag = false;
@@ -112,6 +160,25 @@ pub fn uart_handler(
                 if num != 0 {
                     'inner: for i in buf.iter().take(num) {
                         let received_byte = *i;
+                        // reports from the drone are framed by "[" and "]" and never start inside a telemetry message
+                        if report_decoder.is_receiving()
+                            || (received_byte == REPORT_START_FLAG && !start_receiving)
+                        {
+                            // the decoder only returns reports with a valid CRC and end flag
+                            if let Some(report) = report_decoder.push(received_byte) {
+                                if report.get_kind() == Some(ReportKind::Incident) {
+                                    if let Some(incident) = IncidentReport::from_report(&report) {
+                                        if let Err(e) = incident_writer
+                                            .write_record(incident_csv_record(&incident))
+                                        {
+                                            println!("Error writing record: {}", e);
+                                        }
+                                    }
+                                }
+                                let _feedback_gui = device_report_to_gui.send(report);
+                            }
+                            continue 'inner;
+                        }
                         if received_byte == 0x7b && !start_receiving {
                             message_buffer.clear();
                             start_receiving = true;
@@ -156,14 +223,26 @@ pub fn uart_handler(
                                     }
                                 }
 
+                                if nice_received_message.get_mode() < 10 {
+                                    let status = accel_calibration.get_status().to_string();
+                                    accel_commands.extend(
+                                        accel_calibration
+                                            .add_sample(nice_received_message.get_acc()),
+                                    );
+                                    if accel_calibration.get_status() != status {
+                                        let _feedback_gui = accel_calibration_to_gui
+                                            .send(accel_calibration.get_status().to_string());
+                                    }
+                                }
                                 let _feedback_gui = device_data_to_gui.send(nice_received_message);
 
                                 // clean everything, initialize everything and start receiving again
+                                // the rest of the bytes may already hold a report, so keep going through them
                                 message_buffer.clear();
                                 received_bytes_count = 0;
                                 start_receiving = false;
                                 repeat_flag = false;
-                                continue 'outer;
+                                continue 'inner;
                             }
                         }
                     }
@@ -178,6 +257,22 @@ pub fn uart_handler(
                 }
             }
             Err(_) => {
+                while accel_calibration_input.try_recv().is_ok() {
+                    accel_commands.extend(accel_calibration.on_key());
+                    let _feedback_gui =
+                        accel_calibration_to_gui.send(accel_calibration.get_status().to_string());
+                }
+                // one-off commands go out first, they are rare and should not wait behind the messages
+                for command in accel_commands.drain(..) {
+                    let mut message = Vec::new();
+                    command.form_message(&mut message);
+                    let _write_result = serial.write(&message);
+                }
+                while let Ok(command) = command_input.try_recv() {
+                    let mut message = Vec::new();
+                    command.form_message(&mut message);
+                    let _write_result = serial.write(&message);
+                }
                 loop {
                     // if there is nothing to read, we check if there is something to be sent, if there is, we send it, if not, we continue
                     let read_user = user_input.try_recv();
@@ -214,14 +309,20 @@ pub fn uart_handler(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn user_input(
     user_input: Sender<HostProtocol>,
+    command_input: Sender<HostCommand>,
     keyboard_input: Receiver<KeyboardControl>,
     joystick_input: Receiver<JoystickControl>,
     ack: Receiver<bool>,
     user_input_to_gui: Sender<HostProtocol>,
+    session_to_gui: Sender<u16>,
+    accel_calibration: Sender<()>,
 ) {
     let mut mode = 0b0000_0000;
+    // the session that is downloaded or erased, chosen from the listed sessions
+    let mut session = 1u16;
 
     // everything is u8 on the host side, we map each value to corresponding values on the device side
     let mut lift = 90u8;
@@ -231,6 +332,13 @@ pub fn user_input(
     let mut p = 50u8;
     let mut p1 = 50u8;
     let mut p2 = 50u8;
+    // the attitude estimator the drone flies on, None follows the mode
+    let mut attitude_estimator: Option<AttitudeSource> = None;
+    let mut compare_attitude = false;
+    let mut notch_tuning = NotchTuning::Off;
+
+    // show what the drone left behind if it crashed during the last run
+    let _feedback = command_input.send(HostCommand::new(CommandId::FetchCrashDump));
 
     loop {
         // Read the joystick input in this thread and send commands continuously, when keyboard is pressed, then add the command to the current one
@@ -319,8 +427,7 @@ pub fn user_input(
                     mode = 0b0000_1000;
                 }
                 KeyboardControl::Mode9 => {
-                    // for now, this also leads to panic
-                    // nothing yet, but leads to panic on the drone
+                    // self test
                     mode = 0b0000_1001;
                 }
                 KeyboardControl::ExitTerminal => {
@@ -471,6 +578,91 @@ pub fn user_input(
                     // read logs
                     mode = 0b0000_1010;
                 }
+                KeyboardControl::Arm => {
+                    let _feedback = command_input.send(HostCommand::new(CommandId::Arm));
+                }
+                KeyboardControl::Disarm => {
+                    let _feedback = command_input.send(HostCommand::new(CommandId::Disarm));
+                }
+                KeyboardControl::SaveConfig => {
+                    let _feedback = command_input.send(HostCommand::new(CommandId::SaveConfig));
+                }
+                KeyboardControl::ListSessions => {
+                    let _feedback = command_input.send(HostCommand::new(CommandId::ListSessions));
+                }
+                KeyboardControl::PreviousSession => {
+                    session = session.saturating_sub(1).max(1);
+                    let _feedback = session_to_gui.send(session);
+                }
+                KeyboardControl::NextSession => {
+                    session = session.saturating_add(1);
+                    let _feedback = session_to_gui.send(session);
+                }
+                KeyboardControl::DownloadSession => {
+                    let _feedback = command_input.send(HostCommand::with_session(
+                        CommandId::DownloadSession,
+                        session,
+                    ));
+                    // the logs of the session are sent in read logs mode
+                    mode = 0b0000_1010;
+                }
+                KeyboardControl::EraseSession => {
+                    let _feedback = command_input
+                        .send(HostCommand::with_session(CommandId::EraseSession, session));
+                }
+                KeyboardControl::EraseAllSessions => {
+                    let _feedback =
+                        command_input.send(HostCommand::new(CommandId::EraseAllSessions));
+                }
+                KeyboardControl::ReadIncidents => {
+                    let _feedback = command_input.send(HostCommand::new(CommandId::ReadIncidents));
+                }
+                KeyboardControl::ReadEvents => {
+                    let _feedback = command_input.send(HostCommand::new(CommandId::ReadEvents));
+                }
+                KeyboardControl::ClearCrashDump => {
+                    let _feedback = command_input.send(HostCommand::new(CommandId::ClearCrashDump));
+                }
+                KeyboardControl::AccelCalibration => {
+                    let _feedback = accel_calibration.send(());
+                }
+                KeyboardControl::NextAttitudeEstimator => {
+                    // by mode, then every estimator in turn
+                    attitude_estimator = match attitude_estimator {
+                        None => Some(AttitudeSource::ALL[0]),
+                        Some(estimator) => AttitudeSource::from_index(estimator.index() + 1),
+                    };
+                    let _feedback = command_input.send(HostCommand::with_attitude_estimator(
+                        attitude_estimator,
+                        compare_attitude,
+                    ));
+                }
+                KeyboardControl::ToggleAttitudeComparison => {
+                    compare_attitude = !compare_attitude;
+                    let _feedback = command_input.send(HostCommand::with_attitude_estimator(
+                        attitude_estimator,
+                        compare_attitude,
+                    ));
+                }
+                KeyboardControl::NextNotchTuning => {
+                    // off, fixed, tracking and off again
+                    notch_tuning = NotchTuning::from_index(notch_tuning.index() + 1)
+                        .unwrap_or(NotchTuning::Off);
+                    let _feedback = command_input.send(HostCommand::with_notch_filter(
+                        notch_tuning,
+                        NOTCH_CENTRE,
+                        NOTCH_MILLIHERTZ_PER_COMMAND,
+                        NOTCH_QUALITY_TENTHS,
+                    ));
+                }
+                KeyboardControl::SendParameters => match read_parameters(PARAMETER_FILE) {
+                    Ok(commands) => {
+                        for command in commands {
+                            let _feedback = command_input.send(command);
+                        }
+                    }
+                    Err(e) => println!("Error reading the parameters: {}", e),
+                },
             },
             Err(_) => {
                 // println!("Nothing on the keyboard pressed")
@@ -652,6 +844,57 @@ pub fn keyboard_monitor(keyboard_input: Sender<KeyboardControl>) {
                 Key::Char('B') => {
                     read_logs(keyboard_input.clone());
                 }
+                Key::Char('m') => {
+                    arm(keyboard_input.clone());
+                }
+                Key::Char('M') => {
+                    disarm(keyboard_input.clone());
+                }
+                Key::Char('c') => {
+                    save_config(keyboard_input.clone());
+                }
+                Key::Char('v') => {
+                    send_session_control(keyboard_input.clone(), KeyboardControl::ListSessions);
+                }
+                Key::Char('[') => {
+                    send_session_control(keyboard_input.clone(), KeyboardControl::PreviousSession);
+                }
+                Key::Char(']') => {
+                    send_session_control(keyboard_input.clone(), KeyboardControl::NextSession);
+                }
+                Key::Char('g') => {
+                    send_session_control(keyboard_input.clone(), KeyboardControl::DownloadSession);
+                }
+                Key::Char('x') => {
+                    send_session_control(keyboard_input.clone(), KeyboardControl::EraseSession);
+                }
+                Key::Char('n') => {
+                    read_incidents(keyboard_input.clone());
+                }
+                Key::Char('e') => {
+                    read_events(keyboard_input.clone());
+                }
+                Key::Char('C') => {
+                    clear_crash_dump(keyboard_input.clone());
+                }
+                Key::Char('s') => {
+                    accel_calibration_step(keyboard_input.clone());
+                }
+                Key::Char('X') => {
+                    send_session_control(keyboard_input.clone(), KeyboardControl::EraseAllSessions);
+                }
+                Key::Char('r') => {
+                    next_attitude_estimator(keyboard_input.clone());
+                }
+                Key::Char('R') => {
+                    toggle_attitude_comparison(keyboard_input.clone());
+                }
+                Key::Char('N') => {
+                    next_notch_tuning(keyboard_input.clone());
+                }
+                Key::Char('P') => {
+                    send_parameters(keyboard_input.clone());
+                }
                 /*PLEASE READ THIS! ALWAYS REMEMBER THAT THE WAY TO EXIT IS: CTRL + Q */
                 Key::Ctrl('q') => {
                     switch_mode_exit_terminal(keyboard_input.clone());
@@ -1149,6 +1392,131 @@ fn read_logs(keyboard_input: Sender<KeyboardControl>) {
     }
 }
 
+fn arm(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input.send(KeyboardControl::Arm).is_ok() {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn disarm(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input.send(KeyboardControl::Disarm).is_ok() {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn save_config(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input.send(KeyboardControl::SaveConfig).is_ok() {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn read_incidents(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input.send(KeyboardControl::ReadIncidents).is_ok() {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn read_events(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input.send(KeyboardControl::ReadEvents).is_ok() {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn clear_crash_dump(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input.send(KeyboardControl::ClearCrashDump).is_ok() {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn accel_calibration_step(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input
+        .send(KeyboardControl::AccelCalibration)
+        .is_ok()
+    {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn next_attitude_estimator(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input
+        .send(KeyboardControl::NextAttitudeEstimator)
+        .is_ok()
+    {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn toggle_attitude_comparison(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input
+        .send(KeyboardControl::ToggleAttitudeComparison)
+        .is_ok()
+    {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn next_notch_tuning(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input
+        .send(KeyboardControl::NextNotchTuning)
+        .is_ok()
+    {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+fn send_parameters(keyboard_input: Sender<KeyboardControl>) {
+    if keyboard_input.send(KeyboardControl::SendParameters).is_ok() {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
+// One line of the incident log: the incident, the time of the sample relative to it in ticks, the
+// attitude in milliradians, the raw angular rates, the motors and the setpoints
+fn incident_csv_record(incident: &IncidentReport) -> Vec<String> {
+    let sample = incident.sample;
+    let mut record = vec![
+        incident.number.to_string(),
+        incident.cause.description().to_string(),
+        incident.trigger_tick.to_string(),
+        incident.tick_offset().to_string(),
+    ];
+    record.extend(sample.attitude.iter().map(|value| value.to_string()));
+    record.extend(sample.rates.iter().map(|value| value.to_string()));
+    record.extend(sample.motors.iter().map(|value| value.to_string()));
+    record.extend(sample.setpoints.iter().map(|value| value.to_string()));
+    record
+}
+
+fn send_session_control(keyboard_input: Sender<KeyboardControl>, control: KeyboardControl) {
+    if keyboard_input.send(control).is_ok() {
+        println!("Message sent to message formatter");
+    } else {
+        println!("Message not sent to message formatter");
+    }
+}
+
 fn verify_message(message: &DeviceProtocol) -> bool {
     // we check the start bit and the end bit first
     if message.get_start_flag() != 0x7b || message.get_end_flag() != 0x7d {
@@ -1246,6 +1614,7 @@ fn print_ack(ack: &u8) {
         0b0000_0001 => information = AckByteCorespondingState::RemainingOnTheSameMode,
         0b0000_0010 => information = AckByteCorespondingState::FromPanic,
         0b0000_0011 => information = AckByteCorespondingState::FromReadLogs,
+        0b0000_0100 => information = AckByteCorespondingState::NotArmed,
         _ => information = AckByteCorespondingState::NotDefined,
     }
     match information {
@@ -1264,5 +1633,6 @@ fn print_ack(ack: &u8) {
         }
         AckByteCorespondingState::FromPanic => println!("ACK: Panicked\r"),
         AckByteCorespondingState::FromReadLogs => println!("ACK: Read logs\r"),
+        AckByteCorespondingState::NotArmed => println!("ACK: Not armed\r"),
     }
 }
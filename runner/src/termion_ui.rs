@@ -1,5 +1,10 @@
 use crate::{app::App, ui};
 use protocol::format::{DeviceProtocol, HostProtocol};
+use protocol::report::{
+    ArmingReport, AttitudeReport, CalibrationReport, ConfigReport, CrashReport, DeviceReport,
+    EventReport, FaultEvent, IncidentReport, ProfilingReport, ReportKind, SelfTestReport,
+    SessionReport, StatusReport, VibrationReport,
+};
 use std::{error::Error, sync::mpsc::Receiver};
 use tui::{backend::Backend, Terminal};
 
@@ -30,6 +35,9 @@ pub(crate) fn run_app<B: Backend>(
     // tick_rate: Duration,
     user_input: Receiver<HostProtocol>,
     device_data: Receiver<DeviceProtocol>,
+    device_report: Receiver<DeviceReport>,
+    session_selection: Receiver<u16>,
+    accel_calibration_status: Receiver<String>,
 ) -> Result<(), Box<dyn Error>> {
     // let events = events(tick_rate);
     // terminal.draw(|f| ui::draw(f, &mut app))?;
@@ -90,6 +98,83 @@ pub(crate) fn run_app<B: Backend>(
                 // app.error= format!("Error: {}", e);
             }
         }
+        if let Ok(report) = device_report.try_recv() {
+            match report.get_kind() {
+                Some(ReportKind::Profiling) => {
+                    if let Some(profiling) = ProfilingReport::from_report(&report) {
+                        app.profiling = profiling;
+                    }
+                }
+                Some(ReportKind::Status) => {
+                    if let Some(status) = StatusReport::from_report(&report) {
+                        app.status = status;
+                    }
+                }
+                Some(ReportKind::Fault) => {
+                    if let Some(fault) = FaultEvent::from_report(&report) {
+                        app.on_fault(fault);
+                    }
+                }
+                Some(ReportKind::Arming) => {
+                    if let Some(arming) = ArmingReport::from_report(&report) {
+                        app.arming = Some(arming);
+                    }
+                }
+                Some(ReportKind::SelfTest) => {
+                    if let Some(self_test) = SelfTestReport::from_report(&report) {
+                        app.self_test = Some(self_test);
+                    }
+                }
+                Some(ReportKind::Calibration) => {
+                    if let Some(calibration) = CalibrationReport::from_report(&report) {
+                        app.calibration = Some(calibration);
+                    }
+                }
+                Some(ReportKind::Attitude) => {
+                    if let Some(attitude) = AttitudeReport::from_report(&report) {
+                        app.attitude = Some(attitude);
+                    }
+                }
+                Some(ReportKind::Vibration) => {
+                    if let Some(vibration) = VibrationReport::from_report(&report) {
+                        app.vibration = Some(vibration);
+                    }
+                }
+                Some(ReportKind::Config) => {
+                    if let Some(config) = ConfigReport::from_report(&report) {
+                        app.config = Some(config);
+                    }
+                }
+                Some(ReportKind::Session) => {
+                    if let Some(session) = SessionReport::from_report(&report) {
+                        app.on_session(session);
+                    }
+                }
+                Some(ReportKind::Event) => {
+                    if let Some(event) = EventReport::from_report(&report) {
+                        app.on_event(event);
+                    }
+                }
+                Some(ReportKind::Incident) => {
+                    if let Some(incident) = IncidentReport::from_report(&report) {
+                        app.incident = Some(incident);
+                    }
+                }
+                Some(ReportKind::Crash) => {
+                    // an empty report means the record is gone
+                    app.crash = CrashReport::from_report(&report);
+                }
+                None => {
+                    // unknown report, probably from a newer firmware
+                }
+            }
+        }
+        if let Ok(session) = session_selection.try_recv() {
+            app.selected_session = session;
+        }
+        if let Ok(status) = accel_calibration_status.try_recv() {
+            app.accel_calibration = status;
+        }
         if app.should_quit {
             return Ok(());
         }
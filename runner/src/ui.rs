@@ -1,4 +1,11 @@
 use crate::app::App;
+use protocol::report::{
+    ArmingEvent, AttitudeSource, ConfigEvent, EventKind, EventReport, FaultCode, NotchTuning,
+    ProfileStage, StageTiming, ATTITUDE_BY_MODE, CALIBRATION_ACCEL, CALIBRATION_ATTITUDE,
+    CALIBRATION_GYRO, CALIBRATION_PRESSURE, PREARM_CHECKS, SELFTEST_ACCEL, SELFTEST_BAROMETER,
+    SELFTEST_BATTERY, SELFTEST_GYRO, SELFTEST_MOTORS, STATUS_ARMED, STATUS_BATTERY_CRITICAL,
+    STATUS_BATTERY_LOW,
+};
 use tui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout, Rect},
@@ -123,6 +130,20 @@ where
             Span::from("\r"),
             Span::from(app.pres.to_string()),
         ]),
+        Spans::from(vec![
+            Span::styled(
+                "ALT:",
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            Span::from("\r"),
+            Span::from(format!(
+                "{:.2} m {:.2} m/s",
+                app.status.altitude as f32 / 1000.0,
+                app.status.vertical_velocity as f32 / 1000.0
+            )),
+        ]),
         Spans::from(vec![Span::from("CRC: \r"), Span::from(app.crc.to_string())]),
         Spans::from(vec![
             Span::from("ACK: \r"),
@@ -142,6 +163,7 @@ fn match_corres_ack(ack: u8) -> String {
         0b0011_1100 => String::from("TRANSITION ALLOWED"),
         0b0000_0001 => String::from("REMAINING SAME MODE"),
         0b0000_0010 => String::from("PANIC TO SAFE"),
+        0b0000_0100 => String::from("NOT ARMED"),
         _ => String::from("   "),
     }
 }
@@ -157,6 +179,7 @@ fn match_mode_to_string(mode: u8) -> String {
         6 => String::from("Raw Control"),
         7 => String::from("Height Control"),
         8 => String::from("Wireless"),
+        9 => String::from("Self Test"),
         _ => String::from("Not Defined"),
     }
 }
@@ -264,11 +287,30 @@ where
         )
         .percent(app.motor[3].saturating_div(max_motor_speed));
     f.render_widget(gauge3, chunks[3]);
+    // the filtered voltage from the status report is steadier than the raw reading in the telemetry
+    let battery_title = if app.status.has_flag(STATUS_BATTERY_CRITICAL) {
+        format!(
+            "Battery Level {:.2} V CRITICAL",
+            app.status.battery as f32 / 100.0
+        )
+    } else if app.status.has_flag(STATUS_BATTERY_LOW) {
+        format!(
+            "Battery Level {:.2} V LOW",
+            app.status.battery as f32 / 100.0
+        )
+    } else {
+        format!("Battery Level {:.2} V", app.status.battery as f32 / 100.0)
+    };
+    let battery_color = if app.status.has_flag(STATUS_BATTERY_LOW) {
+        Color::Red
+    } else {
+        Color::LightRed
+    };
     let gauge3 = Gauge::default()
-        .block(Block::default().title("Battery Level"))
+        .block(Block::default().title(battery_title))
         .gauge_style(
             Style::default()
-                .fg(Color::LightRed)
+                .fg(battery_color)
                 .bg(Color::White)
                 .add_modifier(Modifier::BOLD),
         )
@@ -346,6 +388,83 @@ where
             ),
             Span::raw(" for P2 increase/decrease."),
         ]),
+        Spans::from(vec![
+            Span::styled(
+                "m/M",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" for arm/disarm."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "c",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" to save the configuration in flash."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "v, [/]",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" to list the flight sessions, select the previous/next one."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "g, x/X",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" to download the selected session, erase it/all sessions."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "n",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" to read the black-box incidents into incident_log.csv."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "e",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" to read the event log in flash."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "C",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" to clear the crash record in flash."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "s",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" for the six-position accelerometer calibration, once per position."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "r/R",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" to select the attitude estimator, compare all estimators."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "N",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" to switch the vibration notch off, to a fixed or a tracked frequency."),
+        ]),
+        Spans::from(vec![
+            Span::styled(
+                "P",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" to send the limits and settings in parameters.txt, in safe mode."),
+        ]),
     ];
     let block = Block::default()
         .borders(Borders::RIGHT | Borders::LEFT | Borders::TOP)
@@ -399,6 +518,9 @@ where
         8 => {
             str_mode = "ᛒᛒᛒ Wireless Control..not implemented :(".to_string();
         }
+        9 => {
+            str_mode = "Self Test".to_string();
+        }
         0b1111_1110 => {
             app.should_quit = true;
         }
@@ -840,7 +962,17 @@ where
         + str_roll.parse::<f32>().unwrap()
         + str_yaw.parse::<f32>().unwrap())
     .to_string();
-    let rows = vec![
+    let str_armed = if app.status.has_flag(STATUS_ARMED) {
+        "yes"
+    } else {
+        "no"
+    };
+    let armed_style = if app.status.has_flag(STATUS_ARMED) {
+        Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+    } else {
+        up_style
+    };
+    let mut rows = vec![
         Row::new(vec!["pitch", &str_pitch]).style(pitch_style),
         Row::new(vec!["roll", &str_roll]).style(roll_style),
         Row::new(vec!["yaw", &str_yaw]).style(yaw_style),
@@ -853,7 +985,63 @@ where
         Row::new(vec!["P1", &str_p1]).style(p1_style),
         Row::new(vec!["P2", &str_p2]).style(p2_style),
         Row::new(vec!["mode", &str_mode]).style(up_style),
+        Row::new(vec!["armed", str_armed]).style(armed_style),
     ];
+    // the last arming event, with every pre-arm check that failed when arming was refused
+    if let Some(arming) = app.arming {
+        rows.push(Row::new(vec!["arming", arming.event.description()]).style(up_style));
+        if arming.event == ArmingEvent::Refused {
+            for (check, reason) in PREARM_CHECKS {
+                if arming.has_failed(check) {
+                    rows.push(
+                        Row::new(vec!["refused", reason]).style(Style::default().fg(Color::Red)),
+                    );
+                }
+            }
+        }
+    }
+    // the configuration in flash, with the sequence number of the record in use
+    let str_config;
+    if let Some(config) = app.config {
+        str_config = format!("{} (#{})", config.event.description(), config.sequence);
+        let config_style = if config.event == ConfigEvent::SaveFailed {
+            Style::default().fg(Color::Red)
+        } else {
+            up_style
+        };
+        rows.push(Row::new(vec!["config", &str_config]).style(config_style));
+    }
+    // the black-box incident that is being read, with the sample that arrived last
+    let str_incident;
+    if let Some(incident) = app.incident {
+        str_incident = format!(
+            "#{} {} {}/{}",
+            incident.number,
+            incident.cause.description(),
+            incident.index + 1,
+            incident.count
+        );
+        rows.push(Row::new(vec!["incident", &str_incident]).style(up_style));
+    }
+    // the crash record of an earlier run, where it happened and the state of the last tick
+    let str_crash;
+    let str_crash_location;
+    let str_crash_state;
+    if let Some(crash) = &app.crash {
+        let crash_style = Style::default().fg(Color::Red);
+        str_crash = format!("{} @ {}", crash.cause.description(), crash.tick);
+        str_crash_location = format!("{}:{}", crash.file, crash.line);
+        str_crash_state = format!(
+            "{} bat {} m {:?}",
+            match_mode_to_string(crash.mode),
+            crash.battery,
+            crash.motors
+        );
+        rows.push(Row::new(vec!["crash", &str_crash]).style(crash_style));
+        rows.push(Row::new(vec!["crash at", &str_crash_location]).style(crash_style));
+        rows.push(Row::new(vec!["crash msg", &crash.message]).style(crash_style));
+        rows.push(Row::new(vec!["crash state", &str_crash_state]).style(crash_style));
+    }
     let table = Table::new(rows)
         .header(
             Row::new(vec!["Signal", "Value"])
@@ -868,6 +1056,348 @@ where
         .widths(&[Constraint::Length(15), Constraint::Length(15)]);
     f.render_widget(table, area);
 }
+fn draw_profiling<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let normal_style = Style::default().fg(Color::White);
+    let overrun_style = Style::default().fg(Color::Red);
+    let timing_row = |name: &'static str, timing: &StageTiming| {
+        let style = if timing.overruns > 0 {
+            overrun_style
+        } else {
+            normal_style
+        };
+        Row::new(vec![
+            name.to_string(),
+            timing.avg_us.to_string(),
+            timing.max_us.to_string(),
+            timing.overruns.to_string(),
+        ])
+        .style(style)
+    };
+    let mut rows: Vec<Row> = ProfileStage::ALL
+        .iter()
+        .map(|stage| timing_row(stage.name(), &app.profiling.stages[stage.index()]))
+        .collect();
+    rows.push(timing_row("whole loop", &app.profiling.whole_loop));
+    let table = Table::new(rows)
+        .header(
+            Row::new(vec!["Stage", "Avg us", "Max us", "Overruns"])
+                .style(Style::default().fg(Color::Yellow))
+                .bottom_margin(1),
+        )
+        .block(Block::default().title("Loop Profile").borders(Borders::ALL))
+        .widths(&[
+            Constraint::Length(12),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(9),
+        ]);
+    f.render_widget(table, area);
+}
+
+// The outcome of the last self test as a checklist, with the measured value of every check.
+fn draw_self_test<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let block = Block::default()
+        .title("Self Test (arm, then press 9)")
+        .borders(Borders::ALL);
+    let report = match app.self_test {
+        Some(report) => report,
+        None => {
+            f.render_widget(Paragraph::new("Not run yet").block(block), area);
+            return;
+        }
+    };
+    let check_row = |name: String, check: u16, value: String| {
+        let (result, style) = if report.has_failed(check) {
+            ("FAIL", Style::default().fg(Color::Red))
+        } else {
+            ("PASS", Style::default().fg(Color::Green))
+        };
+        Row::new(vec![name, result.to_string(), value]).style(style)
+    };
+    let mut rows = Vec::new();
+    for (motor, check) in SELFTEST_MOTORS.iter().enumerate() {
+        rows.push(check_row(
+            format!("motor {}", motor + 1),
+            *check,
+            format!("vibration {}", report.motor_vibration[motor]),
+        ));
+    }
+    rows.push(check_row(
+        "accelerometer".to_string(),
+        SELFTEST_ACCEL,
+        format!("noise {}", report.accel_noise),
+    ));
+    rows.push(check_row(
+        "gyroscope".to_string(),
+        SELFTEST_GYRO,
+        format!("noise {}", report.gyro_noise),
+    ));
+    rows.push(check_row(
+        "barometer".to_string(),
+        SELFTEST_BAROMETER,
+        format!("{} Pa", report.pressure),
+    ));
+    rows.push(check_row(
+        "battery".to_string(),
+        SELFTEST_BATTERY,
+        format!("{:.2} V", report.battery as f32 / 100.0),
+    ));
+    let table = Table::new(rows)
+        .header(
+            Row::new(vec!["Check", "Result", "Value"])
+                .style(Style::default().fg(Color::Yellow))
+                .bottom_margin(1),
+        )
+        .block(block)
+        .widths(&[
+            Constraint::Length(14),
+            Constraint::Length(7),
+            Constraint::Length(15),
+        ]);
+    f.render_widget(table, area);
+}
+
+fn draw_calibration<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let mut rows = Vec::new();
+    let mut title = "Calibration (press 3)".to_string();
+    if let Some(report) = app.calibration {
+        let check_row = |name: &str, check: u8, offset: String, variance: u32| {
+            let (result, style) = if report.has_failed(check) {
+                ("MOVED", Style::default().fg(Color::Red))
+            } else {
+                ("OK", Style::default().fg(Color::Green))
+            };
+            Row::new(vec![
+                name.to_string(),
+                result.to_string(),
+                offset,
+                variance.to_string(),
+            ])
+            .style(style)
+        };
+        rows.push(check_row(
+            "gyroscope",
+            CALIBRATION_GYRO,
+            format!("{:?}", report.gyro),
+            report.gyro_variance,
+        ));
+        rows.push(check_row(
+            "accelerometer",
+            CALIBRATION_ACCEL,
+            format!("{:?}", report.accel),
+            report.accel_variance,
+        ));
+        rows.push(check_row(
+            "attitude mrad",
+            CALIBRATION_ATTITUDE,
+            format!("{:?}", report.attitude),
+            report.attitude_variance,
+        ));
+        rows.push(check_row(
+            "barometer Pa",
+            CALIBRATION_PRESSURE,
+            report.pressure.to_string(),
+            report.pressure_variance,
+        ));
+        let result = if report.is_accepted() {
+            "accepted"
+        } else {
+            "rejected, hold still"
+        };
+        title = format!("{}: {} ({} samples)", title, result, report.samples);
+    }
+    // the six-position accelerometer calibration is guided from here
+    rows.push(Row::new(vec![
+        "six-position".to_string(),
+        "s".to_string(),
+        app.accel_calibration.clone(),
+    ]));
+    let table = Table::new(rows)
+        .header(
+            Row::new(vec!["Sensor", "Result", "Offset", "Variance"])
+                .style(Style::default().fg(Color::Yellow))
+                .bottom_margin(1),
+        )
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .widths(&[
+            Constraint::Length(14),
+            Constraint::Length(6),
+            Constraint::Length(20),
+            Constraint::Length(9),
+        ]);
+    f.render_widget(table, area);
+}
+
+// The drone sends a status report every 20 ticks of its 150 Hz control loop.
+const STATUS_REPORTS_PER_SECOND: u16 = 150 / 20;
+
+fn draw_faults<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let rows: Vec<Row> = app
+        .faults
+        .iter()
+        .map(|fault| {
+            Row::new(vec![
+                fault.tick.to_string(),
+                match_mode_to_string(fault.mode),
+                fault.code.description().to_string(),
+                fault.value.to_string(),
+            ])
+            .style(Style::default().fg(Color::Red))
+        })
+        .collect();
+    let title = format!(
+        "Fault Events (missed ticks: {}, saturated: {}/s, {} total, dropped reports: {})",
+        app.missed_ticks,
+        app.status.saturated_mixes * STATUS_REPORTS_PER_SECOND,
+        app.status.saturated_total,
+        app.status.dropped_reports
+    );
+    let table = Table::new(rows)
+        .header(
+            Row::new(vec!["Tick", "Mode", "Fault", "Value"])
+                .style(Style::default().fg(Color::Yellow))
+                .bottom_margin(1),
+        )
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .widths(&[
+            Constraint::Length(8),
+            Constraint::Length(14),
+            Constraint::Length(14),
+            Constraint::Length(6),
+        ]);
+    f.render_widget(table, area);
+}
+
+// What the reason code of an event means, depending on its kind.
+fn event_reason(event: &EventReport) -> String {
+    match event.kind {
+        EventKind::Transition | EventKind::Boot => String::new(),
+        EventKind::Declined => match_corres_ack(event.reason),
+        EventKind::Fault => FaultCode::from_byte(event.reason)
+            .map_or("unknown", |code| code.description())
+            .to_string(),
+        EventKind::Arming => ArmingEvent::from_byte(event.reason)
+            .map_or("unknown", |arming| arming.description())
+            .to_string(),
+        EventKind::Calibration => {
+            if event.reason != 0 {
+                String::from("done")
+            } else {
+                String::from("no samples")
+            }
+        }
+    }
+}
+
+fn draw_events<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let rows: Vec<Row> = app
+        .events
+        .iter()
+        .map(|event| {
+            let modes = if event.from == event.to {
+                match_mode_to_string(event.from)
+            } else {
+                format!(
+                    "{} > {}",
+                    match_mode_to_string(event.from),
+                    match_mode_to_string(event.to)
+                )
+            };
+            let style = match event.kind {
+                EventKind::Declined | EventKind::Fault => Style::default().fg(Color::Red),
+                _ => Style::default(),
+            };
+            Row::new(vec![
+                event.tick.to_string(),
+                event.kind.description().to_string(),
+                modes,
+                event_reason(event),
+            ])
+            .style(style)
+        })
+        .collect();
+    let table = Table::new(rows)
+        .header(
+            Row::new(vec!["Tick", "Event", "Mode", "Reason"])
+                .style(Style::default().fg(Color::Yellow))
+                .bottom_margin(1),
+        )
+        .block(
+            Block::default()
+                .title("Event Timeline")
+                .borders(Borders::ALL),
+        )
+        .widths(&[
+            Constraint::Length(8),
+            Constraint::Length(11),
+            Constraint::Length(28),
+            Constraint::Length(20),
+        ]);
+    f.render_widget(table, area);
+}
+
+fn draw_sessions<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let rows: Vec<Row> = app
+        .sessions
+        .iter()
+        .map(|session| {
+            let version = session.firmware_version;
+            let row = Row::new(vec![
+                session.number.to_string(),
+                session.start_tick.to_string(),
+                format!("{}.{}.{}", version[0], version[1], version[2]),
+                format!("{:04X}", session.config_hash),
+            ]);
+            if session.number == app.selected_session {
+                row.style(Style::default().fg(Color::Black).bg(Color::Yellow))
+            } else {
+                row
+            }
+        })
+        .collect();
+    let title = match app.session_event {
+        Some(event) => format!(
+            "Flight Sessions (selected #{}, #{} {})",
+            app.selected_session,
+            event.number,
+            event.event.description()
+        ),
+        None => format!("Flight Sessions (selected #{})", app.selected_session),
+    };
+    let table = Table::new(rows)
+        .header(
+            Row::new(vec!["#", "Start tick", "Firmware", "Config"])
+                .style(Style::default().fg(Color::Yellow))
+                .bottom_margin(1),
+        )
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .widths(&[
+            Constraint::Length(6),
+            Constraint::Length(11),
+            Constraint::Length(9),
+            Constraint::Length(6),
+        ]);
+    f.render_widget(table, area);
+}
+
 fn draw_drone<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
@@ -1119,6 +1649,101 @@ where
     draw_input_values(f, app, chunks[1]);
     // draw_drone(f, app, chunks[1]);
 }
+fn draw_attitude<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let mut rows = Vec::new();
+    let mut title = "Attitude estimators (r/R)".to_string();
+    if let Some(report) = app.attitude {
+        for estimator in AttitudeSource::ALL {
+            let active = report.active as usize == estimator.index();
+            let style = if active {
+                Style::default()
+                    .fg(Color::Green)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            let mut row = vec![estimator.name().to_string()];
+            if report.is_running(estimator) {
+                row.extend(
+                    report.estimates[estimator.index()]
+                        .iter()
+                        .map(|angle| angle.to_string()),
+                );
+            } else {
+                row.push("-".to_string());
+            }
+            rows.push(Row::new(row).style(style));
+        }
+        let selection = if report.selected == ATTITUDE_BY_MODE {
+            "by mode"
+        } else {
+            "selected"
+        };
+        title = format!("{}: {}", title, selection);
+    }
+    let table = Table::new(rows)
+        .header(
+            Row::new(vec!["Estimator", "Yaw", "Pitch", "Roll"])
+                .style(Style::default().fg(Color::Yellow))
+                .bottom_margin(1),
+        )
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .widths(&[
+            Constraint::Length(14),
+            Constraint::Length(7),
+            Constraint::Length(7),
+            Constraint::Length(7),
+        ]);
+    f.render_widget(table, area);
+}
+
+fn draw_vibration<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let mut rows = Vec::new();
+    let mut title = "Vibration (N)".to_string();
+    if let Some(report) = app.vibration {
+        let signals = ["gyro", "accel"].iter().zip([report.gyro, report.accel]);
+        for (signal, axes) in signals {
+            for (axis, [before, after]) in ["x", "y", "z"].iter().zip(axes) {
+                rows.push(Row::new(vec![
+                    format!("{} {}", signal, axis),
+                    before.to_string(),
+                    after.to_string(),
+                ]));
+            }
+        }
+        let tuning = NotchTuning::from_index(report.tuning as usize).map_or("?", NotchTuning::name);
+        title = if report.centre == 0 {
+            format!("{}: notch {}, passing", title, tuning)
+        } else {
+            format!(
+                "{}: notch {} at {:.1} Hz",
+                title,
+                tuning,
+                report.centre as f32 / 10.0
+            )
+        };
+    }
+    let table = Table::new(rows)
+        .header(
+            Row::new(vec!["mrad/s, mg", "Before", "After"])
+                .style(Style::default().fg(Color::Yellow))
+                .bottom_margin(1),
+        )
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .widths(&[
+            Constraint::Length(11),
+            Constraint::Length(7),
+            Constraint::Length(7),
+        ]);
+    f.render_widget(table, area);
+}
+
 fn draw_legend<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
@@ -1128,6 +1753,9 @@ where
             Constraint::Min(20),
             Constraint::Min(40),
             Constraint::Min(20),
+            Constraint::Min(12),
+            Constraint::Min(10),
+            Constraint::Min(10),
         ])
         .direction(Direction::Vertical)
         .margin(1)
@@ -1135,6 +1763,10 @@ where
     // draw_input_values(f, app, chunks[0]);
     draw_serial(f, app, chunks[0]);
     draw_two(f, app, chunks[1]);
+    draw_profiling(f, app, chunks[2]);
+    draw_self_test(f, app, chunks[3]);
+    draw_calibration(f, app, chunks[4]);
+    draw_sessions(f, app, chunks[5]);
     // draw_input_values(f, app, chunks[2]);
     // draw_drone(f, app, chunks[1]);
 }
@@ -1144,13 +1776,27 @@ where
     B: Backend,
 {
     let chunks = Layout::default()
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .constraints(
+            [
+                Constraint::Percentage(26),
+                Constraint::Percentage(18),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+            ]
+            .as_ref(),
+        )
         .direction(Direction::Vertical)
         .margin(1)
         .split(area);
     draw_gauges(f, app, chunks[0]);
     // draw_bar(f,app,chunks[1]);
     draw_charts(f, app, chunks[1]);
+    draw_attitude(f, app, chunks[2]);
+    draw_vibration(f, app, chunks[3]);
+    draw_faults(f, app, chunks[4]);
+    draw_events(f, app, chunks[5]);
     // draw_serial(f, app, chunks[1]);
 }
 